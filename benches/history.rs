@@ -0,0 +1,142 @@
+//! Benchmarks of the history path over a full sensor log of 2016 readings: reassembling the
+//! notifications, decoding them, reconstructing timestamps and writing archives. Run with
+//! `cargo bench`.
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use tokio::runtime::Runtime;
+
+use arachiver::csv_io::{save_history_csv, CsvTimestamps};
+use arachiver::device::{get_history, History, HistoryMetric};
+use arachiver::mock::MockAranet4;
+use arachiver::parquet_io::{save_history_parquet, TimestampUnit};
+use arachiver::types::{history_records, HumidityData, TemperatureData, Units};
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap()
+}
+
+fn full_history(runtime: &Runtime) -> History {
+    runtime
+        .block_on(get_history(&MockAranet4::simulated()))
+        .unwrap()
+}
+
+fn bench_reassembly(c: &mut Criterion) {
+    let runtime = runtime();
+    let sensor = MockAranet4::simulated();
+    // Includes the mock packing the packets for each request, as the sensor would
+    c.bench_function("reassemble full history", |b| {
+        b.iter(|| runtime.block_on(get_history(&sensor)).unwrap())
+    });
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let history = full_history(&runtime());
+    let temperature: Vec<u8> = history
+        .temperature
+        .values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+    let humidity = history.humidity.values.clone();
+    c.bench_function("decode u16 history", |b| {
+        b.iter(|| TemperatureData::try_from(black_box(&temperature[..])).unwrap())
+    });
+    c.bench_function("decode u8 history", |b| {
+        b.iter(|| HumidityData::try_from(black_box(&humidity[..])).unwrap())
+    });
+}
+
+fn bench_timestamps(c: &mut Criterion) {
+    let history = full_history(&runtime());
+    c.bench_function("history timestamps", |b| {
+        b.iter(|| {
+            history
+                .time
+                .timestamps()
+                .map(|time| time.timestamp())
+                .sum::<i64>()
+        })
+    });
+    c.bench_function("history records", |b| {
+        b.iter(|| {
+            history_records(
+                &history.time,
+                &history.temperature,
+                &history.humidity,
+                &history.pressure,
+                &history.co2,
+            )
+            .map(|record| record.unwrap().co2_ppm as u64)
+            .sum::<u64>()
+        })
+    });
+}
+
+fn bench_archives(c: &mut Criterion) {
+    let runtime = runtime();
+    let history = full_history(&runtime);
+    c.bench_function("write CSV", |b| {
+        b.iter_batched(
+            || history.clone(),
+            |history| {
+                let mut dest = Vec::new();
+                runtime
+                    .block_on(save_history_csv(
+                        history.time,
+                        history.temperature,
+                        history.humidity,
+                        history.pressure,
+                        history.co2,
+                        &mut dest,
+                        Units::Metric,
+                        CsvTimestamps::default(),
+                        &HistoryMetric::ALL,
+                        false,
+                    ))
+                    .unwrap();
+                dest
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("write Parquet", |b| {
+        b.iter_batched(
+            || history.clone(),
+            |history| {
+                let mut dest = Vec::new();
+                runtime
+                    .block_on(save_history_parquet(
+                        history.time,
+                        history.temperature,
+                        history.humidity,
+                        history.pressure,
+                        history.co2,
+                        &mut dest,
+                        TimestampUnit::default(),
+                        Units::Metric,
+                        &HistoryMetric::ALL,
+                        false,
+                        None,
+                    ))
+                    .unwrap();
+                dest
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+criterion_group!(
+    benches,
+    bench_reassembly,
+    bench_decode,
+    bench_timestamps,
+    bench_archives
+);
+criterion_main!(benches);
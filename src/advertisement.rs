@@ -0,0 +1,130 @@
+//! Readings broadcast in advertisements. With "Smart Home integrations" enabled in the Aranet
+//! Home app, the Aranet4 includes its current reading in its manufacturer data, so readings can be
+//! collected from every sensor in range without connecting to any of them.
+
+use std::collections::HashMap;
+
+use btleplug::api::{Central as _, CentralEvent, ScanFilter};
+use btleplug::platform::{Adapter, PeripheralId};
+use chrono::Utc;
+use color_eyre::Result;
+use tokio_stream::StreamExt;
+
+use crate::device::{get_local_name, DevicePattern};
+use crate::shutdown::shutdown_signal;
+use crate::types::{Aranet4Error, CurrentSensorMeasurement};
+
+/// Bluetooth SIG company identifier of SAF Tehnika, the maker of the Aranet4.
+pub const SAF_TEHNIKA_MANUFACTURER_ID: u16 = 0x0702;
+
+/// Decode the current reading from Aranet4 manufacturer data. The measurement is only
+/// broadcast when "Smart Home integrations" is enabled in the Aranet Home app, in which case
+/// an 8-byte header is followed by the same layout as the current readings characteristic
+/// (see the Aranet4-Python project).
+pub fn decode_advertisement(data: &[u8]) -> Result<CurrentSensorMeasurement, Aranet4Error> {
+    const HEADER_LEN: usize = 8;
+    const MEASUREMENT_LEN: usize = 13;
+    if data.len() < HEADER_LEN + MEASUREMENT_LEN {
+        return Err(Aranet4Error::InvalidResponse(
+            "Advertisement has no measurement; is Smart Home integration enabled?".to_string(),
+        ));
+    }
+    let measurement_bytes: [u8; MEASUREMENT_LEN] = data[HEADER_LEN..HEADER_LEN + MEASUREMENT_LEN]
+        .try_into()
+        .unwrap();
+    Ok(measurement_bytes.into())
+}
+
+/// The reading in a device's advertised manufacturer data, if it is an Aranet4 with Smart Home
+/// integration enabled.
+pub fn advertised_reading(
+    manufacturer_data: &HashMap<u16, Vec<u8>>,
+) -> Option<CurrentSensorMeasurement> {
+    manufacturer_data
+        .get(&SAF_TEHNIKA_MANUFACTURER_ID)
+        .and_then(|data| decode_advertisement(data).ok())
+}
+
+/// Passively decode advertised readings from sensors whose name matches `device_pattern`,
+/// calling `on_reading` with the sensor's name for every new measurement, until interrupted
+/// with Ctrl-C or SIGTERM. Sensors repeat each measurement in many advertisements, so
+/// repeats are suppressed.
+pub async fn listen_for_advertisements<F>(
+    central: &Adapter,
+    device_pattern: &DevicePattern,
+    mut on_reading: F,
+) -> Result<()>
+where
+    F: FnMut(&str, &CurrentSensorMeasurement) -> Result<()>,
+{
+    let mut events = central.events().await?;
+    central.start_scan(ScanFilter::default()).await?;
+    let mut last_seen: HashMap<PeripheralId, i64> = HashMap::new();
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        let event = tokio::select! {
+            _ = &mut shutdown => break,
+            event = events.next() => match event {
+                Some(event) => event,
+                None => break,
+            },
+        };
+        let CentralEvent::ManufacturerDataAdvertisement {
+            id,
+            manufacturer_data,
+        } = event
+        else {
+            continue;
+        };
+        let Some(reading) = advertised_reading(&manufacturer_data) else {
+            continue;
+        };
+        let peripheral = central.peripheral(&id).await?;
+        let Some(local_name) = get_local_name(&peripheral).await else {
+            continue;
+        };
+        if !device_pattern.matches(&local_name) {
+            continue;
+        }
+        let measured_at = Utc::now().timestamp() - reading.age().as_secs() as i64;
+        // `ago` ticks up between measurements, so compare reconstructed measurement times
+        // with some slack rather than the raw readings.
+        let is_new = last_seen
+            .get(&id)
+            .is_none_or(|last| measured_at - last > reading.interval().as_secs() as i64 / 2);
+        if is_new {
+            last_seen.insert(id, measured_at);
+            on_reading(&local_name, &reading)?;
+        }
+    }
+    central.stop_scan().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::advertisement::{
+        advertised_reading, decode_advertisement, SAF_TEHNIKA_MANUFACTURER_ID,
+    };
+
+    #[test]
+    fn test_decode_advertisement() {
+        let data = [
+            0x22, 0x13, 0x04, 0x01, 0x00, 0x0c, 0x0f, 0x01, 0x9e, 0x03, 0x9d, 0x01, 0xbe, 0x27,
+            0x21, 0x16, 0x01, 0x2c, 0x01, 0xff, 0x00, 0x3a,
+        ];
+        let reading = decode_advertisement(&data).unwrap();
+        assert_eq!(reading.co2, 926);
+        assert_eq!(reading.temperature_value(), 20.65);
+        assert_eq!(reading.interval, 300);
+        // Without Smart Home integration only the header is advertised
+        assert!(decode_advertisement(&data[..8]).is_err());
+
+        let manufacturer_data = HashMap::from([(SAF_TEHNIKA_MANUFACTURER_ID, data.to_vec())]);
+        assert!(advertised_reading(&manufacturer_data).is_some());
+        assert!(advertised_reading(&HashMap::from([(0x004c, data.to_vec())])).is_none());
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Arc;
+
+use arrow_array::{Float32Array, Int32Array, RecordBatch, TimestampSecondArray};
+use arrow_ipc::writer::FileWriter;
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use color_eyre::eyre::Result;
+
+use crate::device::HistoryTime;
+use crate::types::{
+    history_records, CO2Data, HistoryRecord, HumidityData, Metadata, PressureData, TemperatureData,
+};
+
+fn field_with_unit(name: &str, data_type: DataType, unit: &str) -> Field {
+    Field::new(name, data_type, false)
+        .with_metadata(HashMap::from([("unit".to_string(), unit.to_string())]))
+}
+
+/// The history schema: the same columns as the Parquet output, but with a proper UTC
+/// timestamp type. Each field carries its unit in a `unit` metadata entry.
+fn history_arrow_schema() -> Schema {
+    Schema::new(vec![
+        field_with_unit(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Second, Some("UTC".into())),
+            "UNIX time",
+        ),
+        field_with_unit("temperature", DataType::Float32, TemperatureData::LABEL),
+        field_with_unit("humidity", DataType::Int32, HumidityData::LABEL),
+        field_with_unit("pressure", DataType::Float32, PressureData::LABEL),
+        field_with_unit("co2", DataType::Int32, CO2Data::LABEL),
+    ])
+}
+
+/// Write the history as an Arrow IPC file, also known as Feather v2.
+pub async fn save_history_arrow<W: Write>(
+    history_time: HistoryTime,
+    temperature: TemperatureData,
+    humidity: HumidityData,
+    pressure: PressureData,
+    co2: CO2Data,
+    dest: &mut W,
+) -> Result<()> {
+    write_history_arrow(
+        history_records(&history_time, &temperature, &humidity, &pressure, &co2),
+        dest,
+    )
+}
+
+/// Like [`save_history_arrow`], for readings from any source, e.g. an archive being converted.
+pub fn write_history_arrow<W: Write>(
+    records: impl IntoIterator<Item = Result<HistoryRecord>>,
+    dest: &mut W,
+) -> Result<()> {
+    let records: Vec<HistoryRecord> = records.into_iter().collect::<Result<_>>()?;
+    let schema = Arc::new(history_arrow_schema());
+    let batch = RecordBatch::try_new(
+        schema.clone(),
+        vec![
+            Arc::new(
+                TimestampSecondArray::from_iter_values(records.iter().map(|r| r.timestamp))
+                    .with_timezone("UTC"),
+            ),
+            Arc::new(Float32Array::from_iter_values(
+                records.iter().map(|r| r.temperature_c as f32),
+            )),
+            Arc::new(Int32Array::from_iter_values(
+                records.iter().map(|r| i32::from(r.humidity_pct)),
+            )),
+            Arc::new(Float32Array::from_iter_values(
+                records.iter().map(|r| r.pressure_mbar as f32),
+            )),
+            Arc::new(Int32Array::from_iter_values(
+                records.iter().map(|r| i32::from(r.co2_ppm)),
+            )),
+        ],
+    )?;
+    let mut writer = FileWriter::try_new(dest, &schema)?;
+    writer.write(&batch)?;
+    writer.finish()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::arrow_io::save_history_arrow;
+    use crate::device::HistoryTime;
+    use crate::types::{CO2Data, HumidityData, PressureData, TemperatureData};
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::TimestampSecondType;
+    use arrow_ipc::reader::FileReader;
+    use chrono::DateTime;
+    use std::io::Cursor;
+
+    #[tokio::test]
+    async fn test_save_history_arrow() {
+        let bytes = [144u8, 1, 164, 1];
+        let history_time = HistoryTime {
+            num_samples: 2,
+            update_interval: 300,
+            since_update: 24,
+            now: DateTime::from_timestamp(1738621353, 0).unwrap(),
+            offset: 0,
+        };
+        let mut output = Vec::new();
+        save_history_arrow(
+            history_time,
+            TemperatureData::try_from(&bytes[..]).unwrap(),
+            HumidityData::try_from(&bytes[0..2]).unwrap(),
+            PressureData::try_from(&bytes[..]).unwrap(),
+            CO2Data::try_from(&bytes[..]).unwrap(),
+            &mut output,
+        )
+        .await
+        .unwrap();
+        assert_eq!(output[0..6], *b"ARROW1");
+        let mut reader = FileReader::try_new(Cursor::new(output), None).unwrap();
+        assert_eq!(reader.schema().field(4).metadata()["unit"], "CO₂ (ppm)");
+        let batch = reader.next().unwrap().unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        let timestamps = batch.column(0).as_primitive::<TimestampSecondType>();
+        assert_eq!(timestamps.value(1), 1738621329);
+    }
+}
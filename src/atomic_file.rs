@@ -0,0 +1,102 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Result, WrapErr};
+
+/// A file that only appears under its name once it is complete. Writes go to `<name>.tmp` next
+/// to it, which [`commit`](AtomicFile::commit) renames into place; if the `AtomicFile` is
+/// dropped without committing, e.g. because the download failed, the temp file is removed.
+pub struct AtomicFile {
+    file: Option<File>,
+    path: PathBuf,
+    temp_path: PathBuf,
+}
+
+impl AtomicFile {
+    pub fn create(path: &Path) -> Result<Self> {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp_path = path.with_file_name(format!("{}.tmp", file_name));
+        let file = File::create(&temp_path)
+            .wrap_err_with(|| format!("Could not create writeable file {}", temp_path.display()))?;
+        Ok(AtomicFile {
+            file: Some(file),
+            path: path.to_path_buf(),
+            temp_path,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Where the file is written until it is committed, for writers that need a path rather
+    /// than a stream.
+    pub fn temp_path(&self) -> &Path {
+        &self.temp_path
+    }
+
+    /// Flush everything to disk and move the file into place, replacing any existing file.
+    pub fn commit(mut self) -> Result<()> {
+        self.file().flush()?;
+        self.file().sync_all()?;
+        // Close the file before renaming it, which Windows requires
+        drop(self.file.take());
+        let renamed = fs::rename(&self.temp_path, &self.path);
+        if renamed.is_err() {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+        renamed.wrap_err_with(|| format!("Could not replace {}", self.path.display()))
+    }
+
+    fn file(&mut self) -> &mut File {
+        self.file.as_mut().expect("AtomicFile used after commit")
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file().flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.temp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::atomic_file::AtomicFile;
+    use std::io::Write;
+
+    #[test]
+    fn test_atomic_file() {
+        let dir = std::env::temp_dir().join("arachiver_test_atomic_file");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history.csv");
+        let temp_path = dir.join("history.csv.tmp");
+        std::fs::write(&path, "old").unwrap();
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"partial").unwrap();
+        assert!(temp_path.exists());
+        drop(file);
+        assert!(!temp_path.exists());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old");
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"new").unwrap();
+        file.commit().unwrap();
+        assert!(!temp_path.exists());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,159 @@
+use std::process::Command;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+
+use crate::types::CurrentSensorMeasurement;
+
+/// Result of comparing a reading against the `check` thresholds, ordered by severity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CheckStatus {
+    Ok,
+    Warning,
+    Critical,
+}
+
+impl CheckStatus {
+    /// Exit status by the Nagios plugin convention.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            CheckStatus::Ok => 0,
+            CheckStatus::Warning => 1,
+            CheckStatus::Critical => 2,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            CheckStatus::Ok => "OK",
+            CheckStatus::Warning => "WARNING",
+            CheckStatus::Critical => "CRITICAL",
+        }
+    }
+}
+
+/// Nagios exit status for when the sensor couldn't be read at all.
+pub const UNKNOWN_EXIT_CODE: i32 = 3;
+
+/// CO₂ levels in ppm at or above which `check` warns or goes critical. The defaults match the
+/// sensor's own yellow and red indicator thresholds.
+#[derive(Debug, Clone, Copy)]
+pub struct Co2Thresholds {
+    pub warn: u16,
+    pub crit: u16,
+}
+
+impl Default for Co2Thresholds {
+    fn default() -> Self {
+        Co2Thresholds {
+            warn: 1000,
+            crit: 1400,
+        }
+    }
+}
+
+impl Co2Thresholds {
+    pub fn new(warn: u16, crit: u16) -> Result<Self> {
+        if warn > crit {
+            return Err(eyre!(
+                "CO₂ warning threshold {} ppm is above the critical threshold {} ppm",
+                warn,
+                crit
+            ));
+        }
+        Ok(Co2Thresholds { warn, crit })
+    }
+
+    pub fn status(&self, co2: u16) -> CheckStatus {
+        if co2 >= self.crit {
+            CheckStatus::Critical
+        } else if co2 >= self.warn {
+            CheckStatus::Warning
+        } else {
+            CheckStatus::Ok
+        }
+    }
+
+    pub fn reading_status(&self, reading: &CurrentSensorMeasurement) -> CheckStatus {
+        self.status(reading.co2_ppm())
+    }
+}
+
+/// One line in the Nagios plugin format: a summary, then performance data after the `|`.
+pub fn status_line(
+    name: &str,
+    reading: &CurrentSensorMeasurement,
+    thresholds: &Co2Thresholds,
+) -> String {
+    format!(
+        "CO2 {} - {}: {} ppm | co2={};{};{};0 temperature={} humidity={}% pressure={} battery={}%",
+        thresholds.reading_status(reading).label(),
+        name,
+        reading.co2_ppm(),
+        reading.co2_ppm(),
+        thresholds.warn,
+        thresholds.crit,
+        reading.temperature_value(),
+        reading.humidity_percent(),
+        reading.pressure_value(),
+        reading.battery_percent(),
+    )
+}
+
+/// Run `command` through the shell, with the reading in `ARANET_*` environment variables, and
+/// wait for it to finish.
+pub fn run_alert_hook(
+    command: &str,
+    status: CheckStatus,
+    name: &str,
+    reading: &CurrentSensorMeasurement,
+) -> Result<()> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let exit_status = shell
+        .arg(command)
+        .env("ARANET_STATUS", status.label())
+        .env("ARANET_NAME", name)
+        .env("ARANET_CO2", reading.co2_ppm().to_string())
+        .env(
+            "ARANET_TEMPERATURE",
+            reading.temperature_value().to_string(),
+        )
+        .env("ARANET_HUMIDITY", reading.humidity_percent().to_string())
+        .env("ARANET_PRESSURE", reading.pressure_value().to_string())
+        .status()
+        .wrap_err_with(|| format!("Could not run alert hook {}", command))?;
+    if !exit_status.success() {
+        return Err(eyre!("Alert hook {} failed with {}", command, exit_status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::check::{status_line, CheckStatus, Co2Thresholds};
+    use crate::types::CurrentSensorMeasurement;
+
+    #[test]
+    fn test_check_thresholds() {
+        let thresholds = Co2Thresholds::default();
+        assert_eq!(thresholds.status(999), CheckStatus::Ok);
+        assert_eq!(thresholds.status(1000), CheckStatus::Warning);
+        assert_eq!(thresholds.status(1400), CheckStatus::Critical);
+        assert!(Co2Thresholds::new(1400, 1000).is_err());
+
+        let reading = CurrentSensorMeasurement::from([
+            0x9e, 0x03, 0x9d, 0x01, 0xbe, 0x27, 0x21, 0x16, 0x01, 0x2c, 0x01, 0xff, 0x00,
+        ]);
+        assert_eq!(
+            status_line("Aranet4 1BA27", &reading, &Co2Thresholds::new(900, 1200).unwrap()),
+            "CO2 WARNING - Aranet4 1BA27: 926 ppm | co2=926;900;1200;0 temperature=20.65 humidity=33% pressure=1017.4 battery=22%"
+        );
+    }
+}
@@ -0,0 +1,494 @@
+use btleplug::api::{Central as _, Peripheral as _};
+use btleplug::platform::{Adapter, Peripheral};
+use chrono::{TimeDelta, Utc};
+use color_eyre::Result;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{debug, instrument, warn};
+
+use crate::clock::{get_clock_skew, sync_device_time, TimestampSource};
+use crate::device::{
+    find_known_peripheral, get_address, get_current_sensor_data, get_first_adapter,
+    get_history_after, get_history_from, get_history_range, get_local_name, get_model,
+    get_model_history, get_model_sensor_data, get_serial_number, scan_for_address,
+    scan_for_all_sensors, scan_for_sensor, set_update_interval, subscribe_current_readings,
+    with_retries, with_timeout, CurrentReadingStream, DeviceInfo, DevicePattern, History,
+    HistoryMetric, HistoryProgress, HistoryTime, HistoryTransfer, ModelHistory, RetryPolicy,
+    SensorStatus, ARANET4_NOTIFY_CURRENT_READINGS_UUID, ARANET4_NOTIFY_HISTORY_UUID,
+    DEFAULT_OP_TIMEOUT, DEFAULT_SCAN_TIMEOUT,
+};
+use crate::device_cache::DeviceCache;
+use crate::mock::MockAranet4;
+use crate::reading_cache::ReadingCache;
+use crate::time_range::HistoryRange;
+use crate::transport::{ConnectedSensor, SensorBackend, SensorTransport};
+use crate::types::{Aranet4Error, AranetModel, CurrentSensorMeasurement, ModelMeasurement};
+
+/// What [`Aranet4Client::id`] and [`Aranet4Client::address`] return for the simulated sensor.
+const SIMULATED_ID: &str = "simulated";
+
+/// A handle to a single Aranet4 sensor found on a Bluetooth adapter.
+pub struct Aranet4Client {
+    /// The adapter the sensor was found with; `None` for a simulated sensor.
+    central: Option<Adapter>,
+    sensor: ConnectedSensor<SensorBackend>,
+    progress: Option<Box<dyn Fn(HistoryProgress) + Send + Sync>>,
+    retry: RetryPolicy,
+    op_timeout: Duration,
+    transfer: HistoryTransfer,
+    metrics: Vec<HistoryMetric>,
+    raw_timestamps: bool,
+    timestamp_source: TimestampSource,
+    reading_cache: Option<PathBuf>,
+}
+
+impl Aranet4Client {
+    /// Scan the first Bluetooth adapter for a sensor with `device_pattern` in its name, in any
+    /// case.
+    pub async fn connect(device_pattern: &str) -> Result<Self> {
+        Self::connect_with_timeout(
+            &DevicePattern::substring(device_pattern, false),
+            DEFAULT_SCAN_TIMEOUT,
+        )
+        .await
+    }
+
+    pub async fn connect_with_timeout(
+        device_pattern: &DevicePattern,
+        scan_timeout: Duration,
+    ) -> Result<Self> {
+        let central = get_first_adapter().await?;
+        Self::connect_with_adapter(central, device_pattern, scan_timeout).await
+    }
+
+    /// Scan `central` for a sensor matching `device_pattern`.
+    pub async fn connect_with_adapter(
+        central: Adapter,
+        device_pattern: &DevicePattern,
+        scan_timeout: Duration,
+    ) -> Result<Self> {
+        let peripheral = scan_for_sensor(&central, device_pattern, scan_timeout).await?;
+        Ok(Self::from_peripheral(central, peripheral))
+    }
+
+    /// Connect to the sensor with the given MAC address or platform peripheral ID. If `cache`
+    /// has a peripheral ID for `address` that `central` still knows, no scan is needed;
+    /// otherwise the ID found by scanning is added to `cache`.
+    pub async fn connect_with_address(
+        central: Adapter,
+        address: &str,
+        scan_timeout: Duration,
+        cache: &mut DeviceCache,
+    ) -> Result<Self> {
+        let known = match cache.peripheral_id(address) {
+            Some(id) => find_known_peripheral(&central, id).await?,
+            None => None,
+        };
+        if known.is_some() {
+            debug!(address, "Using cached peripheral ID instead of scanning");
+        }
+        let peripheral = match known {
+            Some(peripheral) => peripheral,
+            None => {
+                let peripheral = scan_for_address(&central, address, scan_timeout).await?;
+                cache.insert(address, peripheral.id().to_string());
+                peripheral
+            }
+        };
+        Ok(Self::from_peripheral(central, peripheral))
+    }
+
+    /// Connect to the sensor with platform peripheral ID `id` without scanning, if `central`
+    /// still knows it.
+    pub async fn connect_known(central: Adapter, id: &str) -> Result<Option<Self>> {
+        Ok(find_known_peripheral(&central, id)
+            .await?
+            .map(|peripheral| Self::from_peripheral(central, peripheral)))
+    }
+
+    /// Scan the first Bluetooth adapter for every sensor with `device_pattern` in its name, in
+    /// any case.
+    pub async fn connect_all(device_pattern: &str) -> Result<Vec<Self>> {
+        Self::connect_all_with_timeout(
+            &DevicePattern::substring(device_pattern, false),
+            DEFAULT_SCAN_TIMEOUT,
+        )
+        .await
+    }
+
+    pub async fn connect_all_with_timeout(
+        device_pattern: &DevicePattern,
+        scan_timeout: Duration,
+    ) -> Result<Vec<Self>> {
+        let central = get_first_adapter().await?;
+        Self::connect_all_with_adapter(central, device_pattern, scan_timeout).await
+    }
+
+    /// Scan `central` for every sensor matching `device_pattern`.
+    pub async fn connect_all_with_adapter(
+        central: Adapter,
+        device_pattern: &DevicePattern,
+        scan_timeout: Duration,
+    ) -> Result<Vec<Self>> {
+        let peripherals = scan_for_all_sensors(&central, device_pattern, scan_timeout).await?;
+        Ok(peripherals
+            .into_iter()
+            .map(|peripheral| Self::from_peripheral(central.clone(), peripheral))
+            .collect())
+    }
+
+    pub fn from_peripheral(central: Adapter, peripheral: Peripheral) -> Self {
+        Self::from_backend(Some(central), SensorBackend::Bluetooth(peripheral))
+    }
+
+    /// A client for the built-in simulated Aranet4, [`MockAranet4::simulated`], which needs no
+    /// Bluetooth adapter.
+    pub fn simulated() -> Self {
+        Self::from_backend(
+            None,
+            SensorBackend::Simulated(Box::new(MockAranet4::simulated())),
+        )
+    }
+
+    fn from_backend(central: Option<Adapter>, backend: SensorBackend) -> Self {
+        Aranet4Client {
+            central,
+            sensor: ConnectedSensor::new(backend),
+            progress: None,
+            retry: RetryPolicy::default(),
+            op_timeout: DEFAULT_OP_TIMEOUT,
+            transfer: HistoryTransfer::default(),
+            metrics: HistoryMetric::ALL.to_vec(),
+            raw_timestamps: false,
+            timestamp_source: TimestampSource::default(),
+            reading_cache: None,
+        }
+    }
+
+    /// The Bluetooth peripheral, unless the sensor is simulated.
+    pub fn peripheral(&self) -> Option<&Peripheral> {
+        match self.sensor.sensor() {
+            SensorBackend::Bluetooth(peripheral) => Some(peripheral),
+            SensorBackend::Simulated(_) => None,
+        }
+    }
+
+    /// The platform peripheral ID, or `simulated` for the simulated sensor.
+    pub fn id(&self) -> String {
+        match self.peripheral() {
+            Some(peripheral) => peripheral.id().to_string(),
+            None => SIMULATED_ID.to_string(),
+        }
+    }
+
+    /// Keep the sensor connected from the next operation on, instead of connecting and
+    /// disconnecting around each one, until [`Aranet4Client::disconnect`]. See
+    /// [`ConnectedSensor`].
+    pub fn stay_connected(&self) {
+        self.sensor.hold();
+    }
+
+    /// Undo [`Aranet4Client::stay_connected`] and disconnect.
+    pub async fn disconnect(&self) -> Result<(), Aranet4Error> {
+        self.sensor.close().await
+    }
+
+    pub async fn local_name(&self) -> Option<String> {
+        match self.peripheral() {
+            Some(peripheral) => get_local_name(peripheral).await,
+            None => self.sensor.local_name().await,
+        }
+    }
+
+    /// The MAC address, or the platform peripheral ID where the address is hidden (macOS).
+    pub async fn address(&self) -> String {
+        match self.peripheral() {
+            Some(peripheral) => get_address(peripheral).await,
+            None => SIMULATED_ID.to_string(),
+        }
+    }
+
+    /// Signal strength in dBm, if the platform has reported it for this sensor.
+    pub async fn rssi(&self) -> Option<i16> {
+        self.peripheral()?.properties().await.ok().flatten()?.rssi
+    }
+
+    pub async fn serial_number(&self) -> Result<String> {
+        get_serial_number(&self.sensor).await
+    }
+
+    #[instrument(skip_all, fields(device = %self.id()))]
+    pub async fn read_device_info(&self) -> Result<DeviceInfo> {
+        DeviceInfo::read_from_sensor(&self.sensor).await
+    }
+
+    /// How many readings are stored and how often they are taken, without downloading them.
+    #[instrument(skip_all, fields(device = %self.id()))]
+    pub async fn read_status(&self) -> Result<SensorStatus> {
+        SensorStatus::read_from_sensor(&self.sensor).await
+    }
+
+    /// Read the current measurement, or with a reading cache set, the cached one if the sensor
+    /// hasn't taken a newer one since.
+    #[instrument(skip_all, fields(device = %self.id()))]
+    pub async fn read_current(&self) -> Result<CurrentSensorMeasurement, Aranet4Error> {
+        let device = self.id();
+        if let Some(path) = &self.reading_cache {
+            match ReadingCache::load(path) {
+                Ok(cache) => {
+                    if let Some(measurement) = cache.fresh(&device, Utc::now()) {
+                        debug!("Using the cached reading from {} s ago", measurement.ago);
+                        return Ok(measurement);
+                    }
+                }
+                Err(e) => warn!("{:#}", e),
+            }
+        }
+        let (_, measurement) = with_retries(&self.sensor, self.retry, || {
+            with_timeout(
+                self.op_timeout,
+                "reading current measurement",
+                get_current_sensor_data(&self.sensor),
+            )
+        })
+        .await?;
+        if let Some(path) = &self.reading_cache {
+            // Reload, as another process may have cached other sensors in the meantime
+            let mut cache = ReadingCache::load(path).unwrap_or_default();
+            cache.insert(&device, measurement.clone(), Utc::now());
+            if let Err(e) = cache.save(path) {
+                warn!("{:#}", e);
+            }
+        }
+        Ok(measurement)
+    }
+
+    /// Stay connected and receive each reading the sensor notifies after measuring, instead of
+    /// connecting for every [`Aranet4Client::read_current`]. Only the Aranet4 notifies its
+    /// readings. End with [`Aranet4Client::unsubscribe_current`].
+    #[instrument(skip_all, fields(device = %self.id()))]
+    pub async fn subscribe_current(&self) -> Result<CurrentReadingStream, Aranet4Error> {
+        self.stay_connected();
+        with_timeout(
+            self.op_timeout,
+            "subscribing to current readings",
+            subscribe_current_readings(&self.sensor),
+        )
+        .await
+    }
+
+    /// Stop the notifications of [`Aranet4Client::subscribe_current`] and disconnect.
+    pub async fn unsubscribe_current(&self) -> Result<(), Aranet4Error> {
+        let unsubscribed = self
+            .sensor
+            .unsubscribe(ARANET4_NOTIFY_CURRENT_READINGS_UUID)
+            .await;
+        self.disconnect().await?;
+        unsubscribed
+    }
+
+    /// Which Aranet model the sensor is. Usually known from its name without connecting.
+    pub async fn model(&self) -> Result<AranetModel> {
+        get_model(&self.sensor).await
+    }
+
+    /// Like [`Aranet4Client::read_current`], for any Aranet model.
+    #[instrument(skip(self), fields(device = %self.id()))]
+    pub async fn read_model_current(
+        &self,
+        model: AranetModel,
+    ) -> Result<ModelMeasurement, Aranet4Error> {
+        with_retries(&self.sensor, self.retry, || {
+            with_timeout(
+                self.op_timeout,
+                "reading current measurement",
+                get_model_sensor_data(&self.sensor, model),
+            )
+        })
+        .await
+    }
+
+    /// Read the complete history of an Aranet2 or Aranet Radon Plus.
+    #[instrument(skip(self), fields(device = %self.id()))]
+    pub async fn read_model_history(&self, model: AranetModel) -> Result<ModelHistory> {
+        let mut history = get_model_history(
+            &self.sensor,
+            model,
+            &|p| self.report_progress(p),
+            self.retry,
+            self.op_timeout,
+        )
+        .await?;
+        self.adjust_timestamps(&mut history.time).await;
+        Ok(history)
+    }
+
+    /// Retry failed reads according to `retry`. Defaults to [`RetryPolicy::default`].
+    pub fn set_retry_policy(&mut self, retry: RetryPolicy) {
+        self.retry = retry;
+    }
+
+    /// Give up on a single sensor operation, e.g. one metric's history, after `timeout`.
+    /// Defaults to [`DEFAULT_OP_TIMEOUT`].
+    pub fn set_op_timeout(&mut self, timeout: Duration) {
+        self.op_timeout = timeout;
+    }
+
+    /// Request the history metrics interleaved or one at a time; see [`HistoryTransfer`].
+    /// Defaults to [`HistoryTransfer::Sequential`].
+    pub fn set_history_transfer(&mut self, transfer: HistoryTransfer) {
+        self.transfer = transfer;
+    }
+
+    /// Send history requests without waiting for the sensor to acknowledge them; see
+    /// [`ConnectedSensor::set_requests_without_response`]. Defaults to `false`.
+    pub fn set_requests_without_response(&mut self, without_response: bool) {
+        self.sensor.set_requests_without_response(without_response);
+    }
+
+    /// Download only these metrics of the Aranet4's history, leaving the others empty to save
+    /// transfer time. Defaults to all of them.
+    pub fn set_history_metrics(&mut self, metrics: &[HistoryMetric]) {
+        self.metrics = metrics.to_vec();
+    }
+
+    /// The metrics history downloads are limited to; see [`Aranet4Client::set_history_metrics`].
+    pub fn history_metrics(&self) -> &[HistoryMetric] {
+        &self.metrics
+    }
+
+    /// Keep history timestamps as reconstructed from the host clock instead of snapping them to
+    /// the measurement grid; see [`HistoryTime::snap_to_grid`]. Defaults to `false`.
+    pub fn set_raw_timestamps(&mut self, raw: bool) {
+        self.raw_timestamps = raw;
+    }
+
+    /// Count history timestamps back from the host's clock or the sensor's. Defaults to
+    /// [`TimestampSource::Host`]. Sensors without a readable clock fall back to the host's.
+    pub fn set_timestamp_source(&mut self, source: TimestampSource) {
+        self.timestamp_source = source;
+    }
+
+    /// Share current readings with other processes through the cache file at `path`, so that
+    /// the sensor is only connected to once per measurement interval.
+    pub fn set_reading_cache(&mut self, path: PathBuf) {
+        self.reading_cache = Some(path);
+    }
+
+    async fn adjust_timestamps(&self, time: &mut HistoryTime) {
+        if self.timestamp_source == TimestampSource::Device {
+            match get_clock_skew(&self.sensor).await {
+                Ok(skew) => {
+                    debug!(skew = skew.num_seconds(), "Using the sensor's clock");
+                    time.use_raw_timestamps();
+                    time.now += skew;
+                    time.snap_to_grid();
+                }
+                Err(e) => warn!("Could not read the sensor's clock, using the host's: {e}"),
+            }
+        }
+        if self.raw_timestamps {
+            time.use_raw_timestamps();
+        } else {
+            debug!(
+                offset = time.offset,
+                "Snapped history timestamps to the grid"
+            );
+        }
+    }
+
+    /// Report the progress of every subsequent history download to `callback`.
+    pub fn set_progress_callback(&mut self, callback: Box<dyn Fn(HistoryProgress) + Send + Sync>) {
+        self.progress = Some(callback);
+    }
+
+    fn report_progress(&self, progress: HistoryProgress) {
+        if let Some(callback) = &self.progress {
+            callback(progress);
+        }
+    }
+
+    #[instrument(skip_all, fields(device = %self.id()))]
+    pub async fn read_history(&self) -> Result<History> {
+        let mut history = get_history_from(
+            &self.sensor,
+            1,
+            &|p| self.report_progress(p),
+            self.retry,
+            self.op_timeout,
+            self.transfer,
+            &self.metrics,
+        )
+        .await?;
+        self.adjust_timestamps(&mut history.time).await;
+        Ok(history)
+    }
+
+    /// Read the history taken within `range`.
+    #[instrument(skip(self), fields(device = %self.id()))]
+    pub async fn read_history_range(&self, range: HistoryRange) -> Result<History> {
+        let mut history = get_history_range(
+            &self.sensor,
+            range,
+            &|p| self.report_progress(p),
+            self.retry,
+            self.op_timeout,
+            self.transfer,
+            &self.metrics,
+        )
+        .await?;
+        self.adjust_timestamps(&mut history.time).await;
+        Ok(history)
+    }
+
+    /// Read only the history newer than `last_timestamp` (UNIX time).
+    #[instrument(skip(self), fields(device = %self.id()))]
+    pub async fn read_history_after(&self, last_timestamp: i64) -> Result<History> {
+        let mut history = get_history_after(
+            &self.sensor,
+            last_timestamp,
+            &|p| self.report_progress(p),
+            self.retry,
+            self.op_timeout,
+            self.transfer,
+            &self.metrics,
+        )
+        .await?;
+        self.adjust_timestamps(&mut history.time).await;
+        Ok(history)
+    }
+
+    /// Change the measurement interval to 1, 2, 5 or 10 minutes. Returns the new interval in
+    /// seconds as read back from the sensor.
+    #[instrument(skip(self), fields(device = %self.id()))]
+    pub async fn set_update_interval(&self, minutes: u8) -> Result<u16, Aranet4Error> {
+        set_update_interval(&self.sensor, minutes).await
+    }
+
+    /// Set the sensor's clock to the host's. Returns how far ahead it was before.
+    #[instrument(skip(self), fields(device = %self.id()))]
+    pub async fn sync_time(&self) -> Result<TimeDelta, Aranet4Error> {
+        sync_device_time(&self.sensor).await
+    }
+
+    /// Disconnect, if still connected, and stop scanning on the adapter. Call once done with
+    /// the sensor.
+    pub async fn close(self) -> Result<()> {
+        self.sensor.close().await?;
+        if let Some(central) = &self.central {
+            central.stop_scan().await?;
+        }
+        Ok(())
+    }
+
+    /// Clean up after an interrupted operation: unsubscribe from history notifications,
+    /// disconnect and stop scanning. Errors are ignored, as the connection may already be gone;
+    /// this is best effort so that the next connection doesn't find the sensor mid-transfer.
+    pub async fn abort(&self) {
+        let _ = self.sensor.unsubscribe(ARANET4_NOTIFY_HISTORY_UUID).await;
+        let _ = self.sensor.close().await;
+        if let Some(central) = &self.central {
+            let _ = central.stop_scan().await;
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! The sensor's own clock, as the standard Bluetooth Current Time characteristic, which some
+//! firmware versions have. It keeps local time, without a time zone.
+
+use chrono::{DateTime, Datelike, Local, NaiveDate, TimeDelta, TimeZone, Timelike, Utc};
+use clap::ValueEnum;
+use uuid::{uuid, Uuid};
+
+use crate::device::connected;
+use crate::transport::SensorTransport;
+use crate::types::Aranet4Error;
+
+pub(crate) const GENERIC_GATT_CURRENT_TIME_UUID: Uuid =
+    uuid!("00002a2b-0000-1000-8000-00805f9b34fb");
+
+/// Where history timestamps come from. Either way they count back from the sensor's latest
+/// measurement; with `Device`, "now" is the sensor's clock rather than the host's.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TimestampSource {
+    #[default]
+    Host,
+    Device,
+}
+
+impl TimestampSource {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "host" => Some(TimestampSource::Host),
+            "device" => Some(TimestampSource::Device),
+            _ => None,
+        }
+    }
+}
+
+/// Decode a Current Time value: the date and time, day of the week, 1/256 s and the reason for
+/// the last adjustment. The time is taken to be in the host's time zone.
+pub fn decode_current_time(bytes: &[u8]) -> Result<DateTime<Utc>, Aranet4Error> {
+    let invalid = || Aranet4Error::InvalidResponse(format!("invalid current time {:?}", bytes));
+    if bytes.len() < 7 {
+        return Err(invalid());
+    }
+    let year = u16::from_le_bytes([bytes[0], bytes[1]]);
+    let fraction = bytes.get(8).copied().unwrap_or(0);
+    let time = NaiveDate::from_ymd_opt(year.into(), bytes[2].into(), bytes[3].into())
+        .and_then(|date| {
+            date.and_hms_milli_opt(
+                bytes[4].into(),
+                bytes[5].into(),
+                bytes[6].into(),
+                u32::from(fraction) * 1000 / 256,
+            )
+        })
+        .ok_or_else(invalid)?;
+    Ok(Local
+        .from_local_datetime(&time)
+        .earliest()
+        .ok_or_else(invalid)?
+        .to_utc())
+}
+
+/// Encode `time` as a Current Time value, marked as a manual adjustment.
+pub fn encode_current_time(time: DateTime<Local>) -> [u8; 10] {
+    let year = (time.year() as u16).to_le_bytes();
+    [
+        year[0],
+        year[1],
+        time.month() as u8,
+        time.day() as u8,
+        time.hour() as u8,
+        time.minute() as u8,
+        time.second() as u8,
+        time.weekday().number_from_monday() as u8,
+        (time.timestamp_subsec_millis() * 256 / 1000) as u8,
+        // Manual time update
+        0x01,
+    ]
+}
+
+pub async fn get_device_time<S: SensorTransport>(
+    sensor: &S,
+) -> Result<DateTime<Utc>, Aranet4Error> {
+    connected(sensor, async {
+        decode_current_time(&sensor.read(GENERIC_GATT_CURRENT_TIME_UUID).await?)
+    })
+    .await
+}
+
+/// Set the sensor's clock to the host's and return how far it was ahead before, as
+/// [`get_clock_skew`] does. The official app does this whenever it connects.
+pub async fn sync_device_time<S: SensorTransport>(sensor: &S) -> Result<TimeDelta, Aranet4Error> {
+    connected(sensor, async {
+        let bytes = sensor.read(GENERIC_GATT_CURRENT_TIME_UUID).await?;
+        let skew = decode_current_time(&bytes)? - Utc::now();
+        sensor
+            .write(
+                GENERIC_GATT_CURRENT_TIME_UUID,
+                &encode_current_time(Local::now()),
+            )
+            .await?;
+        Ok(skew)
+    })
+    .await
+}
+
+/// How far the sensor's clock is ahead of the host's. Fails with
+/// [`Aranet4Error::CharacteristicNotFound`] if the sensor doesn't have a clock to read.
+pub async fn get_clock_skew<S: SensorTransport>(sensor: &S) -> Result<TimeDelta, Aranet4Error> {
+    connected(sensor, async {
+        let bytes = sensor.read(GENERIC_GATT_CURRENT_TIME_UUID).await?;
+        Ok(decode_current_time(&bytes)? - Utc::now())
+    })
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Local, TimeDelta};
+
+    use crate::clock::{
+        decode_current_time, encode_current_time, get_clock_skew, sync_device_time,
+        GENERIC_GATT_CURRENT_TIME_UUID,
+    };
+    use crate::mock::MockAranet4;
+    use crate::types::Aranet4Error;
+
+    #[test]
+    fn test_current_time_round_trip() {
+        let time = Local::now();
+        let bytes = encode_current_time(time);
+        let decoded = decode_current_time(&bytes).unwrap();
+        assert!((decoded - time.to_utc()).abs() < TimeDelta::milliseconds(5));
+        assert!(decode_current_time(&[0xe9, 0x07, 13, 1, 0, 0, 0]).is_err());
+        assert!(decode_current_time(&[0xe9, 0x07]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_clock_skew() {
+        let ahead = Local::now() + TimeDelta::seconds(120);
+        let sensor = MockAranet4::new("Aranet4 1BA27")
+            .with_characteristic(GENERIC_GATT_CURRENT_TIME_UUID, &encode_current_time(ahead));
+        let skew = get_clock_skew(&sensor).await.unwrap();
+        assert!((skew - TimeDelta::seconds(120)).abs() < TimeDelta::seconds(1));
+
+        let sensor = MockAranet4::new("Aranet4 1BA27");
+        assert!(matches!(
+            get_clock_skew(&sensor).await,
+            Err(Aranet4Error::CharacteristicNotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sync_device_time() {
+        let behind = Local::now() - TimeDelta::seconds(300);
+        let sensor = MockAranet4::new("Aranet4 1BA27")
+            .with_characteristic(GENERIC_GATT_CURRENT_TIME_UUID, &encode_current_time(behind));
+        let skew = sync_device_time(&sensor).await.unwrap();
+        assert!((skew + TimeDelta::seconds(300)).abs() < TimeDelta::seconds(1));
+        let (uuid, written) = sensor.writes().pop().unwrap();
+        assert_eq!(uuid, GENERIC_GATT_CURRENT_TIME_UUID);
+        let set = decode_current_time(&written).unwrap();
+        assert!((set - chrono::Utc::now()).abs() < TimeDelta::seconds(1));
+        assert!(!sensor.is_connected());
+    }
+}
@@ -0,0 +1,500 @@
+//! The history of several sensors in one table, aligned on a shared measurement grid, so that
+//! rooms can be compared without joining per-device archives first.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use parquet::{
+    basic::{LogicalType, Repetition, Type},
+    data_type::{ByteArray, ByteArrayType, DataType, FloatType, Int32Type, Int64Type},
+    file::writer::{SerializedFileWriter, SerializedRowGroupWriter},
+    schema::types,
+};
+
+use crate::csv_io::{csv_header, metric_fields, CsvTimestamps};
+use crate::device::{History, HistoryMetric};
+use crate::parquet_io::{
+    history_writer_properties, metric_type, required_field, timestamp_field, HistoryRow,
+    TimestampUnit,
+};
+use crate::types::{history_records, HistoryRecord, Units};
+
+/// How the devices' readings are laid out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum CombinedLayout {
+    /// A row per grid time with a column per device and metric, empty where a device has no
+    /// reading.
+    #[default]
+    Wide,
+    /// A row per reading with `device` and `serial` columns.
+    Long,
+}
+
+impl CombinedLayout {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "wide" => Some(CombinedLayout::Wide),
+            "long" => Some(CombinedLayout::Long),
+            _ => None,
+        }
+    }
+}
+
+/// Formats a combined table can be written in, told apart by file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CombinedFormat {
+    Csv,
+    Parquet,
+}
+
+impl CombinedFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(CombinedFormat::Csv),
+            "parquet" => Some(CombinedFormat::Parquet),
+            _ => None,
+        }
+    }
+}
+
+/// One device's downloaded history.
+#[derive(Debug, Clone)]
+pub struct DeviceHistory {
+    pub name: String,
+    pub serial: String,
+    /// Seconds between the device's readings.
+    pub interval: i64,
+    pub records: Vec<HistoryRecord>,
+}
+
+impl DeviceHistory {
+    pub fn new(name: String, serial: String, history: &History) -> Result<Self> {
+        Ok(DeviceHistory {
+            name,
+            serial,
+            interval: i64::from(history.time.update_interval),
+            records: history_records(
+                &history.time,
+                &history.temperature,
+                &history.humidity,
+                &history.pressure,
+                &history.co2,
+            )
+            .collect::<Result<_>>()?,
+        })
+    }
+}
+
+/// The readings of several devices by grid time. Sensors measure at their own moments, so
+/// each reading is moved to the nearest multiple of the grid interval; where a device has
+/// several readings in one grid interval, the last one is kept.
+#[derive(Debug, Clone)]
+pub struct CombinedHistory {
+    /// Each device's name, with its serial number added where two devices share a name.
+    labels: Vec<String>,
+    serials: Vec<String>,
+    /// The readings at each grid time, one per device.
+    rows: BTreeMap<i64, Vec<Option<HistoryRecord>>>,
+}
+
+impl CombinedHistory {
+    /// Combine `devices` on a grid of `grid` seconds, or by default the longest measurement
+    /// interval among them, which keeps each device to one reading per grid time.
+    pub fn new(devices: Vec<DeviceHistory>, grid: Option<i64>) -> Self {
+        let grid = grid
+            .unwrap_or_else(|| {
+                devices
+                    .iter()
+                    .map(|device| device.interval)
+                    .max()
+                    .unwrap_or(1)
+            })
+            .max(1);
+        let labels = devices
+            .iter()
+            .map(|device| {
+                let shared = devices
+                    .iter()
+                    .filter(|other| other.name == device.name)
+                    .count()
+                    > 1;
+                if shared {
+                    format!("{} {}", device.name, device.serial)
+                } else {
+                    device.name.clone()
+                }
+            })
+            .collect();
+        let mut rows = BTreeMap::new();
+        for (i, device) in devices.iter().enumerate() {
+            for record in &device.records {
+                let timestamp = (record.timestamp + grid / 2).div_euclid(grid) * grid;
+                let readings = rows
+                    .entry(timestamp)
+                    .or_insert_with(|| vec![None; devices.len()]);
+                readings[i] = Some(HistoryRecord {
+                    timestamp,
+                    ..*record
+                });
+            }
+        }
+        CombinedHistory {
+            labels,
+            serials: devices.into_iter().map(|device| device.serial).collect(),
+            rows,
+        }
+    }
+
+    /// How many rows `layout` has.
+    pub fn len(&self, layout: CombinedLayout) -> usize {
+        match layout {
+            CombinedLayout::Wide => self.rows.len(),
+            CombinedLayout::Long => self.readings().count(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Every reading with its device's index, by grid time and then device.
+    fn readings(&self) -> impl Iterator<Item = (usize, &HistoryRecord)> {
+        self.rows.values().flat_map(|readings| {
+            readings
+                .iter()
+                .enumerate()
+                .filter_map(|(i, reading)| Some((i, reading.as_ref()?)))
+        })
+    }
+
+    /// The column of a wide table for `metric` of the device with this label.
+    fn wide_name(label: &str, metric: &str) -> String {
+        format!("{}_{}", label, metric)
+    }
+
+    pub fn write_csv<W: Write>(
+        &self,
+        dest: &mut W,
+        layout: CombinedLayout,
+        units: Units,
+        timestamps: CsvTimestamps,
+    ) -> Result<()> {
+        let mut dest = csv::Writer::from_writer(dest);
+        let labels = &csv_header(units, &HistoryMetric::ALL)[1..];
+        match layout {
+            CombinedLayout::Wide => {
+                let mut header = vec!["timestamp".to_string()];
+                for device in &self.labels {
+                    header.extend(labels.iter().map(|label| format!("{} {}", device, label)));
+                }
+                dest.write_record(header)
+                    .wrap_err("Failed while writing CSV header")?;
+                for (&timestamp, readings) in &self.rows {
+                    let mut fields = vec![timestamps.format(timestamp)?];
+                    for reading in readings {
+                        match reading {
+                            Some(record) => {
+                                fields.extend(metric_fields(record, units, &HistoryMetric::ALL))
+                            }
+                            None => fields.extend(labels.iter().map(|_| String::new())),
+                        }
+                    }
+                    dest.write_record(fields)?;
+                }
+            }
+            CombinedLayout::Long => {
+                let mut header = vec!["timestamp", "device", "serial"];
+                header.extend(labels);
+                dest.write_record(header)
+                    .wrap_err("Failed while writing CSV header")?;
+                for (i, record) in self.readings() {
+                    let mut fields = vec![
+                        timestamps.format(record.timestamp)?,
+                        self.labels[i].clone(),
+                        self.serials[i].clone(),
+                    ];
+                    fields.extend(metric_fields(record, units, &HistoryMetric::ALL));
+                    dest.write_record(fields)?;
+                }
+            }
+        }
+        dest.flush()?;
+        Ok(())
+    }
+
+    fn parquet_schema(&self, layout: CombinedLayout, unit: TimestampUnit) -> Arc<types::Type> {
+        let mut fields = vec![timestamp_field(unit)];
+        match layout {
+            CombinedLayout::Wide => {
+                for label in &self.labels {
+                    for metric in HistoryMetric::ALL {
+                        fields.push(Arc::new(
+                            types::Type::primitive_type_builder(
+                                &Self::wide_name(label, metric.name()),
+                                metric_type(metric),
+                            )
+                            .with_repetition(Repetition::OPTIONAL)
+                            .build()
+                            .unwrap(),
+                        ));
+                    }
+                }
+            }
+            CombinedLayout::Long => {
+                for name in ["device", "serial"] {
+                    fields.push(Arc::new(
+                        types::Type::primitive_type_builder(name, Type::BYTE_ARRAY)
+                            .with_repetition(Repetition::REQUIRED)
+                            .with_logical_type(Some(LogicalType::String))
+                            .build()
+                            .unwrap(),
+                    ));
+                }
+                for metric in HistoryMetric::ALL {
+                    fields.push(required_field(metric.name(), metric_type(metric)));
+                }
+            }
+        }
+        Arc::new(
+            types::Type::group_type_builder("schema")
+                .with_fields(fields)
+                .build()
+                .unwrap(),
+        )
+    }
+
+    /// Write the table as a Parquet file with a single row group; even a year of readings of a
+    /// dozen sensors is only a few million values.
+    pub fn write_parquet<W: Write + Send>(
+        &self,
+        dest: W,
+        layout: CombinedLayout,
+        timestamp_unit: TimestampUnit,
+        units: Units,
+    ) -> Result<()> {
+        let mut writer = SerializedFileWriter::new(
+            dest,
+            self.parquet_schema(layout, timestamp_unit),
+            history_writer_properties(units, None),
+        )?;
+        let mut row_group = writer.next_row_group()?;
+        let per_second = timestamp_unit.per_second();
+        match layout {
+            CombinedLayout::Wide => {
+                let timestamps: Vec<i64> = self
+                    .rows
+                    .keys()
+                    .map(|seconds| seconds * per_second)
+                    .collect();
+                write_column::<_, Int64Type>(&mut row_group, &timestamps, None)?;
+                for i in 0..self.labels.len() {
+                    let rows: Vec<Option<HistoryRow>> = self
+                        .rows
+                        .values()
+                        .map(|readings| {
+                            readings[i].map(|record| HistoryRow::from_record(&record, units))
+                        })
+                        .collect();
+                    let def_levels: Vec<i16> =
+                        rows.iter().map(|row| i16::from(row.is_some())).collect();
+                    let present: Vec<HistoryRow> = rows.into_iter().flatten().collect();
+                    for metric in HistoryMetric::ALL {
+                        write_metric(&mut row_group, metric, &present, Some(&def_levels))?;
+                    }
+                }
+            }
+            CombinedLayout::Long => {
+                let (devices, records): (Vec<usize>, Vec<&HistoryRecord>) = self.readings().unzip();
+                let timestamps: Vec<i64> = records
+                    .iter()
+                    .map(|record| record.timestamp * per_second)
+                    .collect();
+                write_column::<_, Int64Type>(&mut row_group, &timestamps, None)?;
+                for names in [&self.labels, &self.serials] {
+                    let values: Vec<ByteArray> = devices
+                        .iter()
+                        .map(|&i| ByteArray::from(names[i].as_str()))
+                        .collect();
+                    write_column::<_, ByteArrayType>(&mut row_group, &values, None)?;
+                }
+                let rows: Vec<HistoryRow> = records
+                    .into_iter()
+                    .map(|record| HistoryRow::from_record(record, units))
+                    .collect();
+                for metric in HistoryMetric::ALL {
+                    write_metric(&mut row_group, metric, &rows, None)?;
+                }
+            }
+        }
+        row_group.close()?;
+        writer.close()?;
+        Ok(())
+    }
+}
+
+fn write_column<W: Write + Send, T: DataType>(
+    row_group: &mut SerializedRowGroupWriter<'_, W>,
+    values: &[T::T],
+    def_levels: Option<&[i16]>,
+) -> Result<()> {
+    let mut column = row_group
+        .next_column()?
+        .ok_or(eyre!("More columns were written than the schema has"))?;
+    column.typed::<T>().write_batch(values, def_levels, None)?;
+    column.close()?;
+    Ok(())
+}
+
+/// Write `metric` of `rows`, the rows that have a value if `def_levels` are given.
+fn write_metric<W: Write + Send>(
+    row_group: &mut SerializedRowGroupWriter<'_, W>,
+    metric: HistoryMetric,
+    rows: &[HistoryRow],
+    def_levels: Option<&[i16]>,
+) -> Result<()> {
+    match metric {
+        HistoryMetric::Temperature => {
+            let values: Vec<f32> = rows.iter().map(|row| row.temperature).collect();
+            write_column::<_, FloatType>(row_group, &values, def_levels)
+        }
+        HistoryMetric::Humidity => {
+            let values: Vec<i32> = rows.iter().map(|row| row.humidity).collect();
+            write_column::<_, Int32Type>(row_group, &values, def_levels)
+        }
+        HistoryMetric::Pressure => {
+            let values: Vec<f32> = rows.iter().map(|row| row.pressure).collect();
+            write_column::<_, FloatType>(row_group, &values, def_levels)
+        }
+        HistoryMetric::Co2 => {
+            let values: Vec<i32> = rows.iter().map(|row| row.co2).collect();
+            write_column::<_, Int32Type>(row_group, &values, def_levels)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::RowAccessor;
+
+    use crate::combined::{CombinedHistory, CombinedLayout, DeviceHistory};
+    use crate::csv_io::CsvTimestamps;
+    use crate::parquet_io::TimestampUnit;
+    use crate::types::{HistoryRecord, Units};
+
+    fn device(name: &str, serial: &str, readings: &[(i64, u16)]) -> DeviceHistory {
+        DeviceHistory {
+            name: name.to_string(),
+            serial: serial.to_string(),
+            interval: 300,
+            records: readings
+                .iter()
+                .map(|&(timestamp, co2_ppm)| HistoryRecord {
+                    timestamp,
+                    temperature_c: 20.65,
+                    humidity_pct: 33,
+                    pressure_mbar: 1017.4,
+                    co2_ppm,
+                })
+                .collect(),
+        }
+    }
+
+    fn combined() -> CombinedHistory {
+        CombinedHistory::new(
+            vec![
+                device("Kitchen", "1", &[(1000, 900), (1300, 910)]),
+                device("Bedroom", "2", &[(1170, 600)]),
+            ],
+            None,
+        )
+    }
+
+    #[test]
+    fn test_combined_csv() {
+        let combined = combined();
+        let mut wide = Vec::new();
+        combined
+            .write_csv(
+                &mut wide,
+                CombinedLayout::Wide,
+                Units::Metric,
+                CsvTimestamps::default(),
+            )
+            .unwrap();
+        let wide = String::from_utf8(wide).unwrap();
+        let mut lines = wide.lines();
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("timestamp,Kitchen Temperature (°C),"));
+        // Kitchen's second reading and Bedroom's are in the same grid interval
+        assert_eq!(lines.next(), Some("900,20.65,33,1017.4,900,,,,"));
+        assert_eq!(
+            lines.next(),
+            Some("1200,20.65,33,1017.4,910,20.65,33,1017.4,600")
+        );
+        assert_eq!(lines.next(), None);
+
+        let mut long = Vec::new();
+        combined
+            .write_csv(
+                &mut long,
+                CombinedLayout::Long,
+                Units::Metric,
+                CsvTimestamps::default(),
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(long).unwrap(),
+            "timestamp,device,serial,Temperature (°C),Humidity (%),Pressure (hPa),CO₂ (ppm)\n\
+             900,Kitchen,1,20.65,33,1017.4,900\n\
+             1200,Kitchen,1,20.65,33,1017.4,910\n\
+             1200,Bedroom,2,20.65,33,1017.4,600\n"
+        );
+        assert_eq!(combined.len(CombinedLayout::Wide), 2);
+        assert_eq!(combined.len(CombinedLayout::Long), 3);
+    }
+
+    #[test]
+    fn test_combined_parquet() {
+        let path = std::env::temp_dir().join("arachiver_test_combined.parquet");
+        let file = std::fs::File::create(&path).unwrap();
+        combined()
+            .write_parquet(
+                file,
+                CombinedLayout::Wide,
+                TimestampUnit::Millis,
+                Units::Metric,
+            )
+            .unwrap();
+        let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+        let columns: Vec<String> = reader
+            .metadata()
+            .file_metadata()
+            .schema_descr()
+            .columns()
+            .iter()
+            .map(|column| column.name().to_string())
+            .collect();
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(columns.len(), 9);
+        assert_eq!(columns[4], "Kitchen_co2");
+        assert_eq!(columns[8], "Bedroom_co2");
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].get_int(4).unwrap(), 900);
+        assert!(rows[0].get_int(8).is_err());
+        assert_eq!(rows[1].get_int(4).unwrap(), 910);
+        assert_eq!(rows[1].get_int(8).unwrap(), 600);
+    }
+}
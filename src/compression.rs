@@ -0,0 +1,135 @@
+//! Compressed archives. Most tools that read CSV, such as pandas, polars and DuckDB, decompress
+//! gzip and Zstandard files themselves.
+
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use flate2::write::GzEncoder;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_name(name: &str) -> Option<Compression> {
+        match name {
+            "gzip" => Some(Compression::Gzip),
+            "zstd" => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// The extension added to the names of compressed files, without the dot.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    /// `path` with this compression's extension added, unless it already ends with it.
+    pub fn add_extension(self, path: &Path) -> PathBuf {
+        if path.extension().is_some_and(|ext| ext == self.extension()) {
+            return path.to_path_buf();
+        }
+        let mut name = OsString::from(path.as_os_str());
+        name.push(".");
+        name.push(self.extension());
+        PathBuf::from(name)
+    }
+}
+
+/// A writer that compresses what is written to `W`, or with no compression, passes it
+/// through. [`finish`](CompressedWriter::finish) must be called to complete the stream.
+pub enum CompressedWriter<W: Write> {
+    Plain(W),
+    Gzip(GzEncoder<W>),
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    pub fn new(dest: W, compression: Option<Compression>) -> io::Result<Self> {
+        Ok(match compression {
+            None => CompressedWriter::Plain(dest),
+            Some(Compression::Gzip) => {
+                CompressedWriter::Gzip(GzEncoder::new(dest, flate2::Compression::default()))
+            }
+            Some(Compression::Zstd) => CompressedWriter::Zstd(zstd::Encoder::new(dest, 0)?),
+        })
+    }
+
+    /// Write the end of the compressed stream and return the underlying writer.
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            CompressedWriter::Plain(dest) => Ok(dest),
+            CompressedWriter::Gzip(encoder) => encoder.finish(),
+            CompressedWriter::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressedWriter::Plain(dest) => dest.write(buf),
+            CompressedWriter::Gzip(encoder) => encoder.write(buf),
+            CompressedWriter::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressedWriter::Plain(dest) => dest.flush(),
+            CompressedWriter::Gzip(encoder) => encoder.flush(),
+            CompressedWriter::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::path::{Path, PathBuf};
+
+    use flate2::read::GzDecoder;
+
+    use crate::compression::{CompressedWriter, Compression};
+
+    const CSV: &[u8] = b"timestamp,co2\n2025-02-03T22:20:00Z,400\n";
+
+    fn compress(compression: Option<Compression>) -> Vec<u8> {
+        let mut writer = CompressedWriter::new(Vec::new(), compression).unwrap();
+        writer.write_all(CSV).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_compressed_writer_round_trips() {
+        assert_eq!(compress(None), CSV);
+        let mut gunzipped = Vec::new();
+        GzDecoder::new(&compress(Some(Compression::Gzip))[..])
+            .read_to_end(&mut gunzipped)
+            .unwrap();
+        assert_eq!(gunzipped, CSV);
+        assert_eq!(
+            zstd::decode_all(&compress(Some(Compression::Zstd))[..]).unwrap(),
+            CSV
+        );
+    }
+
+    #[test]
+    fn test_add_extension() {
+        assert_eq!(
+            Compression::Gzip.add_extension(Path::new("aranet.csv")),
+            PathBuf::from("aranet.csv.gz")
+        );
+        assert_eq!(
+            Compression::Zstd.add_extension(Path::new("aranet.csv.zst")),
+            PathBuf::from("aranet.csv.zst")
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! Converting an archive to another format without the sensor, so that history downloaded
+//! once can be loaded into a format added later without downloading it again.
+
+use std::path::Path;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use rusqlite::Connection;
+
+use crate::arrow_io::write_history_arrow;
+use crate::atomic_file::AtomicFile;
+use crate::csv_io::{write_history_csv, CsvTimestamps};
+use crate::device::HistoryMetric;
+use crate::influx_io::write_history_influx;
+use crate::json_io::{write_history_json, write_history_ndjson};
+use crate::merge::read_archive_records;
+use crate::msgpack_io::write_history_msgpack;
+use crate::parquet_io::{write_history_file, HistoryRow, TimestampUnit};
+use crate::sqlite_io::write_history_sqlite;
+use crate::types::Units;
+use crate::xlsx_io::write_history_xlsx;
+
+/// Formats `convert` writes, told apart by file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvertFormat {
+    Csv,
+    Parquet,
+    Json,
+    Ndjson,
+    Msgpack,
+    Arrow,
+    Xlsx,
+    Sqlite,
+    /// InfluxDB line protocol.
+    LineProtocol,
+}
+
+impl ConvertFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(ConvertFormat::Csv),
+            "parquet" => Some(ConvertFormat::Parquet),
+            "json" => Some(ConvertFormat::Json),
+            "ndjson" | "jsonl" => Some(ConvertFormat::Ndjson),
+            "msgpack" | "mpk" => Some(ConvertFormat::Msgpack),
+            "arrow" | "feather" => Some(ConvertFormat::Arrow),
+            "xlsx" => Some(ConvertFormat::Xlsx),
+            "sqlite" | "db" => Some(ConvertFormat::Sqlite),
+            "lp" => Some(ConvertFormat::LineProtocol),
+            _ => None,
+        }
+    }
+}
+
+/// How to write the converted archive. The device name and serial number are only needed for
+/// the formats that record them.
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// Units of CSV, Parquet and XLSX output; the other formats are always metric.
+    pub units: Units,
+    pub timestamp_unit: TimestampUnit,
+    pub timestamps: CsvTimestamps,
+    pub name: Option<String>,
+    pub serial: Option<String>,
+    /// Add dew point and absolute humidity to CSV, Parquet and JSON output.
+    pub derived: bool,
+}
+
+fn required<'a>(value: &'a Option<String>, option: &str, format: &str) -> Result<&'a str> {
+    value
+        .as_deref()
+        .ok_or(eyre!("Converting to {} needs --{}", format, option))
+}
+
+/// Convert the CSV, Parquet, JSON or MessagePack archive at `input` to the format of `output`'s extension,
+/// replacing `output` once it is complete, or for SQLite, upserting into the database there.
+/// Returns how many readings were written, which for SQLite are the new ones.
+pub fn convert_archive(input: &Path, output: &Path, options: &ConvertOptions) -> Result<usize> {
+    let format = ConvertFormat::from_path(output).ok_or(eyre!(
+        "Can't tell which format to convert to from {}; expected a .csv, .parquet, .json, \
+         .ndjson, .msgpack, .arrow, .xlsx, .sqlite or .lp file",
+        output.display()
+    ))?;
+    let records = read_archive_records(input)?;
+    let each = || records.iter().copied().map(Ok);
+    if format == ConvertFormat::Sqlite {
+        let serial = required(&options.serial, "serial", "SQLite")?;
+        let mut conn = Connection::open(output)
+            .wrap_err_with(|| format!("Could not open SQLite database {}", output.display()))?;
+        return write_history_sqlite(serial, each(), &mut conn);
+    }
+    let mut file = AtomicFile::create(output)?;
+    match format {
+        ConvertFormat::Csv => write_history_csv(
+            each(),
+            &mut file,
+            options.units,
+            options.timestamps,
+            &HistoryMetric::ALL,
+            options.derived,
+            true,
+        )?,
+        ConvertFormat::Parquet => write_history_file(
+            &mut file,
+            records
+                .iter()
+                .map(|record| HistoryRow::from_record(record, options.units)),
+            options.timestamp_unit,
+            options.units,
+            options.derived,
+            None,
+        )?,
+        ConvertFormat::Json => write_history_json(each(), options.derived, &mut file)?,
+        ConvertFormat::Ndjson => write_history_ndjson(each(), options.derived, &mut file)?,
+        ConvertFormat::Msgpack => write_history_msgpack(each(), &mut file)?,
+        ConvertFormat::Arrow => write_history_arrow(each(), &mut file)?,
+        ConvertFormat::Xlsx => write_history_xlsx(each(), &mut file, options.units)?,
+        ConvertFormat::LineProtocol => write_history_influx(
+            required(&options.name, "name", "line protocol")?,
+            required(&options.serial, "serial", "line protocol")?,
+            each(),
+            &mut file,
+        )?,
+        ConvertFormat::Sqlite => unreachable!(),
+    }
+    file.commit()?;
+    Ok(records.len())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::convert::{convert_archive, ConvertOptions};
+    use crate::json_io::read_history_json;
+    use crate::types::HistoryRecord;
+
+    #[test]
+    fn test_convert_imperial_csv() {
+        let dir = std::env::temp_dir();
+        let input = dir.join("arachiver_test_convert_input.csv");
+        let json = dir.join("arachiver_test_convert_output.json");
+        let csv = dir.join("arachiver_test_convert_output.csv");
+        let lines = dir.join("arachiver_test_convert_output.lp");
+        std::fs::write(
+            &input,
+            "timestamp,Temperature (°F),Humidity (%),Pressure (inHg),CO₂ (ppm)\n\
+             1000,69.17,33,30.04,926\n\
+             1300,69.26,33,30.05,930\n",
+        )
+        .unwrap();
+        let options = ConvertOptions::default();
+        let converted = convert_archive(&input, &json, &options).unwrap();
+        let records = read_history_json(&json).unwrap();
+        convert_archive(&json, &csv, &options).unwrap();
+        let csv_contents = std::fs::read_to_string(&csv).unwrap();
+        let without_serial = convert_archive(&input, &lines, &options);
+        for path in [input, json, csv] {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        assert_eq!(converted, 2);
+        assert_eq!(
+            records[0],
+            HistoryRecord {
+                timestamp: 1000,
+                temperature_c: 20.65,
+                humidity_pct: 33,
+                pressure_mbar: 1017.3,
+                co2_ppm: 926,
+            }
+        );
+        assert_eq!(records[1].temperature_c, 20.7);
+        assert_eq!(
+            csv_contents,
+            "timestamp,Temperature (°C),Humidity (%),Pressure (hPa),CO₂ (ppm)\n\
+             1000,20.65,33,1017.3,926\n\
+             1300,20.70,33,1017.6,930\n"
+        );
+        assert!(without_serial.is_err());
+    }
+}
@@ -1,11 +1,154 @@
+use std::fs::File;
 use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-use color_eyre::eyre::Result;
-use csv;
+use chrono::{DateTime, Local, NaiveDateTime, SecondsFormat, TimeZone};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Result, WrapErr};
 
-use crate::device::HistoryTime;
-use crate::types::{CO2Data, HumidityData, Metadata, PressureData, TemperatureData};
+use crate::atomic_file::AtomicFile;
+use crate::device::{HistoryMetric, HistoryTime, ModelHistory};
+use crate::parquet_io::{ArchiveSource, HistoryColumns};
+use crate::types::{
+    history_records, CO2Data, DerivedHumidity, HistoryRecord, Humidity2Data, HumidityData,
+    Metadata, PressureData, RadonData, TemperatureData, Units,
+};
 
+/// How the `timestamp` column is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum CsvTimeFormat {
+    /// UNIX time in seconds.
+    #[default]
+    Unix,
+    /// e.g. `2025-02-21T10:32:36Z`, or with the offset of the chosen time zone.
+    Rfc3339,
+    /// e.g. `2025-02-21 10:32:36`, which spreadsheets recognise as a date and time.
+    Local,
+}
+
+impl CsvTimeFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "unix" => Some(CsvTimeFormat::Unix),
+            "rfc3339" => Some(CsvTimeFormat::Rfc3339),
+            "local" => Some(CsvTimeFormat::Local),
+            _ => None,
+        }
+    }
+}
+
+/// Where a CSV archive records the sensor it came from, which Parquet archives keep in their
+/// key-value metadata.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum CsvMetadata {
+    #[default]
+    None,
+    /// `# key=value` lines before the header, which the archive readers skip.
+    CommentHeader,
+    /// A JSON object in a `.meta.json` file next to the archive.
+    SidecarJson,
+}
+
+impl CsvMetadata {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(CsvMetadata::None),
+            "comment-header" => Some(CsvMetadata::CommentHeader),
+            "sidecar-json" => Some(CsvMetadata::SidecarJson),
+            _ => None,
+        }
+    }
+}
+
+/// Write `source` as `# key=value` lines, to go before the CSV header.
+pub fn write_csv_comment_header<W: Write>(source: &ArchiveSource, dest: &mut W) -> Result<()> {
+    for (key, value) in source.metadata() {
+        writeln!(dest, "# {}={}", key, value).wrap_err("Failed while writing CSV metadata")?;
+    }
+    Ok(())
+}
+
+/// The sidecar of the CSV archive at `path`, e.g. `history.meta.json` for `history.csv`.
+pub fn csv_sidecar_path(path: &Path) -> PathBuf {
+    path.with_extension("meta.json")
+}
+
+/// Write `source` as a JSON object to the sidecar of the CSV archive at `path`, returning the
+/// sidecar's path.
+pub fn save_csv_sidecar(path: &Path, source: &ArchiveSource) -> Result<PathBuf> {
+    let sidecar = csv_sidecar_path(path);
+    let metadata: serde_json::Map<String, serde_json::Value> = source
+        .metadata()
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.into()))
+        .collect();
+    let mut file = AtomicFile::create(&sidecar)?;
+    serde_json::to_writer_pretty(&mut file, &metadata)
+        .wrap_err_with(|| format!("Failed while writing {}", sidecar.display()))?;
+    writeln!(file)?;
+    file.commit()?;
+    Ok(sidecar)
+}
+
+/// A reader of a CSV archive that skips the lines of a comment header.
+fn archive_reader(file: File) -> csv::Reader<File> {
+    csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .from_reader(file)
+}
+
+const LOCAL_TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// The `timestamp` column's format and the time zone for formats other than UNIX time. Without
+/// a time zone, RFC 3339 times are in UTC and local times in the host's time zone.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CsvTimestamps {
+    pub format: CsvTimeFormat,
+    pub timezone: Option<Tz>,
+}
+
+impl CsvTimestamps {
+    pub fn format(&self, timestamp: i64) -> Result<String> {
+        let time = DateTime::from_timestamp(timestamp, 0)
+            .ok_or(eyre!("Timestamp {} is out of range", timestamp))?;
+        Ok(match (self.format, self.timezone) {
+            (CsvTimeFormat::Unix, _) => timestamp.to_string(),
+            (CsvTimeFormat::Rfc3339, None) => time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            (CsvTimeFormat::Rfc3339, Some(tz)) => time
+                .with_timezone(&tz)
+                .to_rfc3339_opts(SecondsFormat::Secs, true),
+            (CsvTimeFormat::Local, None) => time
+                .with_timezone(&Local)
+                .format(LOCAL_TIME_FORMAT)
+                .to_string(),
+            (CsvTimeFormat::Local, Some(tz)) => time
+                .with_timezone(&tz)
+                .format(LOCAL_TIME_FORMAT)
+                .to_string(),
+        })
+    }
+
+    /// Read back a timestamp in any of the formats, so that archives can be appended to
+    /// whichever format they were started in. Local times are taken to be in this time zone.
+    pub fn parse(&self, field: &str) -> Option<i64> {
+        if let Ok(timestamp) = field.parse() {
+            return Some(timestamp);
+        }
+        if let Ok(time) = DateTime::parse_from_rfc3339(field) {
+            return Some(time.timestamp());
+        }
+        let time = NaiveDateTime::parse_from_str(field, LOCAL_TIME_FORMAT).ok()?;
+        let local = match self.timezone {
+            Some(tz) => tz.from_local_datetime(&time).earliest()?.timestamp(),
+            None => Local.from_local_datetime(&time).earliest()?.timestamp(),
+        };
+        Some(local)
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn save_history_csv<W: Write>(
     history_time: HistoryTime,
     temperature: TemperatureData,
@@ -13,29 +156,540 @@ pub async fn save_history_csv<W: Write>(
     pressure: PressureData,
     co2: CO2Data,
     dest: &mut W,
+    units: Units,
+    timestamps: CsvTimestamps,
+    metrics: &[HistoryMetric],
+    derived: bool,
+) -> Result<()> {
+    write_history_csv(
+        history_records(&history_time, &temperature, &humidity, &pressure, &co2),
+        dest,
+        units,
+        timestamps,
+        metrics,
+        derived,
+        true,
+    )
+}
+
+/// Like `save_history_csv`, but without the header row, for appending to an existing archive.
+#[allow(clippy::too_many_arguments)]
+pub async fn append_history_csv<W: Write>(
+    history_time: HistoryTime,
+    temperature: TemperatureData,
+    humidity: HumidityData,
+    pressure: PressureData,
+    co2: CO2Data,
+    dest: &mut W,
+    units: Units,
+    timestamps: CsvTimestamps,
+    metrics: &[HistoryMetric],
+) -> Result<()> {
+    write_history_csv(
+        history_records(&history_time, &temperature, &humidity, &pressure, &co2),
+        dest,
+        units,
+        timestamps,
+        metrics,
+        false,
+        false,
+    )
+}
+
+pub(crate) fn csv_header(units: Units, metrics: &[HistoryMetric]) -> Vec<&'static str> {
+    let mut header = vec!["timestamp"];
+    header.extend(
+        HistoryMetric::in_order(metrics)
+            .into_iter()
+            .map(|metric| metric.kind().unit_label(units)),
+    );
+    header
+}
+
+/// The CSV fields of the values of `metrics`, which must be in order, in `record`.
+pub(crate) fn metric_fields(
+    record: &HistoryRecord,
+    units: Units,
+    metrics: &[HistoryMetric],
+) -> Vec<String> {
+    metrics
+        .iter()
+        .map(|metric| match metric {
+            HistoryMetric::Temperature => format!(
+                "{:.*}",
+                TemperatureData::precision(units),
+                record.temperature_in(units)
+            ),
+            HistoryMetric::Humidity => record.humidity_pct.to_string(),
+            HistoryMetric::Pressure => format!(
+                "{:.*}",
+                PressureData::precision(units),
+                record.pressure_in(units)
+            ),
+            HistoryMetric::Co2 => record.co2_ppm.to_string(),
+        })
+        .collect()
+}
+
+/// Check that the CSV archive at `path` has the header [`save_history_csv`] writes for `units`
+/// and `metrics`, so that appending doesn't mix units or columns within one file.
+pub fn check_csv_header(path: &Path, units: Units, metrics: &[HistoryMetric]) -> Result<()> {
+    let file = File::open(path).wrap_err_with(|| format!("Could not open {}", path.display()))?;
+    let mut reader = archive_reader(file);
+    let header = reader
+        .headers()
+        .wrap_err_with(|| format!("Failed while reading the CSV header of {}", path.display()))?;
+    let expected = csv_header(units, metrics);
+    if header.iter().ne(expected.iter().copied()) {
+        return Err(eyre!(
+            "{} has columns {:?}, which don't match the {:?} being written",
+            path.display(),
+            header.iter().collect::<Vec<_>>(),
+            expected
+        ));
+    }
+    Ok(())
+}
+
+/// The timestamp of the last row of a CSV archive, or `None` if it has no data rows.
+pub fn last_csv_timestamp(path: &Path, timestamps: CsvTimestamps) -> Result<Option<i64>> {
+    let file = File::open(path).wrap_err_with(|| format!("Could not open {}", path.display()))?;
+    let mut last = None;
+    for (i, record) in archive_reader(file).records().enumerate() {
+        let record = record.wrap_err_with(|| format!("Failed while reading CSV row {}", i + 1))?;
+        let timestamp = record
+            .get(0)
+            .and_then(|field| timestamps.parse(field))
+            .ok_or(eyre!(
+                "No timestamp in CSV row {} of {}",
+                i + 1,
+                path.display()
+            ))?;
+        last = Some(timestamp);
+    }
+    Ok(last)
+}
+
+/// Read back a CSV archive written by [`save_history_csv`], along with the units its header
+/// says the values are in.
+pub(crate) fn read_history_csv(path: &Path) -> Result<(HistoryColumns, Units)> {
+    let file = File::open(path).wrap_err_with(|| format!("Could not open {}", path.display()))?;
+    let mut reader = archive_reader(file);
+    let header = reader
+        .headers()
+        .wrap_err_with(|| format!("Failed while reading the CSV header of {}", path.display()))?;
+    let units = [Units::Metric, Units::Imperial]
+        .into_iter()
+        .find(|&units| header.iter().eq(csv_header(units, &HistoryMetric::ALL)))
+        .ok_or(eyre!(
+            "{} has columns {:?}, which aren't those of a history archive",
+            path.display(),
+            header.iter().collect::<Vec<_>>()
+        ))?;
+    let mut columns = HistoryColumns::default();
+    for (i, record) in reader.records().enumerate() {
+        let record = record.wrap_err_with(|| format!("Failed while reading CSV row {}", i + 1))?;
+        let field = |column: usize| {
+            record.get(column).ok_or(eyre!(
+                "Missing column {} in CSV row {} of {}",
+                column + 1,
+                i + 1,
+                path.display()
+            ))
+        };
+        let timestamp = field(0)?;
+        columns
+            .timestamp
+            .push(CsvTimestamps::default().parse(timestamp).ok_or(eyre!(
+                "Invalid timestamp '{}' in CSV row {} of {}",
+                timestamp,
+                i + 1,
+                path.display()
+            ))?);
+        columns.temperature.push(parse_field(field(1)?, i, path)?);
+        columns.humidity.push(parse_field(field(2)?, i, path)?);
+        columns.pressure.push(parse_field(field(3)?, i, path)?);
+        columns.co2.push(parse_field(field(4)?, i, path)?);
+    }
+    Ok((columns, units))
+}
+
+fn parse_field<T: FromStr>(field: &str, row: usize, path: &Path) -> Result<T> {
+    field.parse().map_err(|_| {
+        eyre!(
+            "Invalid value '{}' in CSV row {} of {}",
+            field,
+            row + 1,
+            path.display()
+        )
+    })
+}
+
+/// Write `columns`, which are already in `units`, as a CSV archive with a header.
+pub(crate) fn write_history_columns_csv<W: Write>(
+    columns: &HistoryColumns,
+    dest: &mut W,
+    units: Units,
 ) -> Result<()> {
     let mut dest = csv::Writer::from_writer(dest);
-    dest.write_record([
-        "timestamp",
-        temperature.label(),
-        humidity.label(),
-        pressure.label(),
-        co2.label(),
-    ])
-    .expect("Failed while writing CSV header");
-    for i in 0..temperature.values.len() {
+    dest.write_record(csv_header(units, &HistoryMetric::ALL))
+        .wrap_err("Failed while writing CSV header")?;
+    for i in 0..columns.len() {
         dest.write_record([
-            history_time.get_timestamp(i)?.to_string(),
-            temperature.get_value_string(i),
-            humidity.get_value_string(i),
-            pressure.get_value_string(i),
-            co2.get_value_string(i),
+            columns.timestamp[i].to_string(),
+            format!(
+                "{:.*}",
+                TemperatureData::precision(units),
+                columns.temperature[i]
+            ),
+            columns.humidity[i].to_string(),
+            format!("{:.*}", PressureData::precision(units), columns.pressure[i]),
+            columns.co2[i].to_string(),
         ])
-        .expect(&format!(
-            "Failed while writing CSV row {} (data record {})",
-            i + 1,
-            i
-        ));
+        .wrap_err_with(|| format!("Failed while writing CSV row {}", i + 1))?;
     }
+    dest.flush()?;
     Ok(())
 }
+
+/// Write `records` as CSV rows with the columns of `metrics`, followed by the dew point and
+/// absolute humidity if `derived`, after a header row if `header`.
+pub(crate) fn write_history_csv<W: Write>(
+    records: impl IntoIterator<Item = Result<HistoryRecord>>,
+    dest: &mut W,
+    units: Units,
+    timestamps: CsvTimestamps,
+    metrics: &[HistoryMetric],
+    derived: bool,
+    header: bool,
+) -> Result<()> {
+    let mut dest = csv::Writer::from_writer(dest);
+    if header {
+        let mut header = csv_header(units, metrics);
+        if derived {
+            header.push(DerivedHumidity::dew_point_label(units));
+            header.push(DerivedHumidity::ABSOLUTE_HUMIDITY_LABEL);
+        }
+        dest.write_record(header)
+            .wrap_err("Failed while writing CSV header")?;
+    }
+    let metrics = HistoryMetric::in_order(metrics);
+    for (i, record) in records.into_iter().enumerate() {
+        let record = record?;
+        let mut fields = vec![timestamps.format(record.timestamp)?];
+        fields.extend(metric_fields(&record, units, &metrics));
+        if derived {
+            let quantities = record.derived();
+            fields.push(
+                quantities
+                    .dew_point_in(units)
+                    .map(|dew_point| format!("{:.1}", dew_point))
+                    .unwrap_or_default(),
+            );
+            fields.push(format!("{:.1}", quantities.absolute_humidity));
+        }
+        dest.write_record(fields).wrap_err_with(|| {
+            format!("Failed while writing CSV row {} (data record {})", i + 1, i)
+        })?;
+    }
+    dest.flush()?;
+    Ok(())
+}
+
+/// Like `save_history_csv`, for an Aranet2 or Aranet Radon Plus. The pressure and radon columns
+/// are only written for the latter.
+pub async fn save_model_history_csv<W: Write>(
+    history: ModelHistory,
+    dest: &mut W,
+    units: Units,
+    timestamps: CsvTimestamps,
+) -> Result<()> {
+    let mut dest = csv::Writer::from_writer(dest);
+    let mut header = vec![
+        "timestamp",
+        TemperatureData::unit_label(units),
+        Humidity2Data::unit_label(units),
+    ];
+    if history.pressure.is_some() {
+        header.push(PressureData::unit_label(units));
+    }
+    if history.radon.is_some() {
+        header.push(RadonData::LABEL);
+    }
+    dest.write_record(header)
+        .wrap_err("Failed while writing CSV header")?;
+    for (i, time) in history.time.timestamps().enumerate() {
+        let mut record = vec![
+            timestamps.format(time.timestamp())?,
+            history.temperature.get_value_string_in(i, units),
+            history.humidity.get_value_string_in(i, units),
+        ];
+        if let Some(pressure) = &history.pressure {
+            record.push(pressure.get_value_string_in(i, units));
+        }
+        if let Some(radon) = &history.radon {
+            record.push(radon.values[i].to_string());
+        }
+        dest.write_record(record).wrap_err_with(|| {
+            format!("Failed while writing CSV row {} (data record {})", i + 1, i)
+        })?;
+    }
+    dest.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::csv_io::{
+        check_csv_header, csv_sidecar_path, last_csv_timestamp, save_csv_sidecar, save_history_csv,
+        save_model_history_csv, write_csv_comment_header, CsvTimeFormat, CsvTimestamps,
+    };
+    use crate::device::{HistoryMetric, HistoryTime, ModelHistory};
+    use crate::parquet_io::ArchiveSource;
+    use crate::types::{
+        AranetModel, CO2Data, Humidity2Data, HumidityData, PressureData, RadonData,
+        TemperatureData, Units,
+    };
+
+    #[test]
+    fn test_last_csv_timestamp() {
+        let path = std::env::temp_dir().join("arachiver_test_last_csv_timestamp.csv");
+        let header = "timestamp,Temperature (°C),Humidity (%),Pressure (hPa),CO₂ (ppm)\n";
+        std::fs::write(&path, header).unwrap();
+        assert_eq!(
+            last_csv_timestamp(&path, CsvTimestamps::default()).unwrap(),
+            None
+        );
+        std::fs::write(
+            &path,
+            format!(
+                "{}1738621029,20.65,33,1017.4,926\n1738621329,20.70,33,1017.3,930\n",
+                header
+            ),
+        )
+        .unwrap();
+        let last = last_csv_timestamp(&path, CsvTimestamps::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(last, Some(1738621329));
+    }
+
+    #[test]
+    fn test_csv_metadata() {
+        let source = ArchiveSource {
+            name: "Aranet4 1A2B3".to_string(),
+            serial: "317960113191".to_string(),
+            model: "Aranet4".to_string(),
+            firmware: "v1.4.19".to_string(),
+            interval: 300,
+            serial_column: false,
+        };
+        let path = std::env::temp_dir().join("arachiver_test_csv_metadata.csv");
+        let mut contents = Vec::new();
+        write_csv_comment_header(&source, &mut contents).unwrap();
+        contents.extend_from_slice(
+            "timestamp,Temperature (°C),Humidity (%),Pressure (hPa),CO₂ (ppm)\n\
+             1738621029,20.65,33,1017.4,926\n"
+                .as_bytes(),
+        );
+        std::fs::write(&path, &contents).unwrap();
+        let header = check_csv_header(&path, Units::Metric, &HistoryMetric::ALL);
+        let last = last_csv_timestamp(&path, CsvTimestamps::default()).unwrap();
+        let sidecar = save_csv_sidecar(&path, &source).unwrap();
+        let metadata: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&sidecar).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&sidecar).unwrap();
+        let contents = String::from_utf8(contents).unwrap();
+        assert!(contents.starts_with("# device_name=Aranet4 1A2B3\n# device_serial=317960113191\n"));
+        assert!(contents.contains("# measurement_interval=300\n"));
+        assert!(header.is_ok());
+        assert_eq!(last, Some(1738621029));
+        assert_eq!(sidecar, csv_sidecar_path(&path));
+        assert!(sidecar.ends_with("arachiver_test_csv_metadata.meta.json"));
+        assert_eq!(metadata["firmware_revision"], "v1.4.19");
+    }
+
+    #[test]
+    fn test_csv_timestamps() {
+        let rfc3339 = CsvTimestamps {
+            format: CsvTimeFormat::Rfc3339,
+            timezone: None,
+        };
+        assert_eq!(rfc3339.format(1738621029).unwrap(), "2025-02-03T22:17:09Z");
+        let helsinki = CsvTimestamps {
+            format: CsvTimeFormat::Local,
+            timezone: Some(chrono_tz::Europe::Helsinki),
+        };
+        assert_eq!(helsinki.format(1738621029).unwrap(), "2025-02-04 00:17:09");
+        let with_offset = CsvTimestamps {
+            format: CsvTimeFormat::Rfc3339,
+            ..helsinki
+        };
+        assert_eq!(
+            with_offset.format(1738621029).unwrap(),
+            "2025-02-04T00:17:09+02:00"
+        );
+        for field in [
+            "1738621029",
+            "2025-02-03T22:17:09Z",
+            "2025-02-04T00:17:09+02:00",
+            "2025-02-04 00:17:09",
+        ] {
+            assert_eq!(helsinki.parse(field), Some(1738621029));
+        }
+        assert_eq!(helsinki.parse("yesterday"), None);
+    }
+
+    #[tokio::test]
+    async fn test_save_history_csv_imperial() {
+        let history_time = HistoryTime {
+            num_samples: 1,
+            update_interval: 300,
+            since_update: 0,
+            now: chrono::DateTime::from_timestamp(1738621029, 0).unwrap(),
+            offset: 0,
+        };
+        let mut output = Vec::new();
+        save_history_csv(
+            history_time,
+            TemperatureData { values: vec![413] },
+            HumidityData { values: vec![33] },
+            PressureData {
+                values: vec![10174],
+            },
+            CO2Data { values: vec![926] },
+            &mut output,
+            Units::Imperial,
+            CsvTimestamps::default(),
+            &HistoryMetric::ALL,
+            false,
+        )
+        .await
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(
+            output,
+            "timestamp,Temperature (°F),Humidity (%),Pressure (inHg),CO₂ (ppm)\n\
+             1738621029,69.17,33,30.04,926\n"
+        );
+
+        let path = std::env::temp_dir().join("arachiver_test_check_csv_header.csv");
+        std::fs::write(&path, &output).unwrap();
+        assert!(check_csv_header(&path, Units::Imperial, &HistoryMetric::ALL).is_ok());
+        assert!(check_csv_header(&path, Units::Metric, &HistoryMetric::ALL).is_err());
+        assert!(check_csv_header(&path, Units::Imperial, &[HistoryMetric::Co2]).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_save_history_csv_columns() {
+        let history_time = HistoryTime {
+            num_samples: 2,
+            update_interval: 300,
+            since_update: 0,
+            now: chrono::DateTime::from_timestamp(1738621329, 0).unwrap(),
+            offset: 0,
+        };
+        let mut output = Vec::new();
+        // Only CO₂ and temperature were downloaded
+        save_history_csv(
+            history_time,
+            TemperatureData {
+                values: vec![413, 414],
+            },
+            HumidityData { values: vec![] },
+            PressureData { values: vec![] },
+            CO2Data {
+                values: vec![926, 930],
+            },
+            &mut output,
+            Units::Metric,
+            CsvTimestamps::default(),
+            &[HistoryMetric::Co2, HistoryMetric::Temperature],
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "timestamp,Temperature (°C),CO₂ (ppm)\n\
+             1738621029,20.65,926\n\
+             1738621329,20.70,930\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_history_csv_derived() {
+        let history_time = HistoryTime {
+            num_samples: 2,
+            update_interval: 300,
+            since_update: 0,
+            now: chrono::DateTime::from_timestamp(1738621329, 0).unwrap(),
+            offset: 0,
+        };
+        let mut output = Vec::new();
+        save_history_csv(
+            history_time,
+            TemperatureData {
+                values: vec![413, 360],
+            },
+            HumidityData {
+                values: vec![33, 0],
+            },
+            PressureData {
+                values: vec![10174, 10173],
+            },
+            CO2Data {
+                values: vec![926, 930],
+            },
+            &mut output,
+            Units::Metric,
+            CsvTimestamps::default(),
+            &HistoryMetric::ALL,
+            true,
+        )
+        .await
+        .unwrap();
+        // There is no dew point in perfectly dry air
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "timestamp,Temperature (°C),Humidity (%),Pressure (hPa),CO₂ (ppm),Dew point (°C),Absolute humidity (g/m³)\n\
+             1738621029,20.65,33,1017.4,926,3.8,5.9\n\
+             1738621329,18.00,0,1017.3,930,,0.0\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_radon_history_csv() {
+        let history = ModelHistory {
+            model: AranetModel::Radon,
+            time: HistoryTime {
+                num_samples: 1,
+                update_interval: 600,
+                since_update: 0,
+                now: chrono::DateTime::from_timestamp(1738621029, 0).unwrap(),
+                offset: 0,
+            },
+            temperature: TemperatureData { values: vec![413] },
+            humidity: Humidity2Data { values: vec![452] },
+            pressure: Some(PressureData {
+                values: vec![10174],
+            }),
+            radon: Some(RadonData { values: vec![45] }),
+        };
+        let mut output = Vec::new();
+        save_model_history_csv(
+            history,
+            &mut output,
+            Units::Metric,
+            CsvTimestamps::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "timestamp,Temperature (°C),Humidity (%),Pressure (hPa),Radon (Bq/m³)\n\
+             1738621029,20.65,45.2,1017.4,45\n"
+        );
+    }
+}
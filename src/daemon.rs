@@ -0,0 +1,234 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::Deserialize;
+
+use crate::monitor::SinkFormat;
+
+/// Archive formats a `[[history]]` job can keep up to date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryFormat {
+    Csv,
+    Parquet,
+    Sqlite,
+}
+
+impl HistoryFormat {
+    pub fn name(self) -> &'static str {
+        match self {
+            HistoryFormat::Csv => "csv",
+            HistoryFormat::Parquet => "parquet",
+            HistoryFormat::Sqlite => "sqlite",
+        }
+    }
+}
+
+fn default_history_every() -> u64 {
+    3600
+}
+
+fn default_readout_every() -> u64 {
+    60
+}
+
+/// Download new history from each sensor every `every` seconds and add it to an archive.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HistoryJob {
+    pub format: HistoryFormat,
+    /// Archive path, with the same placeholders as `--filename-template`.
+    pub output: String,
+    #[serde(default = "default_history_every")]
+    pub every: u64,
+}
+
+/// Take a reading from each sensor every `every` seconds and append it to a file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReadoutJob {
+    /// File path, with the same placeholders as `--filename-template`.
+    pub output: String,
+    /// `csv` or `ndjson`; by default, guessed from the file extension.
+    pub format: Option<String>,
+    #[serde(default = "default_readout_every")]
+    pub every: u64,
+}
+
+impl ReadoutJob {
+    pub fn sink_format(&self) -> Result<SinkFormat> {
+        let format = match &self.format {
+            Some(name) => SinkFormat::from_name(name),
+            None => SinkFormat::from_path(Path::new(&self.output)),
+        }
+        .ok_or(eyre!(
+            "Could not determine output format for readout job {}; set format",
+            self.output
+        ))?;
+        // A Parquet sink starts a new file, which would overwrite the readings from before a
+        // restart
+        if format == SinkFormat::Parquet {
+            return Err(eyre!(
+                "Readout job {} can't append to Parquet; use csv or ndjson",
+                self.output
+            ));
+        }
+        Ok(format)
+    }
+}
+
+/// The jobs `arachiver daemon` runs, read from a TOML file with `[[history]]` and
+/// `[[readout]]` tables.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DaemonConfig {
+    #[serde(default)]
+    pub history: Vec<HistoryJob>,
+    #[serde(default)]
+    pub readout: Vec<ReadoutJob>,
+}
+
+impl DaemonConfig {
+    /// `arachiver/daemon.toml` in the platform config directory, e.g. `~/.config` on Linux.
+    pub fn default_path() -> Result<PathBuf> {
+        dirs::config_dir()
+            .map(|dir| dir.join("arachiver").join("daemon.toml"))
+            .ok_or(eyre!("Could not determine the user config directory"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Could not read daemon config {}", path.display()))?;
+        Self::parse(&contents).wrap_err_with(|| format!("Invalid daemon config {}", path.display()))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self> {
+        let config: DaemonConfig = toml::from_str(contents)?;
+        if config.history.is_empty() && config.readout.is_empty() {
+            return Err(eyre!("No [[history]] or [[readout]] jobs configured"));
+        }
+        let intervals = config.history.iter().map(|job| (&job.output, job.every));
+        let intervals = intervals.chain(config.readout.iter().map(|job| (&job.output, job.every)));
+        for (output, every) in intervals {
+            if every == 0 {
+                return Err(eyre!("Job {} must run every 1 or more seconds", output));
+            }
+        }
+        for job in &config.readout {
+            job.sink_format()?;
+        }
+        Ok(config)
+    }
+}
+
+/// Quote `arg` for an `ExecStart=` line, if it needs it.
+fn systemd_quote(arg: &str) -> String {
+    let escaped = arg.replace('%', "%%").replace('$', "$$");
+    if !escaped.is_empty()
+        && !escaped
+            .chars()
+            .any(|c| c.is_whitespace() || matches!(c, '"' | '\'' | '\\' | ';'))
+    {
+        return escaped;
+    }
+    format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// A systemd service unit running `command`, which should end in `daemon`. The service
+/// reports readiness, so it is `Type=notify`, and is restarted if the watchdog isn't fed for
+/// ten minutes, which allows for slow history downloads.
+pub fn systemd_unit(command: &[String]) -> String {
+    let exec_start: Vec<String> = command.iter().map(|arg| systemd_quote(arg)).collect();
+    format!(
+        "[Unit]
+Description=Aranet4 archiver
+After=bluetooth.target
+Wants=bluetooth.target
+
+[Service]
+Type=notify
+ExecStart={}
+Restart=on-failure
+RestartSec=30
+WatchdogSec=600
+
+[Install]
+WantedBy=default.target
+",
+        exec_start.join(" ")
+    )
+}
+
+/// Tell systemd, if we were started by it, that the daemon is up.
+pub fn notify_ready() {
+    #[cfg(unix)]
+    let _ = sd_notify::notify(&[sd_notify::NotifyState::Ready]);
+}
+
+pub fn notify_stopping() {
+    #[cfg(unix)]
+    let _ = sd_notify::notify(&[sd_notify::NotifyState::Stopping]);
+}
+
+/// Feed the systemd watchdog.
+pub fn notify_watchdog() {
+    #[cfg(unix)]
+    let _ = sd_notify::notify(&[sd_notify::NotifyState::Watchdog]);
+}
+
+/// How often to feed the watchdog, if systemd enabled it: twice per timeout, as recommended.
+pub fn watchdog_interval() -> Option<Duration> {
+    #[cfg(unix)]
+    return sd_notify::watchdog_enabled().map(|timeout| timeout / 2);
+    #[cfg(not(unix))]
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use crate::daemon::{systemd_unit, DaemonConfig, HistoryFormat};
+    use crate::monitor::SinkFormat;
+
+    #[test]
+    fn test_parse_daemon_config() {
+        let config = DaemonConfig::parse(
+            r#"
+            [[history]]
+            format = "sqlite"
+            output = "/var/lib/arachiver/aranet.sqlite"
+
+            [[readout]]
+            output = "/var/lib/arachiver/{serial}_readings.ndjson"
+            every = 300
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.history[0].format, HistoryFormat::Sqlite);
+        assert_eq!(config.history[0].every, 3600);
+        assert_eq!(config.readout[0].every, 300);
+        assert_eq!(config.readout[0].sink_format().unwrap(), SinkFormat::Ndjson);
+
+        assert!(DaemonConfig::parse("").is_err());
+        assert!(DaemonConfig::parse("[[readout]]\noutput = \"readings.parquet\"").is_err());
+        assert!(DaemonConfig::parse("[[readout]]\noutput = \"readings.csv\"\nevery = 0").is_err());
+        assert!(DaemonConfig::parse("[[history]]\nformat = \"xlsx\"\noutput = \"a\"").is_err());
+    }
+
+    #[test]
+    fn test_systemd_unit() {
+        let unit = systemd_unit(&[
+            "/usr/local/bin/arachiver".to_string(),
+            "--device".to_string(),
+            "Aranet4 1BA27".to_string(),
+            "daemon".to_string(),
+            "--config".to_string(),
+            "/home/me/50%.toml".to_string(),
+        ]);
+        assert!(unit.contains(
+            "ExecStart=/usr/local/bin/arachiver --device \"Aranet4 1BA27\" daemon --config /home/me/50%%.toml\n"
+        ));
+        assert!(unit.contains("Type=notify\n"));
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use chrono::Utc;
+use color_eyre::eyre::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph, Sparkline};
+use ratatui::Frame;
+use tokio::sync::mpsc;
+
+use crate::client::Aranet4Client;
+use crate::monitor::reading_timestamp;
+use crate::shutdown::shutdown_signal;
+use crate::types::{Co2Indicator, CurrentSensorMeasurement};
+
+/// How many readings the sparklines keep; wider than most terminals.
+const HISTORY_LEN: usize = 240;
+
+/// What the dashboard shows. Kept apart from the terminal so rendering can be tested.
+#[derive(Debug, Default)]
+pub struct DashboardState {
+    pub name: String,
+    pub latest: Option<CurrentSensorMeasurement>,
+    /// UNIX time the latest reading was measured.
+    pub measured_at: i64,
+    pub rssi: Option<i16>,
+    /// Raw sensor values, oldest first, as in [`CurrentSensorMeasurement`].
+    pub co2: VecDeque<u16>,
+    pub temperature: VecDeque<u16>,
+    pub humidity: VecDeque<u16>,
+    pub pressure: VecDeque<u16>,
+    /// The last error, shown until the next successful reading.
+    pub error: Option<String>,
+}
+
+impl DashboardState {
+    pub fn push(&mut self, reading: CurrentSensorMeasurement) {
+        for (values, value) in [
+            (&mut self.co2, reading.co2),
+            (&mut self.temperature, reading.temperature),
+            (&mut self.humidity, reading.humidity as u16),
+            (&mut self.pressure, reading.pressure),
+        ] {
+            if values.len() == HISTORY_LEN {
+                values.pop_front();
+            }
+            values.push_back(value);
+        }
+        self.measured_at = reading_timestamp(&reading);
+        self.latest = Some(reading);
+        self.error = None;
+    }
+}
+
+/// Sparkline heights relative to the smallest value shown, so that e.g. a temperature drifting
+/// by a degree is visible rather than a flat line near the top.
+fn sparkline_data(values: &VecDeque<u16>, width: usize) -> Vec<u64> {
+    let shown = values.iter().skip(values.len().saturating_sub(width));
+    let min = shown.clone().min().copied().unwrap_or(0);
+    shown.map(|value| u64::from(value - min) + 1).collect()
+}
+
+fn metric_row(frame: &mut Frame, area: Rect, title: Line, value: String, values: &VecDeque<u16>) {
+    let block = Block::bordered().title(title);
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+    let [value_area, sparkline_area] =
+        Layout::horizontal([Constraint::Length(14), Constraint::Min(0)]).areas(inner);
+    frame.render_widget(Paragraph::new(value), value_area);
+    let data = sparkline_data(values, sparkline_area.width as usize);
+    frame.render_widget(
+        Sparkline::default()
+            .data(&data)
+            .style(Style::default().fg(Color::Cyan)),
+        sparkline_area,
+    );
+}
+
+pub fn render(frame: &mut Frame, state: &DashboardState, now: i64) {
+    let [header, co2, temperature, humidity, pressure, footer] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(3),
+        Constraint::Min(3),
+        Constraint::Min(3),
+        Constraint::Min(3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let Some(latest) = &state.latest else {
+        let message = state
+            .error
+            .as_deref()
+            .unwrap_or("Waiting for the first reading…");
+        frame.render_widget(
+            Paragraph::new(message).block(Block::bordered().title(state.name.as_str())),
+            header,
+        );
+        return;
+    };
+    let rssi = match state.rssi {
+        Some(rssi) => format!("{} dBm", rssi),
+        None => "-".to_string(),
+    };
+    let status = match &state.error {
+        Some(error) => error.clone(),
+        None => format!(
+            "Battery {}%   RSSI {}   Updated {} s ago   Interval {} s",
+            latest.battery,
+            rssi,
+            (now - state.measured_at).max(0),
+            latest.interval
+        ),
+    };
+    frame.render_widget(
+        Paragraph::new(status).block(Block::bordered().title(state.name.as_str())),
+        header,
+    );
+
+    let co2_color = match latest.status_flags().co2_indicator() {
+        Co2Indicator::Green => Color::Green,
+        Co2Indicator::Yellow => Color::Yellow,
+        Co2Indicator::Red => Color::Red,
+        Co2Indicator::None => Color::Reset,
+    };
+    metric_row(
+        frame,
+        co2,
+        Line::styled("CO₂", Style::default().fg(co2_color)),
+        format!("{} ppm", latest.co2),
+        &state.co2,
+    );
+    metric_row(
+        frame,
+        temperature,
+        Line::from("Temperature"),
+        format!("{:.2} °C", latest.temperature_value()),
+        &state.temperature,
+    );
+    metric_row(
+        frame,
+        humidity,
+        Line::from("Humidity"),
+        format!("{} %", latest.humidity),
+        &state.humidity,
+    );
+    metric_row(
+        frame,
+        pressure,
+        Line::from("Pressure"),
+        format!("{:.1} hPa", latest.pressure_value()),
+        &state.pressure,
+    );
+    frame.render_widget(Paragraph::new("q to quit"), footer);
+}
+
+/// How long to wait before reading again: just after the sensor's next measurement, or a
+/// little while after a failure.
+fn next_read_delay(state: &DashboardState, now: i64) -> Duration {
+    const AFTER_MEASUREMENT: i64 = 2;
+    const AFTER_FAILURE: Duration = Duration::from_secs(10);
+    match (&state.latest, &state.error) {
+        (Some(latest), None) => {
+            let next = state.measured_at + latest.interval as i64 + AFTER_MEASUREMENT;
+            Duration::from_secs((next - now).max(AFTER_MEASUREMENT) as u64)
+        }
+        _ => AFTER_FAILURE,
+    }
+}
+
+/// Seed the sparklines with the readings the sensor stored before the dashboard started.
+async fn load_recent_history(client: &Aranet4Client, state: &mut DashboardState) -> Result<()> {
+    let Some(latest) = &state.latest else {
+        return Ok(());
+    };
+    let since = state.measured_at - (HISTORY_LEN as i64) * latest.interval as i64;
+    let history = client.read_history_after(since).await?;
+    // The newest stored reading is the one just read
+    let stored = history.co2.values.len().saturating_sub(1);
+    let seed = |values: &mut VecDeque<u16>, history: Vec<u16>| {
+        let latest = values.pop_back();
+        values.extend(history.into_iter().take(stored));
+        values.extend(latest);
+    };
+    seed(&mut state.co2, history.co2.values);
+    seed(&mut state.temperature, history.temperature.values);
+    seed(
+        &mut state.humidity,
+        history.humidity.values.into_iter().map(u16::from).collect(),
+    );
+    seed(&mut state.pressure, history.pressure.values);
+    Ok(())
+}
+
+/// Show a live dashboard of `client`'s readings until the user quits with `q`, Esc, Ctrl-C or
+/// SIGTERM.
+pub async fn dashboard(client: &Aranet4Client) -> Result<()> {
+    let mut state = DashboardState {
+        name: client.local_name().await.unwrap_or_default(),
+        ..Default::default()
+    };
+    let mut terminal = ratatui::init();
+    let result = run(client, &mut state, &mut terminal).await;
+    ratatui::restore();
+    result
+}
+
+async fn read(client: &Aranet4Client, state: &mut DashboardState) {
+    match client.read_current().await {
+        Ok(reading) => state.push(reading),
+        Err(e) => state.error = Some(format!("Failed to take reading: {}", e)),
+    }
+    state.rssi = client.rssi().await;
+}
+
+async fn run(
+    client: &Aranet4Client,
+    state: &mut DashboardState,
+    terminal: &mut ratatui::DefaultTerminal,
+) -> Result<()> {
+    // Terminal input blocks, so it is read on its own thread
+    let (keys, mut key_events) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if let Event::Key(key) = event {
+                if key.kind == KeyEventKind::Press && keys.send(key.code).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    terminal.draw(|frame| render(frame, state, Utc::now().timestamp()))?;
+    read(client, state).await;
+    terminal.draw(|frame| render(frame, state, Utc::now().timestamp()))?;
+    if let Err(e) = load_recent_history(client, state).await {
+        state.error = Some(format!("Failed to load history: {}", e));
+    }
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let mut redraw = tokio::time::interval(Duration::from_secs(1));
+    let next_read = tokio::time::sleep(next_read_delay(state, Utc::now().timestamp()));
+    tokio::pin!(next_read);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            key = key_events.recv() => match key {
+                Some(KeyCode::Char('q') | KeyCode::Esc) | None => break,
+                Some(_) => continue,
+            },
+            _ = &mut next_read => {
+                read(client, state).await;
+                let delay = next_read_delay(state, Utc::now().timestamp());
+                next_read.as_mut().reset(tokio::time::Instant::now() + delay);
+            }
+            _ = redraw.tick() => {}
+        }
+        terminal.draw(|frame| render(frame, state, Utc::now().timestamp()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    use crate::dashboard::{render, sparkline_data, DashboardState};
+    use crate::types::CurrentSensorMeasurement;
+
+    #[test]
+    fn test_render_dashboard() {
+        let mut state = DashboardState {
+            name: "Aranet4 1BA27".to_string(),
+            rssi: Some(-62),
+            ..Default::default()
+        };
+        state.push(CurrentSensorMeasurement::from([
+            0x9e, 0x03, 0x9d, 0x01, 0xbe, 0x27, 0x21, 0x16, 0x01, 0x2c, 0x01, 0x00, 0x00,
+        ]));
+        let now = state.measured_at + 30;
+        let mut terminal = Terminal::new(TestBackend::new(80, 16)).unwrap();
+        terminal.draw(|frame| render(frame, &state, now)).unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("926 ppm"));
+        assert!(screen.contains("20.65 °C"));
+        assert!(screen.contains("RSSI -62 dBm"));
+        assert!(screen.contains("Updated 30 s ago"));
+    }
+
+    #[test]
+    fn test_sparkline_data_is_relative() {
+        let values = [410, 400, 420].into_iter().collect();
+        assert_eq!(sparkline_data(&values, 80), vec![11, 1, 21]);
+        assert_eq!(sparkline_data(&values, 2), vec![1, 21]);
+    }
+}
@@ -1,38 +1,66 @@
 use btleplug::api::{
-    bleuuid::uuid_from_u16, Central as _, CentralEvent, CharPropFlags, Characteristic,
-    Peripheral as _, ScanFilter, WriteType,
+    bleuuid::uuid_from_u16, Central as _, CentralEvent, Manager as _, Peripheral as _,
+    PeripheralProperties, ScanFilter, ValueNotification,
 };
-use btleplug::platform::{Adapter, Peripheral};
+use btleplug::platform::{Adapter, Manager, Peripheral, PeripheralId};
 use chrono::{DateTime, TimeDelta, Utc};
-use color_eyre::{eyre::eyre, Result};
+use clap::ValueEnum;
+use color_eyre::{
+    eyre::{eyre, WrapErr},
+    Result,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
 use std::mem::size_of;
+use std::pin::Pin;
+use std::sync::Mutex;
 use std::time::{Duration, Instant};
-use tokio_stream::StreamExt;
+use tokio_stream::{Stream, StreamExt};
+use tracing::{debug, instrument, trace, warn};
 use uuid::{uuid, Uuid};
 
+use crate::advertisement::{advertised_reading, SAF_TEHNIKA_MANUFACTURER_ID};
+use crate::time_range::HistoryRange;
+use crate::transport::{Connection, SensorTransport};
 use crate::types::*;
 
 pub const ARANET4_SERVICE_UUID: Uuid = uuid_from_u16(0xfce0);
-const ARANET4_CURRENT_READINGS_UUID: Uuid = uuid!("f0cd3001-95da-4f4b-9ac8-aa55d312af0c");
-const ARANET4_NOTIFY_HISTORY_UUID: Uuid = uuid!("f0cd2003-95da-4f4b-9ac8-aa55d312af0c");
-const ARANET4_COMMAND_UUID: Uuid = uuid!("f0cd1402-95da-4f4b-9ac8-aa55d312af0c");
-const ARANET4_TOTAL_READINGS_UUID: Uuid = uuid!("f0cd2001-95da-4f4b-9ac8-aa55d312af0c");
-const ARANET4_TIME_SINCE_UPDATE_UUID: Uuid = uuid!("f0cd2004-95da-4f4b-9ac8-aa55d312af0c");
-const ARANET4_UPDATE_INTERVAL_UUID: Uuid = uuid!("f0cd2002-95da-4f4b-9ac8-aa55d312af0c");
-
-const GENERIC_GATT_DEVICE_MODEL_NUMBER_STRING_UUID: Uuid =
+pub(crate) const ARANET4_CURRENT_READINGS_UUID: Uuid =
+    uuid!("f0cd3001-95da-4f4b-9ac8-aa55d312af0c");
+/// Current readings of the Aranet2, Aranet Radiation and Aranet Radon Plus; see
+/// [`ModelMeasurement::decode`].
+pub(crate) const ARANET_CURRENT_READINGS_DETAILED_UUID: Uuid =
+    uuid!("f0cd1504-95da-4f4b-9ac8-aa55d312af0c");
+/// Notified with a current reading, laid out like [`ARANET4_CURRENT_READINGS_UUID`]'s, after
+/// every measurement.
+pub(crate) const ARANET4_NOTIFY_CURRENT_READINGS_UUID: Uuid =
+    uuid!("f0cd3003-95da-4f4b-9ac8-aa55d312af0c");
+pub(crate) const ARANET4_NOTIFY_HISTORY_UUID: Uuid = uuid!("f0cd2003-95da-4f4b-9ac8-aa55d312af0c");
+pub(crate) const ARANET4_COMMAND_UUID: Uuid = uuid!("f0cd1402-95da-4f4b-9ac8-aa55d312af0c");
+pub(crate) const ARANET4_TOTAL_READINGS_UUID: Uuid = uuid!("f0cd2001-95da-4f4b-9ac8-aa55d312af0c");
+pub(crate) const ARANET4_TIME_SINCE_UPDATE_UUID: Uuid =
+    uuid!("f0cd2004-95da-4f4b-9ac8-aa55d312af0c");
+pub(crate) const ARANET4_UPDATE_INTERVAL_UUID: Uuid = uuid!("f0cd2002-95da-4f4b-9ac8-aa55d312af0c");
+/// The CO₂ indicator thresholds set in the Aranet Home app; see [`Co2IndicatorThresholds`].
+/// Firmware without configurable thresholds doesn't have it.
+pub(crate) const ARANET4_CO2_THRESHOLDS_UUID: Uuid = uuid!("f0cd1505-95da-4f4b-9ac8-aa55d312af0c");
+
+pub(crate) const GENERIC_GATT_DEVICE_MODEL_NUMBER_STRING_UUID: Uuid =
     uuid!("00002a24-0000-1000-8000-00805f9b34fb");
-const GENERIC_GATT_SERIAL_NUMBER_STRING_UUID: Uuid = uuid!("00002a25-0000-1000-8000-00805f9b34fb");
-const GENERIC_GATT_HARDWARE_REVISION_STRING_UUID: Uuid =
+pub(crate) const GENERIC_GATT_SERIAL_NUMBER_STRING_UUID: Uuid =
+    uuid!("00002a25-0000-1000-8000-00805f9b34fb");
+pub(crate) const GENERIC_GATT_HARDWARE_REVISION_STRING_UUID: Uuid =
     uuid!("00002a27-0000-1000-8000-00805f9b34fb");
-const GENERIC_GATT_SOFTWARE_REVISION_STRING_UUID: Uuid =
+pub(crate) const GENERIC_GATT_SOFTWARE_REVISION_STRING_UUID: Uuid =
     uuid!("00002a28-0000-1000-8000-00805f9b34fb");
-const GENERIC_GATT_MANUFACTURER_NAME_STRING_UUID: Uuid =
+pub(crate) const GENERIC_GATT_MANUFACTURER_NAME_STRING_UUID: Uuid =
     uuid!("00002a29-0000-1000-8000-00805f9b34fb");
-const GENERIC_GATT_FIRMWARE_REVISION_STRING_UUID: Uuid =
+pub(crate) const GENERIC_GATT_FIRMWARE_REVISION_STRING_UUID: Uuid =
     uuid!("00002a26-0000-1000-8000-00805f9b34fb");
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeviceInfo {
     pub device_name: String,
     pub model_number: String,
@@ -41,22 +69,39 @@ pub struct DeviceInfo {
     pub software_revision: String,
     pub manufacturer_name: String,
     pub firmware_revision: String,
+    /// Battery charge in %, from the current readings.
+    pub battery: u8,
+    /// `None` for other models and for Aranet4 firmware without configurable thresholds.
+    #[serde(default)]
+    pub co2_thresholds: Option<Co2IndicatorThresholds>,
 }
-async fn get_string(sensor: &Peripheral, uuid: Uuid) -> Result<String> {
-    let char = get_characteristic(sensor, uuid)?;
-    let bytes = sensor.read(&char).await?;
+async fn get_string<S: SensorTransport>(sensor: &S, uuid: Uuid) -> Result<String> {
+    let bytes = sensor.read(uuid).await?;
     Ok(String::from_utf8_lossy(&bytes).to_string())
 }
 
-impl DeviceInfo {
-    pub async fn read_from_sensor(sensor: &Peripheral) -> Result<Self> {
-        // connect to the device
-        sensor.connect().await?;
+/// Run `op` on a connection to `sensor` and disconnect afterwards, however `op` ends.
+pub(crate) async fn connected<S, T, E, Fut>(sensor: &S, op: Fut) -> Result<T, E>
+where
+    S: SensorTransport,
+    E: From<Aranet4Error>,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let connection = Connection::open(sensor).await?;
+    let result = op.await;
+    // A failed disconnect doesn't make what we read any less valid
+    let _ = connection.close().await;
+    result
+}
 
-        // discover services and characteristics
-        sensor.discover_services().await?;
+impl DeviceInfo {
+    pub async fn read_from_sensor<S: SensorTransport>(sensor: &S) -> Result<Self> {
+        connected(sensor, Self::read_connected(sensor)).await
+    }
 
-        let device_name = get_local_name(sensor)
+    async fn read_connected<S: SensorTransport>(sensor: &S) -> Result<Self> {
+        let device_name = sensor
+            .local_name()
             .await
             .unwrap_or("<Missing device name>".to_string());
         let model_number = get_string(sensor, GENERIC_GATT_DEVICE_MODEL_NUMBER_STRING_UUID).await?;
@@ -69,6 +114,9 @@ impl DeviceInfo {
             get_string(sensor, GENERIC_GATT_MANUFACTURER_NAME_STRING_UUID).await?;
         let firmware_revision =
             get_string(sensor, GENERIC_GATT_FIRMWARE_REVISION_STRING_UUID).await?;
+        let model = AranetModel::detect(&model_number).unwrap_or(AranetModel::Aranet4);
+        let current = read_model_reading(sensor, model).await?;
+        let co2_thresholds = read_model_co2_thresholds(sensor, model).await?;
         Ok(DeviceInfo {
             device_name,
             model_number,
@@ -77,6 +125,8 @@ impl DeviceInfo {
             software_revision,
             manufacturer_name,
             firmware_revision,
+            battery: current.battery(),
+            co2_thresholds,
         })
     }
 }
@@ -91,257 +141,2587 @@ fn bytes_to_single_u16(bytes: &[u8]) -> Result<u16, Aranet4Error> {
     }
 }
 
-async fn get_total_readings(sensor: &Peripheral) -> Result<u16, Aranet4Error> {
-    let char = get_characteristic(sensor, ARANET4_TOTAL_READINGS_UUID)?;
-    let bytes = sensor.read(&char).await?;
+/// How many readings the sensor has stored. Like the other `get_` functions without a
+/// `connected` wrapper, this expects `sensor` to be connected already.
+pub async fn get_total_readings<S: SensorTransport>(sensor: &S) -> Result<u16, Aranet4Error> {
+    let bytes = sensor.read(ARANET4_TOTAL_READINGS_UUID).await?;
     bytes_to_single_u16(&bytes)
 }
 
-async fn get_time_since_update(sensor: &Peripheral) -> Result<u16, Aranet4Error> {
-    let char = get_characteristic(sensor, ARANET4_TIME_SINCE_UPDATE_UUID)?;
-    let bytes = sensor.read(&char).await?;
+/// Seconds since the latest measurement.
+pub async fn get_time_since_update<S: SensorTransport>(sensor: &S) -> Result<u16, Aranet4Error> {
+    let bytes = sensor.read(ARANET4_TIME_SINCE_UPDATE_UUID).await?;
     bytes_to_single_u16(&bytes)
 }
 
-async fn get_update_interval(sensor: &Peripheral) -> Result<u16, Aranet4Error> {
-    let char = get_characteristic(sensor, ARANET4_UPDATE_INTERVAL_UUID)?;
-    let bytes = sensor.read(&char).await?;
+/// Seconds between measurements.
+pub async fn get_update_interval<S: SensorTransport>(sensor: &S) -> Result<u16, Aranet4Error> {
+    let bytes = sensor.read(ARANET4_UPDATE_INTERVAL_UUID).await?;
     bytes_to_single_u16(&bytes)
 }
 
-pub async fn get_current_sensor_data(
-    sensor: &Peripheral,
+/// The levels at which the sensor's CO₂ indicator changes colour, or `None` if its firmware
+/// doesn't let them be configured, in which case they are [`Co2IndicatorThresholds::DEFAULT`].
+pub async fn get_co2_thresholds<S: SensorTransport>(
+    sensor: &S,
+) -> Result<Option<Co2IndicatorThresholds>, Aranet4Error> {
+    match sensor.read(ARANET4_CO2_THRESHOLDS_UUID).await {
+        Ok(bytes) => Ok(Some(Co2IndicatorThresholds::try_from(&bytes[..])?)),
+        Err(Aranet4Error::CharacteristicNotFound) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// [`get_co2_thresholds`] for models with a CO₂ sensor.
+async fn read_model_co2_thresholds<S: SensorTransport>(
+    sensor: &S,
+    model: AranetModel,
+) -> Result<Option<Co2IndicatorThresholds>, Aranet4Error> {
+    match model {
+        AranetModel::Aranet4 => get_co2_thresholds(sensor).await,
+        _ => Ok(None),
+    }
+}
+
+/// What `status` shows: enough to tell how much history there is to archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SensorStatus {
+    pub total_readings: u16,
+    /// Seconds between measurements.
+    pub update_interval: u16,
+    /// Seconds since the latest measurement.
+    pub since_update: u16,
+    /// Battery charge in %.
+    pub battery: u8,
+    /// See [`DeviceInfo::co2_thresholds`].
+    #[serde(default)]
+    pub co2_thresholds: Option<Co2IndicatorThresholds>,
+}
+
+impl SensorStatus {
+    pub async fn read_from_sensor<S: SensorTransport>(sensor: &S) -> Result<Self> {
+        let model = get_model(sensor).await?;
+        connected(sensor, async {
+            Ok(SensorStatus {
+                total_readings: get_total_readings(sensor).await?,
+                update_interval: get_update_interval(sensor).await?,
+                since_update: get_time_since_update(sensor).await?,
+                battery: read_model_reading(sensor, model).await?.battery(),
+                co2_thresholds: read_model_co2_thresholds(sensor, model).await?,
+            })
+        })
+        .await
+    }
+
+    /// How long ago the oldest stored reading was measured, assuming the interval hasn't
+    /// changed since; the sensor discards its history when it does.
+    pub fn history_span(&self) -> Duration {
+        let intervals = u64::from(self.total_readings.saturating_sub(1));
+        Duration::from_secs(
+            intervals * u64::from(self.update_interval) + u64::from(self.since_update),
+        )
+    }
+
+    /// How many stored readings a download of `range` would return, the newest having been
+    /// measured `since_update` seconds before `now`.
+    pub fn readings_in(&self, range: HistoryRange, now: i64) -> u16 {
+        let latest = now - i64::from(self.since_update);
+        let first = match range.since {
+            Some(since) => {
+                start_index_since(since, latest, self.update_interval, self.total_readings)
+            }
+            None => 1,
+        };
+        let interval = i64::from(self.update_interval);
+        let too_new = match range.until {
+            Some(until) if until < latest && interval > 0 => {
+                ((latest - until + interval - 1) / interval).min(i64::from(self.total_readings))
+                    as u16
+            }
+            _ => 0,
+        };
+        (self.total_readings - (first - 1)).saturating_sub(too_new)
+    }
+
+    /// How many stored readings are newer than `last_timestamp`, as a download after it would
+    /// return.
+    pub fn readings_after(&self, last_timestamp: i64, now: i64) -> u16 {
+        let latest = now - i64::from(self.since_update);
+        let first = start_index_after(
+            last_timestamp,
+            latest,
+            self.update_interval,
+            self.total_readings,
+        );
+        self.total_readings - (first - 1)
+    }
+}
+
+/// Measurement intervals supported by the Aranet4, in minutes.
+pub const SUPPORTED_INTERVALS_MINUTES: [u8; 4] = [1, 2, 5, 10];
+
+/// Change the measurement interval and return the interval in seconds read back from the
+/// sensor. Note that the sensor discards its stored history when the interval changes.
+pub async fn set_update_interval<S: SensorTransport>(
+    sensor: &S,
+    minutes: u8,
+) -> Result<u16, Aranet4Error> {
+    if !SUPPORTED_INTERVALS_MINUTES.contains(&minutes) {
+        return Err(Aranet4Error::UnsupportedSetting(format!(
+            "measurement interval must be one of {:?} minutes, not {}",
+            SUPPORTED_INTERVALS_MINUTES, minutes
+        )));
+    }
+    connected(sensor, async {
+        sensor.write(ARANET4_COMMAND_UUID, &[0x90, minutes]).await?;
+        let interval = get_update_interval(sensor).await?;
+        if interval != minutes as u16 * 60 {
+            return Err(Aranet4Error::InvalidResponse(format!(
+                "Sensor reports a {} s interval after setting {} min",
+                interval, minutes
+            )));
+        }
+        Ok(interval)
+    })
+    .await
+}
+
+#[instrument(level = "debug", skip_all)]
+pub async fn get_current_sensor_data<S: SensorTransport>(
+    sensor: &S,
 ) -> Result<(String, CurrentSensorMeasurement), Aranet4Error> {
-    let local_name = get_local_name(sensor).await.unwrap();
+    let local_name = sensor.local_name().await.unwrap_or_default();
 
-    // connect to the device
+    // instantaneous measurement for nice printing
+    let measurement_bytes = connected(sensor, sensor.read(ARANET4_CURRENT_READINGS_UUID)).await?;
+    Ok((local_name, decode_current_reading(&measurement_bytes)?))
+}
+
+/// Current readings as the sensor notifies them; see [`subscribe_current_readings`].
+pub type CurrentReadingStream =
+    Pin<Box<dyn Stream<Item = Result<CurrentSensorMeasurement, Aranet4Error>> + Send>>;
+
+/// Subscribe to the current readings the Aranet4 notifies after each measurement. Unlike the
+/// other operations, this leaves the sensor connected, as the notifications stop with the
+/// connection; hold it, e.g. with [`crate::transport::ConnectedSensor::hold`], and
+/// unsubscribe once done. The stream skips notifications of other characteristics, such as
+/// history packets.
+#[instrument(level = "debug", skip_all)]
+pub async fn subscribe_current_readings<S: SensorTransport>(
+    sensor: &S,
+) -> Result<CurrentReadingStream, Aranet4Error> {
     sensor.connect().await?;
+    sensor
+        .subscribe(ARANET4_NOTIFY_CURRENT_READINGS_UUID)
+        .await?;
+    let notifications = sensor.notifications().await?;
+    Ok(Box::pin(notifications.filter_map(|notification| {
+        (notification.uuid == ARANET4_NOTIFY_CURRENT_READINGS_UUID)
+            .then(|| decode_current_reading(&notification.value))
+    })))
+}
 
-    // discover services and characteristics
-    sensor.discover_services().await?;
+fn decode_current_reading(bytes: &[u8]) -> Result<CurrentSensorMeasurement, Aranet4Error> {
+    let bytes: [u8; 13] = bytes.try_into().map_err(|_| {
+        Aranet4Error::InvalidResponse("Unexpected current measurement length".to_string())
+    })?;
+    Ok(bytes.into())
+}
 
-    // instantaneous measurement for nice printing
-    let co2_char = get_characteristic(sensor, ARANET4_CURRENT_READINGS_UUID)?;
-    let measurement_bytes = sensor.read(&co2_char).await?;
-    if measurement_bytes.len() != 13 {
-        return Err(Aranet4Error::InvalidResponse(
-            "Unexpected current measurement length".to_string(),
-        ));
+/// Which Aranet model `sensor` is, from its advertised name or, if that has been changed, its
+/// model number.
+pub async fn get_model<S: SensorTransport>(sensor: &S) -> Result<AranetModel> {
+    if let Some(model) = sensor
+        .local_name()
+        .await
+        .as_deref()
+        .and_then(AranetModel::detect)
+    {
+        return Ok(model);
     }
-    let measurement_bytes: [u8; 13] = measurement_bytes[..13].try_into().unwrap();
-    Ok((local_name, measurement_bytes.into()))
+    let model_number = connected(
+        sensor,
+        get_string(sensor, GENERIC_GATT_DEVICE_MODEL_NUMBER_STRING_UUID),
+    )
+    .await?;
+    AranetModel::detect(&model_number)
+        .ok_or(eyre!("Unknown Aranet model number '{}'", model_number))
+}
+
+pub(crate) async fn read_model_reading<S: SensorTransport>(
+    sensor: &S,
+    model: AranetModel,
+) -> Result<ModelMeasurement, Aranet4Error> {
+    let uuid = match model {
+        AranetModel::Aranet4 => ARANET4_CURRENT_READINGS_UUID,
+        _ => ARANET_CURRENT_READINGS_DETAILED_UUID,
+    };
+    ModelMeasurement::decode(model, &sensor.read(uuid).await?)
 }
 
-fn get_characteristic(
-    sensor: &Peripheral,
-    char_uuid: Uuid,
-) -> Result<Characteristic, Aranet4Error> {
-    let chars = sensor.characteristics();
-    chars
-        .iter()
-        .find(|c| c.uuid == char_uuid)
-        .cloned()
-        .ok_or(Aranet4Error::CharacteristicNotFound)
+/// Like [`get_current_sensor_data`], for any Aranet model.
+#[instrument(level = "debug", skip(sensor))]
+pub async fn get_model_sensor_data<S: SensorTransport>(
+    sensor: &S,
+    model: AranetModel,
+) -> Result<ModelMeasurement, Aranet4Error> {
+    connected(sensor, read_model_reading(sensor, model)).await
 }
 
-async fn get_single_history_type<T, const SENSORTYPE: u8>(
-    sensor: &Peripheral,
+/// How often to retry a failed sensor operation. Between attempts the sensor is disconnected,
+/// so that the retry starts from a fresh connection.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt; 0 disables retrying.
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each subsequent one.
+    pub delay: Duration,
+}
+
+impl RetryPolicy {
+    pub const NONE: RetryPolicy = RetryPolicy {
+        retries: 0,
+        delay: Duration::ZERO,
+    };
+
+    pub(crate) fn delay_before_retry(&self, retry: u32) -> Duration {
+        self.delay.saturating_mul(2u32.saturating_pow(retry))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            retries: 2,
+            delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Run `op` until it succeeds or `policy` is exhausted. `op` must (re)connect to the sensor
+/// itself, as every operation in this module does.
+pub async fn with_retries<S, T, F, Fut>(
+    sensor: &S,
+    policy: RetryPolicy,
+    mut op: F,
+) -> Result<T, Aranet4Error>
+where
+    S: SensorTransport,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Aranet4Error>>,
+{
+    let mut retry = 0;
+    loop {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(e) if retry < policy.retries && e.is_retryable() => {
+                match std::error::Error::source(&e) {
+                    Some(source) => warn!(
+                        "{}: {}; retrying ({}/{})",
+                        e,
+                        source,
+                        retry + 1,
+                        policy.retries
+                    ),
+                    None => warn!("{}; retrying ({}/{})", e, retry + 1, policy.retries),
+                }
+                // The link may be half-dead; drop it so the next attempt reconnects cleanly.
+                let _ = sensor.disconnect().await;
+                tokio::time::sleep(policy.delay_before_retry(retry)).await;
+                retry += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Default deadline for a single sensor operation, such as downloading one metric's history.
+pub const DEFAULT_OP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Fail with [`Aranet4Error::Timeout`] if `op` doesn't finish within `timeout`. Notification
+/// streams in particular never end on their own if the sensor stops sending.
+pub async fn with_timeout<T, Fut>(timeout: Duration, what: &str, op: Fut) -> Result<T, Aranet4Error>
+where
+    Fut: Future<Output = Result<T, Aranet4Error>>,
+{
+    tokio::time::timeout(timeout, op).await.map_err(|_| {
+        Aranet4Error::Timeout(format!("after {:.1} s {}", timeout.as_secs_f64(), what))
+    })?
+}
+
+/// How far along a history download is, reported after every received packet.
+#[derive(Debug, Clone, Copy)]
+pub struct HistoryProgress {
+    /// Label of the metric currently being downloaded.
+    pub metric: &'static str,
+    /// Which of the `num_metrics` metrics is being downloaded, starting at 0.
+    pub metric_index: usize,
+    pub num_metrics: usize,
+    /// Readings of the current metric received so far, out of `num_readings`.
+    pub received: usize,
+    pub num_readings: usize,
+    /// Bytes in the notification just received, header included. The largest a download gets
+    /// is set by the ATT MTU negotiated when connecting, three bytes more.
+    pub packet_len: usize,
+}
+
+impl HistoryProgress {
+    pub fn is_complete(&self) -> bool {
+        self.metric_index + 1 == self.num_metrics && self.received == self.num_readings
+    }
+}
+
+/// How the metrics of a history download are requested from the sensor.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HistoryTransfer {
+    /// One metric after the other, each with its own request and subscription.
+    #[default]
+    Sequential,
+    /// All four requests back to back under one subscription, with the notifications sorted by
+    /// the type code in their headers. Whatever this fails to deliver, e.g. because the firmware
+    /// only serves one request at a time, is then downloaded sequentially.
+    Interleaved,
+}
+
+/// Callback for history download progress; see [`HistoryProgress`].
+pub type ProgressFn<'a> = &'a (dyn Fn(HistoryProgress) + Send + Sync);
+
+/// What earlier attempts at downloading one metric received, so that a retry only requests the
+/// readings that are still missing instead of starting over.
+#[derive(Debug, Default)]
+struct PartialHistory {
+    /// 1-based index of the first element of `bytes`.
+    start_index: u16,
+    /// The elements received so far without a gap.
+    bytes: Vec<u8>,
+    /// The sensor's reading count when the download was last requested.
+    total_readings: u16,
+}
+
+impl PartialHistory {
+    /// 1-based index of the first reading not received yet.
+    fn next_index(&self, bytes_per_elem: usize) -> u16 {
+        self.start_index + (self.bytes.len() / bytes_per_elem) as u16
+    }
+
+    /// Put a packet's elements in place by the index in its header. Packets re-sent after a
+    /// resumed request may overlap what was received, and must agree with it; a packet starting
+    /// past the next index means some were lost, which is an error to retry from.
+    fn add_packet(
+        &mut self,
+        header: &HistoryResponseHeader,
+        elements: &[u8],
+        bytes_per_elem: usize,
+    ) -> Result<(), Aranet4Error> {
+        let num_elems = header.packet_num_elem as usize;
+        if elements.len() < num_elems * bytes_per_elem {
+            return Err(Aranet4Error::InvalidResponse(format!(
+                "History packet at index {} is shorter than its {} elements",
+                header.start_index, num_elems
+            )));
+        }
+        let end_index = header.start_index as usize + num_elems;
+        if header.start_index < self.start_index || end_index > self.total_readings as usize + 1 {
+            return Err(Aranet4Error::InvalidResponse(format!(
+                "History packet for readings {}..{} is outside the requested {}..={}",
+                header.start_index, end_index, self.start_index, self.total_readings
+            )));
+        }
+        let next_index = self.next_index(bytes_per_elem);
+        if header.start_index > next_index {
+            return Err(Aranet4Error::InvalidResponse(format!(
+                "Missing history readings {}..{}",
+                next_index, header.start_index
+            )));
+        }
+        let elements = &elements[..num_elems * bytes_per_elem];
+        let offset = (header.start_index - self.start_index) as usize * bytes_per_elem;
+        let overlap = (self.bytes.len() - offset).min(elements.len());
+        if self.bytes[offset..offset + overlap] != elements[..overlap] {
+            return Err(Aranet4Error::InvalidResponse(format!(
+                "History packet at index {} disagrees with readings already received",
+                header.start_index
+            )));
+        }
+        self.bytes.extend_from_slice(&elements[overlap..]);
+        Ok(())
+    }
+}
+
+/// Download readings `start_index..=total_readings` (1-based, oldest first) of one metric,
+/// where `total_readings` is the sensor's reading count unless given. `metric_index` and
+/// `num_metrics` are only used for progress reports.
+///
+/// Received readings are kept in `partial` as they arrive, so calling this again with the same
+/// `partial` after a dropped connection or a timeout resumes the download.
+async fn get_single_history_type<S, T, const SENSORTYPE: u8>(
+    sensor: &S,
+    start_index: u16,
+    total_readings: Option<u16>,
+    metric_index: usize,
+    num_metrics: usize,
+    progress: ProgressFn<'_>,
+    partial: &Mutex<PartialHistory>,
 ) -> Result<SensorData<T, SENSORTYPE>, Aranet4Error>
 where
+    S: SensorTransport,
     SensorData<T, SENSORTYPE>: Metadata + for<'a> TryFrom<&'a [u8], Error = Aranet4Error>,
 {
-    // connect to the device
+    // connect to the device and discover services and characteristics
     sensor.connect().await?;
 
-    // discover services and characteristics
-    sensor.discover_services().await?;
-
-    let subscribe_char = get_characteristic(sensor, ARANET4_NOTIFY_HISTORY_UUID)?;
-    let command_char = get_characteristic(sensor, ARANET4_COMMAND_UUID)?;
-    if !subscribe_char.properties.contains(CharPropFlags::NOTIFY) {
+    // Perform the arcane ritual
+    let total_readings = match total_readings {
+        Some(total_readings) => total_readings,
+        None => get_total_readings(sensor).await?,
+    };
+    if start_index == 0 {
         return Err(Aranet4Error::InvalidResponse(
-            "No NOTIFY flag on subscribe characteristic!".to_string(),
+            "History start index is 1-based".to_string(),
         ));
     }
-
-    // Perform the arcane ritual
-    let total_readings = get_total_readings(sensor).await?;
+    if start_index > total_readings {
+        return Ok(SensorData { values: Vec::new() });
+    }
+    let num_readings = (total_readings - start_index + 1) as usize;
+    let bytes_per_elem = size_of::<T>();
+    let request_index = {
+        let mut partial = partial.lock().unwrap();
+        // The sensor only appends readings, so what arrived before is still valid unless its
+        // log was cleared in the meantime
+        if total_readings < partial.total_readings || partial.start_index != start_index {
+            *partial = PartialHistory {
+                start_index,
+                ..Default::default()
+            };
+        }
+        partial.total_readings = total_readings;
+        partial.next_index(bytes_per_elem)
+    };
+    if request_index > start_index {
+        debug!(
+            metric = SensorData::<T, SENSORTYPE>::LABEL,
+            request_index, total_readings, "Resuming interrupted history download"
+        );
+    } else {
+        debug!(
+            metric = SensorData::<T, SENSORTYPE>::LABEL,
+            start_index, num_readings, "Requesting history"
+        );
+    }
     let get_history_command_bytes: &[u8] = &[
         0x82,
         SENSORTYPE,
         0x00,
         0x00,
-        0x01,
-        0x00,
+        (request_index & 0xFF) as u8,
+        (request_index >> 8) as u8,
         (total_readings & 0xFF) as u8,
         (total_readings >> 8) as u8,
     ];
-    sensor.unsubscribe(&subscribe_char).await?;
-    sensor
-        .write(
-            &command_char,
-            get_history_command_bytes,
-            WriteType::WithResponse,
+    // The previous attempt may have lost the connection after receiving everything
+    let complete = || partial.lock().unwrap().bytes.len() >= bytes_per_elem * num_readings;
+    if !complete() {
+        sensor.unsubscribe(ARANET4_NOTIFY_HISTORY_UUID).await?;
+        sensor
+            .write_request(ARANET4_COMMAND_UUID, get_history_command_bytes)
+            .await?;
+        sensor.subscribe(ARANET4_NOTIFY_HISTORY_UUID).await?;
+        receive_history_packets::<S, T, SENSORTYPE>(
+            sensor,
+            metric_index,
+            num_metrics,
+            num_readings,
+            progress,
+            partial,
         )
         .await?;
-    sensor.subscribe(&subscribe_char).await?;
+        sensor.unsubscribe(ARANET4_NOTIFY_HISTORY_UUID).await?;
+    }
+    let history_bytes = {
+        let mut partial = partial.lock().unwrap();
+        if partial.bytes.len() != bytes_per_elem * num_readings {
+            return Err(Aranet4Error::InvalidResponse(
+                "Received unexpected number of bytes".to_string(),
+            ));
+        }
+        std::mem::take(&mut partial.bytes)
+    };
+
+    let history_data = history_bytes[..].try_into()?;
+    Ok(history_data)
+}
 
+/// Collect history notifications into `partial` until it holds `num_readings` elements or the
+/// stream ends.
+async fn receive_history_packets<S, T, const SENSORTYPE: u8>(
+    sensor: &S,
+    metric_index: usize,
+    num_metrics: usize,
+    num_readings: usize,
+    progress: ProgressFn<'_>,
+    partial: &Mutex<PartialHistory>,
+) -> Result<(), Aranet4Error>
+where
+    S: SensorTransport,
+    SensorData<T, SENSORTYPE>: Metadata,
+{
     // Now get that sweet, sweet data
     let bytes_per_elem = size_of::<T>();
     let mut notification_stream = sensor.notifications().await?;
-    let mut history_bytes = Vec::new();
     while let Some(data) = notification_stream.next().await {
-        if data.uuid != ARANET4_NOTIFY_HISTORY_UUID {
-            return Err(Aranet4Error::InvalidResponse(
-                "Expected notification UUID to match ARANET4_NOTIFY_HISTORY_UUID".to_string(),
-            ));
-        }
-        if data.value.len() < 4 {
-            return Err(Aranet4Error::InvalidResponse(
-                "Expected at least 4 bytes for the header".to_string(),
-            ));
-        }
-        let header_bytes: [u8; 4] = data.value[..4].try_into().unwrap();
-        if header_bytes[0] != SENSORTYPE {
-            return Err(Aranet4Error::InvalidResponse(
-                "History type doesn't match what we requested".to_string(),
-            ));
-        }
-        let header = HistoryResponseHeader::from(header_bytes);
-        let bytes_end = 4 + bytes_per_elem * (header.packet_num_elem as usize);
-        history_bytes.extend_from_slice(&data.value[4..bytes_end]);
-        if history_bytes.len() >= bytes_per_elem * (total_readings as usize) {
+        let Some((header, payload)) = history_packet(&data, |type_code| type_code == SENSORTYPE)?
+        else {
+            continue;
+        };
+        trace!(
+            start_index = header.start_index,
+            elements = header.packet_num_elem,
+            "History packet"
+        );
+        let received = {
+            let mut partial = partial.lock().unwrap();
+            partial.add_packet(&header, payload, bytes_per_elem)?;
+            partial.bytes.len() / bytes_per_elem
+        };
+        progress(HistoryProgress {
+            metric: SensorData::<T, SENSORTYPE>::LABEL,
+            metric_index,
+            num_metrics,
+            received: received.min(num_readings),
+            num_readings,
+            packet_len: data.value.len(),
+        });
+        if received >= num_readings {
             break;
         }
     }
-    sensor.unsubscribe(&subscribe_char).await?;
-    if history_bytes.len() != bytes_per_elem * (total_readings as usize) {
+    Ok(())
+}
+
+/// The header and payload of `notification` if it's a history packet of a type that's
+/// `wanted`. The sensor notifies every subscribed characteristic on the same stream, so
+/// notifications of others, such as current readings, are skipped, as are packets of a type
+/// that wasn't requested, e.g. left over from an earlier request.
+fn history_packet(
+    notification: &ValueNotification,
+    wanted: impl Fn(u8) -> bool,
+) -> Result<Option<(HistoryResponseHeader, &[u8])>, Aranet4Error> {
+    if notification.uuid != ARANET4_NOTIFY_HISTORY_UUID {
+        trace!(uuid = %notification.uuid, "Skipping notification of another characteristic");
+        return Ok(None);
+    }
+    let Some((header, payload)) = notification.value.split_first_chunk::<4>() else {
         return Err(Aranet4Error::InvalidResponse(
-            "Received unexpected number of bytes".to_string(),
+            "Expected at least 4 bytes for the header".to_string(),
         ));
+    };
+    let header = HistoryResponseHeader::from(*header);
+    if !wanted(header.type_code) {
+        debug!(
+            type_code = header.type_code,
+            "Skipping history packet of a type that wasn't requested"
+        );
+        return Ok(None);
     }
+    Ok(Some((header, payload)))
+}
 
-    let history_data = history_bytes[..].try_into()?;
-    Ok(history_data)
+/// Element sizes of the Aranet4's history types 1 to 4: temperature, humidity, pressure and CO₂.
+const ARANET4_HISTORY_ELEMENT_SIZES: [usize; 4] = [2, 1, 2, 2];
+
+/// Request every one of `metrics` not yet in `partials` at once and sort the notifications into
+/// `partials` by their type code, so that the sensor doesn't wait on a round trip per metric.
+/// Progress is reported for all metrics together.
+async fn get_interleaved_history<S: SensorTransport>(
+    sensor: &S,
+    start_index: u16,
+    total_readings: u16,
+    progress: ProgressFn<'_>,
+    partials: &[Mutex<PartialHistory>; 4],
+    metrics: &[HistoryMetric],
+) -> Result<(), Aranet4Error> {
+    sensor.connect().await?;
+    if start_index == 0 || start_index > total_readings {
+        return Ok(());
+    }
+    let num_readings = (total_readings - start_index + 1) as usize;
+    let received = |partial: &PartialHistory, bytes_per_elem: usize| {
+        (partial.bytes.len() / bytes_per_elem).min(num_readings)
+    };
+    let wanted = |i: usize| metrics.contains(&HistoryMetric::ALL[i]);
+    let num_metrics = (0..partials.len()).filter(|&i| wanted(i)).count();
+    sensor.unsubscribe(ARANET4_NOTIFY_HISTORY_UUID).await?;
+    let mut requested = 0;
+    for (i, partial) in partials.iter().enumerate() {
+        if !wanted(i) {
+            continue;
+        }
+        let bytes_per_elem = ARANET4_HISTORY_ELEMENT_SIZES[i];
+        let request_index = {
+            let mut partial = partial.lock().unwrap();
+            if total_readings < partial.total_readings || partial.start_index != start_index {
+                *partial = PartialHistory {
+                    start_index,
+                    ..Default::default()
+                };
+            }
+            partial.total_readings = total_readings;
+            if received(&partial, bytes_per_elem) == num_readings {
+                continue;
+            }
+            partial.next_index(bytes_per_elem)
+        };
+        let [request_lo, request_hi] = request_index.to_le_bytes();
+        let [total_lo, total_hi] = total_readings.to_le_bytes();
+        let command = [
+            0x82,
+            HistoryMetric::ALL[i].kind().code(),
+            0x00,
+            0x00,
+            request_lo,
+            request_hi,
+            total_lo,
+            total_hi,
+        ];
+        sensor.write_request(ARANET4_COMMAND_UUID, &command).await?;
+        requested += 1;
+    }
+    debug!(
+        start_index,
+        num_readings, requested, "Requested interleaved history"
+    );
+    if requested == 0 {
+        return Ok(());
+    }
+    sensor.subscribe(ARANET4_NOTIFY_HISTORY_UUID).await?;
+    let mut notification_stream = sensor.notifications().await?;
+    let index = |type_code: u8| {
+        (type_code as usize)
+            .checked_sub(1)
+            .filter(|&i| i < partials.len() && wanted(i))
+    };
+    while let Some(data) = notification_stream.next().await {
+        let Some((header, payload)) =
+            history_packet(&data, |type_code| index(type_code).is_some())?
+        else {
+            continue;
+        };
+        let i = index(header.type_code).unwrap();
+        partials[i].lock().unwrap().add_packet(
+            &header,
+            payload,
+            ARANET4_HISTORY_ELEMENT_SIZES[i],
+        )?;
+        let received: usize = partials
+            .iter()
+            .zip(ARANET4_HISTORY_ELEMENT_SIZES)
+            .enumerate()
+            .filter(|&(i, _)| wanted(i))
+            .map(|(_, (partial, size))| received(&partial.lock().unwrap(), size))
+            .sum();
+        progress(HistoryProgress {
+            metric: "All metrics",
+            metric_index: 0,
+            num_metrics: 1,
+            received,
+            num_readings: num_metrics * num_readings,
+            packet_len: data.value.len(),
+        });
+        if received == num_metrics * num_readings {
+            break;
+        }
+    }
+    sensor.unsubscribe(ARANET4_NOTIFY_HISTORY_UUID).await?;
+    Ok(())
 }
 
-pub async fn get_temperature_history(sensor: &Peripheral) -> Result<TemperatureData, Aranet4Error> {
-    get_single_history_type(sensor).await
+pub async fn get_temperature_history<S: SensorTransport>(
+    sensor: &S,
+) -> Result<TemperatureData, Aranet4Error> {
+    get_single_history_type(sensor, 1, None, 0, 1, &|_| {}, &Mutex::default()).await
 }
 
-pub async fn get_humidity_history(sensor: &Peripheral) -> Result<HumidityData, Aranet4Error> {
-    get_single_history_type(sensor).await
+pub async fn get_humidity_history<S: SensorTransport>(
+    sensor: &S,
+) -> Result<HumidityData, Aranet4Error> {
+    get_single_history_type(sensor, 1, None, 0, 1, &|_| {}, &Mutex::default()).await
 }
 
-pub async fn get_pressure_history(sensor: &Peripheral) -> Result<PressureData, Aranet4Error> {
-    get_single_history_type(sensor).await
+pub async fn get_pressure_history<S: SensorTransport>(
+    sensor: &S,
+) -> Result<PressureData, Aranet4Error> {
+    get_single_history_type(sensor, 1, None, 0, 1, &|_| {}, &Mutex::default()).await
 }
 
-pub async fn get_co2_history(sensor: &Peripheral) -> Result<CO2Data, Aranet4Error> {
-    get_single_history_type(sensor).await
+pub async fn get_co2_history<S: SensorTransport>(sensor: &S) -> Result<CO2Data, Aranet4Error> {
+    get_single_history_type(sensor, 1, None, 0, 1, &|_| {}, &Mutex::default()).await
 }
 
-#[derive(Debug)]
+/// `timestamp` rounded to the nearest multiple of `update_interval`.
+pub fn snap_to_grid(timestamp: i64, update_interval: u16) -> i64 {
+    if update_interval == 0 {
+        return timestamp;
+    }
+    let interval = update_interval as i64;
+    (timestamp + interval / 2).div_euclid(interval) * interval
+}
+
+#[derive(Debug, Clone)]
 pub struct HistoryTime {
     pub num_samples: usize,
     pub update_interval: u16,
     pub since_update: u16,
     pub now: DateTime<Utc>,
+    /// Seconds each reading was taken after its timestamp, which is nonzero once timestamps
+    /// are snapped to the measurement grid.
+    pub offset: i64,
 }
 
 impl HistoryTime {
-    pub async fn from_sensor(sensor: &Peripheral, num_samples: usize) -> Result<Self> {
-        Ok(HistoryTime {
+    /// The timing of the `num_samples` newest readings, with timestamps snapped to the
+    /// measurement grid.
+    pub async fn from_sensor<S: SensorTransport>(sensor: &S, num_samples: usize) -> Result<Self> {
+        let mut time = HistoryTime {
             num_samples,
             update_interval: get_update_interval(sensor).await?,
             since_update: get_time_since_update(sensor).await?,
             now: Utc::now(),
+            offset: 0,
+        };
+        time.snap_to_grid();
+        Ok(time)
+    }
+
+    /// Round the timestamps to multiples of the measurement interval. They are reconstructed
+    /// from the host clock, so without this the same reading gets a timestamp a second or so
+    /// different on every download. How far the readings really are from the grid is kept in
+    /// [`HistoryTime::offset`].
+    pub fn snap_to_grid(&mut self) {
+        let latest = self.now.timestamp() - self.since_update as i64;
+        let shift = latest - snap_to_grid(latest, self.update_interval);
+        self.now -= TimeDelta::seconds(shift);
+        self.offset += shift;
+    }
+
+    /// Undo [`HistoryTime::snap_to_grid`], going back to the timestamps as reconstructed.
+    pub fn use_raw_timestamps(&mut self) {
+        self.now += TimeDelta::seconds(self.offset);
+        self.offset = 0;
+    }
+
+    /// When the oldest reading was taken; with no readings, when the next one will be.
+    fn first_timestamp(&self) -> DateTime<Utc> {
+        self.now
+            - TimeDelta::seconds(
+                (self.num_samples as i64 - 1) * self.update_interval as i64
+                    + self.since_update as i64,
+            )
+    }
+
+    /// When reading `sample` was taken, counting from the oldest, or `None` past the newest.
+    pub fn timestamp_at(&self, sample: usize) -> Option<DateTime<Utc>> {
+        (sample < self.num_samples).then(|| {
+            self.first_timestamp() + TimeDelta::seconds(sample as i64 * self.update_interval as i64)
         })
     }
 
+    /// When each reading was taken, oldest first.
+    pub fn timestamps(&self) -> impl ExactSizeIterator<Item = DateTime<Utc>> {
+        let first = self.first_timestamp();
+        let interval = TimeDelta::seconds(self.update_interval as i64);
+        (0..self.num_samples).map(move |i| first + interval * i as i32)
+    }
+
+    /// Like [`HistoryTime::timestamp_at`], in UNIX time.
     pub fn get_timestamp(&self, sample: usize) -> Result<i64> {
-        if sample >= self.num_samples {
-            return Err(eyre!(
-                "Invalid sample index {} (# samples = {})",
-                sample,
-                self.num_samples
-            ));
-        }
-        let time = self.now
-            - TimeDelta::seconds(
-                (self.num_samples as i64 - sample as i64 - 1) * (self.update_interval as i64)
-                    + (self.since_update as i64),
-            );
-        Ok(time.timestamp())
+        self.timestamp_at(sample)
+            .map(|time| time.timestamp())
+            .ok_or_else(|| {
+                eyre!(
+                    "Invalid sample index {} (# samples = {})",
+                    sample,
+                    self.num_samples
+                )
+            })
     }
 
+    /// Every timestamp of [`HistoryTime::timestamps`] in UNIX time.
     pub fn to_vec(&self) -> Vec<i64> {
-        (0..self.num_samples)
-            .map(|i| self.get_timestamp(i).unwrap())
-            .collect()
+        self.timestamps().map(|time| time.timestamp()).collect()
     }
 }
 
 pub async fn get_local_name(peripheral: &Peripheral) -> Option<String> {
-    peripheral
-        .properties()
-        .await
-        .expect("expect property result")
-        .expect("expect some properties")
-        .local_name
+    match peripheral.properties().await {
+        Ok(properties) => properties?.local_name,
+        Err(e) => {
+            debug!("Could not read peripheral properties: {}", e);
+            None
+        }
+    }
+}
+
+/// The MAC address of a peripheral, or its platform ID where the address is hidden (macOS).
+pub async fn get_address(peripheral: &Peripheral) -> String {
+    match peripheral.properties().await {
+        Ok(Some(properties)) => peripheral_address(&peripheral.id(), &properties),
+        _ => peripheral.id().to_string(),
+    }
 }
 
-pub async fn scan_for_sensor(central: &Adapter, device_pattern: &str) -> Result<Peripheral> {
+pub const DEFAULT_SCAN_TIMEOUT: Duration = Duration::from_secs(5);
+
+async fn scan_matching<F>(
+    central: &Adapter,
+    matches: F,
+    first_only: bool,
+    timeout: Duration,
+) -> Result<Vec<Peripheral>>
+where
+    F: Fn(&PeripheralId, &PeripheralProperties) -> bool,
+{
     // Set global timeout as our main timeout mechanism, but also per-element
     // timeout since global timeout may not be evaluated if the Bluetooth
     // environment is very quiet and no events are generated.
-    const TIMEOUT: Duration = Duration::from_secs(5);
     let start = Instant::now();
-    let mut events = Box::pin(central.events().await?.timeout(TIMEOUT));
+    let mut events = Box::pin(central.events().await?.timeout(timeout));
     central
         .start_scan(ScanFilter {
             services: vec![ARANET4_SERVICE_UUID],
         })
-        .await
-        .unwrap();
+        .await?;
+    let mut found: Vec<Peripheral> = Vec::new();
     while let Ok(Some(event)) = events.try_next().await {
-        match event {
-            CentralEvent::DeviceDiscovered(id) => {
-                let peripheral = central.peripheral(&id).await?;
-                if let Some(local_name) = get_local_name(&peripheral).await {
-                    if local_name.contains(device_pattern) {
-                        return Ok(peripheral);
+        if let CentralEvent::DeviceDiscovered(id) = event {
+            let peripheral = central.peripheral(&id).await?;
+            if let Some(properties) = peripheral.properties().await? {
+                if matches(&id, &properties) && !found.iter().any(|p| p.id() == id) {
+                    debug!(id = %id, name = ?properties.local_name, rssi = ?properties.rssi, "Found sensor");
+                    found.push(peripheral);
+                    if first_only {
+                        break;
                     }
                 }
             }
-            _ => {}
         }
-        if Instant::now().duration_since(start) > TIMEOUT {
+        if Instant::now().duration_since(start) > timeout {
             break;
         }
     }
-    Err(eyre!("No device found before timeout"))
+    Ok(found)
+}
+
+/// Which sensors to select by name: those whose name contains a pattern, in any case unless
+/// asked otherwise, or matches a regular expression. Where no name matches, sensors are also
+/// selected by serial number.
+#[derive(Debug, Clone)]
+pub enum DevicePattern {
+    Substring {
+        pattern: String,
+        case_sensitive: bool,
+    },
+    Regex(Regex),
 }
 
-pub async fn get_history(
-    sensor: &Peripheral,
-) -> Result<(
-    HistoryTime,
-    TemperatureData,
-    HumidityData,
-    PressureData,
-    CO2Data,
-)> {
+impl DevicePattern {
+    pub fn substring(pattern: &str, case_sensitive: bool) -> Self {
+        DevicePattern::Substring {
+            pattern: pattern.to_string(),
+            case_sensitive,
+        }
+    }
+
+    pub fn regex(pattern: &str) -> Result<Self> {
+        let regex =
+            Regex::new(pattern).wrap_err_with(|| format!("Invalid device regex '{}'", pattern))?;
+        Ok(DevicePattern::Regex(regex))
+    }
+
+    /// Identifies the pattern in the [`crate::device_cache::DeviceCache`]; patterns that
+    /// match the same names have the same key.
+    pub fn cache_key(&self) -> String {
+        match self {
+            DevicePattern::Substring {
+                pattern,
+                case_sensitive: true,
+            } => format!("name:{}", pattern),
+            DevicePattern::Substring { pattern, .. } => format!("iname:{}", pattern.to_lowercase()),
+            DevicePattern::Regex(regex) => format!("regex:{}", regex),
+        }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        match self {
+            DevicePattern::Substring {
+                pattern,
+                case_sensitive: true,
+            } => text.contains(pattern.as_str()),
+            DevicePattern::Substring { pattern, .. } => {
+                text.to_lowercase().contains(&pattern.to_lowercase())
+            }
+            DevicePattern::Regex(regex) => regex.is_match(text),
+        }
+    }
+}
+
+impl fmt::Display for DevicePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DevicePattern::Substring { pattern, .. } => write!(f, "'{}'", pattern),
+            DevicePattern::Regex(regex) => write!(f, "/{}/", regex),
+        }
+    }
+}
+
+fn name_matches(properties: &PeripheralProperties, device_pattern: &DevicePattern) -> bool {
+    properties
+        .local_name
+        .as_ref()
+        .is_some_and(|name| device_pattern.matches(name))
+}
+
+/// The Aranet sensors `central` has seen, e.g. in a scan that found none matching.
+pub(crate) async fn seen_sensors(
+    central: &Adapter,
+) -> Result<Vec<(Peripheral, PeripheralProperties)>> {
+    let mut seen = Vec::new();
+    for peripheral in central.peripherals().await? {
+        let Some(properties) = peripheral.properties().await? else {
+            continue;
+        };
+        if properties.services.contains(&ARANET4_SERVICE_UUID)
+            || properties
+                .manufacturer_data
+                .contains_key(&SAF_TEHNIKA_MANUFACTURER_ID)
+        {
+            seen.push((peripheral, properties));
+        }
+    }
+    Ok(seen)
+}
+
+/// The sensors in `seen` whose serial number matches `device_pattern`. Serial numbers aren't
+/// advertised, so each sensor is connected to in turn; that's slow, so it's only done when
+/// no name matches.
+async fn serial_matches(
+    seen: &[(Peripheral, PeripheralProperties)],
+    device_pattern: &DevicePattern,
+    first_only: bool,
+) -> Vec<Peripheral> {
+    let mut found = Vec::new();
+    for (peripheral, _) in seen {
+        match get_serial_number(peripheral).await {
+            Ok(serial) if device_pattern.matches(serial.trim()) => {
+                debug!(id = %peripheral.id(), serial = serial.trim(), "Found sensor by serial number");
+                found.push(peripheral.clone());
+                if first_only {
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(e) => debug!(id = %peripheral.id(), "Could not read serial number: {:#}", e),
+        }
+    }
+    found
+}
+
+/// The MAC address of a peripheral, or its platform ID where the address is hidden (macOS).
+pub(crate) fn peripheral_address(id: &PeripheralId, properties: &PeripheralProperties) -> String {
+    if properties.address.into_inner() == [0; 6] {
+        id.to_string()
+    } else {
+        properties.address.to_string()
+    }
+}
+
+pub(crate) fn address_matches(
+    id: &PeripheralId,
+    properties: &PeripheralProperties,
+    address: &str,
+) -> bool {
+    peripheral_address(id, properties).eq_ignore_ascii_case(address)
+        || id.to_string().eq_ignore_ascii_case(address)
+}
+
+/// The error for a scan that found nothing matching, listing the sensors it did see so that
+/// the pattern can be corrected.
+fn no_device_found(
+    device_pattern: &DevicePattern,
+    timeout: Duration,
+    seen: &[(Peripheral, PeripheralProperties)],
+) -> Aranet4Error {
+    let candidates = if seen.is_empty() {
+        "no Aranet sensors were seen at all".to_string()
+    } else {
+        let names: Vec<String> = seen
+            .iter()
+            .map(|(peripheral, properties)| {
+                format!(
+                    "{} ({})",
+                    properties.local_name.as_deref().unwrap_or("<no name>"),
+                    peripheral_address(&peripheral.id(), properties)
+                )
+            })
+            .collect();
+        format!("sensors seen: {}", names.join(", "))
+    };
+    Aranet4Error::DeviceNotFound(format!(
+        "No device matching {} found within {:.1} s; {}",
+        device_pattern,
+        timeout.as_secs_f64(),
+        candidates
+    ))
+}
+
+/// Scan for the first sensor whose name matches `device_pattern`, or failing that, whose
+/// serial number does.
+#[instrument(skip(central))]
+pub async fn scan_for_sensor(
+    central: &Adapter,
+    device_pattern: &DevicePattern,
+    timeout: Duration,
+) -> Result<Peripheral> {
+    let matches = |_: &PeripheralId, properties: &PeripheralProperties| {
+        name_matches(properties, device_pattern)
+    };
+    if let Some(peripheral) = scan_matching(central, matches, true, timeout)
+        .await?
+        .into_iter()
+        .next()
+    {
+        return Ok(peripheral);
+    }
+    let seen = seen_sensors(central).await?;
+    Ok(serial_matches(&seen, device_pattern, true)
+        .await
+        .into_iter()
+        .next()
+        .ok_or(no_device_found(device_pattern, timeout, &seen))?)
+}
+
+/// Scan for the full timeout and return every sensor whose name matches `device_pattern`, or
+/// failing that, whose serial number does.
+#[instrument(skip(central))]
+pub async fn scan_for_all_sensors(
+    central: &Adapter,
+    device_pattern: &DevicePattern,
+    timeout: Duration,
+) -> Result<Vec<Peripheral>> {
+    let matches = |_: &PeripheralId, properties: &PeripheralProperties| {
+        name_matches(properties, device_pattern)
+    };
+    let found = scan_matching(central, matches, false, timeout).await?;
+    if !found.is_empty() {
+        return Ok(found);
+    }
+    let seen = seen_sensors(central).await?;
+    let found = serial_matches(&seen, device_pattern, false).await;
+    if found.is_empty() {
+        return Err(no_device_found(device_pattern, timeout, &seen).into());
+    }
+    Ok(found)
+}
+
+/// An Aranet device seen while scanning, as listed by the `scan` subcommand.
+#[derive(Debug, Clone)]
+pub struct NearbyDevice {
+    /// MAC address, or the platform peripheral ID where the address is hidden (macOS).
+    pub id: String,
+    pub local_name: Option<String>,
+    pub rssi: Option<i16>,
+    /// Only advertised when Smart Home integration is enabled on the sensor; see
+    /// [`crate::advertisement`].
+    pub reading: Option<CurrentSensorMeasurement>,
+}
+
+/// A rough rating of a signal strength in dBm. Below "fair", expect dropped connections and
+/// retried downloads; moving the sensor or adapter a little often helps.
+pub fn signal_quality(rssi: i16) -> &'static str {
+    match rssi {
+        -60.. => "excellent",
+        -70..=-61 => "good",
+        -80..=-71 => "fair",
+        _ => "poor",
+    }
+}
+
+/// Scan for `timeout` and return every device advertising the Aranet4 service, strongest
+/// signal first.
+#[instrument(skip(central))]
+pub async fn scan_nearby(central: &Adapter, timeout: Duration) -> Result<Vec<NearbyDevice>> {
+    central
+        .start_scan(ScanFilter {
+            services: vec![ARANET4_SERVICE_UUID],
+        })
+        .await?;
+    tokio::time::sleep(timeout).await;
+    central.stop_scan().await?;
+    let mut found = Vec::new();
+    for (peripheral, properties) in seen_sensors(central).await? {
+        let id = peripheral_address(&peripheral.id(), &properties);
+        let reading = advertised_reading(&properties.manufacturer_data);
+        found.push(NearbyDevice {
+            id,
+            local_name: properties.local_name,
+            rssi: properties.rssi,
+            reading,
+        });
+    }
+    found.sort_by_key(|device| std::cmp::Reverse(device.rssi));
+    Ok(found)
+}
+
+/// Scan for the sensor with the given MAC address or platform peripheral ID, ignoring its name.
+#[instrument(skip(central))]
+pub async fn scan_for_address(
+    central: &Adapter,
+    address: &str,
+    timeout: Duration,
+) -> Result<Peripheral> {
+    let matches = |id: &PeripheralId, properties: &PeripheralProperties| {
+        address_matches(id, properties, address)
+    };
+    Ok(scan_matching(central, matches, true, timeout)
+        .await?
+        .into_iter()
+        .next()
+        .ok_or(Aranet4Error::DeviceNotFound(format!(
+            "No device with address {} found within {:.1} s",
+            address,
+            timeout.as_secs_f64()
+        )))?)
+}
+
+/// Look up a peripheral the adapter already knows about, e.g. from an earlier scan or because
+/// it is paired, by its platform peripheral ID. No scan is started.
+pub async fn find_known_peripheral(central: &Adapter, id: &str) -> Result<Option<Peripheral>> {
+    Ok(central
+        .peripherals()
+        .await?
+        .into_iter()
+        .find(|peripheral| peripheral.id().to_string() == id))
+}
+
+pub async fn get_serial_number<S: SensorTransport>(sensor: &S) -> Result<String> {
+    connected(
+        sensor,
+        get_string(sensor, GENERIC_GATT_SERIAL_NUMBER_STRING_UUID),
+    )
+    .await
+}
+
+/// The metrics an Aranet4 logs, in the order they are downloaded and archived.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HistoryMetric {
+    Temperature,
+    Humidity,
+    Pressure,
+    Co2,
+}
+
+impl HistoryMetric {
+    pub const ALL: [HistoryMetric; 4] = [
+        HistoryMetric::Temperature,
+        HistoryMetric::Humidity,
+        HistoryMetric::Pressure,
+        HistoryMetric::Co2,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        HistoryMetric::ALL
+            .into_iter()
+            .find(|metric| metric.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            HistoryMetric::Temperature => "temperature",
+            HistoryMetric::Humidity => "humidity",
+            HistoryMetric::Pressure => "pressure",
+            HistoryMetric::Co2 => "co2",
+        }
+    }
+
+    /// `metrics` in the order of [`HistoryMetric::ALL`], without repeats.
+    pub fn in_order(metrics: &[HistoryMetric]) -> Vec<HistoryMetric> {
+        HistoryMetric::ALL
+            .into_iter()
+            .filter(|metric| metrics.contains(metric))
+            .collect()
+    }
+
+    pub fn kind(self) -> MetricKind {
+        match self {
+            HistoryMetric::Temperature => MetricKind::Temperature,
+            HistoryMetric::Humidity => MetricKind::Humidity,
+            HistoryMetric::Pressure => MetricKind::Pressure,
+            HistoryMetric::Co2 => MetricKind::Co2,
+        }
+    }
+}
+
+/// A downloaded history. Metrics that weren't requested have no values.
+#[derive(Debug, Clone)]
+pub struct History {
+    pub time: HistoryTime,
+    pub temperature: TemperatureData,
+    pub humidity: HumidityData,
+    pub pressure: PressureData,
+    pub co2: CO2Data,
+}
+
+impl History {
+    /// Drop the readings taken after `until` (UNIX time).
+    pub fn truncate_after(&mut self, until: i64) -> Result<()> {
+        let keep = self
+            .time
+            .timestamps()
+            .take_while(|time| time.timestamp() <= until)
+            .count();
+        let dropped = self.time.num_samples - keep;
+        // Timestamps count back from the newest reading, which is now the last one kept
+        self.time.now -= TimeDelta::seconds(dropped as i64 * self.time.update_interval as i64);
+        self.time.num_samples = keep;
+        self.temperature.values.truncate(keep);
+        self.humidity.values.truncate(keep);
+        self.pressure.values.truncate(keep);
+        self.co2.values.truncate(keep);
+        Ok(())
+    }
+
+    /// The values of `kind`, or `None` if an Aranet4 doesn't log it. They are empty if the
+    /// metric wasn't downloaded.
+    pub fn series(&self, kind: MetricKind) -> Option<HistorySeries> {
+        match kind {
+            MetricKind::Temperature => Some(HistorySeries::from(&self.temperature)),
+            MetricKind::Humidity => Some(HistorySeries::from(&self.humidity)),
+            MetricKind::Pressure => Some(HistorySeries::from(&self.pressure)),
+            MetricKind::Co2 => Some(HistorySeries::from(&self.co2)),
+            MetricKind::Humidity2 | MetricKind::Radon => None,
+        }
+    }
+}
+
+/// History of an Aranet2 or Aranet Radon Plus. Both log temperature and humidity, the latter in
+/// 1/10 %; only the Radon Plus logs pressure and radon.
+#[derive(Debug)]
+pub struct ModelHistory {
+    pub model: AranetModel,
+    pub time: HistoryTime,
+    pub temperature: TemperatureData,
+    pub humidity: Humidity2Data,
+    pub pressure: Option<PressureData>,
+    pub radon: Option<RadonData>,
+}
+
+impl ModelHistory {
+    /// The values of `kind`, or `None` if this model doesn't log it.
+    pub fn series(&self, kind: MetricKind) -> Option<HistorySeries> {
+        match kind {
+            MetricKind::Temperature => Some(HistorySeries::from(&self.temperature)),
+            MetricKind::Humidity2 => Some(HistorySeries::from(&self.humidity)),
+            MetricKind::Pressure => self.pressure.as_ref().map(HistorySeries::from),
+            MetricKind::Radon => self.radon.as_ref().map(HistorySeries::from),
+            MetricKind::Humidity | MetricKind::Co2 => None,
+        }
+    }
+}
+
+/// Download the complete history of a model other than the Aranet4, which has [`get_history`].
+/// Only models for which [`AranetModel::has_model_history`] is true are supported.
+pub async fn get_model_history<S: SensorTransport>(
+    sensor: &S,
+    model: AranetModel,
+    progress: ProgressFn<'_>,
+    retry: RetryPolicy,
+    op_timeout: Duration,
+) -> Result<ModelHistory> {
+    if !model.has_model_history() {
+        return Err(eyre!("Downloading {} history is not supported", model));
+    }
+    connected(sensor, async {
+        let radon_plus = model == AranetModel::Radon;
+        let num_metrics = if radon_plus { 4 } else { 2 };
+        let partial = Mutex::default();
+        let temperature: TemperatureData = with_retries(sensor, retry, || {
+            with_timeout(
+                op_timeout,
+                "downloading temperature history",
+                get_single_history_type(sensor, 1, None, 0, num_metrics, progress, &partial),
+            )
+        })
+        .await?;
+        let partial = Mutex::default();
+        let humidity: Humidity2Data = with_retries(sensor, retry, || {
+            with_timeout(
+                op_timeout,
+                "downloading humidity history",
+                get_single_history_type(sensor, 1, None, 1, num_metrics, progress, &partial),
+            )
+        })
+        .await?;
+        let mut pressure = None;
+        let mut radon = None;
+        if radon_plus {
+            let partial = Mutex::default();
+            pressure = Some(
+                with_retries(sensor, retry, || {
+                    with_timeout(
+                        op_timeout,
+                        "downloading pressure history",
+                        get_single_history_type(
+                            sensor,
+                            1,
+                            None,
+                            2,
+                            num_metrics,
+                            progress,
+                            &partial,
+                        ),
+                    )
+                })
+                .await?,
+            );
+            let partial = Mutex::default();
+            radon = Some(
+                with_retries(sensor, retry, || {
+                    with_timeout(
+                        op_timeout,
+                        "downloading radon history",
+                        get_single_history_type(
+                            sensor,
+                            1,
+                            None,
+                            3,
+                            num_metrics,
+                            progress,
+                            &partial,
+                        ),
+                    )
+                })
+                .await?,
+            );
+        }
+        let num_samples = temperature.values.len();
+        let lengths = [
+            Some(humidity.values.len()),
+            pressure
+                .as_ref()
+                .map(|data: &PressureData| data.values.len()),
+            radon.as_ref().map(|data: &RadonData| data.values.len()),
+        ];
+        if lengths.into_iter().flatten().any(|len| len != num_samples) {
+            return Err(eyre!(
+                "{} history metrics have different numbers of readings",
+                model
+            ));
+        }
+        Ok(ModelHistory {
+            model,
+            time: HistoryTime::from_sensor(sensor, num_samples).await?,
+            temperature,
+            humidity,
+            pressure,
+            radon,
+        })
+    })
+    .await
+}
+
+/// The platform's Bluetooth manager; failing to reach it, e.g. because BlueZ isn't running,
+/// means there is no usable adapter.
+pub(crate) async fn bluetooth_manager() -> Result<Manager> {
+    Ok(Manager::new().await.map_err(|e| {
+        Aranet4Error::NoAdapter(format!("Could not reach the Bluetooth stack: {}", e))
+    })?)
+}
+
+pub async fn get_first_adapter() -> Result<Adapter> {
+    let manager = bluetooth_manager().await?;
+    let adapters = manager.adapters().await?;
+    Ok(adapters.into_iter().next().ok_or(Aranet4Error::NoAdapter(
+        "No Bluetooth adapter found".to_string(),
+    ))?)
+}
+
+/// Every Bluetooth adapter on the system, with the platform's description of it.
+pub async fn list_adapters() -> Result<Vec<(Adapter, String)>> {
+    let manager = bluetooth_manager().await?;
+    let mut adapters = Vec::new();
+    for adapter in manager.adapters().await? {
+        let info = adapter.adapter_info().await?;
+        adapters.push((adapter, info));
+    }
+    Ok(adapters)
+}
+
+/// Select an adapter by its index in [`list_adapters`], or by a substring of its description,
+/// e.g. `hci1`.
+pub async fn get_adapter(selector: &str) -> Result<Adapter> {
+    let adapters = list_adapters().await?;
+    if let Ok(index) = selector.parse::<usize>() {
+        let count = adapters.len();
+        return Ok(adapters
+            .into_iter()
+            .nth(index)
+            .map(|(adapter, _)| adapter)
+            .ok_or(Aranet4Error::NoAdapter(format!(
+                "No Bluetooth adapter with index {} ({} found)",
+                index, count
+            )))?);
+    }
+    Ok(adapters
+        .into_iter()
+        .find(|(_, info)| info.contains(selector))
+        .map(|(adapter, _)| adapter)
+        .ok_or(Aranet4Error::NoAdapter(format!(
+            "No Bluetooth adapter matching '{}'",
+            selector
+        )))?)
+}
+
+pub async fn get_history<S: SensorTransport>(sensor: &S) -> Result<History> {
+    get_history_subset(sensor, &HistoryMetric::ALL).await
+}
+
+/// Download the whole history of only `metrics`, leaving the others empty; each metric is a
+/// transfer of its own, so e.g. only CO₂ takes about a quarter of the time of all four.
+pub async fn get_history_subset<S: SensorTransport>(
+    sensor: &S,
+    metrics: &[HistoryMetric],
+) -> Result<History> {
+    get_history_from(
+        sensor,
+        1,
+        &|_| {},
+        RetryPolicy::NONE,
+        DEFAULT_OP_TIMEOUT,
+        HistoryTransfer::Sequential,
+        metrics,
+    )
+    .await
+}
+
+/// Download the readings of `metrics` from 1-based `start_index` through the most recent one.
+///
+/// Each metric is retried separately according to `retry`, and a retry only requests the
+/// readings the dropped attempt didn't receive. Each attempt times out after `op_timeout`.
+pub async fn get_history_from<S: SensorTransport>(
+    sensor: &S,
+    start_index: u16,
+    progress: ProgressFn<'_>,
+    retry: RetryPolicy,
+    op_timeout: Duration,
+    transfer: HistoryTransfer,
+    metrics: &[HistoryMetric],
+) -> Result<History> {
+    connected(
+        sensor,
+        download_history(
+            sensor,
+            start_index,
+            progress,
+            retry,
+            op_timeout,
+            transfer,
+            metrics,
+        ),
+    )
+    .await
+}
+
+/// Download one metric of [`download_history`], or nothing if it isn't one of `metrics`.
+#[allow(clippy::too_many_arguments)]
+async fn download_metric<S, T, const SENSORTYPE: u8>(
+    sensor: &S,
+    start_index: u16,
+    total_readings: u16,
+    metrics: &[HistoryMetric],
+    progress: ProgressFn<'_>,
+    retry: RetryPolicy,
+    op_timeout: Duration,
+    partial: &Mutex<PartialHistory>,
+) -> Result<SensorData<T, SENSORTYPE>, Aranet4Error>
+where
+    S: SensorTransport,
+    SensorData<T, SENSORTYPE>: Metadata + for<'a> TryFrom<&'a [u8], Error = Aranet4Error>,
+{
+    let kind = SensorData::<T, SENSORTYPE>::KIND;
+    let Some(metric) = HistoryMetric::ALL.into_iter().find(|m| m.kind() == kind) else {
+        return Err(Aranet4Error::InvalidResponse(format!(
+            "an Aranet4 doesn't log {}",
+            kind.name()
+        )));
+    };
+    let requested = HistoryMetric::in_order(metrics);
+    let Some(metric_index) = requested.iter().position(|&m| m == metric) else {
+        return Ok(SensorData { values: Vec::new() });
+    };
+    let what = format!("downloading {} history", metric.name());
+    with_retries(sensor, retry, || {
+        with_timeout(
+            op_timeout,
+            &what,
+            get_single_history_type(
+                sensor,
+                start_index,
+                Some(total_readings),
+                metric_index,
+                requested.len(),
+                progress,
+                partial,
+            ),
+        )
+    })
+    .await
+}
+
+/// How often [`download_history`] starts over because the sensor overwrote readings while it
+/// was under way, before giving up.
+const MAX_LOG_REALIGNMENTS: usize = 2;
+
+/// Slack for the sensor's time since update being read a little early or late, in seconds.
+const SINCE_UPDATE_SLACK: f64 = 2.0;
+
+/// Where the sensor's log stood at one moment, to tell whether it took measurements while a
+/// history download was under way.
+#[derive(Debug, Clone, Copy)]
+struct LogSnapshot {
+    total_readings: u16,
+    update_interval: u16,
+    since_update: u16,
+    now: DateTime<Utc>,
+    at: Instant,
+}
+
+impl LogSnapshot {
+    async fn read<S: SensorTransport>(sensor: &S) -> Result<Self, Aranet4Error> {
+        sensor.connect().await?;
+        Ok(LogSnapshot {
+            total_readings: get_total_readings(sensor).await?,
+            update_interval: get_update_interval(sensor).await?,
+            since_update: get_time_since_update(sensor).await?,
+            now: Utc::now(),
+            at: Instant::now(),
+        })
+    }
+
+    /// How many measurements the sensor took between `self` and `later`. The time since the
+    /// latest one only grows in between, by the time that passed, unless one was taken.
+    fn measurements_until(&self, later: &LogSnapshot) -> u16 {
+        let elapsed = later.at.saturating_duration_since(self.at).as_secs_f64();
+        let expected = f64::from(self.since_update) + elapsed;
+        let since_update = f64::from(later.since_update);
+        if since_update >= expected - SINCE_UPDATE_SLACK {
+            return 0;
+        }
+        let interval = f64::from(later.update_interval.max(1));
+        ((expected - since_update) / interval).round().max(1.0) as u16
+    }
+
+    /// How many of the oldest readings the sensor overwrote between `self` and `later`. Once
+    /// its log is full, a measurement replaces the oldest reading instead of adding one, so the
+    /// same index then refers to a newer reading.
+    fn dropped_until(&self, later: &LogSnapshot) -> u16 {
+        let added = later.total_readings.saturating_sub(self.total_readings);
+        self.measurements_until(later).saturating_sub(added)
+    }
+
+    /// The timing of `num_samples` readings, the newest of which is `newer` readings older than
+    /// the sensor's newest as of this snapshot.
+    fn history_time(&self, num_samples: usize, newer: u16) -> HistoryTime {
+        let mut time = HistoryTime {
+            num_samples,
+            update_interval: self.update_interval,
+            since_update: self.since_update,
+            now: self.now - TimeDelta::seconds(i64::from(newer) * i64::from(self.update_interval)),
+            offset: 0,
+        };
+        time.snap_to_grid();
+        time
+    }
+}
+
+/// The metrics of a history download, before its timestamps are known. Metrics that weren't
+/// requested have no values.
+struct MetricValues {
+    temperature: TemperatureData,
+    humidity: HumidityData,
+    pressure: PressureData,
+    co2: CO2Data,
+}
+
+impl MetricValues {
+    /// The number of readings of each metric, in the order of [`HistoryMetric::ALL`].
+    fn lengths(&self) -> [usize; 4] {
+        [
+            self.temperature.values.len(),
+            self.humidity.values.len(),
+            self.pressure.values.len(),
+            self.co2.values.len(),
+        ]
+    }
+
+    /// The number of readings of every one of `metrics`, which must be the same.
+    fn num_samples(&self, metrics: &[HistoryMetric]) -> Result<usize, Aranet4Error> {
+        let lengths = self.lengths();
+        let mut requested = HistoryMetric::ALL
+            .into_iter()
+            .zip(lengths)
+            .filter(|(metric, _)| metrics.contains(metric))
+            .map(|(_, len)| len);
+        let num_samples = requested.next().unwrap_or(0);
+        if requested.any(|len| len != num_samples) {
+            let [temperature, humidity, pressure, co2] = lengths;
+            return Err(Aranet4Error::InconsistentHistoryLengths {
+                temperature,
+                humidity,
+                pressure,
+                co2,
+            });
+        }
+        Ok(num_samples)
+    }
+
+    /// Those of `metrics` with fewer readings than the longest one.
+    fn shorter(&self, metrics: &[HistoryMetric]) -> Vec<HistoryMetric> {
+        let lengths = self.lengths();
+        let longest = lengths.into_iter().max().unwrap_or(0);
+        HistoryMetric::ALL
+            .into_iter()
+            .zip(lengths)
+            .filter(|&(metric, len)| metrics.contains(&metric) && len < longest)
+            .map(|(metric, _)| metric)
+            .collect()
+    }
+
+    /// Take the values of `metrics` from `other`.
+    fn replace(&mut self, other: MetricValues, metrics: &[HistoryMetric]) {
+        let MetricValues {
+            temperature,
+            humidity,
+            pressure,
+            co2,
+        } = other;
+        if metrics.contains(&HistoryMetric::Temperature) {
+            self.temperature = temperature;
+        }
+        if metrics.contains(&HistoryMetric::Humidity) {
+            self.humidity = humidity;
+        }
+        if metrics.contains(&HistoryMetric::Pressure) {
+            self.pressure = pressure;
+        }
+        if metrics.contains(&HistoryMetric::Co2) {
+            self.co2 = co2;
+        }
+    }
+}
+
+/// Download readings `start_index..=total_readings` of `metrics`, where `total_readings` is
+/// pinned for all of them so that a measurement in between doesn't add a reading to some
+/// metrics only.
+#[allow(clippy::too_many_arguments)]
+async fn download_metrics<S: SensorTransport>(
+    sensor: &S,
+    start_index: u16,
+    total_readings: u16,
+    progress: ProgressFn<'_>,
+    retry: RetryPolicy,
+    op_timeout: Duration,
+    transfer: HistoryTransfer,
+    metrics: &[HistoryMetric],
+) -> Result<MetricValues> {
+    let partials: [Mutex<PartialHistory>; 4] = Default::default();
+    if transfer == HistoryTransfer::Interleaved {
+        let interleaved = with_timeout(
+            op_timeout,
+            "downloading interleaved history",
+            get_interleaved_history(
+                sensor,
+                start_index,
+                total_readings,
+                progress,
+                &partials,
+                metrics,
+            ),
+        )
+        .await;
+        if let Err(e) = interleaved {
+            warn!("Interleaved history download failed, continuing one metric at a time: {e}");
+        }
+    }
     // Await each one sequentially because while we could do two separate devices in
     // parallel, there's no speedup to be had by multiply querying a single device and
-    // it would probably confuse the device.
-    let temperature = get_temperature_history(sensor).await?;
-    let humidity = get_humidity_history(sensor).await?;
-    let pressure = get_pressure_history(sensor).await?;
-    let co2 = get_co2_history(sensor).await?;
-    assert_eq!(temperature.values.len(), humidity.values.len());
-    assert_eq!(temperature.values.len(), pressure.values.len());
-    assert_eq!(temperature.values.len(), co2.values.len());
-    let history_time = HistoryTime::from_sensor(sensor, temperature.values.len()).await?;
-    Ok((history_time, temperature, humidity, pressure, co2))
+    // it would probably confuse the device. A retry resumes where the attempt before it
+    // stopped, and a metric the interleaved download completed isn't requested again.
+    let temperature: TemperatureData = download_metric(
+        sensor,
+        start_index,
+        total_readings,
+        metrics,
+        progress,
+        retry,
+        op_timeout,
+        &partials[0],
+    )
+    .await?;
+    let humidity: HumidityData = download_metric(
+        sensor,
+        start_index,
+        total_readings,
+        metrics,
+        progress,
+        retry,
+        op_timeout,
+        &partials[1],
+    )
+    .await?;
+    let pressure: PressureData = download_metric(
+        sensor,
+        start_index,
+        total_readings,
+        metrics,
+        progress,
+        retry,
+        op_timeout,
+        &partials[2],
+    )
+    .await?;
+    let co2: CO2Data = download_metric(
+        sensor,
+        start_index,
+        total_readings,
+        metrics,
+        progress,
+        retry,
+        op_timeout,
+        &partials[3],
+    )
+    .await?;
+    Ok(MetricValues {
+        temperature,
+        humidity,
+        pressure,
+        co2,
+    })
+}
+
+/// Download readings `start_index..` of `metrics`. If the sensor's log is full and a
+/// measurement overwrites its oldest reading mid-download, the metrics fetched before and after
+/// it would be a reading apart, so the download starts over from the same reading.
+#[instrument(name = "history", skip_all, fields(start_index))]
+async fn download_history<S: SensorTransport>(
+    sensor: &S,
+    start_index: u16,
+    progress: ProgressFn<'_>,
+    retry: RetryPolicy,
+    op_timeout: Duration,
+    transfer: HistoryTransfer,
+    metrics: &[HistoryMetric],
+) -> Result<History> {
+    let mut start_index = start_index;
+    let mut realignments = 0;
+    loop {
+        let before = LogSnapshot::read(sensor).await?;
+        let mut values = download_metrics(
+            sensor,
+            start_index,
+            before.total_readings,
+            progress,
+            retry,
+            op_timeout,
+            transfer,
+            metrics,
+        )
+        .await?;
+        let after = LogSnapshot::read(sensor).await?;
+        let dropped = before.dropped_until(&after);
+        if dropped > 0 {
+            if realignments == MAX_LOG_REALIGNMENTS {
+                return Err(eyre!(
+                    "The sensor kept overwriting readings during the history download"
+                ));
+            }
+            realignments += 1;
+            // The readings wanted moved towards the start of the log
+            start_index = start_index.saturating_sub(dropped).max(1);
+            warn!(
+                dropped,
+                start_index, "The sensor took a measurement mid-download; downloading again"
+            );
+            continue;
+        }
+        let num_samples = match values.num_samples(metrics) {
+            Ok(num_samples) => num_samples,
+            Err(e) => {
+                // A metric cut short is more likely a transfer gone wrong than the sensor's
+                // log, so it gets one more try
+                let shorter = values.shorter(metrics);
+                warn!("{e}; downloading {:?} again", shorter);
+                let again = download_metrics(
+                    sensor,
+                    start_index,
+                    before.total_readings,
+                    progress,
+                    retry,
+                    op_timeout,
+                    HistoryTransfer::Sequential,
+                    &shorter,
+                )
+                .await?;
+                values.replace(again, &shorter);
+                values.num_samples(metrics)?
+            }
+        };
+        // Readings added since `before` weren't downloaded
+        let newer = after.total_readings.saturating_sub(before.total_readings);
+        let MetricValues {
+            temperature,
+            humidity,
+            pressure,
+            co2,
+        } = values;
+        return Ok(History {
+            time: after.history_time(num_samples, newer),
+            temperature,
+            humidity,
+            pressure,
+            co2,
+        });
+    }
+}
+
+/// Download only the readings taken after `last_timestamp` (UNIX time), e.g. the newest
+/// reading of a previous download.
+pub async fn get_history_after<S: SensorTransport>(
+    sensor: &S,
+    last_timestamp: i64,
+    progress: ProgressFn<'_>,
+    retry: RetryPolicy,
+    op_timeout: Duration,
+    transfer: HistoryTransfer,
+    metrics: &[HistoryMetric],
+) -> Result<History> {
+    connected(sensor, async {
+        let total_readings = get_total_readings(sensor).await?;
+        let update_interval = get_update_interval(sensor).await?;
+        let since_update = get_time_since_update(sensor).await?;
+        let latest_timestamp = Utc::now().timestamp() - since_update as i64;
+        let start_index = start_index_after(
+            last_timestamp,
+            latest_timestamp,
+            update_interval,
+            total_readings,
+        );
+        debug!(
+            last_timestamp,
+            total_readings, start_index, "Resuming history download"
+        );
+        download_history(
+            sensor,
+            start_index,
+            progress,
+            retry,
+            op_timeout,
+            transfer,
+            metrics,
+        )
+        .await
+    })
+    .await
+}
+
+/// Download the readings taken within `range`. Only those from the start of the range on are
+/// requested from the sensor, so a short range downloads quickly.
+pub async fn get_history_range<S: SensorTransport>(
+    sensor: &S,
+    range: HistoryRange,
+    progress: ProgressFn<'_>,
+    retry: RetryPolicy,
+    op_timeout: Duration,
+    transfer: HistoryTransfer,
+    metrics: &[HistoryMetric],
+) -> Result<History> {
+    connected(sensor, async {
+        let start_index = match range.since {
+            Some(since) => {
+                let total_readings = get_total_readings(sensor).await?;
+                let update_interval = get_update_interval(sensor).await?;
+                let since_update = get_time_since_update(sensor).await?;
+                // Compared with the timestamps as they will be once snapped to the grid
+                let latest_timestamp = snap_to_grid(
+                    Utc::now().timestamp() - since_update as i64,
+                    update_interval,
+                );
+                start_index_since(since, latest_timestamp, update_interval, total_readings)
+            }
+            None => 1,
+        };
+        debug!(?range, start_index, "Downloading history range");
+        let mut history = download_history(
+            sensor,
+            start_index,
+            progress,
+            retry,
+            op_timeout,
+            transfer,
+            metrics,
+        )
+        .await?;
+        if let Some(until) = range.until {
+            history.truncate_after(until)?;
+        }
+        Ok(history)
+    })
+    .await
+}
+
+/// The 1-based history index of the first reading taken at or after `since`, given that the
+/// newest of `total_readings` readings was taken at `latest_timestamp`. Returns
+/// `total_readings + 1` if there is none.
+pub fn start_index_since(
+    since: i64,
+    latest_timestamp: i64,
+    update_interval: u16,
+    total_readings: u16,
+) -> u16 {
+    if update_interval == 0 {
+        return 1;
+    }
+    if since > latest_timestamp {
+        return total_readings.saturating_add(1);
+    }
+    let readings = (latest_timestamp - since) / update_interval as i64 + 1;
+    if readings >= total_readings as i64 {
+        1
+    } else {
+        total_readings - readings as u16 + 1
+    }
+}
+
+/// The 1-based history index of the first reading newer than `last_timestamp`, given that the
+/// newest of `total_readings` readings was taken at `latest_timestamp`. Returns
+/// `total_readings + 1` if nothing is newer.
+///
+/// Timestamps are reconstructed from the host clock on every download, so they jitter by a
+/// second or so between runs; snapping both to the measurement grid absorbs that.
+pub fn start_index_after(
+    last_timestamp: i64,
+    latest_timestamp: i64,
+    update_interval: u16,
+    total_readings: u16,
+) -> u16 {
+    if update_interval == 0 {
+        return 1;
+    }
+    let interval = update_interval as i64;
+    // `last_timestamp` may be on the grid or not, depending on how it was archived
+    let new_readings = (snap_to_grid(latest_timestamp, update_interval)
+        - snap_to_grid(last_timestamp, update_interval))
+        / interval;
+    if new_readings <= 0 {
+        total_readings.saturating_add(1)
+    } else if new_readings >= total_readings as i64 {
+        1
+    } else {
+        total_readings - new_readings as u16 + 1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use btleplug::api::ValueNotification;
+    use chrono::{DateTime, TimeDelta};
+    use tokio_stream::StreamExt;
+
+    use crate::device::{
+        get_co2_thresholds, get_history, get_history_from, get_history_range, get_history_subset,
+        get_model, get_model_history, get_model_sensor_data, get_update_interval,
+        set_update_interval, signal_quality, snap_to_grid, start_index_after, start_index_since,
+        subscribe_current_readings, DeviceInfo, DevicePattern, HistoryMetric, HistoryTime,
+        HistoryTransfer, LogSnapshot, MetricValues, RetryPolicy, SensorStatus,
+        ARANET4_CO2_THRESHOLDS_UUID, ARANET4_CURRENT_READINGS_UUID, ARANET4_NOTIFY_HISTORY_UUID,
+        ARANET_CURRENT_READINGS_DETAILED_UUID, DEFAULT_OP_TIMEOUT,
+        GENERIC_GATT_DEVICE_MODEL_NUMBER_STRING_UUID,
+    };
+    use crate::mock::{history_packets, MockAranet4};
+    use crate::time_range::HistoryRange;
+    use crate::types::{Aranet4Error, AranetModel, Co2IndicatorThresholds, MetricKind, SensorData};
+
+    const SEQUENTIAL: HistoryTransfer = HistoryTransfer::Sequential;
+    const ALL: &[HistoryMetric] = &HistoryMetric::ALL;
+
+    fn mock_with_history(num_readings: u16) -> MockAranet4 {
+        let temperature: Vec<u16> = (0..num_readings).map(|i| 400 + i).collect();
+        let humidity: Vec<u8> = (0..num_readings).map(|i| (i % 100) as u8).collect();
+        let pressure: Vec<u16> = (0..num_readings).map(|i| 10_000 + i).collect();
+        let co2: Vec<u16> = (0..num_readings).map(|i| 500 + i).collect();
+        MockAranet4::new("Aranet4 1BA27").with_history(&temperature, &humidity, &pressure, &co2)
+    }
+
+    #[test]
+    fn test_signal_quality() {
+        assert_eq!(signal_quality(-45), "excellent");
+        assert_eq!(signal_quality(-60), "excellent");
+        assert_eq!(signal_quality(-61), "good");
+        assert_eq!(signal_quality(-80), "fair");
+        assert_eq!(signal_quality(-95), "poor");
+    }
+
+    #[test]
+    fn test_device_pattern_matches() {
+        let pattern = DevicePattern::substring("aranet4 1b", false);
+        assert!(pattern.matches("Aranet4 1BA27"));
+        assert!(!pattern.matches("Aranet2 1BA27"));
+        assert!(!DevicePattern::substring("aranet4", true).matches("Aranet4 1BA27"));
+        let regex = DevicePattern::regex("^Aranet4 (1BA27|0C3F1)$").unwrap();
+        assert!(regex.matches("Aranet4 0C3F1"));
+        assert!(!regex.matches("Aranet4 0C3F12"));
+        assert!(DevicePattern::regex("Aranet4 (").is_err());
+        assert_eq!(regex.to_string(), "/^Aranet4 (1BA27|0C3F1)$/");
+    }
+
+    #[test]
+    fn test_start_index_after() {
+        // Nothing new since the last download, allowing for timestamp jitter
+        assert_eq!(start_index_after(1000, 1000, 300, 2016), 2017);
+        assert_eq!(start_index_after(1001, 1000, 300, 2016), 2017);
+        assert_eq!(start_index_after(999, 1000, 300, 2016), 2017);
+        // Two new readings
+        assert_eq!(start_index_after(1000, 1601, 300, 2016), 2015);
+        // Everything is new
+        assert_eq!(start_index_after(0, 1_000_000, 300, 2016), 1);
+        assert_eq!(start_index_after(1000, 1600, 300, 2), 1);
+    }
+
+    #[test]
+    fn test_snap_timestamps_to_grid() {
+        let mut time = HistoryTime {
+            num_samples: 2,
+            update_interval: 300,
+            since_update: 24,
+            now: DateTime::from_timestamp(1738621353, 0).unwrap(),
+            offset: 0,
+        };
+        time.snap_to_grid();
+        assert_eq!(time.to_vec(), vec![1738620900, 1738621200]);
+        assert_eq!(time.offset, 129);
+        // Another download a second later lands on the same grid
+        let mut later = HistoryTime {
+            now: DateTime::from_timestamp(1738621354, 0).unwrap(),
+            offset: 0,
+            ..time.clone()
+        };
+        later.snap_to_grid();
+        assert_eq!(later.to_vec(), time.to_vec());
+        time.use_raw_timestamps();
+        assert_eq!(time.to_vec(), vec![1738621029, 1738621329]);
+        assert_eq!(
+            time.timestamp_at(1),
+            DateTime::from_timestamp(1738621329, 0)
+        );
+        assert_eq!(time.timestamp_at(2), None);
+        assert_eq!(time.timestamps().len(), 2);
+        assert!(time.get_timestamp(2).is_err());
+        assert_eq!(snap_to_grid(1738621351, 300), 1738621500);
+    }
+
+    #[tokio::test]
+    async fn test_get_history_reassembles_packets() {
+        // More than one notification's worth, so every metric spans several packets
+        let sensor = mock_with_history(300);
+        let history = get_history(&sensor).await.unwrap();
+        assert_eq!(history.time.num_samples, 300);
+        assert_eq!(history.temperature.values[0], 400);
+        assert_eq!(history.temperature.values[299], 699);
+        assert_eq!(history.humidity.values[150], 50);
+        assert_eq!(history.pressure.values[299], 10_299);
+        assert_eq!(history.co2.values.len(), 300);
+        assert_eq!(history.co2.values[120], 620);
+        assert!(!sensor.is_connected());
+    }
+
+    #[tokio::test]
+    async fn test_get_history_from_start_index() {
+        let sensor = mock_with_history(10);
+        let history = get_history_from(
+            &sensor,
+            8,
+            &|_| {},
+            RetryPolicy::NONE,
+            DEFAULT_OP_TIMEOUT,
+            SEQUENTIAL,
+            ALL,
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.co2.values, vec![507, 508, 509]);
+        let history = get_history_from(
+            &sensor,
+            11,
+            &|_| {},
+            RetryPolicy::NONE,
+            DEFAULT_OP_TIMEOUT,
+            SEQUENTIAL,
+            ALL,
+        )
+        .await
+        .unwrap();
+        assert!(history.co2.values.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_history_rejects_short_stream() {
+        // The stream ends after the first of the three packets the sensor should send
+        let co2: Vec<u8> = (0..300u16).flat_map(|i| (500 + i).to_le_bytes()).collect();
+        let packets = history_packets(4, 1, &co2, 2).into_iter().take(1).collect();
+        let sensor = mock_with_history(300).with_history_packets(4, packets);
+        assert!(get_history(&sensor).await.is_err());
+        assert!(!sensor.is_connected());
+    }
+
+    #[tokio::test]
+    async fn test_get_history_resumes_interrupted_download() {
+        let sensor = mock_with_history(300).with_interrupted_history(4, 2);
+        let retry = RetryPolicy {
+            retries: 1,
+            delay: Duration::ZERO,
+        };
+        let history = get_history_from(
+            &sensor,
+            1,
+            &|_| {},
+            retry,
+            DEFAULT_OP_TIMEOUT,
+            SEQUENTIAL,
+            ALL,
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.co2.values, (500..800).collect::<Vec<u16>>());
+        // The retry only asks for what the first two packets didn't bring
+        let co2_requests: Vec<Vec<u8>> = sensor
+            .writes()
+            .into_iter()
+            .filter(|(_, command)| command[..2] == [0x82, 4])
+            .map(|(_, command)| command)
+            .collect();
+        assert_eq!(co2_requests.len(), 2);
+        assert_eq!(co2_requests[1][4..6], 241u16.to_le_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_get_interleaved_history() {
+        let sensor = mock_with_history(300);
+        let interleaved = HistoryTransfer::Interleaved;
+        let history = get_history_from(
+            &sensor,
+            1,
+            &|_| {},
+            RetryPolicy::NONE,
+            DEFAULT_OP_TIMEOUT,
+            interleaved,
+            ALL,
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.temperature.values[299], 699);
+        assert_eq!(history.co2.values, (500..800).collect::<Vec<u16>>());
+        // All four requests went out at once, and none again one metric at a time
+        let requests: Vec<u8> = sensor
+            .writes()
+            .into_iter()
+            .filter(|(_, command)| command[0] == 0x82)
+            .map(|(_, command)| command[1])
+            .collect();
+        assert_eq!(requests, vec![1, 2, 3, 4]);
+
+        // What the interleaved transfer misses is requested one metric at a time
+        let sensor = mock_with_history(300).with_interrupted_history(4, 1);
+        let history = get_history_from(
+            &sensor,
+            1,
+            &|_| {},
+            RetryPolicy::NONE,
+            DEFAULT_OP_TIMEOUT,
+            interleaved,
+            ALL,
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.co2.values, (500..800).collect::<Vec<u16>>());
+        let (_, command) = sensor.writes().pop().unwrap();
+        assert_eq!(command[..2], [0x82, 4]);
+        assert_eq!(command[4..6], 121u16.to_le_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_get_history_skips_unrelated_notifications() {
+        let current_readings = ValueNotification {
+            uuid: ARANET4_CURRENT_READINGS_UUID,
+            value: vec![
+                0x9e, 0x03, 0x9d, 0x01, 0xbe, 0x27, 0x21, 0x16, 0x01, 0x2c, 0x01,
+            ],
+        };
+        // A humidity packet left over from an earlier request
+        let stale = ValueNotification {
+            uuid: ARANET4_NOTIFY_HISTORY_UUID,
+            value: vec![2, 1, 0, 2, 40, 41],
+        };
+        for transfer in [SEQUENTIAL, HistoryTransfer::Interleaved] {
+            let sensor = mock_with_history(300)
+                .with_interleaved_notifications(vec![current_readings.clone(), stale.clone()]);
+            let history = get_history_from(
+                &sensor,
+                1,
+                &|_| {},
+                RetryPolicy::NONE,
+                DEFAULT_OP_TIMEOUT,
+                transfer,
+                &[HistoryMetric::Temperature, HistoryMetric::Co2],
+            )
+            .await
+            .unwrap();
+            assert_eq!(history.temperature.values[299], 699);
+            assert_eq!(history.co2.values, (500..800).collect::<Vec<u16>>());
+        }
+
+        // A history packet too short for its header is still an error
+        let sensor = mock_with_history(300).with_history_packets(4, vec![vec![4, 1]]);
+        let error = get_history(&sensor).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<Aranet4Error>(),
+            Some(Aranet4Error::InvalidResponse(message)) if message == "Expected at least 4 bytes for the header"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_current_readings() {
+        let sensor = MockAranet4::new("Aranet4 1BA27");
+        let mut readings = subscribe_current_readings(&sensor).await.unwrap();
+        let reading = readings.next().await.unwrap().unwrap();
+        assert_eq!(reading.co2_ppm(), 926);
+        assert_eq!(reading.interval().as_secs(), 300);
+        // The notifications need the connection
+        assert!(sensor.is_connected());
+    }
+
+    #[tokio::test]
+    async fn test_get_history_realigns_after_log_wraps() {
+        // The temperature is downloaded before the measurement, the other metrics after it
+        let sensor = mock_with_history(10).with_measurement_after(1);
+        let history = get_history(&sensor).await.unwrap();
+        let shifted =
+            |first: u16| -> Vec<u16> { (first + 1..first + 10).chain([first + 9]).collect() };
+        assert_eq!(history.temperature.values, shifted(400));
+        assert_eq!(history.pressure.values, shifted(10_000));
+        assert_eq!(history.co2.values, shifted(500));
+        assert_eq!(history.time.since_update, 0);
+        let requests = sensor
+            .writes()
+            .into_iter()
+            .filter(|(_, command)| command[0] == 0x82)
+            .count();
+        assert_eq!(requests, 8);
+    }
+
+    #[test]
+    fn test_metric_values_lengths() {
+        let values = |temperature: usize, co2: usize| MetricValues {
+            temperature: SensorData {
+                values: vec![400; temperature],
+            },
+            humidity: SensorData { values: Vec::new() },
+            pressure: SensorData { values: Vec::new() },
+            co2: SensorData {
+                values: vec![500; co2],
+            },
+        };
+        let metrics = [HistoryMetric::Temperature, HistoryMetric::Co2];
+        assert_eq!(values(10, 10).num_samples(&metrics).unwrap(), 10);
+        let mut cut_short = values(10, 7);
+        assert!(matches!(
+            cut_short.num_samples(&metrics),
+            Err(Aranet4Error::InconsistentHistoryLengths {
+                temperature: 10,
+                humidity: 0,
+                pressure: 0,
+                co2: 7,
+            })
+        ));
+        assert_eq!(cut_short.shorter(&metrics), vec![HistoryMetric::Co2]);
+        cut_short.replace(values(0, 10), &[HistoryMetric::Co2]);
+        assert_eq!(cut_short.num_samples(&metrics).unwrap(), 10);
+        // Only the requested metrics need to agree
+        assert_eq!(values(0, 7).num_samples(&[HistoryMetric::Co2]).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_log_snapshot_measurements() {
+        let before = LogSnapshot {
+            total_readings: 100,
+            update_interval: 300,
+            since_update: 250,
+            now: DateTime::from_timestamp(1738621353, 0).unwrap(),
+            at: Instant::now(),
+        };
+        let later = |total_readings, since_update, seconds| LogSnapshot {
+            total_readings,
+            since_update,
+            now: before.now + TimeDelta::seconds(seconds as i64),
+            at: before.at + Duration::from_secs(seconds),
+            ..before
+        };
+        // No measurement in 40 s
+        assert_eq!(before.measurements_until(&later(100, 290, 40)), 0);
+        // One appended to a log with room to spare
+        let appended = later(101, 10, 60);
+        assert_eq!(before.measurements_until(&appended), 1);
+        assert_eq!(before.dropped_until(&appended), 0);
+        // One overwriting the oldest reading of a full log
+        assert_eq!(before.dropped_until(&later(100, 10, 60)), 1);
+        // The newest downloaded reading is the one before the newest
+        let time = appended.history_time(100, 1);
+        assert_eq!(
+            time.get_timestamp(99).unwrap(),
+            snap_to_grid(1738621353 + 60 - 10 - 300, 300)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_history_metrics() {
+        let sensor = mock_with_history(10);
+        let history = get_history_subset(&sensor, &[HistoryMetric::Co2])
+            .await
+            .unwrap();
+        assert_eq!(history.time.num_samples, 10);
+        assert_eq!(history.co2.values[9], 509);
+        assert!(history.temperature.values.is_empty());
+        assert_eq!(history.series(MetricKind::Co2).unwrap().get(9), 509.0);
+        assert!(history.series(MetricKind::Temperature).unwrap().is_empty());
+        assert_eq!(history.series(MetricKind::Radon), None);
+        let requests = |sensor: &MockAranet4| -> Vec<u8> {
+            sensor
+                .writes()
+                .into_iter()
+                .filter(|(_, command)| command[0] == 0x82)
+                .map(|(_, command)| command[1])
+                .collect()
+        };
+        assert_eq!(requests(&sensor), vec![4]);
+
+        let sensor = mock_with_history(10);
+        let history = get_history_from(
+            &sensor,
+            1,
+            &|_| {},
+            RetryPolicy::NONE,
+            DEFAULT_OP_TIMEOUT,
+            HistoryTransfer::Interleaved,
+            &[HistoryMetric::Co2, HistoryMetric::Temperature],
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.temperature.values[0], 400);
+        assert!(history.pressure.values.is_empty());
+        assert_eq!(requests(&sensor), vec![1, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_get_history_places_packets_by_index() {
+        let co2: Vec<u8> = (0..300u16).flat_map(|i| (500 + i).to_le_bytes()).collect();
+        let packets = history_packets(4, 1, &co2, 2);
+
+        // A repeated packet is absorbed
+        let repeated = vec![
+            packets[0].clone(),
+            packets[1].clone(),
+            packets[1].clone(),
+            packets[2].clone(),
+        ];
+        let sensor = mock_with_history(300).with_history_packets(4, repeated);
+        let history = get_history(&sensor).await.unwrap();
+        assert_eq!(history.co2.values, (500..800).collect::<Vec<u16>>());
+
+        // A lost packet is an error rather than readings shifted into the wrong slots
+        let gap = vec![packets[0].clone(), packets[2].clone(), packets[1].clone()];
+        let sensor = mock_with_history(300).with_history_packets(4, gap);
+        let error = get_history(&sensor).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<Aranet4Error>(),
+            Some(Aranet4Error::InvalidResponse(message)) if message == "Missing history readings 121..241"
+        ));
+
+        // So is a repeated packet with different contents
+        let mut corrupt = packets[1].clone();
+        corrupt[4] ^= 0xff;
+        let conflicting = vec![packets[0].clone(), packets[1].clone(), corrupt];
+        let sensor = mock_with_history(300).with_history_packets(4, conflicting);
+        assert!(get_history(&sensor).await.is_err());
+    }
+
+    #[test]
+    fn test_start_index_since() {
+        // The newest reading is at 10_000, the ones before it every 300 s
+        assert_eq!(start_index_since(10_000, 10_000, 300, 2016), 2016);
+        assert_eq!(start_index_since(9_700, 10_000, 300, 2016), 2015);
+        assert_eq!(start_index_since(9_701, 10_000, 300, 2016), 2016);
+        assert_eq!(start_index_since(10_001, 10_000, 300, 2016), 2017);
+        assert_eq!(start_index_since(0, 10_000, 300, 20), 1);
+    }
+
+    #[test]
+    fn test_status_readings_in() {
+        // The newest reading was 100 s before 10_100, at 10_000
+        let status = SensorStatus {
+            total_readings: 2016,
+            update_interval: 300,
+            since_update: 100,
+            battery: 90,
+            co2_thresholds: None,
+        };
+        let now = 10_100;
+        assert_eq!(status.readings_in(HistoryRange::ALL, now), 2016);
+        let last_hour = HistoryRange::new(Some(10_000 - 3600), None).unwrap();
+        assert_eq!(status.readings_in(last_hour, now), 13);
+        let hour_before = HistoryRange::new(Some(10_000 - 7200), Some(10_000 - 3601)).unwrap();
+        assert_eq!(status.readings_in(hour_before, now), 12);
+        let future = HistoryRange::new(Some(10_001), None).unwrap();
+        assert_eq!(status.readings_in(future, now), 0);
+        assert_eq!(status.readings_after(10_000 - 600, now), 2);
+        assert_eq!(status.readings_after(10_000, now), 0);
+        // Before the oldest reading, at 10_000 - 2015 * 300
+        assert_eq!(status.readings_after(10_000 - 2016 * 300, now), 2016);
+    }
+
+    #[tokio::test]
+    async fn test_get_history_range() {
+        let sensor = mock_with_history(10);
+        let now = chrono::Utc::now().timestamp();
+        // The mock's newest reading was 24 s ago. The range ends are off the grid so that a
+        // second's jitter doesn't move them past a reading
+        let latest = snap_to_grid(now - 24, 300);
+        let range = HistoryRange::new(Some(latest - 900 - 100), Some(latest - 300 + 100)).unwrap();
+        let history = get_history_range(
+            &sensor,
+            range,
+            &|_| {},
+            RetryPolicy::NONE,
+            DEFAULT_OP_TIMEOUT,
+            SEQUENTIAL,
+            ALL,
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.co2.values, vec![506, 507, 508]);
+        assert_eq!(history.time.num_samples, 3);
+        let last = history.time.get_timestamp(2).unwrap();
+        assert_eq!(last % 300, 0);
+        assert!((now - 24 - 300 - (last + history.time.offset)).abs() <= 1);
+        // Only the readings from the start of the range on were requested
+        let (_, command) = sensor.writes().pop().unwrap();
+        assert_eq!(command[4..6], 7u16.to_le_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_read_device_info() {
+        let sensor = MockAranet4::new("Aranet4 1BA27");
+        let info = DeviceInfo::read_from_sensor(&sensor).await.unwrap();
+        assert_eq!(info.serial_number, "317960113191");
+        assert_eq!(info.battery, 22);
+        assert_eq!(info.co2_thresholds, Some(Co2IndicatorThresholds::DEFAULT));
+        assert!(!sensor.is_connected());
+    }
+
+    #[tokio::test]
+    async fn test_get_co2_thresholds() {
+        let sensor = MockAranet4::new("Aranet4 1BA27")
+            .with_characteristic(ARANET4_CO2_THRESHOLDS_UUID, &[0x20, 0x03, 0xdc, 0x05]);
+        assert_eq!(
+            get_co2_thresholds(&sensor).await.unwrap(),
+            Some(Co2IndicatorThresholds {
+                yellow: 800,
+                red: 1500
+            })
+        );
+        let status = SensorStatus::read_from_sensor(&sensor).await.unwrap();
+        assert_eq!(status.co2_thresholds.unwrap().red, 1500);
+
+        // Older firmware always uses the defaults, without saying so
+        let sensor =
+            MockAranet4::new("Aranet4 1BA27").without_characteristic(ARANET4_CO2_THRESHOLDS_UUID);
+        assert_eq!(get_co2_thresholds(&sensor).await.unwrap(), None);
+        let info = DeviceInfo::read_from_sensor(&sensor).await.unwrap();
+        assert_eq!(info.co2_thresholds, None);
+    }
+
+    #[tokio::test]
+    async fn test_set_update_interval() {
+        let sensor = MockAranet4::new("Aranet4 1BA27");
+        assert_eq!(get_update_interval(&sensor).await.unwrap(), 300);
+        assert_eq!(set_update_interval(&sensor, 1).await.unwrap(), 60);
+        assert_eq!(get_update_interval(&sensor).await.unwrap(), 60);
+    }
+
+    #[tokio::test]
+    async fn test_read_sensor_status() {
+        let sensor = mock_with_history(288);
+        let status = SensorStatus::read_from_sensor(&sensor).await.unwrap();
+        assert_eq!(status.total_readings, 288);
+        assert_eq!(status.update_interval, 300);
+        assert_eq!(status.since_update, 24);
+        assert_eq!(status.battery, 22);
+        assert_eq!(status.history_span(), Duration::from_secs(287 * 300 + 24));
+        assert!(!sensor.is_connected());
+    }
+
+    #[tokio::test]
+    async fn test_aranet2_readout_and_history() {
+        let temperature: Vec<u8> = (0..150u16).flat_map(|i| (400 + i).to_le_bytes()).collect();
+        let humidity: Vec<u8> = (0..150u16).flat_map(|i| (300 + i).to_le_bytes()).collect();
+        // A renamed sensor is recognised by its model number
+        let sensor = MockAranet4::new("Bedroom")
+            .with_characteristic(GENERIC_GATT_DEVICE_MODEL_NUMBER_STRING_UUID, b"Aranet2")
+            .with_characteristic(
+                ARANET_CURRENT_READINGS_DETAILED_UUID,
+                &[
+                    0x02, 0x00, 0x2c, 0x01, 0x1e, 0x00, 0x5a, 0x9d, 0x01, 0x5b, 0x01, 0x00,
+                ],
+            )
+            .with_history_type(1, temperature, 2)
+            .with_history_type(5, humidity, 2);
+        let model = get_model(&sensor).await.unwrap();
+        assert_eq!(model, AranetModel::Aranet2);
+        let reading = get_model_sensor_data(&sensor, model).await.unwrap();
+        assert_eq!(reading.battery(), 90);
+        let info = DeviceInfo::read_from_sensor(&sensor).await.unwrap();
+        assert_eq!(info.battery, 90);
+        assert_eq!(info.co2_thresholds, None);
+
+        let history = get_model_history(
+            &sensor,
+            model,
+            &|_| {},
+            RetryPolicy::NONE,
+            DEFAULT_OP_TIMEOUT,
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.time.num_samples, 150);
+        assert_eq!(history.humidity.values[149], 449);
+        assert!(history.pressure.is_none());
+        assert!(get_model_history(
+            &sensor,
+            AranetModel::Radiation,
+            &|_| {},
+            RetryPolicy::NONE,
+            DEFAULT_OP_TIMEOUT
+        )
+        .await
+        .is_err());
+    }
 }
@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use crate::device::DevicePattern;
+
+/// Platform peripheral IDs resolved from `--address` or `--device`, so later runs can skip
+/// scanning when the adapter still knows the device, and which devices have been paired. The
+/// IDs are whatever identifies a peripheral on the platform: a BlueZ device path with the MAC
+/// address on Linux, a UUID on macOS and the address on Windows.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct DeviceCache {
+    /// Peripheral ID keyed by the address as given on the command line, lowercased.
+    pub peripheral_ids: BTreeMap<String, String>,
+    /// Peripheral ID of the only sensor a device pattern matched, keyed by
+    /// [`DevicePattern::cache_key`].
+    #[serde(default)]
+    pub pattern_peripheral_ids: BTreeMap<String, String>,
+    /// When each device was paired by `pair`, in RFC 3339 time, keyed by lowercased address.
+    #[serde(default)]
+    pub paired: BTreeMap<String, String>,
+}
+
+impl DeviceCache {
+    /// `arachiver/devices.json` in the platform config directory, e.g. `~/.config` on Linux.
+    pub fn default_path() -> Result<PathBuf> {
+        dirs::config_dir()
+            .map(|dir| dir.join("arachiver").join("devices.json"))
+            .ok_or(eyre!("Could not determine the user config directory"))
+    }
+
+    /// Load the cache file, or start from an empty cache if it doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(DeviceCache::default());
+        }
+        let contents = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Could not read device cache {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .wrap_err_with(|| format!("Could not parse device cache {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .wrap_err_with(|| format!("Could not create directory {}", dir.display()))?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .wrap_err_with(|| format!("Could not write device cache {}", path.display()))
+    }
+
+    pub fn peripheral_id(&self, address: &str) -> Option<&str> {
+        self.peripheral_ids
+            .get(&address.to_lowercase())
+            .map(String::as_str)
+    }
+
+    /// Remember `peripheral_id` for `address`. Returns whether the cache changed.
+    pub fn insert(&mut self, address: &str, peripheral_id: String) -> bool {
+        self.peripheral_ids
+            .insert(address.to_lowercase(), peripheral_id.clone())
+            .is_none_or(|previous| previous != peripheral_id)
+    }
+
+    /// Forget the peripheral ID of `address`, so that it is scanned for again.
+    pub fn forget(&mut self, address: &str) {
+        self.peripheral_ids.remove(&address.to_lowercase());
+    }
+
+    pub fn pattern_peripheral_id(&self, device_pattern: &DevicePattern) -> Option<&str> {
+        self.pattern_peripheral_ids
+            .get(&device_pattern.cache_key())
+            .map(String::as_str)
+    }
+
+    /// Remember `peripheral_id` as the only sensor matching `device_pattern`.
+    pub fn insert_pattern(&mut self, device_pattern: &DevicePattern, peripheral_id: String) {
+        self.pattern_peripheral_ids
+            .insert(device_pattern.cache_key(), peripheral_id);
+    }
+
+    /// When `address` was paired, if it has been.
+    pub fn paired_at(&self, address: &str) -> Option<&str> {
+        self.paired.get(&address.to_lowercase()).map(String::as_str)
+    }
+
+    pub fn mark_paired(&mut self, address: &str, at: DateTime<Utc>) {
+        self.paired.insert(
+            address.to_lowercase(),
+            at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::DateTime;
+
+    use crate::device::DevicePattern;
+    use crate::device_cache::DeviceCache;
+
+    #[test]
+    fn test_device_cache_ignores_address_case() {
+        let path = std::env::temp_dir()
+            .join("arachiver_test_device_cache")
+            .join("devices.json");
+        let _ = std::fs::remove_file(&path);
+        let mut cache = DeviceCache::load(&path).unwrap();
+        let id = "/org/bluez/hci0/dev_D4_8E_2A_11_5C_90".to_string();
+        assert!(cache.insert("D4:8E:2A:11:5C:90", id.clone()));
+        assert!(!cache.insert("d4:8e:2a:11:5c:90", id.clone()));
+        cache.save(&path).unwrap();
+        let loaded = DeviceCache::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.peripheral_id("D4:8e:2a:11:5C:90"), Some(id.as_str()));
+        assert_eq!(loaded.peripheral_id("E1:07:93:4B:AA:12"), None);
+    }
+
+    #[test]
+    fn test_device_cache_keys_patterns_by_case() {
+        let mut cache = DeviceCache::default();
+        let id = "/org/bluez/hci0/dev_D4_8E_2A_11_5C_90".to_string();
+        cache.insert_pattern(&DevicePattern::substring("1ba27", false), id.clone());
+        assert_eq!(
+            cache.pattern_peripheral_id(&DevicePattern::substring("1BA27", false)),
+            Some(id.as_str())
+        );
+        assert_eq!(
+            cache.pattern_peripheral_id(&DevicePattern::substring("1BA27", true)),
+            None
+        );
+        assert_eq!(
+            cache.pattern_peripheral_id(&DevicePattern::regex("1BA27").unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn test_device_cache_remembers_pairing() {
+        let mut cache: DeviceCache = serde_json::from_str(r#"{"peripheral_ids": {}}"#).unwrap();
+        assert_eq!(cache.paired_at("D4:8E:2A:11:5C:90"), None);
+        cache.mark_paired(
+            "D4:8E:2A:11:5C:90",
+            DateTime::from_timestamp(1738621200, 0).unwrap(),
+        );
+        assert_eq!(
+            cache.paired_at("d4:8e:2a:11:5c:90"),
+            Some("2025-02-03T22:20:00Z")
+        );
+    }
+}
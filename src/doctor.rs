@@ -0,0 +1,417 @@
+//! The `doctor` subcommand: go through reaching a sensor one step at a time, from the
+//! Bluetooth stack to reading a measurement, and say which step failed and what usually causes
+//! it. Most failures are down to the environment rather than the sensor.
+
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+
+use btleplug::api::{Central as _, Peripheral as _, PeripheralProperties, ScanFilter};
+use btleplug::platform::{Adapter, Peripheral};
+use color_eyre::eyre::{eyre, Result};
+
+use crate::device::{
+    address_matches, bluetooth_manager, get_adapter, get_first_adapter, peripheral_address,
+    read_model_reading, seen_sensors, signal_quality, DevicePattern, ARANET4_SERVICE_UUID,
+};
+use crate::exit_status;
+use crate::types::{Aranet4Error, AranetModel};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    BluetoothStack,
+    Adapter,
+    Scan,
+    Sensor,
+    Connect,
+    Services,
+    Read,
+}
+
+impl Step {
+    pub const ALL: [Step; 7] = [
+        Step::BluetoothStack,
+        Step::Adapter,
+        Step::Scan,
+        Step::Sensor,
+        Step::Connect,
+        Step::Services,
+        Step::Read,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Step::BluetoothStack => "Bluetooth stack",
+            Step::Adapter => "Adapter",
+            Step::Scan => "Scan",
+            Step::Sensor => "Sensor",
+            Step::Connect => "Connect",
+            Step::Services => "Services",
+            Step::Read => "Read",
+        }
+    }
+
+    /// The `arachiver` exit status when this step fails, matching the one the same failure
+    /// gets from the other subcommands.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Step::BluetoothStack | Step::Adapter => exit_status::NO_ADAPTER,
+            Step::Scan | Step::Sensor => exit_status::DEVICE_NOT_FOUND,
+            Step::Connect | Step::Services | Step::Read => exit_status::BLUETOOTH,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed(String),
+    Failed {
+        error: String,
+        hint: Option<&'static str>,
+    },
+    /// Not tried, because an earlier step failed.
+    Skipped,
+}
+
+/// What each step found, in order.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub steps: Vec<(Step, Outcome)>,
+}
+
+impl Report {
+    /// The first step that failed, if any.
+    pub fn failed_step(&self) -> Option<Step> {
+        self.steps
+            .iter()
+            .find(|(_, outcome)| matches!(outcome, Outcome::Failed { .. }))
+            .map(|(step, _)| *step)
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        self.failed_step().map_or(0, Step::exit_code)
+    }
+
+    /// Record how `step` went, returning what it produced for the next step if it passed.
+    fn record<T>(&mut self, step: Step, result: Result<(T, String)>) -> Option<T> {
+        match result {
+            Ok((value, detail)) => {
+                self.steps.push((step, Outcome::Passed(detail)));
+                Some(value)
+            }
+            Err(e) => {
+                let error = format!("{:#}", e);
+                let hint = hint(step, &error);
+                self.steps.push((step, Outcome::Failed { error, hint }));
+                None
+            }
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (step, outcome) in &self.steps {
+            match outcome {
+                Outcome::Passed(detail) => writeln!(f, "ok    {:<16} {}", step.label(), detail)?,
+                Outcome::Failed { error, hint } => {
+                    writeln!(f, "FAIL  {:<16} {}", step.label(), error)?;
+                    if let Some(hint) = hint {
+                        writeln!(f, "      {:<16} {}", "", hint)?;
+                    }
+                }
+                Outcome::Skipped => writeln!(f, "skip  {}", step.label())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Which sensor to try connecting to: the one with `address`, or else the strongest one
+/// matching `device_pattern`.
+pub struct DoctorTarget<'a> {
+    pub adapter: Option<&'a str>,
+    pub device_pattern: &'a DevicePattern,
+    pub address: Option<&'a str>,
+}
+
+/// Go through the steps up to the first that fails; the rest are reported as skipped.
+pub async fn diagnose(
+    target: &DoctorTarget<'_>,
+    scan_timeout: Duration,
+    op_timeout: Duration,
+) -> Report {
+    let mut report = Report::default();
+    run_steps(&mut report, target, scan_timeout, op_timeout).await;
+    for step in Step::ALL.into_iter().skip(report.steps.len()) {
+        report.steps.push((step, Outcome::Skipped));
+    }
+    report
+}
+
+async fn run_steps(
+    report: &mut Report,
+    target: &DoctorTarget<'_>,
+    scan_timeout: Duration,
+    op_timeout: Duration,
+) -> Option<()> {
+    report.record(
+        Step::BluetoothStack,
+        bluetooth_manager()
+            .await
+            .map(|_| ((), "reachable".to_string())),
+    )?;
+    let central = report.record(Step::Adapter, select_adapter(target.adapter).await)?;
+    let seen = report.record(Step::Scan, scan(&central, scan_timeout).await)?;
+    let peripheral = report.record(Step::Sensor, pick_sensor(&seen, target))?;
+    report.record(
+        Step::Connect,
+        within(op_timeout, "connecting", peripheral.connect())
+            .await
+            .map(|_| ((), "connected".to_string())),
+    )?;
+    let services = within(
+        op_timeout,
+        "discovering services",
+        peripheral.discover_services(),
+    )
+    .await
+    .and_then(|_| {
+        if peripheral
+            .services()
+            .iter()
+            .any(|service| service.uuid == ARANET4_SERVICE_UUID)
+        {
+            let count = peripheral.characteristics().len();
+            Ok((
+                (),
+                format!("Aranet service found, {} characteristics", count),
+            ))
+        } else {
+            Err(eyre!("The device doesn't offer the Aranet service"))
+        }
+    });
+    if report.record(Step::Services, services).is_some() {
+        let model = peripheral
+            .properties()
+            .await
+            .ok()
+            .flatten()
+            .and_then(|properties| properties.local_name)
+            .as_deref()
+            .and_then(AranetModel::detect)
+            .unwrap_or(AranetModel::Aranet4);
+        let read = within(
+            op_timeout,
+            "reading the current measurement",
+            read_model_reading(&peripheral, model),
+        )
+        .await
+        .map(|_| ((), format!("current {} reading received", model)));
+        report.record(Step::Read, read);
+    }
+    let _ = peripheral.disconnect().await;
+    Some(())
+}
+
+/// `op`, failing with [`Aranet4Error::Timeout`] if it takes longer than `timeout`.
+async fn within<T, E, F>(timeout: Duration, what: &str, op: F) -> Result<T>
+where
+    F: Future<Output = Result<T, E>>,
+    Aranet4Error: From<E>,
+{
+    match tokio::time::timeout(timeout, op).await {
+        Ok(result) => Ok(result.map_err(Aranet4Error::from)?),
+        Err(_) => {
+            Err(
+                Aranet4Error::Timeout(format!("{} after {:.0} s", what, timeout.as_secs_f64()))
+                    .into(),
+            )
+        }
+    }
+}
+
+async fn select_adapter(selector: Option<&str>) -> Result<(Adapter, String)> {
+    let central = match selector {
+        Some(selector) => get_adapter(selector).await?,
+        None => get_first_adapter().await?,
+    };
+    let info = central.adapter_info().await?;
+    Ok((central, info))
+}
+
+/// Scan for Aranet sensors, failing if none are seen.
+async fn scan(
+    central: &Adapter,
+    timeout: Duration,
+) -> Result<(Vec<(Peripheral, PeripheralProperties)>, String)> {
+    central
+        .start_scan(ScanFilter {
+            services: vec![ARANET4_SERVICE_UUID],
+        })
+        .await?;
+    tokio::time::sleep(timeout).await;
+    central.stop_scan().await?;
+    let seen = seen_sensors(central).await?;
+    if seen.is_empty() {
+        return Err(Aranet4Error::DeviceNotFound(format!(
+            "No Aranet devices seen within {:.1} s",
+            timeout.as_secs_f64()
+        ))
+        .into());
+    }
+    let names: Vec<String> = seen
+        .iter()
+        .map(|(peripheral, properties)| {
+            properties
+                .local_name
+                .clone()
+                .unwrap_or_else(|| peripheral_address(&peripheral.id(), properties))
+        })
+        .collect();
+    let detail = format!("{} Aranet devices seen: {}", seen.len(), names.join(", "));
+    Ok((seen, detail))
+}
+
+fn pick_sensor(
+    seen: &[(Peripheral, PeripheralProperties)],
+    target: &DoctorTarget<'_>,
+) -> Result<(Peripheral, String)> {
+    let chosen = seen
+        .iter()
+        .filter(|(peripheral, properties)| match target.address {
+            Some(address) => address_matches(&peripheral.id(), properties, address),
+            None => properties
+                .local_name
+                .as_ref()
+                .is_some_and(|name| target.device_pattern.matches(name)),
+        })
+        .max_by_key(|(_, properties)| properties.rssi);
+    let Some((peripheral, properties)) = chosen else {
+        return Err(Aranet4Error::DeviceNotFound(match target.address {
+            Some(address) => format!("None of them has address {}", address),
+            None => format!("None of them matches {}", target.device_pattern),
+        })
+        .into());
+    };
+    let signal = match properties.rssi {
+        Some(rssi) => format!(", {} dBm ({})", rssi, signal_quality(rssi)),
+        None => String::new(),
+    };
+    Ok((
+        peripheral.clone(),
+        format!(
+            "{} at {}{}",
+            properties.local_name.as_deref().unwrap_or("unnamed"),
+            peripheral_address(&peripheral.id(), properties),
+            signal
+        ),
+    ))
+}
+
+/// What usually causes `error` at `step`, and what to do about it.
+fn hint(step: Step, error: &str) -> Option<&'static str> {
+    let error = error.to_lowercase();
+    let linux = cfg!(target_os = "linux");
+    let macos = cfg!(target_os = "macos");
+    if [
+        "notready",
+        "not ready",
+        "powered off",
+        "poweredoff",
+        "rfkill",
+    ]
+    .iter()
+    .any(|text| error.contains(text))
+    {
+        return Some(if linux {
+            "The adapter is off; turn it on with `bluetoothctl power on`, or `rfkill unblock bluetooth` if it is blocked"
+        } else {
+            "Bluetooth is off; turn it on in the system settings"
+        });
+    }
+    if [
+        "notpermitted",
+        "not permitted",
+        "accessdenied",
+        "access denied",
+        "unauthorized",
+    ]
+    .iter()
+    .any(|text| error.contains(text))
+    {
+        return Some(if macos {
+            "Allow Bluetooth for this terminal under System Settings > Privacy & Security > Bluetooth"
+        } else {
+            "This user may not use Bluetooth; on most distributions, add it to the bluetooth group"
+        });
+    }
+    match step {
+        Step::BluetoothStack if linux => Some(
+            "Is bluetoothd running? Check with `systemctl status bluetooth`; in a container, the host's D-Bus socket must be mounted",
+        ),
+        Step::BluetoothStack if macos => Some(
+            "Allow Bluetooth for this terminal under System Settings > Privacy & Security > Bluetooth",
+        ),
+        Step::BluetoothStack => Some("Check that Bluetooth is turned on in Settings"),
+        Step::Adapter => Some(
+            "Check that an adapter is plugged in and enabled; list_adapters shows the ones that can be used",
+        ),
+        Step::Scan if macos => Some(
+            "Check that the sensor is in range with a working battery; a scan also finds nothing while Bluetooth is off or not allowed for this terminal",
+        ),
+        Step::Scan => Some("Check that the sensor is in range and its battery isn't flat"),
+        Step::Sensor => Some("Pick one of the devices above with --device or --address"),
+        Step::Connect => Some(
+            "Move the sensor closer to the adapter, and close the Aranet Home app or anything else connected to it: a sensor takes one connection at a time",
+        ),
+        Step::Services => Some(
+            "The device may not be an Aranet sensor; otherwise, update its firmware with the Aranet Home app",
+        ),
+        Step::Read => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::doctor::{hint, Outcome, Report, Step};
+    use crate::exit_status;
+
+    #[test]
+    fn test_hint_recognizes_power_and_permissions() {
+        let off = hint(
+            Step::Scan,
+            "There was a Bluetooth error: org.bluez.Error.NotReady",
+        );
+        assert!(off.unwrap().to_lowercase().contains("on"));
+        let denied = hint(
+            Step::Adapter,
+            "There was a Bluetooth error: org.freedesktop.DBus.Error.AccessDenied",
+        );
+        assert_ne!(denied, hint(Step::Adapter, "No Bluetooth adapter found"));
+        assert_eq!(hint(Step::Read, "Invalid response"), None);
+    }
+
+    #[test]
+    fn test_report_exit_code_is_first_failure() {
+        let mut report = Report::default();
+        report.steps.push((
+            Step::BluetoothStack,
+            Outcome::Passed("reachable".to_string()),
+        ));
+        assert_eq!(report.exit_code(), 0);
+        report.steps.push((
+            Step::Scan,
+            Outcome::Failed {
+                error: "No Aranet devices seen within 5.0 s".to_string(),
+                hint: None,
+            },
+        ));
+        report.steps.push((Step::Connect, Outcome::Skipped));
+        assert_eq!(report.failed_step(), Some(Step::Scan));
+        assert_eq!(report.exit_code(), exit_status::DEVICE_NOT_FOUND);
+        assert!(report
+            .to_string()
+            .contains("FAIL  Scan             No Aranet devices seen"));
+    }
+}
@@ -0,0 +1,126 @@
+//! History in a DuckDB database, for querying years of readings with SQL without running a
+//! server.
+
+use color_eyre::eyre::Result;
+use duckdb::{params, Connection};
+
+use crate::device::HistoryTime;
+use crate::types::{history_records, CO2Data, HumidityData, PressureData, TemperatureData};
+
+const CREATE_HISTORY_TABLE: &str = "CREATE TABLE IF NOT EXISTS history (
+    device_serial VARCHAR NOT NULL,
+    timestamp TIMESTAMPTZ NOT NULL,
+    temperature REAL NOT NULL,
+    humidity INTEGER NOT NULL,
+    pressure REAL NOT NULL,
+    co2 INTEGER NOT NULL,
+    PRIMARY KEY (device_serial, timestamp)
+)";
+
+const INSERT_HISTORY_ROW: &str = "INSERT INTO history
+    (device_serial, timestamp, temperature, humidity, pressure, co2)
+    VALUES (?, to_timestamp(?), ?, ?, ?, ?)
+    ON CONFLICT DO NOTHING";
+
+/// Append the history to the `history` table of `conn`, creating the table if needed.
+/// Readings already in the table are left alone. Returns the number of new rows.
+pub async fn save_history_duckdb(
+    device_serial: &str,
+    history_time: HistoryTime,
+    temperature: TemperatureData,
+    humidity: HumidityData,
+    pressure: PressureData,
+    co2: CO2Data,
+    conn: &mut Connection,
+) -> Result<usize> {
+    let tx = conn.transaction()?;
+    tx.execute(CREATE_HISTORY_TABLE, [])?;
+    let count_rows = |tx: &duckdb::Transaction| -> duckdb::Result<usize> {
+        tx.query_row(
+            "SELECT COUNT(*) FROM history WHERE device_serial = ?",
+            [device_serial],
+            |row| row.get(0),
+        )
+    };
+    let rows_before = count_rows(&tx)?;
+    {
+        let mut insert = tx.prepare(INSERT_HISTORY_ROW)?;
+        for record in history_records(&history_time, &temperature, &humidity, &pressure, &co2) {
+            let record = record?;
+            insert.execute(params![
+                device_serial,
+                record.timestamp,
+                record.temperature_c,
+                record.humidity_pct,
+                record.pressure_mbar,
+                record.co2_ppm,
+            ])?;
+        }
+    }
+    let rows_after = count_rows(&tx)?;
+    tx.commit()?;
+    Ok(rows_after - rows_before)
+}
+
+/// The timestamp (UNIX time) of the newest reading stored for `device_serial`, if any.
+pub fn last_duckdb_timestamp(conn: &Connection, device_serial: &str) -> Result<Option<i64>> {
+    conn.execute(CREATE_HISTORY_TABLE, [])?;
+    let last = conn.query_row(
+        "SELECT epoch(MAX(timestamp))::BIGINT FROM history WHERE device_serial = ?",
+        [device_serial],
+        |row| row.get(0),
+    )?;
+    Ok(last)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::device::HistoryTime;
+    use crate::duckdb_io::{last_duckdb_timestamp, save_history_duckdb};
+    use crate::types::{CO2Data, HumidityData, PressureData, TemperatureData};
+    use chrono::DateTime;
+    use duckdb::Connection;
+
+    async fn save(conn: &mut Connection, now: i64, bytes: &[u8]) -> usize {
+        let history_time = HistoryTime {
+            num_samples: bytes.len() / 2,
+            update_interval: 300,
+            since_update: 0,
+            now: DateTime::from_timestamp(now, 0).unwrap(),
+            offset: 0,
+        };
+        save_history_duckdb(
+            "317960113191",
+            history_time,
+            TemperatureData::try_from(bytes).unwrap(),
+            HumidityData::try_from(&bytes[..bytes.len() / 2]).unwrap(),
+            PressureData::try_from(bytes).unwrap(),
+            CO2Data::try_from(bytes).unwrap(),
+            conn,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_save_history_duckdb_appends() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(save(&mut conn, 1738621200, &[144, 1, 164, 1]).await, 2);
+        // Five minutes later, the ring buffer has shifted by one reading
+        assert_eq!(save(&mut conn, 1738621500, &[164, 1, 184, 1]).await, 1);
+        let (count, co2): (usize, u16) = conn
+            .query_row(
+                "SELECT COUNT(*), MAX(co2) FROM history WHERE device_serial = '317960113191'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(co2, 440);
+        assert_eq!(
+            last_duckdb_timestamp(&conn, "317960113191").unwrap(),
+            Some(1738621500)
+        );
+        assert_eq!(last_duckdb_timestamp(&conn, "other").unwrap(), None);
+    }
+}
@@ -0,0 +1,65 @@
+//! Exit statuses of the `arachiver` command, so that scripts can tell why it failed.
+//!
+//! `check` uses the Nagios statuses in [`crate::check`] instead, and an interrupted command
+//! exits with 128 plus the signal number.
+
+use std::io;
+
+use btleplug::Error as BtleplugError;
+use color_eyre::Report;
+
+use crate::types::Aranet4Error;
+
+/// Any failure without a more specific status, e.g. an invalid argument.
+pub const FAILURE: i32 = 1;
+/// No Bluetooth adapter, or none matching `--adapter`.
+pub const NO_ADAPTER: i32 = 10;
+/// No sensor matching `--device` or `--address` was seen before the scan timed out.
+pub const DEVICE_NOT_FOUND: i32 = 11;
+/// Talking to the sensor failed, timed out or returned something unexpected.
+pub const BLUETOOTH: i32 = 12;
+/// Reading or writing a file, e.g. an archive, failed.
+pub const OUTPUT: i32 = 13;
+/// `battery --warn-below` found a battery below the threshold.
+pub const LOW_BATTERY: i32 = 14;
+/// The sensor refused to talk until paired; see the `pair` subcommand.
+pub const PAIRING_REQUIRED: i32 = 15;
+
+/// The exit status for `error`, from the first error in its chain with a specific status.
+pub fn failure_exit_code(error: &Report) -> i32 {
+    for cause in error.chain() {
+        if let Some(e) = cause.downcast_ref::<Aranet4Error>() {
+            return e.exit_code();
+        }
+        if cause.is::<BtleplugError>() {
+            return BLUETOOTH;
+        }
+        if cause.is::<io::Error>() {
+            return OUTPUT;
+        }
+    }
+    FAILURE
+}
+
+#[cfg(test)]
+mod test {
+    use color_eyre::eyre::{eyre, WrapErr};
+
+    use crate::exit_status::{failure_exit_code, DEVICE_NOT_FOUND, FAILURE, OUTPUT};
+    use crate::types::Aranet4Error;
+
+    #[test]
+    fn test_failure_exit_code() {
+        let not_found: color_eyre::Result<()> =
+            Err(Aranet4Error::DeviceNotFound("No device matching 'Aranet'".to_string()).into());
+        let not_found = not_found.wrap_err("Could not connect").unwrap_err();
+        assert_eq!(failure_exit_code(&not_found), DEVICE_NOT_FOUND);
+
+        let create = std::fs::File::create("/nonexistent/history.csv")
+            .wrap_err("Could not create /nonexistent/history.csv")
+            .unwrap_err();
+        assert_eq!(failure_exit_code(&create), OUTPUT);
+
+        assert_eq!(failure_exit_code(&eyre!("Invalid --hours")), FAILURE);
+    }
+}
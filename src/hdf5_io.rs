@@ -0,0 +1,135 @@
+//! History as an HDF5 file, for analysis pipelines built on h5py. Only built with the `hdf5`
+//! feature, as it links the HDF5 C library.
+
+use std::path::Path;
+
+use color_eyre::eyre::Result;
+use hdf5::types::VarLenUnicode;
+use hdf5::{Dataset, File, H5Type};
+
+use crate::device::HistoryTime;
+use crate::types::{
+    history_records, split_label, CO2Data, HistoryRecord, HumidityData, Metadata, PressureData,
+    TemperatureData, Units,
+};
+
+/// Unit of the `/time` dataset, in the form CF-aware tools like xarray decode.
+const TIME_UNITS: &str = "seconds since 1970-01-01T00:00:00Z";
+
+fn write_dataset<T: H5Type>(file: &File, name: &str, values: &[T], units: &str) -> Result<()> {
+    let dataset: Dataset = file.new_dataset_builder().with_data(values).create(name)?;
+    let units: VarLenUnicode = units.parse()?;
+    dataset
+        .new_attr::<VarLenUnicode>()
+        .create("units")?
+        .write_scalar(&units)?;
+    Ok(())
+}
+
+/// Write the history to a new HDF5 file at `path`, with the one-dimensional datasets `/time`,
+/// `/temperature`, `/humidity`, `/pressure` and `/co2`, each with a `units` attribute.
+/// HDF5 files can't be streamed, hence the path instead of a writer.
+pub async fn save_history_hdf5(
+    path: &Path,
+    history_time: HistoryTime,
+    temperature: TemperatureData,
+    humidity: HumidityData,
+    pressure: PressureData,
+    co2: CO2Data,
+    units: Units,
+) -> Result<()> {
+    let records: Vec<HistoryRecord> =
+        history_records(&history_time, &temperature, &humidity, &pressure, &co2)
+            .collect::<Result<_>>()?;
+    let file = File::create(path)?;
+    let times: Vec<i64> = records.iter().map(|r| r.timestamp).collect();
+    write_dataset(&file, "time", &times, TIME_UNITS)?;
+    let temperature_values: Vec<f32> = records
+        .iter()
+        .map(|r| r.temperature_in(units) as f32)
+        .collect();
+    write_dataset(
+        &file,
+        "temperature",
+        &temperature_values,
+        split_label(TemperatureData::unit_label(units)).1,
+    )?;
+    let humidity_values: Vec<i32> = records.iter().map(|r| i32::from(r.humidity_pct)).collect();
+    write_dataset(
+        &file,
+        "humidity",
+        &humidity_values,
+        split_label(HumidityData::unit_label(units)).1,
+    )?;
+    let pressure_values: Vec<f32> = records
+        .iter()
+        .map(|r| r.pressure_in(units) as f32)
+        .collect();
+    write_dataset(
+        &file,
+        "pressure",
+        &pressure_values,
+        split_label(PressureData::unit_label(units)).1,
+    )?;
+    let co2_values: Vec<i32> = records.iter().map(|r| i32::from(r.co2_ppm)).collect();
+    write_dataset(
+        &file,
+        "co2",
+        &co2_values,
+        split_label(CO2Data::unit_label(units)).1,
+    )?;
+    file.close()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use hdf5::types::VarLenUnicode;
+
+    use crate::device::HistoryTime;
+    use crate::hdf5_io::save_history_hdf5;
+    use crate::types::{CO2Data, HumidityData, PressureData, TemperatureData, Units};
+
+    #[tokio::test]
+    async fn test_save_history_hdf5() {
+        let history_time = HistoryTime {
+            num_samples: 2,
+            update_interval: 300,
+            since_update: 0,
+            now: chrono::DateTime::from_timestamp(1738621329, 0).unwrap(),
+            offset: 0,
+        };
+        let path = std::env::temp_dir().join("arachiver_test_history.h5");
+        save_history_hdf5(
+            &path,
+            history_time,
+            TemperatureData {
+                values: vec![413, 414],
+            },
+            HumidityData {
+                values: vec![33, 34],
+            },
+            PressureData {
+                values: vec![10174, 10173],
+            },
+            CO2Data {
+                values: vec![926, 930],
+            },
+            Units::Metric,
+        )
+        .await
+        .unwrap();
+        let file = hdf5::File::open(&path).unwrap();
+        let time: Vec<i64> = file.dataset("time").unwrap().read_raw().unwrap();
+        let co2 = file.dataset("co2").unwrap();
+        let co2_values: Vec<i32> = co2.read_raw().unwrap();
+        let co2_units: VarLenUnicode = co2.attr("units").unwrap().read_scalar().unwrap();
+        let pressure: Vec<f32> = file.dataset("pressure").unwrap().read_raw().unwrap();
+        drop(file);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(time, vec![1738621029, 1738621329]);
+        assert_eq!(co2_values, vec![926, 930]);
+        assert_eq!(co2_units.as_str(), "ppm");
+        assert_eq!(pressure, vec![1017.4, 1017.3]);
+    }
+}
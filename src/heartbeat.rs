@@ -0,0 +1,71 @@
+//! Heartbeat pings to a monitoring service such as Healthchecks.io, so that an archiving cron
+//! job on a headless box gets noticed when it starts failing, or stops running at all.
+
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use tracing::warn;
+
+use crate::device::RetryPolicy;
+
+fn get(url: &str) -> Result<()> {
+    match ureq::get(url).timeout(Duration::from_secs(10)).call() {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(status, _)) => {
+            Err(eyre!("{} answered the ping with status {}", url, status))
+        }
+        Err(e) => Err(e).wrap_err_with(|| format!("Could not reach {}", url)),
+    }
+}
+
+/// GET `url`, retrying as `retry` allows, since a ping lost to a flaky network would raise a
+/// false alarm.
+pub async fn ping(url: &str, retry: RetryPolicy) -> Result<()> {
+    let mut attempt = 0;
+    loop {
+        let target = url.to_string();
+        match tokio::task::spawn_blocking(move || get(&target)).await? {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < retry.retries => {
+                warn!("{:#}; retrying ({}/{})", e, attempt + 1, retry.retries);
+                tokio::time::sleep(retry.delay_before_retry(attempt)).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    use crate::device::RetryPolicy;
+    use crate::heartbeat::ping;
+
+    #[tokio::test]
+    async fn test_ping() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ping/abc", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let read = stream.read(&mut request).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nOK")
+                .unwrap();
+            String::from_utf8_lossy(&request[..read]).into_owned()
+        });
+        ping(&url, RetryPolicy::NONE).await.unwrap();
+        assert!(server
+            .join()
+            .unwrap()
+            .starts_with("GET /ping/abc HTTP/1.1\r\n"));
+
+        // Nothing listens on the discard port
+        assert!(ping("http://127.0.0.1:9/", RetryPolicy::NONE)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use std::io::Write;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use tracing::warn;
+
+use crate::client::Aranet4Client;
+use crate::device::HistoryTime;
+use crate::monitor::reading_timestamp;
+use crate::shutdown::shutdown_signal;
+use crate::types::{
+    history_records, CO2Data, CurrentSensorMeasurement, HistoryRecord, HumidityData, PressureData,
+    TemperatureData,
+};
+
+/// Every point is written to this measurement, tagged with the device name and serial number.
+pub const MEASUREMENT: &str = "aranet4";
+
+/// An InfluxDB v2 bucket to write to.
+#[derive(Debug, Clone)]
+pub struct InfluxConfig {
+    /// Base URL of the server, e.g. `http://localhost:8086`.
+    pub url: String,
+    pub org: String,
+    pub bucket: String,
+    pub token: Option<String>,
+}
+
+/// Escape a tag key or value: commas, equals signs and spaces need a backslash.
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | '=' | ' ') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn tags(name: &str, serial: &str) -> String {
+    format!(
+        "{},device={},serial={}",
+        MEASUREMENT,
+        escape_tag(name),
+        escape_tag(serial.trim())
+    )
+}
+
+/// A current reading as a line of line protocol, with second precision.
+pub fn current_reading_line(
+    name: &str,
+    serial: &str,
+    reading: &CurrentSensorMeasurement,
+) -> String {
+    format!(
+        "{} co2={}i,temperature={},pressure={},humidity={}i,battery={}i {}",
+        tags(name, serial),
+        reading.co2_ppm(),
+        reading.temperature_value(),
+        reading.pressure_value(),
+        reading.humidity_percent(),
+        reading.battery_percent(),
+        reading_timestamp(reading)
+    )
+}
+
+/// Write the history as line protocol, one point per reading, with second precision.
+#[allow(clippy::too_many_arguments)]
+pub async fn save_history_influx<W: Write>(
+    name: &str,
+    serial: &str,
+    history_time: HistoryTime,
+    temperature: TemperatureData,
+    humidity: HumidityData,
+    pressure: PressureData,
+    co2: CO2Data,
+    dest: &mut W,
+) -> Result<()> {
+    write_history_influx(
+        name,
+        serial,
+        history_records(&history_time, &temperature, &humidity, &pressure, &co2),
+        dest,
+    )
+}
+
+/// Like [`save_history_influx`], for readings from any source, e.g. an archive being converted.
+pub fn write_history_influx<W: Write>(
+    name: &str,
+    serial: &str,
+    records: impl IntoIterator<Item = Result<HistoryRecord>>,
+    dest: &mut W,
+) -> Result<()> {
+    let tags = tags(name, serial);
+    for record in records {
+        let record = record?;
+        writeln!(
+            dest,
+            "{} temperature={},humidity={}i,pressure={},co2={}i {}",
+            tags,
+            record.temperature_c,
+            record.humidity_pct,
+            record.pressure_mbar,
+            record.co2_ppm,
+            record.timestamp
+        )?;
+    }
+    Ok(())
+}
+
+/// POST line protocol to the `/api/v2/write` endpoint of `config`.
+pub async fn write_lines(config: &InfluxConfig, body: String) -> Result<()> {
+    let url = format!("{}/api/v2/write", config.url.trim_end_matches('/'));
+    let config = config.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut request = ureq::post(&url)
+            .timeout(Duration::from_secs(30))
+            .query("org", &config.org)
+            .query("bucket", &config.bucket)
+            .query("precision", "s")
+            .set("Content-Type", "text/plain; charset=utf-8");
+        if let Some(token) = &config.token {
+            request = request.set("Authorization", &format!("Token {}", token));
+        }
+        match request.send_string(&body) {
+            Ok(_) => Ok(()),
+            Err(ureq::Error::Status(status, response)) => Err(eyre!(
+                "InfluxDB rejected the write with status {}: {}",
+                status,
+                response.into_string().unwrap_or_default().trim()
+            )),
+            Err(e) => Err(e).wrap_err_with(|| format!("Could not reach InfluxDB at {}", url)),
+        }
+    })
+    .await?
+}
+
+/// Write a reading to InfluxDB every `interval` until interrupted with Ctrl-C or SIGTERM.
+/// Failed readings and writes are reported on stderr and don't stop the loop.
+pub async fn publish_readings(
+    sensor: &Aranet4Client,
+    config: &InfluxConfig,
+    interval: Duration,
+) -> Result<()> {
+    let name = sensor.local_name().await.unwrap_or_default();
+    let serial = sensor.serial_number().await?;
+    let mut ticker = tokio::time::interval(interval);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = ticker.tick() => {
+                match sensor.read_current().await {
+                    Ok(reading) => {
+                        let line = current_reading_line(&name, &serial, &reading);
+                        if let Err(e) = write_lines(config, line).await {
+                            warn!("Failed to write reading: {:#}", e);
+                        }
+                    }
+                    Err(e) => warn!("Failed to take reading: {}", e),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::device::HistoryTime;
+    use crate::influx_io::save_history_influx;
+    use crate::types::{CO2Data, HumidityData, PressureData, TemperatureData};
+    use chrono::DateTime;
+
+    #[tokio::test]
+    async fn test_save_history_influx() {
+        let bytes = [157u8, 1, 164, 1];
+        let history_time = HistoryTime {
+            num_samples: 2,
+            update_interval: 300,
+            since_update: 24,
+            now: DateTime::from_timestamp(1738621353, 0).unwrap(),
+            offset: 0,
+        };
+        let mut output = Vec::new();
+        save_history_influx(
+            "Aranet4 1BA27",
+            "317960113191",
+            history_time,
+            TemperatureData::try_from(&bytes[..]).unwrap(),
+            HumidityData::try_from(&bytes[0..2]).unwrap(),
+            PressureData::try_from(&bytes[..]).unwrap(),
+            CO2Data::try_from(&bytes[..]).unwrap(),
+            &mut output,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "aranet4,device=Aranet4\\ 1BA27,serial=317960113191 temperature=20.65,humidity=157i,pressure=41.3,co2=413i 1738621029\n\
+             aranet4,device=Aranet4\\ 1BA27,serial=317960113191 temperature=21,humidity=1i,pressure=42,co2=420i 1738621329\n"
+        );
+    }
+}
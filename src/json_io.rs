@@ -0,0 +1,378 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use color_eyre::eyre::{Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use crate::device::{DeviceInfo, HistoryTime, SensorStatus};
+use crate::types::{
+    history_records, CO2Data, CalibrationState, Co2Indicator, CurrentSensorMeasurement,
+    DerivedHumidity, HistoryRecord, HumidityData, Metadata, PressureData, TemperatureData, Units,
+};
+
+/// A single current reading as emitted by `readout --format json` and the NDJSON monitor sink.
+#[derive(Debug, Clone, Serialize)]
+pub struct CurrentReadingRecord {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub timestamp: i64,
+    pub co2: u16,
+    pub temperature: f64,
+    pub pressure: f64,
+    pub humidity: u8,
+    pub battery: u8,
+    /// The raw status byte, decoded in `co2_indicator` and `calibration`.
+    pub status: u8,
+    pub co2_indicator: Co2Indicator,
+    pub calibration: CalibrationState,
+    pub interval: u16,
+    pub ago: u16,
+    /// Dew point and absolute humidity, with `readout --derived`.
+    #[serde(flatten)]
+    pub derived: Option<DerivedHumidity>,
+}
+
+impl CurrentReadingRecord {
+    pub fn new(name: Option<String>, timestamp: i64, reading: &CurrentSensorMeasurement) -> Self {
+        CurrentReadingRecord {
+            name,
+            timestamp,
+            co2: reading.co2,
+            temperature: reading.temperature_value(),
+            pressure: reading.pressure_value(),
+            humidity: reading.humidity,
+            battery: reading.battery,
+            status: reading.status,
+            co2_indicator: reading.status_flags().co2_indicator(),
+            calibration: reading.status_flags().calibration(),
+            interval: reading.interval,
+            ago: reading.ago,
+            derived: None,
+        }
+    }
+
+    /// Add the dew point and absolute humidity derived from the reading.
+    pub fn with_derived(mut self, reading: &CurrentSensorMeasurement) -> Self {
+        self.derived = Some(DerivedHumidity::new(
+            reading.temperature(),
+            reading.humidity(),
+        ));
+        self
+    }
+}
+
+/// The device information as emitted by `device_info --format json` or `yaml`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceInfoRecord {
+    #[serde(flatten)]
+    pub info: DeviceInfo,
+    /// Signal strength in dBm, if the platform has reported it.
+    pub rssi: Option<i16>,
+}
+
+/// The stored history summary as emitted by `status --format json` or `yaml`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SensorStatusRecord {
+    pub name: String,
+    #[serde(flatten)]
+    pub status: SensorStatus,
+    /// Seconds from the oldest stored reading to now.
+    pub history_span: u64,
+    /// When the oldest stored reading was measured, in UNIX time.
+    pub oldest_timestamp: i64,
+}
+
+impl SensorStatusRecord {
+    pub fn new(name: String, status: SensorStatus, now: i64) -> Self {
+        let history_span = status.history_span().as_secs();
+        SensorStatusRecord {
+            name,
+            status,
+            history_span,
+            oldest_timestamp: now - history_span as i64,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct JsonUnits {
+    timestamp: &'static str,
+    temperature: &'static str,
+    humidity: &'static str,
+    pressure: &'static str,
+    co2: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dew_point: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    absolute_humidity: Option<&'static str>,
+}
+
+const UNITS: JsonUnits = JsonUnits {
+    timestamp: "UNIX time",
+    temperature: TemperatureData::LABEL,
+    humidity: HumidityData::LABEL,
+    pressure: PressureData::LABEL,
+    co2: CO2Data::LABEL,
+    dew_point: None,
+    absolute_humidity: None,
+};
+
+/// A history reading, with the quantities derived from it if they were asked for.
+#[derive(Debug, Serialize)]
+struct HistoryReading {
+    #[serde(flatten)]
+    record: HistoryRecord,
+    #[serde(flatten)]
+    derived: Option<DerivedHumidity>,
+}
+
+impl HistoryReading {
+    fn new(record: HistoryRecord, derived: bool) -> Self {
+        HistoryReading {
+            record,
+            derived: derived.then(|| record.derived()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct HistoryDocument {
+    units: JsonUnits,
+    readings: Vec<HistoryReading>,
+}
+
+impl HistoryDocument {
+    pub(crate) fn new(readings: Vec<HistoryRecord>) -> Self {
+        Self::with_derived(readings, false)
+    }
+
+    /// A document whose readings also have their dew point and absolute humidity if `derived`.
+    pub(crate) fn with_derived(readings: Vec<HistoryRecord>, derived: bool) -> Self {
+        let mut units = UNITS;
+        if derived {
+            units.dew_point = Some(DerivedHumidity::dew_point_label(Units::Metric));
+            units.absolute_humidity = Some(DerivedHumidity::ABSOLUTE_HUMIDITY_LABEL);
+        }
+        HistoryDocument {
+            units,
+            readings: readings
+                .into_iter()
+                .map(|record| HistoryReading::new(record, derived))
+                .collect(),
+        }
+    }
+}
+
+/// Write the history as a single JSON document with a `units` object and a `readings` array.
+/// With `derived`, each reading also has its `dew_point` and `absolute_humidity`.
+pub async fn save_history_json<W: Write>(
+    history_time: HistoryTime,
+    temperature: TemperatureData,
+    humidity: HumidityData,
+    pressure: PressureData,
+    co2: CO2Data,
+    derived: bool,
+    dest: &mut W,
+) -> Result<()> {
+    write_history_json(
+        history_records(&history_time, &temperature, &humidity, &pressure, &co2),
+        derived,
+        dest,
+    )
+}
+
+/// Like [`save_history_json`], for readings from any source, e.g. an archive being converted.
+pub fn write_history_json<W: Write>(
+    records: impl IntoIterator<Item = Result<HistoryRecord>>,
+    derived: bool,
+    dest: &mut W,
+) -> Result<()> {
+    let document =
+        HistoryDocument::with_derived(records.into_iter().collect::<Result<_>>()?, derived);
+    serde_json::to_writer_pretty(&mut *dest, &document)?;
+    writeln!(dest)?;
+    Ok(())
+}
+
+/// Write the history as newline-delimited JSON, one reading per line, with the derived
+/// quantities as for [`save_history_json`].
+pub async fn save_history_ndjson<W: Write>(
+    history_time: HistoryTime,
+    temperature: TemperatureData,
+    humidity: HumidityData,
+    pressure: PressureData,
+    co2: CO2Data,
+    derived: bool,
+    dest: &mut W,
+) -> Result<()> {
+    write_history_ndjson(
+        history_records(&history_time, &temperature, &humidity, &pressure, &co2),
+        derived,
+        dest,
+    )
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryDocumentReadings {
+    readings: Vec<HistoryRecord>,
+}
+
+/// Read back the readings of a JSON document written by [`save_history_json`].
+pub(crate) fn read_history_json(path: &Path) -> Result<Vec<HistoryRecord>> {
+    let contents =
+        fs::read_to_string(path).wrap_err_with(|| format!("Could not read {}", path.display()))?;
+    let document: HistoryDocumentReadings = serde_json::from_str(&contents)
+        .wrap_err_with(|| format!("{} isn't a JSON history archive", path.display()))?;
+    Ok(document.readings)
+}
+
+/// Read back the readings of a newline-delimited JSON file written by [`save_history_ndjson`].
+pub(crate) fn read_history_ndjson(path: &Path) -> Result<Vec<HistoryRecord>> {
+    let contents =
+        fs::read_to_string(path).wrap_err_with(|| format!("Could not read {}", path.display()))?;
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line).wrap_err_with(|| {
+                format!("Invalid reading on line {} of {}", i + 1, path.display())
+            })
+        })
+        .collect()
+}
+
+/// Like [`save_history_ndjson`], for readings from any source.
+pub fn write_history_ndjson<W: Write>(
+    records: impl IntoIterator<Item = Result<HistoryRecord>>,
+    derived: bool,
+    dest: &mut W,
+) -> Result<()> {
+    for record in records {
+        serde_json::to_writer(&mut *dest, &HistoryReading::new(record?, derived))?;
+        writeln!(dest)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::device::{HistoryTime, SensorStatus};
+    use crate::json_io::{
+        save_history_json, save_history_ndjson, write_history_json, write_history_ndjson,
+        SensorStatusRecord,
+    };
+    use crate::types::{
+        CO2Data, Co2IndicatorThresholds, HistoryRecord, HumidityData, PressureData, TemperatureData,
+    };
+    use chrono::DateTime;
+
+    fn history() -> (
+        HistoryTime,
+        TemperatureData,
+        HumidityData,
+        PressureData,
+        CO2Data,
+    ) {
+        let bytes = [157u8, 1, 164, 1];
+        (
+            HistoryTime {
+                num_samples: 2,
+                update_interval: 300,
+                since_update: 24,
+                now: DateTime::from_timestamp(1738621353, 0).unwrap(),
+                offset: 0,
+            },
+            TemperatureData::try_from(&bytes[..]).unwrap(),
+            HumidityData::try_from(&bytes[0..2]).unwrap(),
+            PressureData::try_from(&bytes[..]).unwrap(),
+            CO2Data::try_from(&bytes[..]).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_save_history_ndjson() {
+        let (ht, t, h, p, c) = history();
+        let mut output = Vec::new();
+        save_history_ndjson(ht, t, h, p, c, false, &mut output)
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"timestamp\":1738621029,\"temperature\":20.65,\"humidity\":157,\"pressure\":41.3,\"co2\":413}\n\
+             {\"timestamp\":1738621329,\"temperature\":21.0,\"humidity\":1,\"pressure\":42.0,\"co2\":420}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_history_json() {
+        let (ht, t, h, p, c) = history();
+        let mut output = Vec::new();
+        save_history_json(ht, t, h, p, c, false, &mut output)
+            .await
+            .unwrap();
+        let document: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(document["units"]["co2"], "CO₂ (ppm)");
+        assert_eq!(document["readings"].as_array().unwrap().len(), 2);
+        assert_eq!(document["readings"][1]["timestamp"], 1738621329);
+    }
+
+    #[test]
+    fn test_write_history_json_derived() {
+        let records = [
+            HistoryRecord {
+                timestamp: 1738621029,
+                temperature_c: 20.65,
+                humidity_pct: 33,
+                pressure_mbar: 1017.4,
+                co2_ppm: 926,
+            },
+            HistoryRecord {
+                timestamp: 1738621329,
+                temperature_c: 18.0,
+                humidity_pct: 0,
+                pressure_mbar: 1017.3,
+                co2_ppm: 930,
+            },
+        ];
+        let mut output = Vec::new();
+        write_history_ndjson(records.map(Ok), true, &mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"timestamp\":1738621029,\"temperature\":20.65,\"humidity\":33,\"pressure\":1017.4,\"co2\":926,\"dew_point\":3.8,\"absolute_humidity\":5.9}\n\
+             {\"timestamp\":1738621329,\"temperature\":18.0,\"humidity\":0,\"pressure\":1017.3,\"co2\":930,\"dew_point\":null,\"absolute_humidity\":0.0}\n"
+        );
+
+        let mut output = Vec::new();
+        write_history_json(records.map(Ok), true, &mut output).unwrap();
+        let document: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(document["units"]["dew_point"], "Dew point (°C)");
+        assert_eq!(document["readings"][0]["absolute_humidity"], 5.9);
+
+        let mut output = Vec::new();
+        write_history_json(records.map(Ok), false, &mut output).unwrap();
+        let document: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert!(document["units"].get("dew_point").is_none());
+        assert!(document["readings"][0].get("dew_point").is_none());
+    }
+
+    #[test]
+    fn test_sensor_status_record_is_flat() {
+        let status = SensorStatus {
+            total_readings: 3,
+            update_interval: 300,
+            since_update: 24,
+            battery: 85,
+            co2_thresholds: Some(Co2IndicatorThresholds::DEFAULT),
+        };
+        let record = SensorStatusRecord::new("Aranet4 1BA27".to_string(), status, 1738621353);
+        let json: serde_json::Value = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["name"], "Aranet4 1BA27");
+        assert_eq!(json["total_readings"], 3);
+        assert_eq!(json["history_span"], 624);
+        assert_eq!(json["oldest_timestamp"], 1738620729);
+        assert_eq!(json["co2_thresholds"]["yellow"], 1000);
+        assert_eq!(json["co2_thresholds"]["red"], 1400);
+    }
+}
@@ -0,0 +1,65 @@
+//! Archive data from Aranet4 CO₂ sensors over Bluetooth LE.
+//!
+//! The [`Aranet4Client`] is the simplest entry point; the lower-level building blocks live in
+//! [`device`], and the [`csv_io`], [`json_io`] and [`parquet_io`] modules serialize downloaded
+//! history.
+
+pub mod advertisement;
+pub mod arrow_io;
+pub mod atomic_file;
+pub mod check;
+pub mod cli;
+pub mod client;
+pub mod clock;
+pub mod combined;
+pub mod compression;
+pub mod convert;
+pub mod csv_io;
+pub mod daemon;
+pub mod dashboard;
+pub mod device;
+pub mod device_cache;
+pub mod doctor;
+#[cfg(feature = "duckdb")]
+pub mod duckdb_io;
+pub mod exit_status;
+#[cfg(feature = "hdf5")]
+pub mod hdf5_io;
+pub mod heartbeat;
+pub mod influx_io;
+pub mod json_io;
+pub mod manpage;
+pub mod merge;
+pub mod mock;
+pub mod monitor;
+pub mod mqtt;
+pub mod msgpack_io;
+pub mod naming;
+pub mod notify;
+pub mod pairing;
+pub mod parquet_io;
+pub mod plot;
+#[cfg(feature = "postgres")]
+pub mod postgres_io;
+pub mod quantity;
+pub mod reading_cache;
+pub mod server;
+pub mod shutdown;
+pub mod sqlite_io;
+pub mod state;
+pub mod stats;
+pub mod time_range;
+pub mod timing;
+pub mod transport;
+pub mod types;
+pub mod upload;
+pub mod watch;
+pub mod webhook;
+pub mod xlsx_io;
+
+pub use client::Aranet4Client;
+pub use device::{DeviceInfo, History, HistoryMetric, HistoryProgress, HistoryTime, SensorStatus};
+pub use types::{
+    Aranet4Error, AranetModel, Co2IndicatorThresholds, CurrentSensorMeasurement, HistorySeries,
+    MetricKind, StatusFlags, Units,
+};
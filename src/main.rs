@@ -1,47 +1,133 @@
-use std::fs::File;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::future::Future;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
-use btleplug::api::{Central, Manager as _};
-use btleplug::platform::{Manager, Peripheral};
-use chrono::Local;
-use clap::{Arg, Command};
-use color_eyre::eyre::{eyre, Error, Result};
+use btleplug::api::Central as _;
+use btleplug::platform::Adapter;
+use chrono::{DateTime, Local, TimeDelta, Utc};
+use clap::{CommandFactory, Parser};
+use color_eyre::eyre::{eyre, Error, Result, WrapErr};
+use futures::StreamExt;
+use indicatif::{ProgressBar, ProgressStyle};
+use rusqlite::Connection;
+use serde::Serialize;
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
 use unicode_segmentation::UnicodeSegmentation;
 
-mod csv_io;
-mod device;
-mod parquet_io;
-mod types;
-use crate::csv_io::save_history_csv;
-use crate::device::{
-    get_current_sensor_data, get_history, get_local_name, scan_for_sensor, DeviceInfo,
+use arachiver::advertisement::listen_for_advertisements;
+use arachiver::arrow_io::save_history_arrow;
+use arachiver::atomic_file::AtomicFile;
+use arachiver::check::{
+    run_alert_hook, status_line, CheckStatus, Co2Thresholds, UNKNOWN_EXIT_CODE,
 };
-use crate::parquet_io::save_history_parquet;
-use crate::types::CurrentSensorMeasurement;
-
-fn cli() -> Command {
-    Command::new("arachiver")
-        .about("Aranet4 archiver")
-        .subcommand_required(true)
-        .arg_required_else_help(true)
-        .arg(
-            Arg::new("device_pattern")
-                .short('d')
-                .long("device")
-                .default_value("Aranet")
-                .required(false)
-                .help("Select an Aranet4 device with <device_pattern> in its name; by default, the first device with 'Aranet' in its name will be used"),
-        )
-        .subcommand(Command::new("device_info").about("Print device information"))
-        .subcommand(Command::new("readout").about("Print the current sensor readings to stdout"))
-        .subcommand(Command::new("archive_history_csv").about("Save the full history to CSV"))
-        .subcommand(
-            Command::new("archive_history_parquet").about("Save the full history to Parquet"),
-        )
+use arachiver::cli::{
+    BatchArgs, Cli, CombinedArgs, Commands, DaemonArgs, DeviceArgs, InfoFormat, LogFormat,
+    ReadingFormat, ReadoutArgs, UploadArgs,
+};
+use arachiver::combined::{CombinedFormat, CombinedHistory, DeviceHistory};
+use arachiver::compression::{CompressedWriter, Compression};
+use arachiver::convert::{convert_archive, ConvertOptions};
+use arachiver::csv_io::{
+    append_history_csv, check_csv_header, last_csv_timestamp, save_csv_sidecar, save_history_csv,
+    save_model_history_csv, write_csv_comment_header, CsvMetadata, CsvTimestamps,
+};
+use arachiver::daemon::{
+    notify_ready, notify_stopping, notify_watchdog, systemd_unit, watchdog_interval, DaemonConfig,
+    HistoryFormat, HistoryJob, ReadoutJob,
+};
+use arachiver::dashboard::dashboard;
+use arachiver::device::{
+    get_adapter, get_first_adapter, list_adapters, scan_nearby, signal_quality, DevicePattern,
+    HistoryMetric, HistoryProgress, HistoryTransfer, NearbyDevice, RetryPolicy,
+};
+use arachiver::device_cache::DeviceCache;
+use arachiver::doctor::{diagnose, DoctorTarget};
+#[cfg(feature = "duckdb")]
+use arachiver::duckdb_io::{last_duckdb_timestamp, save_history_duckdb};
+use arachiver::exit_status::{self, failure_exit_code};
+#[cfg(feature = "hdf5")]
+use arachiver::hdf5_io::save_history_hdf5;
+use arachiver::heartbeat::ping;
+use arachiver::influx_io::{save_history_influx, write_lines, InfluxConfig};
+use arachiver::json_io::{
+    save_history_json, save_history_ndjson, CurrentReadingRecord, DeviceInfoRecord,
+    SensorStatusRecord,
+};
+use arachiver::manpage::write_man_page;
+use arachiver::merge::merge_archives;
+use arachiver::monitor::{
+    current_readings, monitor, open_sink, reading_timestamp, ReadingSchedule, ReadingSink,
+    SinkFormat,
+};
+use arachiver::mqtt::{parse_broker_url, publish_readings, MqttConfig};
+use arachiver::msgpack_io::save_history_msgpack;
+use arachiver::naming::{
+    render_filename, uses_placeholder, FilenameFields, DEFAULT_TEMPLATE,
+    DEFAULT_TEMPLATE_WITH_SERIAL,
+};
+use arachiver::notify::Co2Alert;
+use arachiver::pairing::pair;
+use arachiver::parquet_io::{
+    check_parquet_units, last_parquet_timestamp, merge_history_parquet, save_history_parquet,
+    ArchiveSource, TimestampUnit,
+};
+use arachiver::plot::{render_terminal_plot, save_history_chart, ChartFormat};
+#[cfg(feature = "postgres")]
+use arachiver::postgres_io::{check_table_name, last_postgres_timestamp, save_history_postgres};
+use arachiver::reading_cache::ReadingCache;
+use arachiver::server::serve;
+use arachiver::shutdown::shutdown_signal;
+use arachiver::sqlite_io::{last_sqlite_timestamp, save_history_sqlite};
+use arachiver::state::{DeviceSyncState, SyncState};
+use arachiver::stats::HistoryStats;
+use arachiver::time_range::{format_span, HistoryRange};
+use arachiver::timing::TransferTiming;
+use arachiver::types::{history_records, DerivedHumidity, HistoryRecord, ModelMeasurement};
+use arachiver::upload::UploadTarget;
+use arachiver::watch::watch;
+use arachiver::webhook::{post_history, WebhookConfig};
+use arachiver::xlsx_io::save_history_xlsx;
+use arachiver::{
+    Aranet4Client, Aranet4Error, AranetModel, Co2IndicatorThresholds, CurrentSensorMeasurement,
+    DeviceInfo, History, SensorStatus, Units,
+};
+
+/// e.g. `-62 dBm (good)`, or `-` if the platform hasn't reported a signal strength.
+fn format_rssi(rssi: Option<i16>) -> String {
+    match rssi {
+        Some(rssi) => format!("{} dBm ({})", rssi, signal_quality(rssi)),
+        None => "-".to_string(),
+    }
 }
 
-fn print_device_info(info: &DeviceInfo) {
+/// e.g. `yellow from 1000 ppm, red from 1400 ppm`, or `-` if the sensor doesn't report its
+/// thresholds.
+fn format_co2_thresholds(thresholds: Option<Co2IndicatorThresholds>) -> String {
+    match thresholds {
+        Some(thresholds) => thresholds.to_string(),
+        None => "-".to_string(),
+    }
+}
+
+/// A bar from -100 dBm (empty) to -40 dBm (full), for watching the signal change.
+fn signal_bar(rssi: i16) -> String {
+    const WIDTH: i16 = 20;
+    let filled = ((rssi.clamp(-100, -40) + 100) * WIDTH / 60) as usize;
+    format!(
+        "{}{}",
+        "█".repeat(filled),
+        "░".repeat(WIDTH as usize - filled)
+    )
+}
+
+fn print_device_info(info: &DeviceInfo, rssi: Option<i16>) {
     println!(
-        "{}\n{}\nModel number: {}\nSerial number: {}\nHardware revision: {}\nSoftware revision: {}\nManufacturer name: {}\nFirmware revision: {}",
+        "{}\n{}\nModel number: {}\nSerial number: {}\nHardware revision: {}\nSoftware revision: {}\nManufacturer name: {}\nFirmware revision: {}\nBattery: {}%\nCO₂ thresholds: {}\nSignal: {}",
         info.device_name,
         "=".repeat(info.device_name.graphemes(true).count()),
         info.model_number,
@@ -49,94 +135,2691 @@ fn print_device_info(info: &DeviceInfo) {
         info.hardware_revision,
         info.software_revision,
         info.manufacturer_name,
-        info.firmware_revision
+        info.firmware_revision,
+        info.battery,
+        format_co2_thresholds(info.co2_thresholds),
+        format_rssi(rssi)
     );
 }
 
-fn print_current_sensor_data(sensor_name: &str, measurement: &CurrentSensorMeasurement) {
+/// The JSON a run prints, which is a single document on stdout: one sensor's record, or with
+/// `--all` an array of every sensor's, printed once they have all been read.
+struct JsonOutput {
+    records: Option<Vec<serde_json::Value>>,
+}
+
+impl JsonOutput {
+    fn new(array: bool) -> Self {
+        JsonOutput {
+            records: array.then(Vec::new),
+        }
+    }
+
+    fn print<T: Serialize>(&mut self, record: &T) -> Result<()> {
+        match &mut self.records {
+            Some(records) => records.push(serde_json::to_value(record)?),
+            None => println!("{}", serde_json::to_string(record)?),
+        }
+        Ok(())
+    }
+
+    /// Print the array of records collected with `--all`, which is empty if every sensor
+    /// failed.
+    fn finish(self) -> Result<()> {
+        if let Some(records) = self.records {
+            println!("{}", serde_json::to_string(&records)?);
+        }
+        Ok(())
+    }
+}
+
+/// Print `record` as JSON or as a YAML document; with `--all` there is one YAML document per
+/// sensor.
+fn print_record<T: Serialize>(record: &T, format: InfoFormat, json: &mut JsonOutput) -> Result<()> {
+    if format == InfoFormat::Yaml {
+        print!("---\n{}", serde_yaml::to_string(record)?);
+        Ok(())
+    } else {
+        json.print(record)
+    }
+}
+
+fn print_sensor_status(sensor_name: &str, status: &SensorStatus) {
+    let oldest = Local::now() - status.history_span();
     println!(
-        "{}\n{}\n{}",
+        "{}\n{}\nStored readings: {}\nInterval: {} s\nLast measurement: {} s ago\nBattery: {}%\nCO₂ thresholds: {}\nHistory span: {} (since {})",
         sensor_name,
         "=".repeat(sensor_name.graphemes(true).count()),
-        measurement
+        status.total_readings,
+        status.update_interval,
+        status.since_update,
+        status.battery,
+        format_co2_thresholds(status.co2_thresholds),
+        format_span(status.history_span()),
+        oldest.format("%Y-%m-%d %H:%M")
     );
 }
 
-async fn archive_history_csv(peripheral: &Peripheral) -> Result<String> {
-    let local_name = get_local_name(&peripheral).await.unwrap();
-    let now = Local::now();
-    let output_filename = format!(
-        "{}_{}_history.csv",
-        now.to_rfc3339(),
-        local_name.replace(" ", "_")
+/// Print the readings under the sensor's name, with the dew point and absolute humidity if
+/// `derived`, which only the Aranet4 has the measurements for.
+fn print_current_sensor_data(
+    sensor_name: &str,
+    measurement: &ModelMeasurement,
+    units: Units,
+    derived: bool,
+) -> Result<()> {
+    let derived = match (derived, measurement) {
+        (false, _) => String::new(),
+        (true, ModelMeasurement::Aranet4(data)) => {
+            DerivedHumidity::new(data.temperature(), data.humidity()).display_in(units)
+        }
+        (true, _) => return Err(eyre!("--derived is only supported on the Aranet4")),
+    };
+    println!(
+        "{}\n{}\n{}{}",
+        sensor_name,
+        "=".repeat(sensor_name.graphemes(true).count()),
+        measurement.display_in(units),
+        derived
     );
-    let mut output_file = File::create(&output_filename).expect(&format!(
-        "Could not create writeable file {}",
-        &output_filename
-    ));
-    let (ht, t, h, p, c) = get_history(&peripheral).await?;
-    save_history_csv(ht, t, h, p, c, &mut output_file).await?;
-    Ok(output_filename)
-}
-
-async fn archive_history_parquet(peripheral: &Peripheral) -> Result<String> {
-    let local_name = get_local_name(&peripheral).await.unwrap();
-    let now = Local::now();
-    let output_filename = format!(
-        "{}_{}_history.parquet",
-        now.to_rfc3339(),
-        local_name.replace(" ", "_")
+    Ok(())
+}
+
+/// `readout --subscribe`: print every reading the sensor notifies until interrupted, one JSON
+/// object per line with `--format json`.
+async fn print_notified_readings(
+    client: &Aranet4Client,
+    sensor_name: &str,
+    args: &ReadoutArgs,
+) -> Result<()> {
+    let schedule = ReadingSchedule::Subscribe;
+    let mut readings = current_readings(client, schedule);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            Some(reading) = readings.next() => {
+                let data = match reading {
+                    Ok(data) => data,
+                    Err(e) => {
+                        warn!("Failed to take reading: {}", e);
+                        continue;
+                    }
+                };
+                if args.format == ReadingFormat::Json {
+                    let mut record = CurrentReadingRecord::new(
+                        Some(sensor_name.to_string()),
+                        reading_timestamp(&data),
+                        &data,
+                    );
+                    if args.derived {
+                        record = record.with_derived(&data);
+                    }
+                    println!("{}", serde_json::to_string(&record)?);
+                } else {
+                    let measurement = ModelMeasurement::Aranet4(data);
+                    print_current_sensor_data(sensor_name, &measurement, args.units, args.derived)?;
+                    println!();
+                }
+            }
+        }
+    }
+    drop(readings);
+    schedule.stop(client).await;
+    Ok(())
+}
+
+/// Fail unless `command` works with `model`: all of them work with the Aranet4, but most assume
+/// its measurements and so don't with the rest of the family.
+fn check_model_supported(command: &Commands, model: AranetModel) -> Result<()> {
+    let supported = match command {
+        _ if model == AranetModel::Aranet4 => true,
+        Commands::Readout(args) if args.subscribe => {
+            return Err(eyre!(
+                "readout --subscribe is only supported on the Aranet4, which notifies its readings"
+            ));
+        }
+        Commands::DeviceInfo(_)
+        | Commands::Status(_)
+        | Commands::Rssi(_)
+        | Commands::Battery(_)
+        | Commands::Readout(_)
+        | Commands::SetInterval(_)
+        | Commands::SyncTime
+        | Commands::Pair(_) => true,
+        Commands::ArchiveHistoryCsv(args) => {
+            if args.incremental
+                || args.append
+                || args.range.range()? != HistoryRange::ALL
+                || args.metrics.is_some()
+                || args.derived
+            {
+                return Err(eyre!(
+                    "The {} history can only be archived to a new CSV file in full",
+                    model
+                ));
+            }
+            model.has_model_history()
+        }
+        _ => false,
+    };
+    if !supported {
+        return Err(eyre!(
+            "{} is not supported on the {}",
+            command.name(),
+            model
+        ));
+    }
+    Ok(())
+}
+
+/// Print the devices found by `scan`, with their advertised readings where there are any.
+fn print_nearby_devices(devices: &[NearbyDevice]) {
+    println!(
+        "{:<20} {:<40} {:>5} {:<9} {:>9} {:>6} {:>6} {:>7}",
+        "NAME", "ADDRESS", "RSSI", "SIGNAL", "CO₂ (ppm)", "T (°C)", "RH (%)", "P (hPa)"
     );
-    let mut output_file = File::create(&output_filename).expect(&format!(
-        "Could not create writeable file {}",
-        &output_filename
-    ));
-    let (ht, t, h, p, c) = get_history(&peripheral).await?;
-    save_history_parquet(ht, t, h, p, c, &mut output_file).await?;
-    Ok(output_filename)
+    for device in devices {
+        let optional = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+        let reading = |format: fn(&CurrentSensorMeasurement) -> String| {
+            optional(device.reading.as_ref().map(format))
+        };
+        println!(
+            "{:<20} {:<40} {:>5} {:<9} {:>9} {:>6} {:>6} {:>7}",
+            optional(device.local_name.clone()),
+            device.id,
+            optional(device.rssi.map(|rssi| rssi.to_string())),
+            optional(device.rssi.map(|rssi| signal_quality(rssi).to_string())),
+            reading(|reading| format!("{:.0}", reading.co2_value())),
+            reading(|reading| format!("{:.1}", reading.temperature_value())),
+            reading(|reading| format!("{:.0}", reading.humidity_value())),
+            reading(|reading| format!("{:.1}", reading.pressure_value())),
+        );
+    }
+}
+
+/// Pick one of several sensors matching `device_pattern`: on a terminal, by asking, and
+/// otherwise not at all, since quietly taking the first could archive the wrong sensor.
+async fn choose_device(
+    mut clients: Vec<Aranet4Client>,
+    device_pattern: &DevicePattern,
+) -> Result<Aranet4Client> {
+    let mut rows = Vec::new();
+    for client in &clients {
+        rows.push(format!(
+            "{:<20} {:<40} {:>8}",
+            client.local_name().await.unwrap_or("-".to_string()),
+            client.id(),
+            client
+                .rssi()
+                .await
+                .map_or("-".to_string(), |rssi| format!("{} dBm", rssi)),
+        ));
+    }
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return Err(eyre!(
+            "{} devices match {}:\n  {}\nSelect one with a more specific --device or --device-regex, or by ID with --address; --first uses the first one found",
+            clients.len(),
+            device_pattern,
+            rows.join("\n  ")
+        ));
+    }
+    eprintln!("Several devices match {}:", device_pattern);
+    for (i, row) in rows.iter().enumerate() {
+        eprintln!("{:>3}) {}", i + 1, row);
+    }
+    loop {
+        eprint!("Which one? [1-{}] ", clients.len());
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer)? == 0 {
+            return Err(eyre!("No device selected"));
+        }
+        match answer.trim().parse::<usize>() {
+            Ok(choice) if (1..=clients.len()).contains(&choice) => {
+                return Ok(clients.swap_remove(choice - 1));
+            }
+            _ => eprintln!("Enter a number from 1 to {}", clients.len()),
+        }
+    }
+}
+
+/// Ask for the PIN the sensor shows on its screen while pairing.
+fn prompt_pin() -> Result<String> {
+    if !io::stdin().is_terminal() {
+        return Err(eyre!(
+            "The sensor asked for its PIN; pass it with --pin when not running in a terminal"
+        ));
+    }
+    eprint!("PIN shown on the sensor's screen: ");
+    io::stderr().flush()?;
+    let mut pin = String::new();
+    io::stdin().read_line(&mut pin)?;
+    Ok(pin.trim().to_string())
+}
+
+/// Show history downloads as a progress bar on stderr, since they take tens of seconds.
+/// Report history downloads of `client` with a progress bar if `show_bar`, and to `timing`.
+fn attach_progress(
+    client: &mut Aranet4Client,
+    show_bar: bool,
+    timing: Option<Arc<TransferTiming>>,
+) {
+    if !show_bar && timing.is_none() {
+        return;
+    }
+    let bar = show_bar.then(|| {
+        ProgressBar::new(0).with_style(
+            ProgressStyle::with_template("{msg:>16} [{bar:40}] {pos}/{len} readings ({eta} left)")
+                .unwrap()
+                .progress_chars("=> "),
+        )
+    });
+    client.set_progress_callback(Box::new(move |progress: HistoryProgress| {
+        if let Some(timing) = &timing {
+            timing.record(&progress);
+        }
+        let Some(bar) = &bar else {
+            return;
+        };
+        if bar.is_finished() {
+            bar.reset();
+        }
+        bar.set_length((progress.num_metrics * progress.num_readings) as u64);
+        bar.set_position(
+            (progress.metric_index * progress.num_readings + progress.received) as u64,
+        );
+        bar.set_message(progress.metric);
+        if progress.is_complete() {
+            bar.finish_and_clear();
+        }
+    }));
+}
+
+fn print_advertised_reading(
+    sensor_name: &str,
+    reading: &CurrentSensorMeasurement,
+    json: bool,
+) -> Result<()> {
+    let timestamp = reading_timestamp(reading);
+    if json {
+        let record = CurrentReadingRecord::new(Some(sensor_name.to_string()), timestamp, reading);
+        println!("{}", serde_json::to_string(&record)?);
+    } else {
+        let measured_at = DateTime::from_timestamp(timestamp, 0)
+            .unwrap()
+            .with_timezone(&Local);
+        println!(
+            "{} {}: {}",
+            measured_at.format("%Y-%m-%d %H:%M:%S"),
+            sensor_name,
+            reading.to_string().trim_end().replace('\n', ", ")
+        );
+    }
+    Ok(())
+}
+
+/// Whether `--derived` was given, which needs temperature and humidity among the `metrics`.
+fn check_derived(derived: bool, metrics: Option<&[HistoryMetric]>) -> Result<bool> {
+    if !derived {
+        return Ok(false);
+    }
+    if let Some(metrics) = metrics {
+        for metric in [HistoryMetric::Temperature, HistoryMetric::Humidity] {
+            if !metrics.contains(&metric) {
+                return Err(eyre!("--derived needs {} in --metrics", metric.name()));
+            }
+        }
+    }
+    Ok(true)
+}
+
+/// Where a subcommand wrote readings, e.g. an archive file or a database, and a note for people
+/// such as how many of them were new.
+#[derive(Debug, Clone)]
+struct Written {
+    target: String,
+    note: Option<String>,
+}
+
+impl Written {
+    fn new_readings(target: impl fmt::Display, readings: usize) -> Self {
+        Written {
+            target: target.to_string(),
+            note: Some(format!("{} new readings", readings)),
+        }
+    }
+}
+
+impl From<String> for Written {
+    fn from(target: String) -> Self {
+        Written { target, note: None }
+    }
+}
+
+impl fmt::Display for Written {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.target)?;
+        if let Some(note) = &self.note {
+            write!(f, " ({})", note)?;
+        }
+        Ok(())
+    }
+}
+
+/// Print what was written, which with `--quiet` is only where, for scripts to pick up.
+fn report_written(written: Option<Written>, quiet: bool) {
+    match written {
+        Some(written) if quiet => println!("{}", written.target),
+        Some(written) => println!("Wrote {}", written),
+        None => {}
+    }
+}
+
+/// The `--upload` destination, if any. It is parsed before the history is downloaded, so that a
+/// mistyped URL fails fast.
+fn upload_target(upload: &UploadArgs, output: Option<&Path>) -> Result<Option<UploadTarget>> {
+    let Some(url) = &upload.upload else {
+        return Ok(None);
+    };
+    if output.is_some_and(is_stdout) {
+        return Err(eyre!("--upload needs an archive file, not stdout"));
+    }
+    Ok(Some(UploadTarget::from_url(url)?))
+}
+
+/// Upload the archive written by a subcommand, which is its `--output` file, compressed with
+/// `compression`, or else the file `written` named by the template, and with
+/// `--delete-local-after-upload`, delete it.
+async fn upload_archive(
+    target: &UploadTarget,
+    upload: &UploadArgs,
+    output: Option<&Path>,
+    compression: Option<Compression>,
+    written: Option<&Written>,
+    quiet: bool,
+) -> Result<()> {
+    let output = output.map(|output| match compression {
+        Some(compression) => compression.add_extension(output),
+        None => output.to_path_buf(),
+    });
+    let Some(path) = output.or(written.map(|written| PathBuf::from(&written.target))) else {
+        return Ok(());
+    };
+    let url = target.upload(&path).await?;
+    if !quiet {
+        println!("Uploaded {} to {}", path.display(), url);
+    }
+    if upload.delete_local_after_upload {
+        fs::remove_file(&path)
+            .wrap_err_with(|| format!("Could not delete {} after uploading", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Where archive files go and what they're called.
+struct ArchiveNaming {
+    output_dir: PathBuf,
+    template: String,
+}
+
+/// The path of a new archive named according to `naming`.
+async fn archive_name(
+    client: &Aranet4Client,
+    ext: &str,
+    naming: &ArchiveNaming,
+) -> Result<PathBuf> {
+    let local_name = client
+        .local_name()
+        .await
+        .unwrap_or_else(|| "Aranet4".to_string())
+        .replace(" ", "_");
+    let serial = if uses_placeholder(&naming.template, "serial") {
+        Some(client.serial_number().await?.trim().to_string())
+    } else {
+        None
+    };
+    let date = Local::now().to_rfc3339();
+    let filename = render_filename(
+        &naming.template,
+        &FilenameFields {
+            name: &local_name,
+            serial: serial.as_deref(),
+            date: &date,
+            format: ext,
+        },
+    )?;
+    Ok(naming.output_dir.join(filename))
+}
+
+/// Like [`archive_name`], creating the directory the archive goes in.
+async fn archive_filename(
+    client: &Aranet4Client,
+    ext: &str,
+    naming: &ArchiveNaming,
+) -> Result<PathBuf> {
+    let path = archive_name(client, ext, naming).await?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .wrap_err_with(|| format!("Could not create directory {}", dir.display()))?;
+    }
+    Ok(path)
+}
+
+fn is_stdout(output: &Path) -> bool {
+    output == Path::new("-")
+}
+
+/// Where an archive is being written. Files only appear under their name once `finish`ed, so a
+/// failed download doesn't leave a truncated archive behind.
+enum ArchiveDest {
+    Stdout(BufWriter<io::Stdout>),
+    File(AtomicFile),
+}
+
+impl ArchiveDest {
+    fn finish(self) -> Result<()> {
+        match self {
+            ArchiveDest::Stdout(mut stdout) => Ok(stdout.flush()?),
+            ArchiveDest::File(file) => file.commit(),
+        }
+    }
+}
+
+impl Write for ArchiveDest {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ArchiveDest::Stdout(stdout) => stdout.write(buf),
+            ArchiveDest::File(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ArchiveDest::Stdout(stdout) => stdout.flush(),
+            ArchiveDest::File(file) => file.flush(),
+        }
+    }
+}
+
+/// Open where an archive should be written: `output` if given, with `-` meaning stdout, or
+/// otherwise a new file named according to `naming`. Also returns the name to report, which is
+/// `None` for stdout so that nothing but the archive is printed there.
+async fn open_archive(
+    client: &Aranet4Client,
+    ext: &str,
+    naming: &ArchiveNaming,
+    output: Option<&Path>,
+) -> Result<(ArchiveDest, Option<String>)> {
+    let path = match output {
+        Some(output) if is_stdout(output) => {
+            return Ok((ArchiveDest::Stdout(BufWriter::new(io::stdout())), None));
+        }
+        Some(output) => output.to_path_buf(),
+        None => archive_filename(client, ext, naming).await?,
+    };
+    let file = AtomicFile::create(&path)?;
+    Ok((ArchiveDest::File(file), Some(path.display().to_string())))
+}
+
+/// Like [`open_archive`], but with `compression`, the archive is compressed and its name gets
+/// the compression's extension.
+async fn open_compressed_archive(
+    client: &Aranet4Client,
+    ext: &str,
+    naming: &ArchiveNaming,
+    output: Option<&Path>,
+    compression: Option<Compression>,
+) -> Result<(CompressedWriter<ArchiveDest>, Option<String>)> {
+    let path = match (output, compression) {
+        (_, None) => output.map(Path::to_path_buf),
+        (Some(output), _) if is_stdout(output) => Some(output.to_path_buf()),
+        (Some(output), Some(compression)) => Some(compression.add_extension(output)),
+        (None, Some(compression)) => {
+            Some(compression.add_extension(&archive_filename(client, ext, naming).await?))
+        }
+    };
+    let (dest, output_name) = open_archive(client, ext, naming, path.as_deref()).await?;
+    Ok((CompressedWriter::new(dest, compression)?, output_name))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn archive_history_csv(
+    client: &Aranet4Client,
+    naming: &ArchiveNaming,
+    output: Option<&Path>,
+    units: Units,
+    timestamps: CsvTimestamps,
+    range: HistoryRange,
+    compression: Option<Compression>,
+    derived: bool,
+    metadata: CsvMetadata,
+) -> Result<Option<String>> {
+    check_csv_metadata_output(metadata, output)?;
+    let (mut output_file, output_name) =
+        open_compressed_archive(client, "csv", naming, output, compression).await?;
+    let history = client.read_history_range(range).await?;
+    let source = csv_source(client, metadata, history.time.update_interval).await?;
+    if metadata == CsvMetadata::CommentHeader {
+        write_csv_comment_header(&source, &mut output_file)?;
+    }
+    save_history_csv(
+        history.time,
+        history.temperature,
+        history.humidity,
+        history.pressure,
+        history.co2,
+        &mut output_file,
+        units,
+        timestamps,
+        client.history_metrics(),
+        derived,
+    )
+    .await?;
+    output_file.finish()?.finish()?;
+    save_csv_metadata_sidecar(metadata, output_name.as_deref(), &source)?;
+    Ok(output_name)
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn archive_model_history_csv(
+    client: &Aranet4Client,
+    model: AranetModel,
+    naming: &ArchiveNaming,
+    output: Option<&Path>,
+    units: Units,
+    timestamps: CsvTimestamps,
+    compression: Option<Compression>,
+    metadata: CsvMetadata,
+) -> Result<Option<String>> {
+    check_csv_metadata_output(metadata, output)?;
+    let (mut output_file, output_name) =
+        open_compressed_archive(client, "csv", naming, output, compression).await?;
+    let history = client.read_model_history(model).await?;
+    let source = csv_source(client, metadata, history.time.update_interval).await?;
+    if metadata == CsvMetadata::CommentHeader {
+        write_csv_comment_header(&source, &mut output_file)?;
+    }
+    save_model_history_csv(history, &mut output_file, units, timestamps).await?;
+    output_file.finish()?.finish()?;
+    save_csv_metadata_sidecar(metadata, output_name.as_deref(), &source)?;
+    Ok(output_name)
+}
+
+fn check_csv_metadata_output(metadata: CsvMetadata, output: Option<&Path>) -> Result<()> {
+    if metadata == CsvMetadata::SidecarJson && output.is_some_and(is_stdout) {
+        return Err(eyre!(
+            "--csv-metadata sidecar-json needs an --output file, not stdout"
+        ));
+    }
+    Ok(())
+}
+
+/// The sensor to record in a CSV archive, which is only read from it if `metadata` asks for it.
+async fn csv_source(
+    client: &Aranet4Client,
+    metadata: CsvMetadata,
+    interval: u16,
+) -> Result<ArchiveSource> {
+    match metadata {
+        CsvMetadata::None => Ok(ArchiveSource::default()),
+        _ => archive_source(client, interval, false).await,
+    }
+}
+
+/// Write the sidecar of the finished CSV archive `output_name` if `metadata` asks for one.
+fn save_csv_metadata_sidecar(
+    metadata: CsvMetadata,
+    output_name: Option<&str>,
+    source: &ArchiveSource,
+) -> Result<()> {
+    if let (CsvMetadata::SidecarJson, Some(output_name)) = (metadata, output_name) {
+        save_csv_sidecar(Path::new(output_name), source)?;
+    }
+    Ok(())
+}
+
+fn existing_archive(path: &Path) -> Result<bool> {
+    if is_stdout(path) {
+        return Err(eyre!("--append needs an --output file, not stdout"));
+    }
+    Ok(path.metadata().is_ok_and(|metadata| metadata.len() > 0))
+}
+
+/// Fetch the history after `last_timestamp`, or all of it for a new archive.
+async fn read_history_since(
+    client: &Aranet4Client,
+    last_timestamp: Option<i64>,
+) -> Result<History> {
+    match last_timestamp {
+        Some(last_timestamp) => client.read_history_after(last_timestamp).await,
+        None => client.read_history().await,
+    }
+}
+
+/// Append readings newer than the last row of the CSV archive at `path`.
+async fn archive_history_csv_append(
+    client: &Aranet4Client,
+    path: &Path,
+    units: Units,
+    timestamps: CsvTimestamps,
+) -> Result<Written> {
+    let exists = existing_archive(path)?;
+    let last_timestamp = if exists {
+        check_csv_header(path, units, client.history_metrics())?;
+        last_csv_timestamp(path, timestamps)?
+    } else {
+        None
+    };
+    let history = read_history_since(client, last_timestamp).await?;
+    let num_samples = history.time.num_samples;
+    if exists {
+        let mut output_file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .wrap_err_with(|| format!("Could not open {} for appending", path.display()))?;
+        append_history_csv(
+            history.time,
+            history.temperature,
+            history.humidity,
+            history.pressure,
+            history.co2,
+            &mut output_file,
+            units,
+            timestamps,
+            client.history_metrics(),
+        )
+        .await?;
+    } else {
+        let mut output_file = AtomicFile::create(path)?;
+        save_history_csv(
+            history.time,
+            history.temperature,
+            history.humidity,
+            history.pressure,
+            history.co2,
+            &mut output_file,
+            units,
+            timestamps,
+            client.history_metrics(),
+            false,
+        )
+        .await?;
+        output_file.commit()?;
+    }
+    Ok(Written::new_readings(path.display(), num_samples))
+}
+
+/// The sensor being archived, which measures every `interval` seconds, for an archive's
+/// metadata.
+async fn archive_source(
+    client: &Aranet4Client,
+    interval: u16,
+    serial_column: bool,
+) -> Result<ArchiveSource> {
+    let info = client.read_device_info().await?;
+    Ok(ArchiveSource {
+        name: info.device_name,
+        serial: info.serial_number.trim().to_string(),
+        model: info.model_number,
+        firmware: info.firmware_revision,
+        interval,
+        serial_column,
+    })
+}
+
+/// Merge readings newer than the newest row of the Parquet archive at `path` into it.
+async fn archive_history_parquet_append(
+    client: &Aranet4Client,
+    path: &Path,
+    timestamp_unit: TimestampUnit,
+    units: Units,
+) -> Result<Written> {
+    if !existing_archive(path)? {
+        let mut output_file = AtomicFile::create(path)?;
+        let history = client.read_history().await?;
+        let num_samples = history.time.num_samples;
+        let source = archive_source(client, history.time.update_interval, false).await?;
+        save_history_parquet(
+            history.time,
+            history.temperature,
+            history.humidity,
+            history.pressure,
+            history.co2,
+            &mut output_file,
+            timestamp_unit,
+            units,
+            client.history_metrics(),
+            false,
+            Some(&source),
+        )
+        .await?;
+        output_file.commit()?;
+        return Ok(Written::new_readings(path.display(), num_samples));
+    }
+    check_parquet_units(path, units)?;
+    let history = read_history_since(client, last_parquet_timestamp(path)?).await?;
+    let source = archive_source(client, history.time.update_interval, false).await?;
+    let added = merge_history_parquet(
+        path,
+        history.time,
+        history.temperature,
+        history.humidity,
+        history.pressure,
+        history.co2,
+        timestamp_unit,
+        units,
+        Some(&source),
+    )
+    .await?;
+    Ok(Written::new_readings(path.display(), added))
+}
+
+/// Append only readings newer than the last run to the device's archive, as recorded in the
+/// state file. Falls back to a full download into a new archive on the first run or if the
+/// archive has gone missing.
+async fn archive_history_csv_incremental(
+    client: &Aranet4Client,
+    naming: &ArchiveNaming,
+    state_path: &Path,
+    units: Units,
+    timestamps: CsvTimestamps,
+) -> Result<Written> {
+    let serial = client.serial_number().await?.trim().to_string();
+    let mut state = SyncState::load(state_path)?;
+    let previous = state
+        .devices
+        .get(&serial)
+        .filter(|device| device.archive.exists())
+        .cloned();
+    let (history, new_archive) = match &previous {
+        Some(device) => {
+            check_csv_header(&device.archive, units, client.history_metrics())?;
+            (
+                client.read_history_after(device.last_timestamp).await?,
+                None,
+            )
+        }
+        None => {
+            let file = AtomicFile::create(&archive_filename(client, "csv", naming).await?)?;
+            (client.read_history().await?, Some(file))
+        }
+    };
+    let last_timestamp = history
+        .time
+        .timestamps()
+        .last()
+        .map(|time| time.timestamp());
+    let num_samples = history.time.num_samples;
+    let archive = match (previous, new_archive) {
+        (Some(device), _) => {
+            let mut output_file = OpenOptions::new()
+                .append(true)
+                .open(&device.archive)
+                .wrap_err_with(|| {
+                    format!("Could not open {} for appending", device.archive.display())
+                })?;
+            append_history_csv(
+                history.time,
+                history.temperature,
+                history.humidity,
+                history.pressure,
+                history.co2,
+                &mut output_file,
+                units,
+                timestamps,
+                client.history_metrics(),
+            )
+            .await?;
+            device.archive
+        }
+        (None, Some(mut output_file)) => {
+            save_history_csv(
+                history.time,
+                history.temperature,
+                history.humidity,
+                history.pressure,
+                history.co2,
+                &mut output_file,
+                units,
+                timestamps,
+                client.history_metrics(),
+                false,
+            )
+            .await?;
+            let path = output_file.path().to_path_buf();
+            output_file.commit()?;
+            path.canonicalize()?
+        }
+        (None, None) => unreachable!("a new archive is opened whenever there is no previous one"),
+    };
+    if let Some(last_timestamp) = last_timestamp {
+        state.devices.insert(
+            serial,
+            DeviceSyncState {
+                last_timestamp,
+                archive: archive.clone(),
+            },
+        );
+        state.save(state_path)?;
+    }
+    Ok(Written::new_readings(archive.display(), num_samples))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn archive_history_parquet(
+    client: &Aranet4Client,
+    naming: &ArchiveNaming,
+    output: Option<&Path>,
+    timestamp_unit: TimestampUnit,
+    units: Units,
+    range: HistoryRange,
+    derived: bool,
+    serial_column: bool,
+) -> Result<Option<String>> {
+    if output.is_some_and(is_stdout) && io::stdout().is_terminal() {
+        return Err(eyre!(
+            "Refusing to write Parquet to a terminal; redirect stdout or pass --output <file>"
+        ));
+    }
+    let (mut output_file, output_name) = open_archive(client, "parquet", naming, output).await?;
+    let history = client.read_history_range(range).await?;
+    let source = archive_source(client, history.time.update_interval, serial_column).await?;
+    save_history_parquet(
+        history.time,
+        history.temperature,
+        history.humidity,
+        history.pressure,
+        history.co2,
+        &mut output_file,
+        timestamp_unit,
+        units,
+        client.history_metrics(),
+        derived,
+        Some(&source),
+    )
+    .await?;
+    output_file.finish()?;
+    Ok(output_name)
+}
+
+async fn archive_history_arrow(
+    client: &Aranet4Client,
+    naming: &ArchiveNaming,
+    output: Option<&Path>,
+    range: HistoryRange,
+) -> Result<Option<String>> {
+    if output.is_some_and(is_stdout) && io::stdout().is_terminal() {
+        return Err(eyre!(
+            "Refusing to write Arrow to a terminal; redirect stdout or pass --output <file>"
+        ));
+    }
+    let (mut output_file, output_name) = open_archive(client, "arrow", naming, output).await?;
+    let history = client.read_history_range(range).await?;
+    save_history_arrow(
+        history.time,
+        history.temperature,
+        history.humidity,
+        history.pressure,
+        history.co2,
+        &mut output_file,
+    )
+    .await?;
+    output_file.finish()?;
+    Ok(output_name)
+}
+
+async fn archive_history_msgpack(
+    client: &Aranet4Client,
+    naming: &ArchiveNaming,
+    output: Option<&Path>,
+    range: HistoryRange,
+) -> Result<Option<String>> {
+    if output.is_some_and(is_stdout) && io::stdout().is_terminal() {
+        return Err(eyre!(
+            "Refusing to write MessagePack to a terminal; redirect stdout or pass --output <file>"
+        ));
+    }
+    let (mut output_file, output_name) = open_archive(client, "msgpack", naming, output).await?;
+    let history = client.read_history_range(range).await?;
+    save_history_msgpack(
+        history.time,
+        history.temperature,
+        history.humidity,
+        history.pressure,
+        history.co2,
+        &mut output_file,
+    )
+    .await?;
+    output_file.finish()?;
+    Ok(output_name)
+}
+
+async fn archive_history_xlsx(
+    client: &Aranet4Client,
+    naming: &ArchiveNaming,
+    output: Option<&Path>,
+    units: Units,
+    range: HistoryRange,
+) -> Result<Option<String>> {
+    if output.is_some_and(is_stdout) && io::stdout().is_terminal() {
+        return Err(eyre!(
+            "Refusing to write XLSX to a terminal; redirect stdout or pass --output <file>"
+        ));
+    }
+    let (mut output_file, output_name) = open_archive(client, "xlsx", naming, output).await?;
+    let history = client.read_history_range(range).await?;
+    save_history_xlsx(
+        history.time,
+        history.temperature,
+        history.humidity,
+        history.pressure,
+        history.co2,
+        &mut output_file,
+        units,
+    )
+    .await?;
+    output_file.finish()?;
+    Ok(output_name)
+}
+
+#[cfg(feature = "hdf5")]
+async fn archive_history_hdf5(
+    client: &Aranet4Client,
+    naming: &ArchiveNaming,
+    output: Option<&Path>,
+    units: Units,
+    range: HistoryRange,
+) -> Result<String> {
+    let path = match output {
+        Some(output) if is_stdout(output) => {
+            return Err(eyre!(
+                "HDF5 can't be written to stdout; pass --output <file>"
+            ));
+        }
+        Some(output) => output.to_path_buf(),
+        None => archive_filename(client, "h5", naming).await?,
+    };
+    let file = AtomicFile::create(&path)?;
+    let history = client.read_history_range(range).await?;
+    save_history_hdf5(
+        file.temp_path(),
+        history.time,
+        history.temperature,
+        history.humidity,
+        history.pressure,
+        history.co2,
+        units,
+    )
+    .await?;
+    file.commit()?;
+    Ok(path.display().to_string())
+}
+
+async fn archive_history_json(
+    client: &Aranet4Client,
+    naming: &ArchiveNaming,
+    output: Option<&Path>,
+    ndjson: bool,
+    range: HistoryRange,
+    derived: bool,
+) -> Result<Option<String>> {
+    let ext = if ndjson { "ndjson" } else { "json" };
+    let (mut output_file, output_name) = open_archive(client, ext, naming, output).await?;
+    let history = client.read_history_range(range).await?;
+    if ndjson {
+        save_history_ndjson(
+            history.time,
+            history.temperature,
+            history.humidity,
+            history.pressure,
+            history.co2,
+            derived,
+            &mut output_file,
+        )
+        .await?;
+    } else {
+        save_history_json(
+            history.time,
+            history.temperature,
+            history.humidity,
+            history.pressure,
+            history.co2,
+            derived,
+            &mut output_file,
+        )
+        .await?;
+    }
+    output_file.finish()?;
+    Ok(output_name)
+}
+
+/// Download the history of each of `clients` and write them to one table.
+async fn archive_history_combined(
+    clients: &[Aranet4Client],
+    args: &CombinedArgs,
+    quiet: bool,
+) -> Result<()> {
+    let output = &args.output;
+    // Check the file name before the downloads rather than after
+    let format = CombinedFormat::from_path(output).ok_or(eyre!(
+        "Could not determine the format of {}; use .csv or .parquet",
+        output.display()
+    ))?;
+    let layout = args.layout;
+    let range = args.range.range()?;
+    let mut devices = Vec::new();
+    for client in clients {
+        let model = client.model().await?;
+        if model != AranetModel::Aranet4 {
+            return Err(eyre!("The {} history can't be combined", model));
+        }
+        let name = client.local_name().await.unwrap_or_default();
+        let serial = client.serial_number().await?;
+        let history = client.read_history_range(range).await?;
+        devices.push(DeviceHistory::new(name, serial, &history)?);
+    }
+    let grid = args.grid.map(i64::from);
+    let combined = CombinedHistory::new(devices, grid);
+    let mut file = AtomicFile::create(output)?;
+    match format {
+        CombinedFormat::Csv => {
+            combined.write_csv(&mut file, layout, args.units, args.time.timestamps())?
+        }
+        CombinedFormat::Parquet => {
+            combined.write_parquet(&mut file, layout, args.timestamp_unit, args.units)?
+        }
+    }
+    file.commit()?;
+    if quiet {
+        println!("{}", output.display());
+    } else {
+        println!(
+            "Wrote {} rows to {}",
+            combined.len(layout),
+            output.display()
+        );
+    }
+    Ok(())
+}
+
+/// Write `history` to `dest` as one of the formats `batch` archives to, by file extension.
+/// Parquet records `source` in its metadata.
+async fn save_history_as(
+    format: &str,
+    history: History,
+    dest: &mut ArchiveDest,
+    units: Units,
+    timestamps: CsvTimestamps,
+    derived: bool,
+    source: &ArchiveSource,
+) -> Result<()> {
+    let History {
+        time,
+        temperature,
+        humidity,
+        pressure,
+        co2,
+    } = history;
+    match format {
+        "csv" => {
+            save_history_csv(
+                time,
+                temperature,
+                humidity,
+                pressure,
+                co2,
+                dest,
+                units,
+                timestamps,
+                &HistoryMetric::ALL,
+                derived,
+            )
+            .await
+        }
+        "parquet" => {
+            save_history_parquet(
+                time,
+                temperature,
+                humidity,
+                pressure,
+                co2,
+                dest,
+                TimestampUnit::default(),
+                units,
+                &HistoryMetric::ALL,
+                derived,
+                Some(source),
+            )
+            .await
+        }
+        "json" => {
+            save_history_json(time, temperature, humidity, pressure, co2, derived, dest).await
+        }
+        "arrow" => save_history_arrow(time, temperature, humidity, pressure, co2, dest).await,
+        "xlsx" => save_history_xlsx(time, temperature, humidity, pressure, co2, dest, units).await,
+        _ => Err(eyre!("Unknown archive format {}", format)),
+    }
+}
+
+/// Run everything `batch` was asked to do, downloading the history at most once however many
+/// formats it is archived in.
+async fn run_batch(
+    client: &Aranet4Client,
+    args: &BatchArgs,
+    naming: &ArchiveNaming,
+    quiet: bool,
+) -> Result<()> {
+    let units = args.units;
+    let derived = args.derived;
+    if args.device_info {
+        let info = client.read_device_info().await?;
+        print_device_info(&info, client.rssi().await);
+    }
+    if args.readout {
+        let sensor_name = client.local_name().await.unwrap_or_default();
+        let data = client.read_current().await?;
+        print_current_sensor_data(
+            &sensor_name,
+            &ModelMeasurement::Aranet4(data),
+            units,
+            derived,
+        )?;
+    }
+    let formats = args.formats();
+    if formats.is_empty() {
+        return Ok(());
+    }
+    let history = client.read_history_range(args.range.range()?).await?;
+    let source = archive_source(client, history.time.update_interval, false).await?;
+    for format in formats {
+        let (mut output_file, output_name) = open_archive(client, format, naming, None).await?;
+        save_history_as(
+            format,
+            history.clone(),
+            &mut output_file,
+            units,
+            args.time.timestamps(),
+            derived,
+            &source,
+        )
+        .await?;
+        output_file.finish()?;
+        report_written(output_name.map(Written::from), quiet);
+    }
+    Ok(())
+}
+
+/// Where an archive subcommand would write, for `--dry-run`: `output`, or else the file named
+/// according to `naming`, with the extension of `compression`.
+async fn planned_archive(
+    client: &Aranet4Client,
+    ext: &str,
+    naming: &ArchiveNaming,
+    output: Option<&Path>,
+    compression: Option<Compression>,
+) -> Result<String> {
+    let path = match output {
+        Some(output) if is_stdout(output) => return Ok("stdout".to_string()),
+        Some(output) => output.to_path_buf(),
+        None => archive_name(client, ext, naming).await?,
+    };
+    let path = match compression {
+        Some(compression) => compression.add_extension(&path),
+        None => path,
+    };
+    Ok(path.display().to_string())
+}
+
+/// How many readings the archive subcommand `command` would download from `client`, whose
+/// status is `status`, and where it would write them.
+async fn archive_plan(
+    command: &Commands,
+    client: &Aranet4Client,
+    status: &SensorStatus,
+    naming: &ArchiveNaming,
+) -> Result<(u16, Vec<String>)> {
+    let now = Utc::now().timestamp();
+    let in_range = match command.history_range() {
+        Some(range) => status.readings_in(range.range()?, now),
+        None => status.total_readings,
+    };
+    let after = |last_timestamp: Option<i64>| match last_timestamp {
+        Some(last_timestamp) => status.readings_after(last_timestamp, now),
+        None => status.total_readings,
+    };
+    let appending = |path: &Path| vec![format!("{} (appending)", path.display())];
+    let plan = match command {
+        Commands::ArchiveHistoryCsv(args) if args.incremental => {
+            let state_path = match &args.state_file {
+                Some(path) => path.clone(),
+                None => SyncState::default_path()?,
+            };
+            let serial = client.serial_number().await?.trim().to_string();
+            let state = SyncState::load(&state_path)?;
+            match state
+                .devices
+                .get(&serial)
+                .filter(|device| device.archive.exists())
+            {
+                Some(device) => (
+                    status.readings_after(device.last_timestamp, now),
+                    appending(&device.archive),
+                ),
+                None => (
+                    status.total_readings,
+                    vec![planned_archive(client, "csv", naming, None, None).await?],
+                ),
+            }
+        }
+        Commands::ArchiveHistoryCsv(args) if args.append => {
+            let path = args.output.as_deref().unwrap();
+            if existing_archive(path)? {
+                let last_timestamp = last_csv_timestamp(path, args.time.timestamps())?;
+                (after(last_timestamp), appending(path))
+            } else {
+                (status.total_readings, vec![path.display().to_string()])
+            }
+        }
+        Commands::ArchiveHistoryCsv(args) => {
+            let output = args.output.as_deref();
+            let target = planned_archive(client, "csv", naming, output, args.compress).await?;
+            (in_range, vec![target])
+        }
+        Commands::ArchiveHistoryParquet(args) if args.append => {
+            let path = args.output.as_deref().unwrap();
+            if existing_archive(path)? {
+                (after(last_parquet_timestamp(path)?), appending(path))
+            } else {
+                (status.total_readings, vec![path.display().to_string()])
+            }
+        }
+        Commands::ArchiveHistoryParquet(args) => {
+            let output = args.output.as_deref();
+            let target = planned_archive(client, "parquet", naming, output, None).await?;
+            (in_range, vec![target])
+        }
+        Commands::ArchiveHistoryCombined(args) => {
+            let target = planned_archive(client, "", naming, Some(&args.output), None).await?;
+            (in_range, vec![target])
+        }
+        Commands::ArchiveHistoryArrow(args) => {
+            let output = args.output.as_deref();
+            let target = planned_archive(client, "arrow", naming, output, None).await?;
+            (in_range, vec![target])
+        }
+        Commands::ArchiveHistoryMsgpack(args) => {
+            let output = args.output.as_deref();
+            let target = planned_archive(client, "msgpack", naming, output, None).await?;
+            (in_range, vec![target])
+        }
+        Commands::ArchiveHistoryXlsx(args) => {
+            let output = args.output.as_deref();
+            let target = planned_archive(client, "xlsx", naming, output, None).await?;
+            (in_range, vec![target])
+        }
+        Commands::ArchiveHistoryHdf5(args) => {
+            let output = args.output.as_deref();
+            let target = planned_archive(client, "h5", naming, output, None).await?;
+            (in_range, vec![target])
+        }
+        Commands::ArchiveHistoryJson(args) => {
+            let ext = if args.ndjson { "ndjson" } else { "json" };
+            let output = args.output.as_deref();
+            let target = planned_archive(client, ext, naming, output, None).await?;
+            (in_range, vec![target])
+        }
+        Commands::ArchiveHistorySqlite(args) => {
+            (in_range, vec![args.database.display().to_string()])
+        }
+        Commands::ArchiveHistoryDuckdb(args) => {
+            (in_range, vec![args.database.display().to_string()])
+        }
+        // Not the DSN, which may hold a password
+        Commands::ArchiveHistoryPostgres(args) => (in_range, vec![format!("table {}", args.table)]),
+        Commands::ArchiveHistoryInflux(args) => {
+            let target = match args.influx.config() {
+                Some(config) => format!("bucket {} at {}", config.bucket, config.url),
+                None => planned_archive(client, "lp", naming, args.output.as_deref(), None).await?,
+            };
+            (in_range, vec![target])
+        }
+        Commands::Batch(args) => {
+            let mut targets = Vec::new();
+            for format in args.formats() {
+                targets.push(planned_archive(client, format, naming, None, None).await?);
+            }
+            if targets.is_empty() {
+                (0, targets)
+            } else {
+                (in_range, targets)
+            }
+        }
+        _ => return Err(eyre!("{} can't be dry run", command.name())),
+    };
+    Ok(plan)
+}
+
+/// `--dry-run`: print how many readings `command` would download from `client` and where it
+/// would write them, reading only the sensor's status.
+async fn print_archive_plan(
+    command: &Commands,
+    client: &Aranet4Client,
+    naming: &ArchiveNaming,
+) -> Result<()> {
+    let sensor_name = client.local_name().await.unwrap_or_default();
+    let status = client.read_status().await?;
+    let (readings, targets) = archive_plan(command, client, &status, naming).await?;
+    println!(
+        "{}: would download {} of {} stored readings, taken every {} s",
+        sensor_name, readings, status.total_readings, status.update_interval
+    );
+    for target in targets {
+        println!("Would write to {}", target);
+    }
+    Ok(())
+}
+
+async fn archive_history_influx(
+    client: &Aranet4Client,
+    naming: &ArchiveNaming,
+    output: Option<&Path>,
+    range: HistoryRange,
+) -> Result<Option<String>> {
+    let (mut output_file, output_name) = open_archive(client, "lp", naming, output).await?;
+    let name = client.local_name().await.unwrap_or_default();
+    let serial = client.serial_number().await?;
+    let history = client.read_history_range(range).await?;
+    save_history_influx(
+        &name,
+        &serial,
+        history.time,
+        history.temperature,
+        history.humidity,
+        history.pressure,
+        history.co2,
+        &mut output_file,
+    )
+    .await?;
+    output_file.finish()?;
+    Ok(output_name)
+}
+
+async fn archive_history_influx_write(
+    client: &Aranet4Client,
+    config: &InfluxConfig,
+    range: HistoryRange,
+) -> Result<Written> {
+    let name = client.local_name().await.unwrap_or_default();
+    let serial = client.serial_number().await?;
+    let history = client.read_history_range(range).await?;
+    let num_samples = history.time.num_samples;
+    let mut body = Vec::new();
+    save_history_influx(
+        &name,
+        &serial,
+        history.time,
+        history.temperature,
+        history.humidity,
+        history.pressure,
+        history.co2,
+        &mut body,
+    )
+    .await?;
+    write_lines(config, String::from_utf8(body)?).await?;
+    Ok(Written {
+        target: format!("bucket {} at {}", config.bucket, config.url),
+        note: Some(format!("{} readings", num_samples)),
+    })
+}
+
+/// Post the readings newer than those posted by the previous run, recorded in the state file
+/// at `state_path`, to the webhook of `config`.
+async fn post_history_webhook(
+    client: &Aranet4Client,
+    config: &WebhookConfig,
+    state_path: &Path,
+) -> Result<String> {
+    let serial = client.serial_number().await?.trim().to_string();
+    let name = client.local_name().await.unwrap_or_default();
+    let mut state = SyncState::load(state_path)?;
+    let history = read_history_since(client, state.webhook.get(&serial).copied()).await?;
+    let records: Vec<HistoryRecord> = history_records(
+        &history.time,
+        &history.temperature,
+        &history.humidity,
+        &history.pressure,
+        &history.co2,
+    )
+    .collect::<Result<_>>()?;
+    let posted = post_history(config, &name, &serial, &records, |timestamp| {
+        state.webhook.insert(serial.clone(), timestamp);
+        state.save(state_path)
+    })
+    .await?;
+    Ok(format!("Posted {} readings to {}", posted, config.url))
+}
+
+/// Upsert `range` of the history into `database`; with [`HistoryRange::ALL`], only the
+/// readings newer than those already in it.
+async fn archive_history_sqlite(
+    client: &Aranet4Client,
+    database: &Path,
+    range: HistoryRange,
+) -> Result<Written> {
+    let serial = client.serial_number().await?.trim().to_string();
+    let mut conn = Connection::open(database)
+        .wrap_err_with(|| format!("Could not open database {}", database.display()))?;
+    let history = if range == HistoryRange::ALL {
+        read_history_since(client, last_sqlite_timestamp(&conn, &serial)?).await?
+    } else {
+        client.read_history_range(range).await?
+    };
+    let new_rows = save_history_sqlite(
+        &serial,
+        history.time,
+        history.temperature,
+        history.humidity,
+        history.pressure,
+        history.co2,
+        &mut conn,
+    )
+    .await?;
+    Ok(Written {
+        target: database.display().to_string(),
+        note: Some(format!("{} new readings for {}", new_rows, serial)),
+    })
+}
+
+/// Append `range` of the history to `database`; with [`HistoryRange::ALL`], only the
+/// readings newer than those already in it.
+#[cfg(feature = "duckdb")]
+async fn archive_history_duckdb(
+    client: &Aranet4Client,
+    database: &Path,
+    range: HistoryRange,
+) -> Result<Written> {
+    let serial = client.serial_number().await?.trim().to_string();
+    let mut conn = duckdb::Connection::open(database)
+        .wrap_err_with(|| format!("Could not open database {}", database.display()))?;
+    let history = if range == HistoryRange::ALL {
+        read_history_since(client, last_duckdb_timestamp(&conn, &serial)?).await?
+    } else {
+        client.read_history_range(range).await?
+    };
+    let new_rows = save_history_duckdb(
+        &serial,
+        history.time,
+        history.temperature,
+        history.humidity,
+        history.pressure,
+        history.co2,
+        &mut conn,
+    )
+    .await?;
+    Ok(Written {
+        target: database.display().to_string(),
+        note: Some(format!("{} new readings for {}", new_rows, serial)),
+    })
+}
+
+/// Insert `range` of the history into `table` of the PostgreSQL database at `dsn`; with
+/// [`HistoryRange::ALL`], only the readings newer than those already in it.
+#[cfg(feature = "postgres")]
+async fn archive_history_postgres(
+    client: &Aranet4Client,
+    dsn: &str,
+    table: &str,
+    range: HistoryRange,
+) -> Result<Written> {
+    check_table_name(table)?;
+    let serial = client.serial_number().await?.trim().to_string();
+    let mut conn = <sqlx::PgConnection as sqlx::Connection>::connect(dsn)
+        .await
+        .wrap_err("Could not connect to PostgreSQL")?;
+    let history = if range == HistoryRange::ALL {
+        read_history_since(
+            client,
+            last_postgres_timestamp(&mut conn, table, &serial).await?,
+        )
+        .await?
+    } else {
+        client.read_history_range(range).await?
+    };
+    let new_rows = save_history_postgres(
+        &serial,
+        history.time,
+        history.temperature,
+        history.humidity,
+        history.pressure,
+        history.co2,
+        table,
+        &mut conn,
+    )
+    .await?;
+    Ok(Written {
+        target: format!("table {}", table),
+        note: Some(format!("{} new readings for {}", new_rows, serial)),
+    })
+}
+
+fn daemon_config_path(args: &DaemonArgs) -> Result<PathBuf> {
+    match &args.config {
+        Some(path) => Ok(path.clone()),
+        None => DaemonConfig::default_path(),
+    }
+}
+
+/// The command line for a systemd unit to run the daemon as we were asked to: this
+/// executable, the options given before `daemon`, and the config file as an absolute path,
+/// since systemd doesn't start services in our working directory.
+fn daemon_command(config_path: &Path) -> Result<Vec<String>> {
+    let exe = std::env::current_exe().wrap_err("Could not determine the arachiver executable")?;
+    let mut command = vec![exe.display().to_string()];
+    command.extend(std::env::args().skip(1).take_while(|arg| arg != "daemon"));
+    let config_path = std::path::absolute(config_path)?;
+    command.extend([
+        "daemon".to_string(),
+        "--config".to_string(),
+        config_path.display().to_string(),
+    ]);
+    Ok(command)
+}
+
+/// The path a daemon job writes to for one device. `{date}` is the day the job runs, so that
+/// it can start a new file every day.
+fn daemon_job_path(template: &str, name: &str, serial: &str, format: &str) -> Result<PathBuf> {
+    let date = Local::now().format("%Y-%m-%d").to_string();
+    let path = PathBuf::from(render_filename(
+        template,
+        &FilenameFields {
+            name: &name.replace(' ', "_"),
+            serial: Some(serial),
+            date: &date,
+            format,
+        },
+    )?);
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .wrap_err_with(|| format!("Could not create directory {}", dir.display()))?;
+    }
+    Ok(path)
+}
+
+async fn run_history_job(
+    client: &Aranet4Client,
+    job: &HistoryJob,
+    name: &str,
+    serial: &str,
+) -> Result<Written> {
+    let path = daemon_job_path(&job.output, name, serial, job.format.name())?;
+    match job.format {
+        HistoryFormat::Csv => {
+            archive_history_csv_append(client, &path, Units::Metric, CsvTimestamps::default()).await
+        }
+        HistoryFormat::Parquet => {
+            archive_history_parquet_append(client, &path, TimestampUnit::default(), Units::Metric)
+                .await
+        }
+        HistoryFormat::Sqlite => archive_history_sqlite(client, &path, HistoryRange::ALL).await,
+    }
+}
+
+/// Take a reading and write it to `sink`, first moving `sink` to a new file if the job's output
+/// path has changed since it was opened. `sink` is left empty if that fails, to be opened again
+/// next time.
+async fn run_readout_job(
+    client: &Aranet4Client,
+    job: &ReadoutJob,
+    name: &str,
+    serial: &str,
+    sink: &mut Option<(PathBuf, Box<dyn ReadingSink>)>,
+) -> Result<()> {
+    let format = job.sink_format()?;
+    let path = daemon_job_path(&job.output, name, serial, format.name())?;
+    if sink
+        .as_ref()
+        .is_some_and(|(open_path, _)| *open_path != path)
+    {
+        let (_, old) = sink.take().unwrap();
+        old.finish()?;
+    }
+    if sink.is_none() {
+        *sink = Some((path.clone(), open_sink(&path, format)?));
+    }
+    let reading = client.read_current().await?;
+    let (_, sink) = sink.as_mut().unwrap();
+    sink.write_reading(reading_timestamp(&reading), &reading)
+}
+
+/// Run the jobs in `config` for each of `clients` whenever they're due, until Ctrl-C or
+/// SIGTERM. A failed job is reported and tried again when it is next due.
+async fn run_daemon(clients: &[Aranet4Client], config: &DaemonConfig) -> Result<()> {
+    let mut devices = Vec::new();
+    for client in clients {
+        let name = client.local_name().await.unwrap_or_default();
+        let serial = client.serial_number().await?.trim().to_string();
+        devices.push((name, serial));
+    }
+    // Readout files stay open between readings, one per job and device, until a new {date}
+    // moves them to a new file
+    let mut sinks = Vec::new();
+    for job in &config.readout {
+        let format = job.sink_format()?;
+        let mut job_sinks = Vec::new();
+        let mut paths = Vec::new();
+        for (name, serial) in &devices {
+            let path = daemon_job_path(&job.output, name, serial, format.name())?;
+            if paths.contains(&path) {
+                return Err(eyre!(
+                    "Readout job {} writes several devices to {}; add {{serial}} to its output",
+                    job.output,
+                    path.display()
+                ));
+            }
+            job_sinks.push(Some((path.clone(), open_sink(&path, format)?)));
+            paths.push(path);
+        }
+        sinks.push(job_sinks);
+    }
+
+    let intervals: Vec<Duration> = config
+        .history
+        .iter()
+        .map(|job| job.every)
+        .chain(config.readout.iter().map(|job| job.every))
+        .map(Duration::from_secs)
+        .collect();
+    let start = tokio::time::Instant::now();
+    let mut due = vec![start; intervals.len()];
+    let mut watchdog = watchdog_interval().map(tokio::time::interval);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    notify_ready();
+    loop {
+        let (next, &at) = due.iter().enumerate().min_by_key(|(_, at)| **at).unwrap();
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = async {
+                match &mut watchdog {
+                    Some(watchdog) => watchdog.tick().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                notify_watchdog();
+                continue;
+            }
+            _ = tokio::time::sleep_until(at) => {}
+        }
+        if let Some(job) = config.history.get(next) {
+            for (client, (name, serial)) in clients.iter().zip(&devices) {
+                match run_history_job(client, job, name, serial).await {
+                    Ok(written) => info!("{}: Wrote {}", name, written),
+                    Err(e) => error!("{}: history job {} failed: {:#}", name, job.output, e),
+                }
+            }
+        } else {
+            let job = next - config.history.len();
+            for (i, (client, (name, serial))) in clients.iter().zip(&devices).enumerate() {
+                let result = run_readout_job(
+                    client,
+                    &config.readout[job],
+                    name,
+                    serial,
+                    &mut sinks[job][i],
+                )
+                .await;
+                if let Err(e) = result {
+                    error!(
+                        "{}: readout job {} failed: {:#}",
+                        name, config.readout[job].output, e
+                    );
+                }
+            }
+        }
+        notify_watchdog();
+        // Skip runs that were missed while other jobs were busy rather than catching up
+        let now = tokio::time::Instant::now();
+        due[next] = at + intervals[next];
+        if due[next] < now {
+            due[next] = now + intervals[next];
+        }
+    }
+    notify_stopping();
+    for (_, sink) in sinks.into_iter().flatten().flatten() {
+        sink.finish()?;
+    }
+    Ok(())
+}
+
+/// Run `op`, unless Ctrl-C or SIGTERM arrives first, in which case `op` is dropped, `cleanup`
+/// is run and we exit with the status for that signal. Dropping `op` discards any unfinished
+/// archive file, leaving an existing archive as it was.
+async fn exit_on_signal<T>(op: impl Future<Output = T>, cleanup: impl Future<Output = ()>) -> T {
+    let signal = tokio::select! {
+        output = op => return output,
+        signal = shutdown_signal() => signal,
+    };
+    cleanup.await;
+    eprintln!("Interrupted");
+    let _ = io::stdout().flush();
+    std::process::exit(signal.exit_code());
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     color_eyre::install()?;
 
-    let matches = cli().get_matches();
+    let cli = Cli::parse();
+    init_logging(&cli);
+    let checking = matches!(cli.command, Commands::Check(_));
+    let result = run(&cli).await;
+    // A dry run isn't the run a heartbeat check is waiting for
+    if !cli.dry_run {
+        ping_heartbeat(&cli, &result).await;
+    }
+    match result {
+        Ok(0) => Ok(()),
+        Ok(exit_code) => std::process::exit(exit_code),
+        // Monitoring systems read any other failure of a check as a WARNING
+        Err(e) if checking => {
+            println!("CO2 UNKNOWN - {}", e);
+            std::process::exit(UNKNOWN_EXIT_CODE);
+        }
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            std::process::exit(failure_exit_code(&e));
+        }
+    }
+}
 
-    // use the first bluetooth adapter
-    let central = {
-        let manager = Manager::new().await.unwrap();
-        let adapters = manager.adapters().await?;
-        adapters.into_iter().next().unwrap()
+/// GET `--ping-url` after a run that exits with status 0, or else `--ping-fail-url`. A failed
+/// ping is only logged, as it doesn't change how the run went.
+async fn ping_heartbeat(cli: &Cli, result: &Result<i32>) {
+    let url = match result {
+        Ok(0) => &cli.ping_url,
+        _ => &cli.ping_fail_url,
     };
-    let sensor = scan_for_sensor(
-        &central,
-        matches
-            .get_one::<String>("device_pattern")
-            .unwrap_or(&"Aranet".to_string()),
-    )
-    .await?;
+    if let Some(url) = url {
+        if let Err(e) = ping(url, RetryPolicy::default()).await {
+            warn!("Heartbeat ping failed: {:#}", e);
+        }
+    }
+}
+
+/// Send log events to stderr, filtered by `RUST_LOG` if set or else by the `-v` count.
+fn init_logging(cli: &Cli) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| {
+        EnvFilter::new(match cli.verbose {
+            0 if cli.quiet => "warn",
+            0 => "warn,arachiver=info",
+            1 => "warn,arachiver=debug",
+            2 => "debug,arachiver=trace",
+            _ => "trace",
+        })
+    });
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(io::stderr);
+    if cli.log_format == LogFormat::Json {
+        builder.json().with_span_list(true).init();
+    } else if cli.verbose == 0 {
+        // By default, log lines read like the plain messages they replaced
+        builder.without_time().with_target(false).init();
+    } else {
+        builder.init();
+    }
+}
 
-    match matches.subcommand() {
-        Some(("device_info", _sub_matches)) => {
-            let info = DeviceInfo::read_from_sensor(&sensor).await?;
-            print_device_info(&info);
+/// Find and connect to the sensors to operate on: the one at `--address`, every one matching
+/// `device_pattern` with `--all`, or else the one matching it, using the peripheral ID an
+/// earlier run remembered for the pattern where possible.
+async fn connect_sensors(
+    device: &DeviceArgs,
+    central: Adapter,
+    device_pattern: &DevicePattern,
+) -> Result<Vec<Aranet4Client>> {
+    let scan_timeout = device.scan_timeout;
+    let scanning = central.clone();
+    let connect = async {
+        if let Some(address) = &device.address {
+            let cache_path = DeviceCache::default_path()?;
+            let mut cache = DeviceCache::load(&cache_path)?;
+            if device.no_cache {
+                cache.forget(address);
+            }
+            let client =
+                Aranet4Client::connect_with_address(central, address, scan_timeout, &mut cache)
+                    .await?;
+            cache.save(&cache_path)?;
+            Ok(vec![client])
+        } else if device.all {
+            Aranet4Client::connect_all_with_adapter(central, device_pattern, scan_timeout).await
+        } else {
+            let cache_path = DeviceCache::default_path()?;
+            let mut cache = DeviceCache::load(&cache_path)?;
+            let known = match cache.pattern_peripheral_id(device_pattern) {
+                Some(id) if !device.no_cache => {
+                    Aranet4Client::connect_known(central.clone(), id).await?
+                }
+                _ => None,
+            };
+            if let Some(client) = known {
+                debug!(%device_pattern, "Using cached peripheral ID instead of scanning");
+                return Ok(vec![client]);
+            }
+            if device.first {
+                return Ok(vec![
+                    Aranet4Client::connect_with_adapter(central, device_pattern, scan_timeout)
+                        .await?,
+                ]);
+            }
+            let clients =
+                Aranet4Client::connect_all_with_adapter(central, device_pattern, scan_timeout)
+                    .await?;
+            // Only an unambiguous match is remembered, so that a second sensor coming into
+            // range is still asked about
+            if let [client] = clients.as_slice() {
+                cache.insert_pattern(device_pattern, client.id());
+                cache.save(&cache_path)?;
+            }
+            Ok(clients)
+        }
+    };
+    exit_on_signal(connect, async {
+        let _ = scanning.stop_scan().await;
+    })
+    .await
+}
+
+/// Everything but setting up; returns the exit status, which only `check`, `battery` and
+/// `doctor` make nonzero.
+async fn run(cli: &Cli) -> Result<i32> {
+    let device_pattern = cli.device.pattern()?;
+    if cli.dry_run && !cli.command.archives_history() {
+        return Err(eyre!(
+            "--dry-run only applies to the subcommands that archive the history"
+        ));
+    }
+    if let Commands::Daemon(args) = &cli.command {
+        let config_path = daemon_config_path(args)?;
+        if args.install_systemd_unit {
+            print!("{}", systemd_unit(&daemon_command(&config_path)?));
+            return Ok(0);
+        }
+        // Check the config before scanning, which takes a while
+        DaemonConfig::load(&config_path)?;
+    }
+    if let Commands::Merge(args) = &cli.command {
+        let summary = merge_archives(
+            &args.inputs,
+            &args.output,
+            args.tolerance,
+            args.timestamp_unit,
+        )?;
+        if cli.quiet {
+            println!("{}", args.output.display());
+        } else {
+            println!(
+                "Wrote {} readings to {}, leaving out {} duplicates",
+                summary.rows_written,
+                args.output.display(),
+                summary.rows_read - summary.rows_written
+            );
+        }
+        return Ok(0);
+    }
+    if let Commands::Convert(args) = &cli.command {
+        let options = ConvertOptions {
+            units: args.units,
+            timestamp_unit: args.timestamp_unit,
+            timestamps: args.time.timestamps(),
+            name: args.name.clone(),
+            serial: args.serial.clone(),
+            derived: args.derived,
+        };
+        let written = convert_archive(&args.input, &args.output, &options)?;
+        if cli.quiet {
+            println!("{}", args.output.display());
+        } else {
+            println!("Wrote {} readings to {}", written, args.output.display());
         }
-        Some(("readout", _sub_matches)) => {
-            let (sensor_name, data) = get_current_sensor_data(&sensor).await?;
-            print_current_sensor_data(&sensor_name, &data);
+        return Ok(0);
+    }
+    if let Commands::Man(args) = &cli.command {
+        match &args.output {
+            Some(path) => {
+                let mut file = AtomicFile::create(path)?;
+                write_man_page(Cli::command(), &mut file)?;
+                file.commit()?;
+            }
+            None => write_man_page(Cli::command(), &mut io::stdout().lock())?,
         }
-        Some(("archive_history_csv", _sub_matches)) => {
-            let fname = archive_history_csv(&sensor).await?;
-            println!("Wrote {}", fname);
+        return Ok(0);
+    }
+    if let Commands::Stats(args) = &cli.command {
+        if let Some(input) = &args.input {
+            let stats = HistoryStats::from_archive(
+                input,
+                args.range.range()?,
+                Co2Thresholds::new(args.co2_warn, args.co2_crit)?,
+            )?;
+            print!("{}", stats.display_in(args.units));
+            return Ok(0);
+        }
+    }
+    let simulate =
+        cli.device.simulate || std::env::var("ARACHIVER_SIMULATE").is_ok_and(|value| value == "1");
+    if simulate
+        && matches!(
+            cli.command,
+            Commands::Doctor
+                | Commands::ListAdapters
+                | Commands::Scan
+                | Commands::Listen(_)
+                | Commands::Pair(_)
+        )
+    {
+        return Err(eyre!(
+            "{} needs a Bluetooth adapter and can't be simulated",
+            cli.command.name()
+        ));
+    }
+    if let Commands::Doctor = cli.command {
+        let target = DoctorTarget {
+            adapter: cli.device.adapter.as_deref(),
+            device_pattern: &device_pattern,
+            address: cli.device.address.as_deref(),
+        };
+        let report = diagnose(&target, cli.device.scan_timeout, cli.op_timeout).await;
+        print!("{}", report);
+        return Ok(report.exit_code());
+    }
+    if let Commands::ListAdapters = cli.command {
+        for (index, (_, info)) in list_adapters().await?.iter().enumerate() {
+            println!("{}: {}", index, info);
         }
-        Some(("archive_history_parquet", _sub_matches)) => {
-            let fname = archive_history_parquet(&sensor).await?;
-            println!("Wrote {}", fname);
+        return Ok(0);
+    }
+    let all = cli.device.all;
+    // The streaming subcommands stop cleanly on a signal by themselves.
+    let streaming = match &cli.command {
+        Commands::Monitor(_)
+        | Commands::Mqtt(_)
+        | Commands::Influx(_)
+        | Commands::Dashboard
+        | Commands::Watch(_) => true,
+        Commands::Readout(args) => args.subscribe,
+        _ => false,
+    };
+    if all && streaming {
+        return Err(eyre!("{} does not support --all", cli.command.name()));
+    }
+    if let Commands::Rssi(args) = &cli.command {
+        if all && args.watch {
+            return Err(eyre!("rssi --watch does not support --all"));
+        }
+    }
+    if all && cli.command.archive_output().is_some() {
+        return Err(eyre!(
+            "--output can't be combined with --all; use --filename-template instead"
+        ));
+    }
+    match cli.command {
+        Commands::ArchiveHistoryHdf5(_) if cfg!(not(feature = "hdf5")) => {
+            return Err(eyre!(
+                "This arachiver was built without HDF5 support; rebuild it with --features hdf5"
+            ));
+        }
+        Commands::ArchiveHistoryDuckdb(_) if cfg!(not(feature = "duckdb")) => {
+            return Err(eyre!(
+                "This arachiver was built without DuckDB support; rebuild it with --features duckdb"
+            ));
+        }
+        Commands::ArchiveHistoryPostgres(_) if cfg!(not(feature = "postgres")) => {
+            return Err(eyre!(
+                "This arachiver was built without PostgreSQL support; rebuild it with --features postgres"
+            ));
+        }
+        _ => {}
+    }
+    // Catch a backwards time range before scanning
+    if let Some(range) = cli.command.history_range() {
+        range.range()?;
+    }
+    let mut clients = if simulate {
+        vec![Aranet4Client::simulated()]
+    } else {
+        let central = match &cli.device.adapter {
+            Some(selector) => get_adapter(selector).await?,
+            None => get_first_adapter().await?,
+        };
+        if let Commands::Scan = cli.command {
+            let devices = exit_on_signal(scan_nearby(&central, cli.device.scan_timeout), async {
+                let _ = central.stop_scan().await;
+            })
+            .await?;
+            if devices.is_empty() {
+                return Err(
+                    Aranet4Error::DeviceNotFound("No Aranet devices found".to_string()).into(),
+                );
+            }
+            print_nearby_devices(&devices);
+            return Ok(0);
+        }
+        if let Commands::Listen(args) = &cli.command {
+            let json = args.format == ReadingFormat::Json;
+            listen_for_advertisements(&central, &device_pattern, |name, reading| {
+                print_advertised_reading(name, reading, json)
+            })
+            .await?;
+            return Ok(0);
+        }
+        connect_sensors(&cli.device, central, &device_pattern).await?
+    };
+    if !all && clients.len() > 1 {
+        clients = vec![choose_device(clients, &device_pattern).await?];
+    }
+
+    let retry = RetryPolicy {
+        retries: cli.retries,
+        delay: cli.retry_delay,
+    };
+    let transfer = if cli.interleave_history {
+        HistoryTransfer::Interleaved
+    } else {
+        HistoryTransfer::Sequential
+    };
+    let metrics = cli.command.metrics();
+    let reading_cache = if cli.reading_cache {
+        Some(ReadingCache::default_path()?)
+    } else {
+        None
+    };
+    let mut timings = Vec::new();
+    for client in clients.iter_mut() {
+        client.set_retry_policy(retry);
+        client.set_op_timeout(cli.op_timeout);
+        client.set_history_transfer(transfer);
+        client.set_requests_without_response(cli.write_without_response);
+        client.set_raw_timestamps(cli.raw_timestamps);
+        client.set_timestamp_source(cli.timestamp_source);
+        if let Some(metrics) = metrics {
+            client.set_history_metrics(metrics);
+        }
+        if let Some(path) = &reading_cache {
+            client.set_reading_cache(path.clone());
+        }
+        let timing = cli.timing.then(|| Arc::new(TransferTiming::new()));
+        attach_progress(client, !cli.quiet, timing.clone());
+        timings.push(timing);
+    }
+
+    let naming = ArchiveNaming {
+        output_dir: cli.output_dir.clone(),
+        template: match &cli.filename_template {
+            Some(template) => template.clone(),
+            None if all => DEFAULT_TEMPLATE_WITH_SERIAL.to_string(),
+            None => DEFAULT_TEMPLATE.to_string(),
+        },
+    };
+
+    // A combined table needs every sensor's history before any of it can be written
+    if let (Commands::ArchiveHistoryCombined(args), false) = (&cli.command, cli.dry_run) {
+        archive_history_combined(&clients, args, cli.quiet).await?;
+        for client in clients {
+            client.close().await?;
+        }
+        return Ok(0);
+    }
+    if let Commands::Daemon(args) = &cli.command {
+        let config = DaemonConfig::load(&daemon_config_path(args)?)?;
+        run_daemon(&clients, &config).await?;
+        for client in clients {
+            client.close().await?;
+        }
+        return Ok(0);
+    }
+    // serve polls all the sensors together rather than one after the other
+    if let Commands::Serve(args) = &cli.command {
+        let db = match &args.database {
+            Some(database) => Connection::open(database)
+                .wrap_err_with(|| format!("Could not open database {}", database.display()))?,
+            None => Connection::open_in_memory()?,
+        };
+        serve(
+            &clients,
+            args.listen,
+            db,
+            args.schedule.schedule(),
+            args.history_interval,
+        )
+        .await?;
+        for client in clients {
+            client.close().await?;
+        }
+        return Ok(0);
+    }
+
+    // Devices are handled one at a time; most adapters cope poorly with several concurrent
+    // connections, and the history download is bound by the sensor, not the host. A failure on
+    // one device shouldn't prevent archiving the others.
+    let mut failures = 0;
+    let mut exit_code = 0;
+    // A one-off subcommand may read the sensor several times, e.g. its model and then its
+    // readings, so it keeps the connection rather than reconnecting for each. `rssi` relies on
+    // advertisements, which the sensor stops sending while connected.
+    let keep_connection = !streaming && !matches!(cli.command, Commands::Rssi(_));
+    let mut json = JsonOutput::new(all && cli.command.prints_json());
+    for (client, timing) in clients.into_iter().zip(timings) {
+        if keep_connection {
+            client.stay_connected();
+        }
+        let run = run_subcommand(cli, &client, &naming, &mut json);
+        let result = if streaming {
+            run.await
+        } else {
+            exit_on_signal(run, client.abort()).await
+        };
+        match result {
+            Ok(code) => exit_code = exit_code.max(code),
+            Err(e) => {
+                if !all {
+                    return Err(e);
+                }
+                let name = client.local_name().await.unwrap_or_default();
+                error!("{}: {:#}", name, e);
+                failures += 1;
+            }
+        }
+        if let Some(timing) = timing {
+            let name = client.local_name().await.unwrap_or_default();
+            if let Some(report) = timing.report(&name) {
+                eprint!("{}", report);
+            }
+        }
+        client.close().await?;
+    }
+    json.finish()?;
+    if failures > 0 {
+        return Err(eyre!("Failed on {} device(s)", failures));
+    }
+    Ok(exit_code)
+}
+
+async fn run_subcommand(
+    cli: &Cli,
+    client: &Aranet4Client,
+    naming: &ArchiveNaming,
+    json: &mut JsonOutput,
+) -> Result<i32> {
+    let command = &cli.command;
+    let all = cli.device.all;
+    let quiet = cli.quiet;
+    let model = client.model().await?;
+    check_model_supported(command, model)?;
+    if cli.dry_run {
+        print_archive_plan(command, client, naming).await?;
+        return Ok(0);
+    }
+    match command {
+        Commands::DeviceInfo(args) => {
+            let info = client.read_device_info().await?;
+            let rssi = client.rssi().await;
+            match args.format {
+                InfoFormat::Text => {
+                    print_device_info(&info, rssi);
+                    if all {
+                        println!();
+                    }
+                }
+                format => print_record(&DeviceInfoRecord { info, rssi }, format, json)?,
+            }
+        }
+        Commands::Rssi(args) => {
+            let sensor_name = client.local_name().await.unwrap_or_default();
+            if !args.watch {
+                println!("{}: {}", sensor_name, format_rssi(client.rssi().await));
+                return Ok(0);
+            }
+            let mut ticks = tokio::time::interval(args.interval);
+            let shutdown = shutdown_signal();
+            tokio::pin!(shutdown);
+            loop {
+                tokio::select! {
+                    _ = &mut shutdown => break,
+                    _ = ticks.tick() => {
+                        let rssi = client.rssi().await;
+                        println!(
+                            "{} {:<24} {}",
+                            Local::now().format("%H:%M:%S"),
+                            format_rssi(rssi),
+                            rssi.map(signal_bar).unwrap_or_default()
+                        );
+                    }
+                }
+            }
+        }
+        Commands::Status(args) => {
+            let sensor_name = client.local_name().await.unwrap_or_default();
+            let status = client.read_status().await?;
+            match args.format {
+                InfoFormat::Text => {
+                    print_sensor_status(&sensor_name, &status);
+                    if all {
+                        println!();
+                    }
+                }
+                format => print_record(
+                    &SensorStatusRecord::new(sensor_name, status, Utc::now().timestamp()),
+                    format,
+                    json,
+                )?,
+            }
+        }
+        Commands::Battery(args) => {
+            let sensor_name = client.local_name().await.unwrap_or_default();
+            let battery = client.read_model_current(model).await?.battery();
+            match args.warn_below {
+                Some(threshold) if battery < threshold => {
+                    println!("{}: {}% (below {}%)", sensor_name, battery, threshold);
+                    return Ok(exit_status::LOW_BATTERY);
+                }
+                _ => println!("{}: {}%", sensor_name, battery),
+            }
+        }
+        Commands::Batch(args) => {
+            run_batch(client, args, naming, quiet).await?;
+            if all {
+                println!();
+            }
+        }
+        Commands::Readout(args) => {
+            let sensor_name = client.local_name().await.unwrap_or_default();
+            if args.subscribe {
+                print_notified_readings(client, &sensor_name, args).await?;
+                return Ok(0);
+            }
+            let as_json = args.format == ReadingFormat::Json;
+            if as_json && model != AranetModel::Aranet4 {
+                return Err(eyre!("JSON readout is only supported on the Aranet4"));
+            }
+            let data = client.read_model_current(model).await?;
+            if let (true, ModelMeasurement::Aranet4(data)) = (as_json, &data) {
+                let mut record =
+                    CurrentReadingRecord::new(Some(sensor_name), reading_timestamp(data), data);
+                if args.derived {
+                    record = record.with_derived(data);
+                }
+                json.print(&record)?;
+            } else {
+                print_current_sensor_data(&sensor_name, &data, args.units, args.derived)?;
+                if all {
+                    println!();
+                }
+            }
+        }
+        Commands::Check(args) => {
+            let thresholds = Co2Thresholds::new(args.co2_warn, args.co2_crit)?;
+            let sensor_name = client.local_name().await.unwrap_or_default();
+            let data = client.read_current().await?;
+            let status = thresholds.reading_status(&data);
+            println!("{}", status_line(&sensor_name, &data, &thresholds));
+            if let Some(command) = &args.on_alert {
+                if status != CheckStatus::Ok {
+                    // The check itself succeeded; a broken hook shouldn't mask its status
+                    if let Err(e) = run_alert_hook(command, status, &sensor_name, &data) {
+                        warn!("{:#}", e);
+                    }
+                }
+            }
+            return Ok(status.exit_code());
+        }
+        Commands::ArchiveHistoryCsv(args) => {
+            let output = args.output.as_deref();
+            let upload = upload_target(&args.upload, output)?;
+            let timestamps = args.time.timestamps();
+            let fname = if args.incremental {
+                let state_path = match &args.state_file {
+                    Some(path) => path.clone(),
+                    None => SyncState::default_path()?,
+                };
+                Some(
+                    archive_history_csv_incremental(
+                        client,
+                        naming,
+                        &state_path,
+                        args.units,
+                        timestamps,
+                    )
+                    .await?,
+                )
+            } else if args.append {
+                Some(
+                    archive_history_csv_append(client, output.unwrap(), args.units, timestamps)
+                        .await?,
+                )
+            } else if model != AranetModel::Aranet4 {
+                archive_model_history_csv(
+                    client,
+                    model,
+                    naming,
+                    output,
+                    args.units,
+                    timestamps,
+                    args.compress,
+                    args.csv_metadata,
+                )
+                .await?
+                .map(Written::from)
+            } else {
+                archive_history_csv(
+                    client,
+                    naming,
+                    output,
+                    args.units,
+                    timestamps,
+                    args.range.range()?,
+                    args.compress,
+                    check_derived(args.derived, args.metrics.as_deref())?,
+                    args.csv_metadata,
+                )
+                .await?
+                .map(Written::from)
+            };
+            report_written(fname.clone(), quiet);
+            if let Some(target) = upload {
+                upload_archive(
+                    &target,
+                    &args.upload,
+                    output,
+                    args.compress,
+                    fname.as_ref(),
+                    quiet,
+                )
+                .await?;
+            }
+        }
+        Commands::ArchiveHistoryParquet(args) => {
+            let output = args.output.as_deref();
+            let upload = upload_target(&args.upload, output)?;
+            let fname = if args.append {
+                Some(
+                    archive_history_parquet_append(
+                        client,
+                        output.unwrap(),
+                        args.timestamp_unit,
+                        args.units,
+                    )
+                    .await?,
+                )
+            } else {
+                archive_history_parquet(
+                    client,
+                    naming,
+                    output,
+                    args.timestamp_unit,
+                    args.units,
+                    args.range.range()?,
+                    check_derived(args.derived, args.metrics.as_deref())?,
+                    args.serial_column,
+                )
+                .await?
+                .map(Written::from)
+            };
+            report_written(fname.clone(), quiet);
+            if let Some(target) = upload {
+                upload_archive(&target, &args.upload, output, None, fname.as_ref(), quiet).await?;
+            }
+        }
+        Commands::ArchiveHistoryArrow(args) => {
+            let fname =
+                archive_history_arrow(client, naming, args.output.as_deref(), args.range.range()?)
+                    .await?;
+            report_written(fname.map(Written::from), quiet);
+        }
+        Commands::ArchiveHistoryMsgpack(args) => {
+            let fname = archive_history_msgpack(
+                client,
+                naming,
+                args.output.as_deref(),
+                args.range.range()?,
+            )
+            .await?;
+            report_written(fname.map(Written::from), quiet);
+        }
+        Commands::ArchiveHistoryXlsx(args) => {
+            let fname = archive_history_xlsx(
+                client,
+                naming,
+                args.output.as_deref(),
+                args.units,
+                args.range.range()?,
+            )
+            .await?;
+            report_written(fname.map(Written::from), quiet);
+        }
+        #[cfg(feature = "hdf5")]
+        Commands::ArchiveHistoryHdf5(args) => {
+            let fname = archive_history_hdf5(
+                client,
+                naming,
+                args.output.as_deref(),
+                args.units,
+                args.range.range()?,
+            )
+            .await?;
+            report_written(Some(Written::from(fname)), quiet);
+        }
+        Commands::ArchiveHistoryJson(args) => {
+            let fname = archive_history_json(
+                client,
+                naming,
+                args.output.as_deref(),
+                args.ndjson,
+                args.range.range()?,
+                args.derived,
+            )
+            .await?;
+            report_written(fname.map(Written::from), quiet);
+        }
+        Commands::ArchiveHistorySqlite(args) => {
+            let fname = archive_history_sqlite(client, &args.database, args.range.range()?).await?;
+            report_written(Some(fname), quiet);
+        }
+        #[cfg(feature = "duckdb")]
+        Commands::ArchiveHistoryDuckdb(args) => {
+            let fname = archive_history_duckdb(client, &args.database, args.range.range()?).await?;
+            report_written(Some(fname), quiet);
+        }
+        #[cfg(feature = "postgres")]
+        Commands::ArchiveHistoryPostgres(args) => {
+            let fname =
+                archive_history_postgres(client, &args.dsn, &args.table, args.range.range()?)
+                    .await?;
+            report_written(Some(fname), quiet);
+        }
+        Commands::Plot(args) => {
+            let metrics = &args.metric;
+            // Check the file name before the download rather than after
+            let chart = args
+                .output
+                .as_deref()
+                .map(|output| {
+                    ChartFormat::from_path(output)
+                        .map(|format| (output, format))
+                        .ok_or(eyre!(
+                            "Could not determine chart format for {}; use .svg or .png",
+                            output.display()
+                        ))
+                })
+                .transpose()?;
+            let since = Utc::now().timestamp() - i64::from(args.hours) * 3600;
+            let history = client.read_history_after(since).await?;
+            match chart {
+                Some((output, format)) => {
+                    let size = (
+                        args.width.map_or(1200, u32::from),
+                        args.height.map_or(300, u32::from) * metrics.len() as u32,
+                    );
+                    save_history_chart(&history, metrics, args.units, output, format, size)?;
+                    report_written(Some(Written::from(output.display().to_string())), quiet);
+                }
+                None => {
+                    let width = args.width.unwrap_or_else(|| {
+                        ratatui::crossterm::terminal::size().map_or(80, |(columns, _)| columns)
+                    });
+                    println!(
+                        "{}",
+                        render_terminal_plot(
+                            &history,
+                            metrics,
+                            args.units,
+                            width,
+                            args.height.unwrap_or(15)
+                        )?
+                    );
+                }
+            }
+        }
+        Commands::Stats(args) => {
+            let thresholds = Co2Thresholds::new(args.co2_warn, args.co2_crit)?;
+            let sensor_name = client.local_name().await.unwrap_or_default();
+            let history = client.read_history_range(args.range.range()?).await?;
+            let stats = HistoryStats::from_history(&history, thresholds)?;
+            println!(
+                "{}\n{}\n{}",
+                sensor_name,
+                "=".repeat(sensor_name.graphemes(true).count()),
+                stats.display_in(args.units)
+            );
+        }
+        Commands::Monitor(args) => {
+            let output = &args.output;
+            let format = args
+                .format
+                .or_else(|| SinkFormat::from_path(output))
+                .ok_or(eyre!(
+                    "Could not determine output format for {}; pass --format",
+                    output.display()
+                ))?;
+            let alert = match args.notify_above {
+                Some(above) => {
+                    let below = args.notify_below.unwrap_or(above.saturating_sub(200));
+                    Some(Co2Alert::new(above, below)?)
+                }
+                None => None,
+            };
+            let sink = open_sink(output, format)?;
+            monitor(client, args.schedule.schedule(), sink, alert).await?;
+            report_written(Some(Written::from(output.display().to_string())), quiet);
+        }
+        Commands::Mqtt(args) => {
+            let (host, port) = parse_broker_url(&args.mqtt_url)?;
+            let config = MqttConfig {
+                host,
+                port,
+                username: args.username.clone(),
+                password: args.password.clone(),
+                topic_prefix: args.topic_prefix.clone(),
+                discovery_prefix: args.discovery_prefix.clone(),
+            };
+            publish_readings(client, &config, args.schedule.schedule()).await?;
+        }
+        Commands::ArchiveHistoryInflux(args) => {
+            let fname = match args.influx.config() {
+                Some(config) => {
+                    Some(archive_history_influx_write(client, &config, args.range.range()?).await?)
+                }
+                None => archive_history_influx(
+                    client,
+                    naming,
+                    args.output.as_deref(),
+                    args.range.range()?,
+                )
+                .await?
+                .map(Written::from),
+            };
+            report_written(fname, quiet);
+        }
+        Commands::Influx(args) => {
+            arachiver::influx_io::publish_readings(client, &args.config(), args.interval).await?;
+        }
+        Commands::Webhook(args) => {
+            let config = WebhookConfig {
+                url: args.url.clone(),
+                format: args.format,
+                batch_size: args.batch_size,
+                retry: RetryPolicy {
+                    retries: args.retries,
+                    ..RetryPolicy::default()
+                },
+            };
+            let state_path = match &args.state_file {
+                Some(path) => path.clone(),
+                None => SyncState::default_path()?,
+            };
+            println!(
+                "{}",
+                post_history_webhook(client, &config, &state_path).await?
+            );
+        }
+        Commands::Dashboard => {
+            dashboard(client).await?;
+        }
+        Commands::Watch(args) => {
+            watch(client, args.interval, args.units).await?;
+        }
+        Commands::SyncTime => {
+            let sensor_name = client.local_name().await.unwrap_or_default();
+            let skew = match client.sync_time().await {
+                Err(Aranet4Error::CharacteristicNotFound) => {
+                    return Err(eyre!("{} has no clock that can be set", sensor_name));
+                }
+                result => result?,
+            };
+            let direction = if skew < TimeDelta::zero() {
+                "behind"
+            } else {
+                "ahead"
+            };
+            println!(
+                "{}: clock set to {} (it was {:.1} s {})",
+                sensor_name,
+                Local::now().format("%Y-%m-%d %H:%M:%S"),
+                skew.abs().as_seconds_f64(),
+                direction
+            );
+        }
+        Commands::Pair(args) => {
+            let sensor_name = client.local_name().await.unwrap_or_default();
+            let address = client.address().await;
+            let cache_path = DeviceCache::default_path()?;
+            let mut cache = DeviceCache::load(&cache_path)?;
+            if let Some(paired_at) = cache.paired_at(&address) {
+                info!("{} was last paired at {}", sensor_name, paired_at);
+            }
+            let pin = args.pin.clone();
+            pair(&address, move || match &pin {
+                Some(pin) => Ok(pin.clone()),
+                None => prompt_pin(),
+            })
+            .await?;
+            // Fails with PairingRequired unless pairing worked; elsewhere than on Linux, this is
+            // what makes the system ask for the PIN
+            client.read_status().await?;
+            cache.mark_paired(&address, Utc::now());
+            cache.save(&cache_path)?;
+            println!("{}: paired", sensor_name);
+        }
+        Commands::SetInterval(args) => {
+            let interval = client.set_update_interval(args.minutes).await?;
+            println!("Measurement interval is now {} s", interval);
         }
         _ => {
             return Err(eyre!("Invalid subcommand"));
         }
     }
-    central.stop_scan().await.unwrap();
-    Ok(())
+    Ok(0)
 }
@@ -0,0 +1,188 @@
+//! The `arachiver(1)` man page, generated from the command-line definition so that it can't
+//! fall out of step with `--help`.
+
+use std::io::Write;
+
+use clap::Command;
+use clap_mangen::roff::{bold, italic, roman, Roff};
+use clap_mangen::Man;
+use color_eyre::eyre::Result;
+
+use crate::check::UNKNOWN_EXIT_CODE;
+use crate::exit_status::{
+    BLUETOOTH, DEVICE_NOT_FOUND, FAILURE, LOW_BATTERY, NO_ADAPTER, OUTPUT, PAIRING_REQUIRED,
+};
+use crate::naming::{DEFAULT_TEMPLATE, DEFAULT_TEMPLATE_WITH_SERIAL};
+
+const EXIT_STATUSES: [(i32, &str); 9] = [
+    (0, "Success."),
+    (
+        FAILURE,
+        "Any failure without a more specific status, e.g. an invalid argument, or only some \
+         devices failing with --all.",
+    ),
+    (
+        NO_ADAPTER,
+        "No Bluetooth adapter, or none matching --adapter.",
+    ),
+    (
+        DEVICE_NOT_FOUND,
+        "No sensor matching --device or --address was seen before --scan-timeout.",
+    ),
+    (
+        BLUETOOTH,
+        "Talking to the sensor failed, timed out or returned something unexpected.",
+    ),
+    (
+        OUTPUT,
+        "Reading or writing a file, e.g. an archive, failed.",
+    ),
+    (
+        LOW_BATTERY,
+        "battery --warn-below found a battery below the threshold.",
+    ),
+    (
+        PAIRING_REQUIRED,
+        "The sensor needs to be paired first; see the pair subcommand.",
+    ),
+    (130, "Interrupted with Ctrl-C, or 143 with SIGTERM."),
+];
+
+const NO_ARGS: [&str; 0] = [];
+
+fn section(roff: &mut Roff, title: &str) {
+    roff.control("SH", [title]);
+}
+
+/// A tagged paragraph: `tag` in bold, then `text` indented below it.
+fn item(roff: &mut Roff, tag: &str, text: &str) {
+    roff.control("TP", NO_ARGS);
+    roff.text([bold(tag)]);
+    roff.text([roman(text)]);
+}
+
+/// The sections `clap_mangen` doesn't derive from `command`.
+fn extra_sections() -> Roff {
+    let mut roff = Roff::new();
+    section(&mut roff, "EXIT STATUS");
+    for (status, meaning) in EXIT_STATUSES {
+        item(&mut roff, &status.to_string(), meaning);
+    }
+    roff.control("PP", NO_ARGS);
+    roff.text([roman(format!(
+        "check exits with the Nagios statuses instead: 0 when CO₂ is OK, 1 at the warning \
+         level, 2 at the critical level and {} when the sensor couldn't be read.",
+        UNKNOWN_EXIT_CODE
+    ))]);
+
+    section(&mut roff, "ARCHIVE NAMES");
+    roff.text([
+        roman("Archives are written to --output-dir, named by --filename-template, "),
+        roman("which defaults to "),
+        italic(DEFAULT_TEMPLATE),
+        roman(", or "),
+        italic(DEFAULT_TEMPLATE_WITH_SERIAL),
+        roman(" with --all. The placeholders are:"),
+    ]);
+    for (placeholder, meaning) in [
+        ("{name}", "the device name, e.g. Aranet4 1BA27"),
+        ("{serial}", "the device serial number"),
+        ("{date}", "the local time the download started, in RFC 3339"),
+        (
+            "{format}",
+            "the archive format's extension, e.g. csv or parquet",
+        ),
+    ] {
+        item(&mut roff, placeholder, meaning);
+    }
+    roff.control("PP", NO_ARGS);
+    roff.text([roman(
+        "--output writes an archive to the given file instead, and --output - to stdout. \
+         Files only appear under their name once complete.",
+    )]);
+
+    section(&mut roff, "FILES");
+    for (path, meaning) in [
+        (
+            "~/.config/arachiver/devices.json",
+            "Peripheral IDs of the sensors found by earlier runs, to skip scanning; see --no-cache.",
+        ),
+        (
+            "~/.config/arachiver/daemon.toml",
+            "The jobs of the daemon subcommand.",
+        ),
+        (
+            "~/.local/share/arachiver/state.json",
+            "The last reading archived by archive_history_csv --incremental and posted by \
+             webhook, per sensor.",
+        ),
+        (
+            "~/.cache/arachiver/readings.json",
+            "Current readings shared with --reading-cache.",
+        ),
+    ] {
+        item(&mut roff, path, meaning);
+    }
+    roff.control("PP", NO_ARGS);
+    roff.text([roman(
+        "These are the Linux locations; other platforms use their own config, data and cache \
+         directories.",
+    )]);
+
+    section(&mut roff, "ENVIRONMENT");
+    for (variable, meaning) in [
+        (
+            "ARACHIVER_SIMULATE",
+            "Set to 1 to talk to a simulated sensor, as with --simulate.",
+        ),
+        (
+            "INFLUX_TOKEN",
+            "The InfluxDB API token, unless --token is given.",
+        ),
+        ("RUST_LOG", "Log filter, overriding --verbose."),
+    ] {
+        item(&mut roff, variable, meaning);
+    }
+    roff
+}
+
+/// Write the man page of `command`, the whole command line, as roff.
+pub fn write_man_page<W: Write>(command: Command, dest: &mut W) -> Result<()> {
+    let man = Man::new(command);
+    man.render_title(dest)?;
+    man.render_name_section(dest)?;
+    man.render_synopsis_section(dest)?;
+    man.render_description_section(dest)?;
+    man.render_options_section(dest)?;
+    man.render_subcommands_section(dest)?;
+    extra_sections().to_writer(dest)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use clap::{Arg, Command};
+
+    use crate::manpage::write_man_page;
+
+    #[test]
+    fn test_write_man_page() {
+        let command = Command::new("arachiver")
+            .about("Aranet4 archiver")
+            .arg(Arg::new("all").long("all").help("Every matching device"))
+            .subcommand(Command::new("readout").about("Print the current sensor readings"));
+        let mut page = Vec::new();
+        write_man_page(command, &mut page).unwrap();
+        let page = String::from_utf8(page).unwrap();
+        for expected in [
+            ".TH arachiver",
+            "Print the current sensor readings",
+            "EXIT STATUS",
+            "No Bluetooth adapter",
+            "{date}_{name}_history.{format}",
+            "ARACHIVER_SIMULATE",
+        ] {
+            assert!(page.contains(expected), "no {} in {}", expected, page);
+        }
+    }
+}
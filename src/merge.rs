@@ -0,0 +1,254 @@
+//! Combining overlapping history archives. Every download includes the sensor's whole ring
+//! buffer, so archives from successive runs mostly repeat each other.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Result};
+
+use crate::atomic_file::AtomicFile;
+use crate::csv_io::{read_history_csv, write_history_columns_csv};
+use crate::json_io::{
+    read_history_json, read_history_ndjson, write_history_json, write_history_ndjson,
+};
+use crate::msgpack_io::{read_history_msgpack, write_history_msgpack};
+use crate::parquet_io::{
+    parquet_units, read_history_columns, write_history_file, HistoryColumns, HistoryRow,
+    TimestampUnit,
+};
+use crate::types::{HistoryRecord, Units};
+
+/// Archive formats `merge` reads and writes, told apart by file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Csv,
+    Parquet,
+    Json,
+    Ndjson,
+    Msgpack,
+}
+
+impl ArchiveFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(ArchiveFormat::Csv),
+            "parquet" => Some(ArchiveFormat::Parquet),
+            "json" => Some(ArchiveFormat::Json),
+            "ndjson" | "jsonl" => Some(ArchiveFormat::Ndjson),
+            "msgpack" | "mpk" => Some(ArchiveFormat::Msgpack),
+            _ => None,
+        }
+    }
+}
+
+fn archive_format(path: &Path) -> Result<ArchiveFormat> {
+    ArchiveFormat::from_path(path).ok_or(eyre!(
+        "Can't tell the format of {}; expected a .csv, .parquet, .json, .ndjson or .msgpack file",
+        path.display()
+    ))
+}
+
+/// JSON and MessagePack archives are always metric.
+fn json_columns(records: Vec<HistoryRecord>) -> (HistoryColumns, Option<Units>) {
+    let mut columns = HistoryColumns::default();
+    for record in &records {
+        columns.push(HistoryRow::from_record(record, Units::Metric));
+    }
+    (columns, Some(Units::Metric))
+}
+
+/// The rows of an archive, along with its units if it records them.
+pub(crate) fn read_archive(path: &Path) -> Result<(HistoryColumns, Option<Units>)> {
+    match archive_format(path)? {
+        ArchiveFormat::Csv => {
+            let (columns, units) = read_history_csv(path)?;
+            Ok((columns, Some(units)))
+        }
+        ArchiveFormat::Parquet => Ok((read_history_columns(path)?, parquet_units(path)?)),
+        ArchiveFormat::Json => Ok(json_columns(read_history_json(path)?)),
+        ArchiveFormat::Ndjson => Ok(json_columns(read_history_ndjson(path)?)),
+        ArchiveFormat::Msgpack => Ok(json_columns(read_history_msgpack(path)?)),
+    }
+}
+
+/// The readings of an archive as metric records, in the order they are stored.
+pub(crate) fn read_archive_records(path: &Path) -> Result<Vec<HistoryRecord>> {
+    let (columns, units) = read_archive(path)?;
+    // Parquet files from before units were recorded are metric
+    let units = units.unwrap_or_default();
+    columns.rows().map(|row| row.to_record(units)).collect()
+}
+
+/// The rows of `archives` sorted by timestamp, leaving out any within `tolerance` seconds of
+/// the row kept before it, which are the same reading with a slightly different timestamp.
+/// Where rows coincide exactly, the one from the earliest archive is kept.
+pub(crate) fn dedup_rows(archives: &[HistoryColumns], tolerance: i64) -> HistoryColumns {
+    let mut rows: Vec<(&HistoryColumns, usize)> = archives
+        .iter()
+        .flat_map(|columns| (0..columns.len()).map(move |i| (columns, i)))
+        .collect();
+    rows.sort_by_key(|&(columns, i)| columns.timestamp[i]);
+    let mut merged = HistoryColumns::default();
+    for (columns, i) in rows {
+        let timestamp = columns.timestamp[i];
+        if merged
+            .timestamp
+            .last()
+            .is_some_and(|&last| timestamp - last <= tolerance)
+        {
+            continue;
+        }
+        merged.push(columns.row(i));
+    }
+    merged
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MergeSummary {
+    pub rows_read: usize,
+    pub rows_written: usize,
+}
+
+/// Merge the CSV, Parquet, JSON and MessagePack archives at `inputs` into one archive at `output`, whose
+/// format is chosen by its extension, dropping duplicate readings as [`dedup_rows`] does. All
+/// inputs must be in the same units. `output` may be one of the inputs, as it is only replaced
+/// once the merged archive is complete.
+pub fn merge_archives(
+    inputs: &[PathBuf],
+    output: &Path,
+    tolerance: i64,
+    timestamp_unit: TimestampUnit,
+) -> Result<MergeSummary> {
+    let output_format = archive_format(output)?;
+    let mut archives = Vec::new();
+    let mut units: Option<(Units, &Path)> = None;
+    for input in inputs {
+        let (columns, input_units) = read_archive(input)?;
+        match (units, input_units) {
+            (Some((units, first)), Some(input_units)) if units != input_units => {
+                return Err(eyre!(
+                    "{} is in {:?} units but {} is in {:?} units",
+                    first.display(),
+                    units,
+                    input.display(),
+                    input_units
+                ));
+            }
+            (None, Some(input_units)) => units = Some((input_units, input)),
+            _ => {}
+        }
+        archives.push(columns);
+    }
+    // Parquet files from before units were recorded are metric
+    let units = units.map(|(units, _)| units).unwrap_or_default();
+    let merged = dedup_rows(&archives, tolerance);
+    let mut file = AtomicFile::create(output)?;
+    match output_format {
+        ArchiveFormat::Csv => write_history_columns_csv(&merged, &mut file, units)?,
+        ArchiveFormat::Parquet => {
+            write_history_file(&mut file, merged.rows(), timestamp_unit, units, false, None)?
+        }
+        ArchiveFormat::Json => write_history_json(
+            merged.rows().map(|row| row.to_record(units)),
+            false,
+            &mut file,
+        )?,
+        ArchiveFormat::Ndjson => write_history_ndjson(
+            merged.rows().map(|row| row.to_record(units)),
+            false,
+            &mut file,
+        )?,
+        ArchiveFormat::Msgpack => {
+            write_history_msgpack(merged.rows().map(|row| row.to_record(units)), &mut file)?
+        }
+    }
+    file.commit()?;
+    Ok(MergeSummary {
+        rows_read: archives.iter().map(HistoryColumns::len).sum(),
+        rows_written: merged.len(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::merge::{dedup_rows, merge_archives, MergeSummary};
+    use crate::parquet_io::{read_history_columns, HistoryColumns, TimestampUnit};
+
+    fn columns(timestamps: &[i64], co2: &[i32]) -> HistoryColumns {
+        HistoryColumns {
+            timestamp: timestamps.to_vec(),
+            temperature: vec![20.5; timestamps.len()],
+            humidity: vec![33; timestamps.len()],
+            pressure: vec![1017.4; timestamps.len()],
+            co2: co2.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_dedup_rows() {
+        // The second download's timestamps are a second later for the same readings
+        let first = columns(&[1000, 1300, 1600], &[500, 510, 520]);
+        let second = columns(&[1301, 1601, 1901], &[511, 521, 530]);
+        let merged = dedup_rows(&[second, first], 2);
+        assert_eq!(merged.timestamp, vec![1000, 1300, 1600, 1901]);
+        assert_eq!(merged.co2, vec![500, 510, 520, 530]);
+
+        let same = columns(&[1000], &[500]);
+        let other = columns(&[1000], &[600]);
+        assert_eq!(dedup_rows(&[other, same], 0).co2, vec![600]);
+    }
+
+    #[test]
+    fn test_merge_archives() {
+        let dir = std::env::temp_dir();
+        let first = dir.join("arachiver_test_merge_first.csv");
+        let second = dir.join("arachiver_test_merge_second.csv");
+        let output = dir.join("arachiver_test_merge_output.parquet");
+        std::fs::write(
+            &first,
+            "timestamp,Temperature (°C),Humidity (%),Pressure (hPa),CO₂ (ppm)\n\
+             1000,20.65,33,1017.4,926\n\
+             1300,20.70,33,1017.5,930\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &second,
+            "timestamp,Temperature (°C),Humidity (%),Pressure (hPa),CO₂ (ppm)\n\
+             1301,20.70,33,1017.5,930\n\
+             1601,20.75,34,1017.6,940\n",
+        )
+        .unwrap();
+        let summary = merge_archives(
+            &[first.clone(), second.clone()],
+            &output,
+            30,
+            TimestampUnit::Millis,
+        )
+        .unwrap();
+        let merged = read_history_columns(&output).unwrap();
+        let imperial = dir.join("arachiver_test_merge_imperial.csv");
+        std::fs::write(
+            &imperial,
+            "timestamp,Temperature (°F),Humidity (%),Pressure (inHg),CO₂ (ppm)\n",
+        )
+        .unwrap();
+        let mixed = merge_archives(
+            &[first.clone(), imperial.clone()],
+            &output,
+            30,
+            TimestampUnit::Millis,
+        );
+        for path in [first, second, output, imperial] {
+            std::fs::remove_file(path).unwrap();
+        }
+        assert_eq!(
+            summary,
+            MergeSummary {
+                rows_read: 4,
+                rows_written: 3
+            }
+        );
+        assert_eq!(merged.timestamp, vec![1000, 1300, 1601]);
+        assert_eq!(merged.co2, vec![926, 930, 940]);
+        assert!(mixed.is_err());
+    }
+}
@@ -0,0 +1,449 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use btleplug::api::ValueNotification;
+use futures::StreamExt;
+use tokio_stream::wrappers::IntervalStream;
+use uuid::Uuid;
+
+use crate::device::{
+    ARANET4_CO2_THRESHOLDS_UUID, ARANET4_COMMAND_UUID, ARANET4_CURRENT_READINGS_UUID,
+    ARANET4_NOTIFY_CURRENT_READINGS_UUID, ARANET4_NOTIFY_HISTORY_UUID,
+    ARANET4_TIME_SINCE_UPDATE_UUID, ARANET4_TOTAL_READINGS_UUID, ARANET4_UPDATE_INTERVAL_UUID,
+    GENERIC_GATT_DEVICE_MODEL_NUMBER_STRING_UUID, GENERIC_GATT_FIRMWARE_REVISION_STRING_UUID,
+    GENERIC_GATT_HARDWARE_REVISION_STRING_UUID, GENERIC_GATT_MANUFACTURER_NAME_STRING_UUID,
+    GENERIC_GATT_SERIAL_NUMBER_STRING_UUID, GENERIC_GATT_SOFTWARE_REVISION_STRING_UUID,
+};
+use crate::transport::{NotificationStream, SensorTransport};
+use crate::types::Aranet4Error;
+
+/// History type codes, as in the history request command and response headers.
+const TEMPERATURE: u8 = 1;
+const HUMIDITY: u8 = 2;
+const PRESSURE: u8 = 3;
+const CO2: u8 = 4;
+
+/// How the Aranet4 packs history notifications; it never sends more than this many elements at
+/// once.
+const MAX_ELEMENTS_PER_PACKET: usize = 120;
+
+/// Readings in the history of [`MockAranet4::simulated`], a week's worth at 5-minute intervals.
+const SIMULATED_READINGS: usize = 2016;
+
+/// Build history notifications the way the sensor sends them: a 4-byte header with the type, the
+/// 1-based index of the first element and the element count, followed by the little-endian
+/// elements of `element_size` bytes each.
+pub fn history_packets(
+    type_code: u8,
+    start_index: u16,
+    elements: &[u8],
+    element_size: usize,
+) -> Vec<Vec<u8>> {
+    elements
+        .chunks(element_size * MAX_ELEMENTS_PER_PACKET)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let first = start_index + (i * MAX_ELEMENTS_PER_PACKET) as u16;
+            let mut packet = vec![type_code];
+            packet.extend_from_slice(&first.to_le_bytes());
+            packet.push((chunk.len() / element_size) as u8);
+            packet.extend_from_slice(chunk);
+            packet
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+struct History {
+    /// Little-endian elements, oldest first.
+    elements: Vec<u8>,
+    element_size: usize,
+}
+
+/// An in-memory Aranet4 for tests. Reads are answered from a table of characteristic values,
+/// setting the interval updates it, and history requests are answered with notifications packed like the real sensor's, or with
+/// verbatim captured packets set with [`MockAranet4::with_history_packets`]. Once subscribed to,
+/// the current reading is notified right away and then every interval.
+#[derive(Debug)]
+pub struct MockAranet4 {
+    name: String,
+    characteristics: Mutex<HashMap<Uuid, Vec<u8>>>,
+    history: Mutex<HashMap<u8, History>>,
+    captured: HashMap<u8, Vec<Vec<u8>>>,
+    interruptions: Mutex<HashMap<u8, usize>>,
+    measurement_after: Mutex<Option<u8>>,
+    interleaved: Vec<ValueNotification>,
+    pending: Mutex<VecDeque<ValueNotification>>,
+    writes: Mutex<Vec<(Uuid, Vec<u8>)>>,
+    unacknowledged_writes: AtomicUsize,
+    notifying_current: AtomicBool,
+    connected: AtomicBool,
+    connects: AtomicUsize,
+}
+
+impl MockAranet4 {
+    /// A sensor with no history, a 5-minute interval and the device information of a typical
+    /// Aranet4.
+    pub fn new(name: &str) -> Self {
+        let text = |value: &str| value.as_bytes().to_vec();
+        let characteristics = HashMap::from([
+            (
+                GENERIC_GATT_DEVICE_MODEL_NUMBER_STRING_UUID,
+                text("Aranet4"),
+            ),
+            (GENERIC_GATT_SERIAL_NUMBER_STRING_UUID, text("317960113191")),
+            (GENERIC_GATT_HARDWARE_REVISION_STRING_UUID, text("12")),
+            (GENERIC_GATT_SOFTWARE_REVISION_STRING_UUID, text("v0.4.14")),
+            (
+                GENERIC_GATT_MANUFACTURER_NAME_STRING_UUID,
+                text("SAF Tehnika"),
+            ),
+            (GENERIC_GATT_FIRMWARE_REVISION_STRING_UUID, text("v1.4.14")),
+            (ARANET4_TOTAL_READINGS_UUID, 0u16.to_le_bytes().to_vec()),
+            (ARANET4_UPDATE_INTERVAL_UUID, 300u16.to_le_bytes().to_vec()),
+            (ARANET4_TIME_SINCE_UPDATE_UUID, 24u16.to_le_bytes().to_vec()),
+            (ARANET4_CO2_THRESHOLDS_UUID, vec![0xe8, 0x03, 0x78, 0x05]),
+            (
+                ARANET4_CURRENT_READINGS_UUID,
+                vec![
+                    0x9e, 0x03, 0x9d, 0x01, 0xbe, 0x27, 0x21, 0x16, 0x01, 0x2c, 0x01, 0xff, 0x00,
+                ],
+            ),
+        ]);
+        MockAranet4 {
+            name: name.to_string(),
+            characteristics: Mutex::new(characteristics),
+            history: Mutex::new(HashMap::new()),
+            captured: HashMap::new(),
+            interruptions: Mutex::new(HashMap::new()),
+            measurement_after: Mutex::new(None),
+            interleaved: Vec::new(),
+            pending: Mutex::new(VecDeque::new()),
+            writes: Mutex::new(Vec::new()),
+            unacknowledged_writes: AtomicUsize::new(0),
+            notifying_current: AtomicBool::new(false),
+            connected: AtomicBool::new(false),
+            connects: AtomicUsize::new(0),
+        }
+    }
+
+    /// The sensor behind `--simulate`: a week of readings, as many as the Aranet4 keeps, that
+    /// follow a day's rhythm in a bedroom, the same on every run. CO₂ builds up overnight until
+    /// the window is opened at 7:00, while temperature and humidity peak in the afternoon.
+    pub fn simulated() -> Self {
+        let minutes_of_day: Vec<usize> = (0..SIMULATED_READINGS).map(|i| i * 5 % 1440).collect();
+        let co2: Vec<u16> = minutes_of_day
+            .iter()
+            .map(|&minute| match minute {
+                0..420 => 450 + minute as u16 * 3 / 2,
+                _ => 450 + (1440 - minute as u16) / 8,
+            })
+            .collect();
+        // How far from 15:00 each reading is, in units of 36 minutes
+        let cooling: Vec<u16> = minutes_of_day
+            .iter()
+            .map(|&minute| (minute.abs_diff(900) / 36) as u16)
+            .collect();
+        let temperature: Vec<u16> = cooling.iter().map(|&c| 440 - c).collect();
+        let humidity: Vec<u8> = cooling.iter().map(|&c| 45 - c as u8 / 2).collect();
+        let pressure: Vec<u16> = (0..SIMULATED_READINGS)
+            .map(|i| 10_100 + (i / 48 % 10) as u16 * 6)
+            .collect();
+        MockAranet4::new("Aranet4 Simulated").with_history(&temperature, &humidity, &pressure, &co2)
+    }
+
+    pub fn with_characteristic(mut self, uuid: Uuid, value: &[u8]) -> Self {
+        self.characteristics
+            .get_mut()
+            .unwrap()
+            .insert(uuid, value.to_vec());
+        self
+    }
+
+    /// Like a sensor whose firmware lacks the characteristic, so that reading it fails.
+    pub fn without_characteristic(mut self, uuid: Uuid) -> Self {
+        self.characteristics.get_mut().unwrap().remove(&uuid);
+        self
+    }
+
+    /// Store raw history values, oldest first, as the sensor would: temperature in 1/20 °C,
+    /// humidity in %, pressure in 1/10 hPa and CO₂ in ppm. All must have the same length.
+    pub fn with_history(
+        mut self,
+        temperature: &[u16],
+        humidity: &[u8],
+        pressure: &[u16],
+        co2: &[u16],
+    ) -> Self {
+        let le_bytes = |values: &[u16]| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect()
+        };
+        self.history = Mutex::new(HashMap::from([
+            (
+                TEMPERATURE,
+                History {
+                    elements: le_bytes(temperature),
+                    element_size: 2,
+                },
+            ),
+            (
+                HUMIDITY,
+                History {
+                    elements: humidity.to_vec(),
+                    element_size: 1,
+                },
+            ),
+            (
+                PRESSURE,
+                History {
+                    elements: le_bytes(pressure),
+                    element_size: 2,
+                },
+            ),
+            (
+                CO2,
+                History {
+                    elements: le_bytes(co2),
+                    element_size: 2,
+                },
+            ),
+        ]));
+        self.with_characteristic(
+            ARANET4_TOTAL_READINGS_UUID,
+            &(temperature.len() as u16).to_le_bytes(),
+        )
+    }
+
+    /// Store the raw history of one metric for `type_code`, e.g. one the Aranet4 doesn't log,
+    /// as little-endian elements of `element_size` bytes, oldest first. Sets the reading count to
+    /// match.
+    pub fn with_history_type(
+        mut self,
+        type_code: u8,
+        elements: Vec<u8>,
+        element_size: usize,
+    ) -> Self {
+        let num_readings = (elements.len() / element_size) as u16;
+        self.history.get_mut().unwrap().insert(
+            type_code,
+            History {
+                elements,
+                element_size,
+            },
+        );
+        self.with_characteristic(ARANET4_TOTAL_READINGS_UUID, &num_readings.to_le_bytes())
+    }
+
+    /// Answer every history request for `type_code` with exactly these notification payloads,
+    /// e.g. as captured from a real sensor, regardless of the requested range.
+    pub fn with_history_packets(mut self, type_code: u8, packets: Vec<Vec<u8>>) -> Self {
+        self.captured.insert(type_code, packets);
+        self
+    }
+
+    /// Cut the next history transfer for `type_code` short after `packets` notifications, like
+    /// a connection dropping mid-transfer.
+    pub fn with_interrupted_history(self, type_code: u8, packets: usize) -> Self {
+        self.interruptions
+            .lock()
+            .unwrap()
+            .insert(type_code, packets);
+        self
+    }
+
+    /// Take a measurement right after answering the next history request for `type_code`, as
+    /// a sensor with a full log does: every metric drops its oldest reading and repeats its
+    /// newest, the reading count stays the same and the time since the update starts over.
+    pub fn with_measurement_after(self, type_code: u8) -> Self {
+        *self.measurement_after.lock().unwrap() = Some(type_code);
+        self
+    }
+
+    /// Send these notifications after every history packet, like a sensor that also notifies
+    /// other subscribed characteristics, such as current readings, in the middle of a transfer.
+    pub fn with_interleaved_notifications(mut self, notifications: Vec<ValueNotification>) -> Self {
+        self.interleaved = notifications;
+        self
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::SeqCst)
+    }
+
+    /// How many times a connection was opened.
+    pub fn connect_count(&self) -> usize {
+        self.connects.load(Ordering::SeqCst)
+    }
+
+    /// Every write so far, oldest first.
+    pub fn writes(&self) -> Vec<(Uuid, Vec<u8>)> {
+        self.writes.lock().unwrap().clone()
+    }
+
+    /// How many of the [`MockAranet4::writes`] were without response.
+    pub fn unacknowledged_write_count(&self) -> usize {
+        self.unacknowledged_writes.load(Ordering::SeqCst)
+    }
+
+    fn queue_history(&self, command: &[u8]) -> Result<(), Aranet4Error> {
+        let [_, type_code, _, _, start_lo, start_hi, end_lo, end_hi] = command else {
+            return Err(Aranet4Error::InvalidResponse(
+                "History command must be 8 bytes".to_string(),
+            ));
+        };
+        let start = u16::from_le_bytes([*start_lo, *start_hi]);
+        let end = u16::from_le_bytes([*end_lo, *end_hi]);
+        let history = self.history.lock().unwrap();
+        let mut packets = match (self.captured.get(type_code), history.get(type_code)) {
+            (Some(packets), _) => packets.clone(),
+            (None, Some(history)) => {
+                let size = history.element_size;
+                let from = (start as usize).saturating_sub(1) * size;
+                let to = (end as usize * size).min(history.elements.len());
+                history_packets(*type_code, start, &history.elements[from.min(to)..to], size)
+            }
+            (None, None) => Vec::new(),
+        };
+        if let Some(sent) = self.interruptions.lock().unwrap().remove(type_code) {
+            packets.truncate(sent);
+        }
+        self.pending
+            .lock()
+            .unwrap()
+            .extend(packets.into_iter().flat_map(|value| {
+                let packet = ValueNotification {
+                    uuid: ARANET4_NOTIFY_HISTORY_UUID,
+                    value,
+                };
+                std::iter::once(packet).chain(self.interleaved.iter().cloned())
+            }));
+        drop(history);
+        let mut measurement_after = self.measurement_after.lock().unwrap();
+        if *measurement_after == Some(*type_code) {
+            *measurement_after = None;
+            self.take_measurement();
+        }
+        Ok(())
+    }
+
+    fn current_reading_notification(&self) -> ValueNotification {
+        ValueNotification {
+            uuid: ARANET4_NOTIFY_CURRENT_READINGS_UUID,
+            value: self.characteristics.lock().unwrap()[&ARANET4_CURRENT_READINGS_UUID].clone(),
+        }
+    }
+
+    fn update_interval(&self) -> Duration {
+        let seconds = &self.characteristics.lock().unwrap()[&ARANET4_UPDATE_INTERVAL_UUID];
+        Duration::from_secs(u16::from_le_bytes([seconds[0], seconds[1]]).into())
+    }
+
+    fn take_measurement(&self) {
+        for history in self.history.lock().unwrap().values_mut() {
+            let size = history.element_size;
+            if history.elements.len() < size {
+                continue;
+            }
+            let newest = history.elements[history.elements.len() - size..].to_vec();
+            history.elements.drain(..size);
+            history.elements.extend(newest);
+        }
+        self.characteristics
+            .lock()
+            .unwrap()
+            .insert(ARANET4_TIME_SINCE_UPDATE_UUID, 0u16.to_le_bytes().to_vec());
+    }
+}
+
+impl SensorTransport for MockAranet4 {
+    async fn connect(&self) -> Result<(), Aranet4Error> {
+        self.connects.fetch_add(1, Ordering::SeqCst);
+        self.connected.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<(), Aranet4Error> {
+        self.connected.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn disconnect_in_background(&self) {
+        self.connected.store(false, Ordering::SeqCst);
+    }
+
+    async fn local_name(&self) -> Option<String> {
+        Some(self.name.clone())
+    }
+
+    async fn read(&self, uuid: Uuid) -> Result<Vec<u8>, Aranet4Error> {
+        self.characteristics
+            .lock()
+            .unwrap()
+            .get(&uuid)
+            .cloned()
+            .ok_or(Aranet4Error::CharacteristicNotFound)
+    }
+
+    async fn write(&self, uuid: Uuid, data: &[u8]) -> Result<(), Aranet4Error> {
+        self.writes.lock().unwrap().push((uuid, data.to_vec()));
+        if uuid != ARANET4_COMMAND_UUID {
+            return Ok(());
+        }
+        match data {
+            [0x82, ..] => self.queue_history(data)?,
+            [0x90, minutes] => {
+                let seconds = *minutes as u16 * 60;
+                self.characteristics
+                    .lock()
+                    .unwrap()
+                    .insert(ARANET4_UPDATE_INTERVAL_UUID, seconds.to_le_bytes().to_vec());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn write_without_response(&self, uuid: Uuid, data: &[u8]) -> Result<(), Aranet4Error> {
+        self.unacknowledged_writes.fetch_add(1, Ordering::SeqCst);
+        self.write(uuid, data).await
+    }
+
+    async fn subscribe(&self, uuid: Uuid) -> Result<(), Aranet4Error> {
+        if uuid == ARANET4_NOTIFY_CURRENT_READINGS_UUID {
+            self.notifying_current.store(true, Ordering::SeqCst);
+            let notification = self.current_reading_notification();
+            self.pending.lock().unwrap().push_back(notification);
+        }
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, uuid: Uuid) -> Result<(), Aranet4Error> {
+        if uuid == ARANET4_NOTIFY_CURRENT_READINGS_UUID {
+            self.notifying_current.store(false, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    /// Yields the notifications queued by the last history request or subscription, then ends,
+    /// unless subscribed to current readings, which go on every interval.
+    async fn notifications(&self) -> Result<NotificationStream, Aranet4Error> {
+        let queued: Vec<ValueNotification> = self.pending.lock().unwrap().drain(..).collect();
+        let queued = futures::stream::iter(queued);
+        if !self.notifying_current.load(Ordering::SeqCst) {
+            return Ok(Box::pin(queued));
+        }
+        let interval = self.update_interval();
+        let notification = self.current_reading_notification();
+        let measurements = IntervalStream::new(tokio::time::interval_at(
+            tokio::time::Instant::now() + interval,
+            interval,
+        ))
+        .map(move |_| notification.clone());
+        Ok(Box::pin(queued.chain(measurements)))
+    }
+}
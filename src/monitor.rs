@@ -0,0 +1,435 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::time::Duration;
+
+use chrono::{TimeDelta, Utc};
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use futures::{Stream, StreamExt};
+use tokio_stream::wrappers::IntervalStream;
+use tracing::warn;
+
+use crate::client::Aranet4Client;
+use crate::device::{CurrentReadingStream, HistoryMetric};
+use crate::json_io::CurrentReadingRecord;
+use crate::notify::{notify_co2, Co2Alert};
+use crate::parquet_io::{HistoryRow, HistoryWriter, TimestampUnit};
+use crate::shutdown::shutdown_signal;
+use crate::types::{
+    Aranet4Error, CO2Data, CurrentSensorMeasurement, HumidityData, Metadata, PressureData,
+    TemperatureData, Units,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SinkFormat {
+    Csv,
+    Parquet,
+    Ndjson,
+}
+
+impl SinkFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "csv" => Some(SinkFormat::Csv),
+            "parquet" => Some(SinkFormat::Parquet),
+            "ndjson" | "jsonl" => Some(SinkFormat::Ndjson),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SinkFormat::Csv => "csv",
+            SinkFormat::Parquet => "parquet",
+            SinkFormat::Ndjson => "ndjson",
+        }
+    }
+
+    /// Guess the format from the output file's extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .and_then(Self::from_name)
+    }
+}
+
+/// Destination for a stream of current readings, one row per reading.
+pub trait ReadingSink {
+    fn write_reading(&mut self, timestamp: i64, reading: &CurrentSensorMeasurement) -> Result<()>;
+
+    /// Flush buffered readings and finalize the output.
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+pub fn open_sink(path: &Path, format: SinkFormat) -> Result<Box<dyn ReadingSink>> {
+    match format {
+        SinkFormat::Csv => Ok(Box::new(CsvReadingSink::open(path)?)),
+        SinkFormat::Parquet => Ok(Box::new(ParquetReadingSink::create(path)?)),
+        SinkFormat::Ndjson => Ok(Box::new(NdjsonReadingSink::open(path)?)),
+    }
+}
+
+fn open_append(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .wrap_err_with(|| format!("Could not open {} for appending", path.display()))
+}
+
+/// Appends to an existing CSV file, writing the header only if the file is new or empty.
+pub struct CsvReadingSink {
+    writer: csv::Writer<File>,
+}
+
+impl CsvReadingSink {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = open_append(path)?;
+        let is_empty = file.metadata()?.len() == 0;
+        let mut writer = csv::Writer::from_writer(file);
+        if is_empty {
+            writer.write_record([
+                "timestamp",
+                TemperatureData::LABEL,
+                HumidityData::LABEL,
+                PressureData::LABEL,
+                CO2Data::LABEL,
+            ])?;
+            writer.flush()?;
+        }
+        Ok(CsvReadingSink { writer })
+    }
+}
+
+impl ReadingSink for CsvReadingSink {
+    fn write_reading(&mut self, timestamp: i64, reading: &CurrentSensorMeasurement) -> Result<()> {
+        self.writer.write_record([
+            timestamp.to_string(),
+            format!(
+                "{:.*}",
+                TemperatureData::DISPLAY_PRECISION,
+                reading.temperature_value()
+            ),
+            format!(
+                "{:.*}",
+                HumidityData::DISPLAY_PRECISION,
+                reading.humidity_value()
+            ),
+            format!(
+                "{:.*}",
+                PressureData::DISPLAY_PRECISION,
+                reading.pressure_value()
+            ),
+            format!("{:.*}", CO2Data::DISPLAY_PRECISION, reading.co2_value()),
+        ])?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Appends one JSON object per line.
+pub struct NdjsonReadingSink {
+    writer: BufWriter<File>,
+}
+
+impl NdjsonReadingSink {
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(NdjsonReadingSink {
+            writer: BufWriter::new(open_append(path)?),
+        })
+    }
+}
+
+impl ReadingSink for NdjsonReadingSink {
+    fn write_reading(&mut self, timestamp: i64, reading: &CurrentSensorMeasurement) -> Result<()> {
+        let record = CurrentReadingRecord::new(None, timestamp, reading);
+        writeln!(self.writer, "{}", serde_json::to_string(&record)?)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Parquet files can't be appended to, so this creates a new file, refusing to replace an existing
+/// one, and writes a row group every `ROW_GROUP_SIZE` readings. The file is only readable once
+/// `finish` has written the footer.
+pub struct ParquetReadingSink {
+    writer: HistoryWriter<File>,
+}
+
+impl ParquetReadingSink {
+    const ROW_GROUP_SIZE: usize = 60;
+
+    pub fn create(path: &Path) -> Result<Self> {
+        let file = match OpenOptions::new().write(true).create_new(true).open(path) {
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                return Err(eyre!(
+                    "{} already exists, and Parquet can't be appended to; choose a new --output or use CSV or NDJSON",
+                    path.display()
+                ));
+            }
+            file => file
+                .wrap_err_with(|| format!("Could not create writeable file {}", path.display()))?,
+        };
+        let writer = HistoryWriter::new(
+            file,
+            TimestampUnit::default(),
+            Units::Metric,
+            &HistoryMetric::ALL,
+            false,
+            None,
+            Self::ROW_GROUP_SIZE,
+        )?;
+        Ok(ParquetReadingSink { writer })
+    }
+}
+
+impl ReadingSink for ParquetReadingSink {
+    fn write_reading(&mut self, timestamp: i64, reading: &CurrentSensorMeasurement) -> Result<()> {
+        self.writer.write(HistoryRow {
+            timestamp,
+            temperature: reading.temperature_value() as f32,
+            humidity: i32::from(reading.humidity_percent()),
+            pressure: reading.pressure_value() as f32,
+            co2: i32::from(reading.co2_ppm()),
+        })
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.writer.finish()
+    }
+}
+
+/// The UNIX time at which the sensor took `reading`.
+pub fn reading_timestamp(reading: &CurrentSensorMeasurement) -> i64 {
+    (Utc::now() - TimeDelta::seconds(reading.age().as_secs() as i64)).timestamp()
+}
+
+/// How a long-running subcommand gets its current readings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadingSchedule {
+    /// Connect and read every interval.
+    Poll(Duration),
+    /// Stay connected and take each reading the sensor notifies after measuring, which spares
+    /// its battery setting up a connection for every reading; see
+    /// [`Aranet4Client::subscribe_current`].
+    Subscribe,
+}
+
+impl ReadingSchedule {
+    /// Unsubscribe and disconnect once done with [`current_readings`], if subscribed.
+    pub async fn stop(self, client: &Aranet4Client) {
+        if self == ReadingSchedule::Subscribe {
+            if let Err(e) = client.unsubscribe_current().await {
+                warn!("Failed to unsubscribe from current readings: {}", e);
+            }
+        }
+    }
+}
+
+/// How long to wait before subscribing again after a subscription failed or went quiet.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(10);
+
+/// Slack on top of two measurement intervals before a quiet subscription is taken for lost.
+const NOTIFICATION_SLACK: Duration = Duration::from_secs(30);
+
+/// The longest interval the sensor can be set to, which bounds the wait for the first reading
+/// of a subscription.
+const LONGEST_INTERVAL: Duration = Duration::from_secs(600);
+
+enum Subscription {
+    Start,
+    /// Readings are expected within the given time.
+    Receiving(CurrentReadingStream, Duration),
+    Failed,
+}
+
+/// The readings of `client` on `schedule`, as a stream that goes on until dropped.
+///
+/// A failed reading is yielded as an error, and the next one is tried as usual. A subscription
+/// that fails, ends, or goes two measurement intervals without a reading, as when the sensor
+/// drops the connection, is set up again from a fresh connection.
+pub fn current_readings(
+    client: &Aranet4Client,
+    schedule: ReadingSchedule,
+) -> Pin<Box<dyn Stream<Item = Result<CurrentSensorMeasurement, Aranet4Error>> + '_>> {
+    let interval = match schedule {
+        ReadingSchedule::Poll(interval) => interval,
+        ReadingSchedule::Subscribe => {
+            return Box::pin(futures::stream::unfold(
+                Subscription::Start,
+                move |subscription| next_notified_reading(client, subscription),
+            ))
+        }
+    };
+    Box::pin(
+        IntervalStream::new(tokio::time::interval(interval)).then(move |_| client.read_current()),
+    )
+}
+
+async fn next_notified_reading(
+    client: &Aranet4Client,
+    subscription: Subscription,
+) -> Option<(Result<CurrentSensorMeasurement, Aranet4Error>, Subscription)> {
+    let (mut readings, quiet_limit) = match subscription {
+        Subscription::Receiving(readings, quiet_limit) => (readings, quiet_limit),
+        Subscription::Start | Subscription::Failed => {
+            if matches!(subscription, Subscription::Failed) {
+                let _ = client.unsubscribe_current().await;
+                tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+            }
+            match client.subscribe_current().await {
+                Ok(readings) => (readings, LONGEST_INTERVAL * 2 + NOTIFICATION_SLACK),
+                Err(e) => return Some((Err(e), Subscription::Failed)),
+            }
+        }
+    };
+    Some(
+        match tokio::time::timeout(quiet_limit, readings.next()).await {
+            Ok(Some(Ok(reading))) => {
+                let quiet_limit = reading.interval() * 2 + NOTIFICATION_SLACK;
+                (Ok(reading), Subscription::Receiving(readings, quiet_limit))
+            }
+            // A garbled notification doesn't mean the next one will be
+            Ok(Some(Err(e))) => (Err(e), Subscription::Receiving(readings, quiet_limit)),
+            Ok(None) => (
+                Err(Aranet4Error::InvalidResponse(
+                    "The sensor stopped notifying current readings".to_string(),
+                )),
+                Subscription::Failed,
+            ),
+            Err(_) => (
+                Err(Aranet4Error::Timeout(format!(
+                    "after {} s without a notified reading",
+                    quiet_limit.as_secs()
+                ))),
+                Subscription::Failed,
+            ),
+        },
+    )
+}
+
+/// Take readings on `schedule` and write them to `sink` until interrupted with Ctrl-C or
+/// SIGTERM.
+///
+/// Failed readings are reported on stderr and retried on the next tick, or for a
+/// subscription, the sensor's next measurement; the underlying connection is re-established
+/// if the sensor dropped it. With `alert`, a desktop notification is shown whenever CO₂
+/// crosses its thresholds.
+pub async fn monitor(
+    client: &Aranet4Client,
+    schedule: ReadingSchedule,
+    mut sink: Box<dyn ReadingSink>,
+    mut alert: Option<Co2Alert>,
+) -> Result<()> {
+    if schedule == ReadingSchedule::Poll(Duration::ZERO) {
+        return Err(eyre!("Monitor interval must be greater than zero"));
+    }
+    let mut readings = current_readings(client, schedule);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            Some(reading) = readings.next() => {
+                match reading {
+                    Ok(reading) => {
+                        sink.write_reading(reading_timestamp(&reading), &reading)?;
+                        let transition = alert.as_mut().and_then(|alert| alert.update(reading.co2_ppm()));
+                        if let Some(transition) = transition {
+                            let name = client.local_name().await.unwrap_or_default();
+                            if let Err(e) = notify_co2(&name, transition, &reading).await {
+                                warn!("{:#}", e);
+                            }
+                        }
+                    }
+                    Err(e) => warn!("Failed to take reading: {}", e),
+                }
+            }
+        }
+    }
+    drop(readings);
+    schedule.stop(client).await;
+    sink.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::client::Aranet4Client;
+    use crate::monitor::{current_readings, open_sink, ReadingSchedule, SinkFormat};
+    use crate::types::CurrentSensorMeasurement;
+    use futures::StreamExt;
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[test]
+    fn test_sink_format_from_path() {
+        assert_eq!(
+            SinkFormat::from_path(Path::new("out.csv")),
+            Some(SinkFormat::Csv)
+        );
+        assert_eq!(
+            SinkFormat::from_path(Path::new("out.jsonl")),
+            Some(SinkFormat::Ndjson)
+        );
+        assert_eq!(SinkFormat::from_path(Path::new("out")), None);
+    }
+
+    #[test]
+    fn test_csv_sink_appends() {
+        let path = std::env::temp_dir().join("arachiver_test_csv_sink_appends.csv");
+        let _ = std::fs::remove_file(&path);
+        let reading = CurrentSensorMeasurement::from([
+            0x9e, 0x03, 0x9d, 0x01, 0xbe, 0x27, 0x21, 0x16, 0x01, 0x2c, 0x01, 0xff, 0x00,
+        ]);
+        for _ in 0..2 {
+            let mut sink = open_sink(&path, SinkFormat::Csv).unwrap();
+            sink.write_reading(1738621029, &reading).unwrap();
+            sink.finish().unwrap();
+        }
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("timestamp,"));
+        assert_eq!(lines[1], "1738621029,20.65,33,1017.4,926");
+        assert_eq!(lines[1], lines[2]);
+    }
+
+    #[test]
+    fn test_parquet_sink_keeps_existing_file() {
+        let path = std::env::temp_dir().join("arachiver_test_parquet_sink_existing.parquet");
+        std::fs::write(&path, "old").unwrap();
+        let error = open_sink(&path, SinkFormat::Parquet).err().unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(error.to_string().contains("already exists"));
+        assert_eq!(contents, "old");
+    }
+
+    #[tokio::test]
+    async fn test_current_readings() {
+        let client = Aranet4Client::simulated();
+        for schedule in [
+            ReadingSchedule::Poll(Duration::from_secs(60)),
+            ReadingSchedule::Subscribe,
+        ] {
+            let mut readings = current_readings(&client, schedule);
+            let reading = readings.next().await.unwrap().unwrap();
+            assert_eq!(reading.co2_ppm(), 926);
+            drop(readings);
+            schedule.stop(&client).await;
+        }
+    }
+}
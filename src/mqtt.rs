@@ -0,0 +1,280 @@
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result};
+use futures::StreamExt;
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, QoS};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::client::Aranet4Client;
+use crate::device::DeviceInfo;
+use crate::json_io::CurrentReadingRecord;
+use crate::monitor::{current_readings, reading_timestamp, ReadingSchedule};
+use crate::shutdown::shutdown_signal;
+
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Readings are published to `<topic_prefix>/<serial>/state`.
+    pub topic_prefix: String,
+    /// Home Assistant's discovery prefix, `homeassistant` unless reconfigured.
+    pub discovery_prefix: String,
+}
+
+/// Split `mqtt://host[:port]` (the scheme is optional) into host and port.
+pub fn parse_broker_url(url: &str) -> Result<(String, u16)> {
+    const DEFAULT_PORT: u16 = 1883;
+    let rest = url.strip_prefix("mqtt://").unwrap_or(url);
+    if rest.contains("://") {
+        return Err(eyre!(
+            "Unsupported MQTT URL {}; expected mqtt://host:port",
+            url
+        ));
+    }
+    let rest = rest.trim_end_matches('/');
+    let (host, port) = match rest.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| eyre!("Invalid port in MQTT URL {}", url))?,
+        ),
+        None => (rest, DEFAULT_PORT),
+    };
+    if host.is_empty() {
+        return Err(eyre!("Missing host in MQTT URL {}", url));
+    }
+    Ok((host.to_string(), port))
+}
+
+#[derive(Debug, Serialize)]
+struct DiscoveryDevice {
+    identifiers: Vec<String>,
+    name: String,
+    model: String,
+    manufacturer: String,
+    sw_version: String,
+}
+
+#[derive(Debug, Serialize)]
+struct DiscoveryConfig {
+    name: &'static str,
+    unique_id: String,
+    object_id: String,
+    state_topic: String,
+    availability_topic: String,
+    value_template: String,
+    unit_of_measurement: &'static str,
+    device_class: &'static str,
+    state_class: &'static str,
+    device: DiscoveryDevice,
+}
+
+/// (field in the state payload, friendly name, unit, Home Assistant device class)
+const DISCOVERY_SENSORS: [(&str, &str, &str, &str); 5] = [
+    ("co2", "CO₂", "ppm", "carbon_dioxide"),
+    ("temperature", "Temperature", "°C", "temperature"),
+    ("humidity", "Humidity", "%", "humidity"),
+    ("pressure", "Pressure", "hPa", "atmospheric_pressure"),
+    ("battery", "Battery", "%", "battery"),
+];
+
+fn device_topic(config: &MqttConfig, serial: &str) -> String {
+    format!("{}/{}", config.topic_prefix, serial)
+}
+
+pub fn state_topic(config: &MqttConfig, serial: &str) -> String {
+    format!("{}/state", device_topic(config, serial))
+}
+
+pub fn availability_topic(config: &MqttConfig, serial: &str) -> String {
+    format!("{}/availability", device_topic(config, serial))
+}
+
+/// Home Assistant MQTT discovery messages, as (topic, payload), one per measured quantity.
+pub fn discovery_messages(config: &MqttConfig, info: &DeviceInfo) -> Result<Vec<(String, String)>> {
+    let serial = info.serial_number.trim();
+    DISCOVERY_SENSORS
+        .iter()
+        .map(|(field, name, unit, device_class)| {
+            let object_id = format!("arachiver_{}_{}", serial, field);
+            let config_message = DiscoveryConfig {
+                name,
+                unique_id: object_id.clone(),
+                object_id: object_id.clone(),
+                state_topic: state_topic(config, serial),
+                availability_topic: availability_topic(config, serial),
+                value_template: format!("{{{{ value_json.{} }}}}", field),
+                unit_of_measurement: unit,
+                device_class,
+                state_class: "measurement",
+                device: DiscoveryDevice {
+                    identifiers: vec![format!("arachiver_{}", serial)],
+                    name: info.device_name.clone(),
+                    model: info.model_number.clone(),
+                    manufacturer: info.manufacturer_name.clone(),
+                    sw_version: info.firmware_revision.clone(),
+                },
+            };
+            Ok((
+                format!("{}/sensor/{}/config", config.discovery_prefix, object_id),
+                serde_json::to_string(&config_message)?,
+            ))
+        })
+        .collect()
+}
+
+fn announce(client: &AsyncClient, config: &MqttConfig, info: &DeviceInfo) -> Result<()> {
+    for (topic, payload) in discovery_messages(config, info)? {
+        client.try_publish(topic, QoS::AtLeastOnce, true, payload)?;
+    }
+    client.try_publish(
+        availability_topic(config, info.serial_number.trim()),
+        QoS::AtLeastOnce,
+        true,
+        "online",
+    )?;
+    Ok(())
+}
+
+/// Publish readings taken on `schedule` until interrupted with Ctrl-C or SIGTERM.
+///
+/// The broker connection is re-established in the background whenever it drops, and discovery
+/// configs are re-announced on every (re)connect. Readings taken while the broker is
+/// unreachable are dropped rather than queued.
+pub async fn publish_readings(
+    sensor: &Aranet4Client,
+    config: &MqttConfig,
+    schedule: ReadingSchedule,
+) -> Result<()> {
+    const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+    let info = sensor.read_device_info().await?;
+    let serial = info.serial_number.trim().to_string();
+    let mut options = MqttOptions::new(format!("arachiver-{}", serial), &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(
+        availability_topic(config, &serial),
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+    let (client, mut eventloop) = AsyncClient::new(options, 16);
+
+    let announcer = client.clone();
+    let announce_config = config.clone();
+    let announce_info = info.clone();
+    let connection = tokio::spawn(async move {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    if let Err(e) = announce(&announcer, &announce_config, &announce_info) {
+                        warn!("Failed to announce to MQTT broker: {}", e);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("MQTT connection error: {}; reconnecting", e);
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    });
+
+    let topic = state_topic(config, &serial);
+    let mut readings = current_readings(sensor, schedule);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            Some(reading) = readings.next() => {
+                match reading {
+                    Ok(reading) => {
+                        let record = CurrentReadingRecord::new(None, reading_timestamp(&reading), &reading);
+                        let payload = serde_json::to_string(&record)?;
+                        if let Err(e) = client.try_publish(&topic, QoS::AtLeastOnce, false, payload) {
+                            warn!("Failed to publish reading: {}", e);
+                        }
+                    }
+                    Err(e) => warn!("Failed to take reading: {}", e),
+                }
+            }
+        }
+    }
+    drop(readings);
+    schedule.stop(sensor).await;
+    let _ = client.try_publish(
+        availability_topic(config, &serial),
+        QoS::AtLeastOnce,
+        true,
+        "offline",
+    );
+    let _ = client.try_disconnect();
+    // Give the event loop a moment to flush the goodbye before tearing it down
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    connection.abort();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::device::DeviceInfo;
+    use crate::mqtt::{discovery_messages, parse_broker_url, MqttConfig};
+
+    #[test]
+    fn test_parse_broker_url() {
+        assert_eq!(
+            parse_broker_url("mqtt://broker.local:1884").unwrap(),
+            ("broker.local".to_string(), 1884)
+        );
+        assert_eq!(
+            parse_broker_url("192.168.1.2").unwrap(),
+            ("192.168.1.2".to_string(), 1883)
+        );
+        assert!(parse_broker_url("mqtts://broker.local").is_err());
+        assert!(parse_broker_url("mqtt://:1883").is_err());
+    }
+
+    #[test]
+    fn test_discovery_messages() {
+        let config = MqttConfig {
+            host: "localhost".to_string(),
+            port: 1883,
+            username: None,
+            password: None,
+            topic_prefix: "arachiver".to_string(),
+            discovery_prefix: "homeassistant".to_string(),
+        };
+        let info = DeviceInfo {
+            device_name: "Aranet4 1BA27".to_string(),
+            model_number: "Aranet4".to_string(),
+            serial_number: "317960113191".to_string(),
+            hardware_revision: "12".to_string(),
+            software_revision: "v0.4.14".to_string(),
+            manufacturer_name: "SAF Tehnika".to_string(),
+            firmware_revision: "v1.4.14".to_string(),
+            battery: 22,
+            co2_thresholds: None,
+        };
+        let messages = discovery_messages(&config, &info).unwrap();
+        assert_eq!(messages.len(), 5);
+        let (topic, payload) = &messages[0];
+        assert_eq!(
+            topic,
+            "homeassistant/sensor/arachiver_317960113191_co2/config"
+        );
+        let payload: serde_json::Value = serde_json::from_str(payload).unwrap();
+        assert_eq!(payload["state_topic"], "arachiver/317960113191/state");
+        assert_eq!(payload["value_template"], "{{ value_json.co2 }}");
+        assert_eq!(
+            payload["device"]["identifiers"][0],
+            "arachiver_317960113191"
+        );
+    }
+}
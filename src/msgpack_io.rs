@@ -0,0 +1,157 @@
+//! A compact MessagePack archive, for moving history off a Pi Zero over a slow link. Two weeks
+//! of readings take about half the space of the same readings as CSV, and `convert` turns
+//! the archive back into CSV or Parquet at the other end.
+//!
+//! The archive is a MessagePack map of:
+//!
+//! - `version`: the schema version, currently 1.
+//! - `columns`: the names of the values in each reading, `["timestamp", "temperature",
+//!   "humidity", "pressure", "co2"]`.
+//! - `readings`: an array with an array of integers per reading, in the order of `columns`:
+//!   UNIX time in seconds, temperature in 0.01 °C, relative humidity in %, pressure in 0.1 hPa
+//!   and CO₂ in ppm.
+//!
+//! Scaled integers keep the readings exact at their display precision, and MessagePack encodes
+//! most of them in three bytes where a float takes five.
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use crate::device::HistoryTime;
+use crate::types::{
+    history_records, CO2Data, HistoryRecord, HumidityData, PressureData, TemperatureData,
+};
+
+const SCHEMA_VERSION: u8 = 1;
+const COLUMNS: [&str; 5] = ["timestamp", "temperature", "humidity", "pressure", "co2"];
+
+/// One reading: UNIX time, temperature in 0.01 °C, humidity in %, pressure in 0.1 hPa and CO₂.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct MsgpackReading(i64, i32, u8, u32, u16);
+
+impl MsgpackReading {
+    fn from_record(record: &HistoryRecord) -> Self {
+        MsgpackReading(
+            record.timestamp,
+            (record.temperature_c * 100.0).round() as i32,
+            record.humidity_pct,
+            (record.pressure_mbar * 10.0).round() as u32,
+            record.co2_ppm,
+        )
+    }
+
+    fn to_record(self) -> HistoryRecord {
+        let MsgpackReading(timestamp, temperature, humidity, pressure, co2) = self;
+        HistoryRecord {
+            timestamp,
+            temperature_c: f64::from(temperature) / 100.0,
+            humidity_pct: humidity,
+            pressure_mbar: f64::from(pressure) / 10.0,
+            co2_ppm: co2,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MsgpackDocument {
+    version: u8,
+    columns: Vec<String>,
+    readings: Vec<MsgpackReading>,
+}
+
+/// Write the history as a MessagePack archive.
+pub async fn save_history_msgpack<W: Write>(
+    history_time: HistoryTime,
+    temperature: TemperatureData,
+    humidity: HumidityData,
+    pressure: PressureData,
+    co2: CO2Data,
+    dest: &mut W,
+) -> Result<()> {
+    write_history_msgpack(
+        history_records(&history_time, &temperature, &humidity, &pressure, &co2),
+        dest,
+    )
+}
+
+/// Like [`save_history_msgpack`], for readings from any source, e.g. an archive being converted.
+pub fn write_history_msgpack<W: Write>(
+    records: impl IntoIterator<Item = Result<HistoryRecord>>,
+    dest: &mut W,
+) -> Result<()> {
+    let readings = records
+        .into_iter()
+        .map(|record| record.map(|record| MsgpackReading::from_record(&record)))
+        .collect::<Result<_>>()?;
+    let document = MsgpackDocument {
+        version: SCHEMA_VERSION,
+        columns: COLUMNS.iter().map(|column| column.to_string()).collect(),
+        readings,
+    };
+    rmp_serde::encode::write_named(dest, &document).wrap_err("Failed while writing MessagePack")?;
+    Ok(())
+}
+
+/// Read back the readings of an archive written by [`save_history_msgpack`].
+pub(crate) fn read_history_msgpack(path: &Path) -> Result<Vec<HistoryRecord>> {
+    let contents = fs::read(path).wrap_err_with(|| format!("Could not read {}", path.display()))?;
+    let document: MsgpackDocument = rmp_serde::from_slice(&contents)
+        .wrap_err_with(|| format!("{} isn't a MessagePack history archive", path.display()))?;
+    if document.version != SCHEMA_VERSION || document.columns != COLUMNS {
+        return Err(eyre!(
+            "{} has schema version {} with columns {:?}; only version {} is supported",
+            path.display(),
+            document.version,
+            document.columns,
+            SCHEMA_VERSION
+        ));
+    }
+    Ok(document
+        .readings
+        .into_iter()
+        .map(MsgpackReading::to_record)
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::msgpack_io::{read_history_msgpack, write_history_msgpack};
+    use crate::types::HistoryRecord;
+
+    #[test]
+    fn test_msgpack_round_trip() {
+        let records = vec![
+            HistoryRecord {
+                timestamp: 1738621029,
+                temperature_c: 20.65,
+                humidity_pct: 33,
+                pressure_mbar: 1017.4,
+                co2_ppm: 926,
+            },
+            HistoryRecord {
+                timestamp: 1738621329,
+                temperature_c: -3.05,
+                humidity_pct: 80,
+                pressure_mbar: 998.1,
+                co2_ppm: 412,
+            },
+        ];
+        let mut output = Vec::new();
+        write_history_msgpack(records.iter().copied().map(Ok), &mut output).unwrap();
+        // A map of three entries, starting with the schema version
+        assert_eq!(output[0], 0x83);
+        assert_eq!(output[1..9], *b"\xa7version");
+        let path = std::env::temp_dir().join("arachiver_test_msgpack_round_trip.msgpack");
+        std::fs::write(&path, &output).unwrap();
+        let read = read_history_msgpack(&path).unwrap();
+        std::fs::write(&path, b"\x81\xa7version\x02").unwrap();
+        let newer = read_history_msgpack(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read, records);
+        assert!(newer.is_err());
+    }
+}
@@ -0,0 +1,77 @@
+use color_eyre::eyre::{eyre, Result};
+
+/// Archive names used when no `--filename-template` is given.
+pub const DEFAULT_TEMPLATE: &str = "{date}_{name}_history.{format}";
+/// Like [`DEFAULT_TEMPLATE`], but distinguishes devices when archiving several with `--all`.
+pub const DEFAULT_TEMPLATE_WITH_SERIAL: &str = "{date}_{name}_{serial}_history.{format}";
+
+/// Values substituted for the `{name}`, `{serial}`, `{date}` and `{format}` placeholders.
+#[derive(Debug)]
+pub struct FilenameFields<'a> {
+    pub name: &'a str,
+    /// Only needed, and so only read from the device, if the template uses `{serial}`.
+    pub serial: Option<&'a str>,
+    pub date: &'a str,
+    pub format: &'a str,
+}
+
+pub fn uses_placeholder(template: &str, placeholder: &str) -> bool {
+    template.contains(&format!("{{{}}}", placeholder))
+}
+
+/// Fill in the placeholders in `template`. Unknown or unterminated placeholders are an error
+/// rather than being left in the filename.
+pub fn render_filename(template: &str, fields: &FilenameFields) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        output.push_str(&rest[..start]);
+        let end = rest[start..].find('}').ok_or(eyre!(
+            "Unterminated placeholder in filename template {}",
+            template
+        ))? + start;
+        let value = match &rest[start + 1..end] {
+            "name" => fields.name,
+            "serial" => fields
+                .serial
+                .ok_or(eyre!("No serial number available for {{serial}}"))?,
+            "date" => fields.date,
+            "format" => fields.format,
+            other => {
+                return Err(eyre!(
+                    "Unknown placeholder {{{}}} in filename template; expected one of {{name}}, {{serial}}, {{date}}, {{format}}",
+                    other
+                ))
+            }
+        };
+        output.push_str(value);
+        rest = &rest[end + 1..];
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::naming::{render_filename, FilenameFields, DEFAULT_TEMPLATE_WITH_SERIAL};
+
+    #[test]
+    fn test_render_filename() {
+        let fields = FilenameFields {
+            name: "Aranet4_1BA27",
+            serial: Some("317960113191"),
+            date: "2025-02-21",
+            format: "csv",
+        };
+        assert_eq!(
+            render_filename(DEFAULT_TEMPLATE_WITH_SERIAL, &fields).unwrap(),
+            "2025-02-21_Aranet4_1BA27_317960113191_history.csv"
+        );
+        assert_eq!(
+            render_filename("{serial}/{date}.{format}", &fields).unwrap(),
+            "317960113191/2025-02-21.csv"
+        );
+        assert!(render_filename("{date}_{room}.csv", &fields).is_err());
+        assert!(render_filename("{date.csv", &fields).is_err());
+    }
+}
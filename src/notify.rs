@@ -0,0 +1,100 @@
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use notify_rust::Notification;
+
+use crate::types::CurrentSensorMeasurement;
+
+/// A change in CO₂ worth telling the user about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Co2Transition {
+    /// CO₂ reached the upper threshold.
+    Rose,
+    /// CO₂ dropped back to the lower threshold after having risen.
+    Fell,
+}
+
+/// Tracks CO₂ against a pair of thresholds. The gap between them is hysteresis: after rising
+/// above `above`, CO₂ has to fall all the way to `below` before it counts as cleared, so a
+/// level hovering around one threshold doesn't notify on every reading.
+#[derive(Debug, Clone)]
+pub struct Co2Alert {
+    above: u16,
+    below: u16,
+    raised: bool,
+}
+
+impl Co2Alert {
+    pub fn new(above: u16, below: u16) -> Result<Self> {
+        if below >= above {
+            return Err(eyre!(
+                "CO₂ notification threshold {} ppm must be below {} ppm",
+                below,
+                above
+            ));
+        }
+        Ok(Co2Alert {
+            above,
+            below,
+            raised: false,
+        })
+    }
+
+    /// Feed the next CO₂ level in ppm, returning the transition it causes, if any.
+    pub fn update(&mut self, co2: u16) -> Option<Co2Transition> {
+        if !self.raised && co2 >= self.above {
+            self.raised = true;
+            Some(Co2Transition::Rose)
+        } else if self.raised && co2 <= self.below {
+            self.raised = false;
+            Some(Co2Transition::Fell)
+        } else {
+            None
+        }
+    }
+}
+
+/// Show a desktop notification for `transition`.
+pub async fn notify_co2(
+    name: &str,
+    transition: Co2Transition,
+    reading: &CurrentSensorMeasurement,
+) -> Result<()> {
+    let summary = match transition {
+        Co2Transition::Rose => format!("CO₂ is high: {} ppm", reading.co2_ppm()),
+        Co2Transition::Fell => format!("CO₂ is back down: {} ppm", reading.co2_ppm()),
+    };
+    let body = match transition {
+        Co2Transition::Rose => format!("{}: time to open a window", name),
+        Co2Transition::Fell => name.to_string(),
+    };
+    // Showing a notification talks to the session's notification daemon synchronously
+    tokio::task::spawn_blocking(move || {
+        Notification::new()
+            .appname("arachiver")
+            .summary(&summary)
+            .body(&body)
+            .show()
+            .map(|_| ())
+    })
+    .await?
+    .wrap_err("Could not show desktop notification")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::notify::{Co2Alert, Co2Transition};
+
+    #[test]
+    fn test_co2_alert_hysteresis() {
+        let mut alert = Co2Alert::new(1000, 800).unwrap();
+        assert_eq!(alert.update(950), None);
+        assert_eq!(alert.update(1010), Some(Co2Transition::Rose));
+        // Hovering around the upper threshold doesn't notify again
+        assert_eq!(alert.update(990), None);
+        assert_eq!(alert.update(1020), None);
+        assert_eq!(alert.update(850), None);
+        assert_eq!(alert.update(800), Some(Co2Transition::Fell));
+        assert_eq!(alert.update(700), None);
+        assert_eq!(alert.update(1000), Some(Co2Transition::Rose));
+        assert!(Co2Alert::new(800, 1000).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+//! Pairing (bonding) with sensors whose firmware only serves the history over an encrypted
+//! link. btleplug can't pair, so on Linux this drives BlueZ's `bluetoothctl`; elsewhere the
+//! system asks for the PIN itself the first time a protected characteristic is read.
+
+#[cfg(target_os = "linux")]
+use std::io::{Read, Write};
+#[cfg(target_os = "linux")]
+use std::process::{Command, Stdio};
+
+use color_eyre::eyre::Result;
+#[cfg(target_os = "linux")]
+use color_eyre::eyre::{eyre, WrapErr};
+#[cfg(target_os = "linux")]
+use tracing::trace;
+
+/// What `bluetoothctl` reported while pairing.
+#[cfg(any(target_os = "linux", test))]
+#[derive(Debug, PartialEq, Eq)]
+enum PairingEvent {
+    /// The agent wants the PIN shown on the sensor's screen. The prompt isn't followed by a
+    /// newline, so this is recognized in partial lines too.
+    PinRequested,
+    Paired,
+    Failed(String),
+}
+
+#[cfg(any(target_os = "linux", test))]
+impl PairingEvent {
+    fn parse(output: &str) -> Option<PairingEvent> {
+        if output.contains("Enter passkey") || output.contains("Enter PIN code") {
+            Some(PairingEvent::PinRequested)
+        } else if output.contains("Pairing successful") {
+            Some(PairingEvent::Paired)
+        } else if let Some((_, reason)) = output.split_once("Failed to pair:") {
+            Some(PairingEvent::Failed(reason.trim().to_string()))
+        } else {
+            None
+        }
+    }
+}
+
+/// Pair with the sensor at `address`, calling `get_pin` for the PIN the sensor shows on its
+/// screen once pairing has started, and trust it so that BlueZ reconnects without asking
+/// again.
+#[cfg(target_os = "linux")]
+pub async fn pair<F>(address: &str, get_pin: F) -> Result<()>
+where
+    F: FnMut() -> Result<String> + Send + 'static,
+{
+    let address = address.to_string();
+    tokio::task::spawn_blocking(move || pair_with_bluetoothctl(&address, get_pin)).await?
+}
+
+/// The system shows its own pairing dialog, asking for the PIN, when a characteristic that
+/// needs pairing is first read, so there is nothing to do beforehand.
+#[cfg(not(target_os = "linux"))]
+pub async fn pair<F>(_address: &str, _get_pin: F) -> Result<()>
+where
+    F: FnMut() -> Result<String> + Send + 'static,
+{
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn pair_with_bluetoothctl<F>(address: &str, mut get_pin: F) -> Result<()>
+where
+    F: FnMut() -> Result<String>,
+{
+    let mut child = Command::new("bluetoothctl")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .wrap_err(
+            "Could not run bluetoothctl; pairing on Linux needs BlueZ's command line tools",
+        )?;
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stdout = child.stdout.take().unwrap();
+    writeln!(stdin, "agent KeyboardOnly")?;
+    writeln!(stdin, "default-agent")?;
+    writeln!(stdin, "pair {}", address)?;
+    let mut outcome = Err(eyre!("bluetoothctl quit before pairing finished"));
+    let mut pending = String::new();
+    let mut buf = [0; 1024];
+    'reading: loop {
+        let n = stdout.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        pending.push_str(&String::from_utf8_lossy(&buf[..n]));
+        // Whole lines, then the rest if it is the PIN prompt, which has no newline
+        let mut chunks: Vec<String> = Vec::new();
+        while let Some(end) = pending.find('\n') {
+            chunks.push(pending.drain(..=end).collect());
+        }
+        if PairingEvent::parse(&pending) == Some(PairingEvent::PinRequested) {
+            chunks.push(std::mem::take(&mut pending));
+        }
+        for chunk in chunks {
+            trace!("bluetoothctl: {}", chunk.trim());
+            match PairingEvent::parse(&chunk) {
+                Some(PairingEvent::PinRequested) => match get_pin() {
+                    Ok(pin) => writeln!(stdin, "{}", pin)?,
+                    Err(e) => {
+                        outcome = Err(e);
+                        break 'reading;
+                    }
+                },
+                Some(PairingEvent::Paired) => {
+                    outcome = Ok(());
+                    break 'reading;
+                }
+                Some(PairingEvent::Failed(reason)) => {
+                    outcome = Err(eyre!("Pairing with {} failed: {}", address, reason));
+                    break 'reading;
+                }
+                None => {}
+            }
+        }
+    }
+    if outcome.is_ok() {
+        writeln!(stdin, "trust {}", address)?;
+    }
+    writeln!(stdin, "quit")?;
+    child.wait()?;
+    outcome
+}
+
+#[cfg(test)]
+mod test {
+    use crate::pairing::PairingEvent;
+
+    #[test]
+    fn test_parse_bluetoothctl_output() {
+        assert_eq!(
+            PairingEvent::parse("[agent] Enter passkey (number in 0-999999): "),
+            Some(PairingEvent::PinRequested)
+        );
+        assert_eq!(
+            PairingEvent::parse("\u{1b}[0;94m[Aranet4 1BA27]\u{1b}[0m# Pairing successful"),
+            Some(PairingEvent::Paired)
+        );
+        assert_eq!(
+            PairingEvent::parse("Failed to pair: org.bluez.Error.AuthenticationFailed"),
+            Some(PairingEvent::Failed(
+                "org.bluez.Error.AuthenticationFailed".to_string()
+            ))
+        );
+        assert_eq!(
+            PairingEvent::parse("[CHG] Device D4:8E:2A:11:5C:90 Connected: yes"),
+            None
+        );
+    }
+}
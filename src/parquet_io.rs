@@ -1,18 +1,70 @@
+use std::collections::BTreeMap;
+use std::fs::File;
 use std::io::Write;
+use std::path::Path;
 use std::sync::Arc;
 
-use color_eyre::eyre::Result;
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Result, WrapErr};
 use parquet::{
-    basic::{Compression, Repetition, Type, ZstdLevel},
-    data_type::{FloatType, Int32Type, Int64Type},
-    file::{metadata::KeyValue, properties::WriterProperties, writer::SerializedFileWriter},
+    basic::{Compression, LogicalType, Repetition, TimeUnit, Type, ZstdLevel},
+    data_type::{ByteArray, ByteArrayType, FloatType, Int32Type, Int64Type},
+    file::{
+        metadata::KeyValue,
+        properties::WriterProperties,
+        reader::{FileReader, SerializedFileReader},
+        writer::SerializedFileWriter,
+    },
+    format::{MicroSeconds, MilliSeconds},
+    record::RowAccessor,
     schema::types,
 };
 
-use crate::device::HistoryTime;
-use crate::types::{CO2Data, HumidityData, Metadata, PressureData, TemperatureData};
+use crate::atomic_file::AtomicFile;
+use crate::device::{HistoryMetric, HistoryTime};
+use crate::types::{
+    history_records, round_to_precision, CO2Data, DerivedHumidity, HistoryRecord, HumidityData,
+    Metadata, PressureData, TemperatureData, Units,
+};
+
+/// Resolution of the `timestamp` column, which is stored as a UTC TIMESTAMP so that readers
+/// load it as datetimes. The sensor only has second resolution either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum TimestampUnit {
+    #[default]
+    Millis,
+    Micros,
+}
+
+impl TimestampUnit {
+    pub fn from_name(name: &str) -> Option<TimestampUnit> {
+        match name {
+            "millis" => Some(TimestampUnit::Millis),
+            "micros" => Some(TimestampUnit::Micros),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn per_second(self) -> i64 {
+        match self {
+            TimestampUnit::Millis => 1_000,
+            TimestampUnit::Micros => 1_000_000,
+        }
+    }
 
-fn required_field(name: &str, ty: Type) -> Arc<types::Type> {
+    fn logical_type(self) -> LogicalType {
+        LogicalType::Timestamp {
+            is_adjusted_to_u_t_c: true,
+            unit: match self {
+                TimestampUnit::Millis => TimeUnit::MILLIS(MilliSeconds {}),
+                TimestampUnit::Micros => TimeUnit::MICROS(MicroSeconds {}),
+            },
+        }
+    }
+}
+
+pub(crate) fn required_field(name: &str, ty: Type) -> Arc<types::Type> {
     Arc::new(
         types::Type::primitive_type_builder(name, ty)
             .with_repetition(Repetition::REQUIRED)
@@ -21,106 +73,633 @@ fn required_field(name: &str, ty: Type) -> Arc<types::Type> {
     )
 }
 
-pub async fn save_history_parquet<W: Write + Send + Sync>(
-    history_time: HistoryTime,
-    temperature: TemperatureData,
-    humidity: HumidityData,
-    pressure: PressureData,
-    co2: CO2Data,
-    dest: &mut W,
-) -> Result<()> {
-    let schema = Arc::new(
+pub(crate) fn timestamp_field(unit: TimestampUnit) -> Arc<types::Type> {
+    Arc::new(
+        types::Type::primitive_type_builder("timestamp", Type::INT64)
+            .with_repetition(Repetition::REQUIRED)
+            .with_logical_type(Some(unit.logical_type()))
+            .build()
+            .unwrap(),
+    )
+}
+
+/// The physical type of a metric's column.
+pub(crate) fn metric_type(metric: HistoryMetric) -> Type {
+    match metric {
+        HistoryMetric::Temperature | HistoryMetric::Pressure => Type::FLOAT,
+        HistoryMetric::Humidity | HistoryMetric::Co2 => Type::INT32,
+    }
+}
+
+/// The schema of a history file with a timestamp column and one for each of `metrics`, followed
+/// by the dew point, which is null in perfectly dry air, and absolute humidity if `derived`,
+/// and the device's serial number if `serial_column`.
+pub(crate) fn history_schema(
+    unit: TimestampUnit,
+    metrics: &[HistoryMetric],
+    derived: bool,
+    serial_column: bool,
+) -> Arc<types::Type> {
+    let mut fields = vec![timestamp_field(unit)];
+    for metric in HistoryMetric::in_order(metrics) {
+        fields.push(required_field(metric.name(), metric_type(metric)));
+    }
+    if derived {
+        fields.push(Arc::new(
+            types::Type::primitive_type_builder(DerivedHumidity::DEW_POINT_NAME, Type::FLOAT)
+                .with_repetition(Repetition::OPTIONAL)
+                .build()
+                .unwrap(),
+        ));
+        fields.push(required_field(
+            DerivedHumidity::ABSOLUTE_HUMIDITY_NAME,
+            Type::FLOAT,
+        ));
+    }
+    if serial_column {
+        fields.push(Arc::new(
+            types::Type::primitive_type_builder(SERIAL_COLUMN, Type::BYTE_ARRAY)
+                .with_repetition(Repetition::REQUIRED)
+                .with_logical_type(Some(LogicalType::String))
+                .build()
+                .unwrap(),
+        ));
+    }
+    Arc::new(
         types::Type::group_type_builder("schema")
-            .with_fields(vec![
-                required_field("timestamp", Type::INT64),
-                required_field("temperature", Type::FLOAT),
-                required_field("humidity", Type::INT32),
-                required_field("pressure", Type::FLOAT),
-                required_field("co2", Type::INT32),
-            ])
+            .with_fields(fields)
             .build()
             .unwrap(),
-    );
+    )
+}
+
+const TEMPERATURE_UNIT_KEY: &str = "temperature_unit";
+const SERIAL_COLUMN: &str = "device_serial";
+
+/// The sensor a history file was downloaded from, recorded in the file's key-value metadata
+/// along with when it was written, so that files from many sensors stay distinguishable once
+/// copied around.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveSource {
+    pub name: String,
+    pub serial: String,
+    pub model: String,
+    pub firmware: String,
+    /// Measurement interval in seconds.
+    pub interval: u16,
+    /// Also write the serial number to every row of a `device_serial` column, for tools that
+    /// drop file metadata, e.g. when concatenating files.
+    pub serial_column: bool,
+}
+
+impl ArchiveSource {
+    /// The keys and values recorded about the sensor, which CSV archives record too.
+    pub(crate) fn metadata(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("device_name", self.name.clone()),
+            ("device_serial", self.serial.clone()),
+            ("device_model", self.model.clone()),
+            ("firmware_revision", self.firmware.clone()),
+            ("measurement_interval", self.interval.to_string()),
+            (
+                "archived_at",
+                Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            ),
+        ]
+    }
+
+    fn key_value_metadata(&self) -> Vec<KeyValue> {
+        self.metadata()
+            .into_iter()
+            .map(|(key, value)| KeyValue::new(key.to_string(), Some(value)))
+            .collect()
+    }
+}
+
+/// Compression and the key-value metadata: the units, and the sensor if `source` is given.
+pub(crate) fn history_writer_properties(
+    units: Units,
+    source: Option<&ArchiveSource>,
+) -> Arc<WriterProperties> {
     const COMPRESSION_LEVEL: i32 = 1; // Zstd has a max compression level of 22
-    let props = Arc::new(
+    let mut metadata = vec![
+        KeyValue::new(
+            TEMPERATURE_UNIT_KEY.to_string(),
+            Some(TemperatureData::unit_label(units).to_string()),
+        ),
+        KeyValue::new(
+            "humidity_unit".to_string(),
+            Some(HumidityData::unit_label(units).to_string()),
+        ),
+        KeyValue::new(
+            "pressure_unit".to_string(),
+            Some(PressureData::unit_label(units).to_string()),
+        ),
+        KeyValue::new(
+            "co2_unit".to_string(),
+            Some(CO2Data::unit_label(units).to_string()),
+        ),
+    ];
+    if let Some(source) = source {
+        metadata.extend(source.key_value_metadata());
+    }
+    Arc::new(
         WriterProperties::builder()
             .set_compression(Compression::ZSTD(
                 ZstdLevel::try_new(COMPRESSION_LEVEL).unwrap(),
             ))
-            .set_key_value_metadata(Some(vec![
-                KeyValue::new("timestamp_unit".to_string(), Some("UNIX time".to_string())),
-                KeyValue::new(
-                    "temperature_unit".to_string(),
-                    Some(temperature.label().to_string()),
-                ),
-                KeyValue::new(
-                    "humidity_unit".to_string(),
-                    Some(humidity.label().to_string()),
-                ),
-                KeyValue::new(
-                    "pressure_unit".to_string(),
-                    Some(pressure.label().to_string()),
-                ),
-                KeyValue::new("co2_unit".to_string(), Some(co2.label().to_string())),
-            ]))
+            .set_key_value_metadata(Some(metadata))
             .build(),
-    );
-    let mut writer = SerializedFileWriter::new(dest, schema, props).unwrap();
-    let mut row_group_writer = writer.next_row_group().unwrap();
-    if let Some(mut col_writer) = row_group_writer.next_column().unwrap() {
+    )
+}
+
+/// One row of history, already scaled to display units, with the timestamp in UNIX seconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct HistoryRow {
+    pub timestamp: i64,
+    pub temperature: f32,
+    pub humidity: i32,
+    pub pressure: f32,
+    pub co2: i32,
+}
+
+impl HistoryRow {
+    pub fn from_record(record: &HistoryRecord, units: Units) -> Self {
+        HistoryRow {
+            timestamp: record.timestamp,
+            temperature: record.temperature_in(units) as f32,
+            humidity: i32::from(record.humidity_pct),
+            pressure: record.pressure_in(units) as f32,
+            co2: i32::from(record.co2_ppm),
+        }
+    }
+
+    /// This row, whose values are in `units`, as a reading in metric units.
+    pub fn to_record(self, units: Units) -> Result<HistoryRecord> {
+        let out_of_range = || {
+            eyre!(
+                "The reading at {} has a value out of the sensor's range",
+                self.timestamp
+            )
+        };
+        Ok(HistoryRecord {
+            timestamp: self.timestamp,
+            temperature_c: round_to_precision::<TemperatureData>(TemperatureData::to_metric(
+                f64::from(self.temperature),
+                units,
+            )),
+            humidity_pct: u8::try_from(self.humidity).map_err(|_| out_of_range())?,
+            pressure_mbar: round_to_precision::<PressureData>(PressureData::to_metric(
+                f64::from(self.pressure),
+                units,
+            )),
+            co2_ppm: u16::try_from(self.co2).map_err(|_| out_of_range())?,
+        })
+    }
+}
+
+/// Columns of a history row group, already scaled to display units. Timestamps are in UNIX
+/// seconds and converted to the file's [`TimestampUnit`] when written.
+#[derive(Debug, Default)]
+pub(crate) struct HistoryColumns {
+    pub timestamp: Vec<i64>,
+    pub temperature: Vec<f32>,
+    pub humidity: Vec<i32>,
+    pub pressure: Vec<f32>,
+    pub co2: Vec<i32>,
+}
+
+impl HistoryColumns {
+    pub fn len(&self) -> usize {
+        self.timestamp.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timestamp.is_empty()
+    }
+
+    pub fn push(&mut self, row: HistoryRow) {
+        self.timestamp.push(row.timestamp);
+        self.temperature.push(row.temperature);
+        self.humidity.push(row.humidity);
+        self.pressure.push(row.pressure);
+        self.co2.push(row.co2);
+    }
+
+    pub fn row(&self, i: usize) -> HistoryRow {
+        HistoryRow {
+            timestamp: self.timestamp[i],
+            temperature: self.temperature[i],
+            humidity: self.humidity[i],
+            pressure: self.pressure[i],
+            co2: self.co2[i],
+        }
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = HistoryRow> + '_ {
+        (0..self.len()).map(|i| self.row(i))
+    }
+}
+
+/// The dew point, in the file's units, and absolute humidity of the rows of a row group.
+#[derive(Debug, Default)]
+pub(crate) struct DerivedColumns {
+    pub dew_point: Vec<Option<f32>>,
+    pub absolute_humidity: Vec<f32>,
+}
+
+impl DerivedColumns {
+    fn push(&mut self, quantities: DerivedHumidity, units: Units) {
+        self.dew_point.push(
+            quantities
+                .dew_point_in(units)
+                .map(|dew_point| dew_point as f32),
+        );
+        self.absolute_humidity
+            .push(quantities.absolute_humidity as f32);
+    }
+}
+
+/// Write the timestamps and the columns of `metrics`, the `derived` ones if any and the constant
+/// `serial` column if any, which must be the ones in the schema.
+pub(crate) fn write_row_group<W: Write + Send>(
+    writer: &mut SerializedFileWriter<W>,
+    columns: &HistoryColumns,
+    unit: TimestampUnit,
+    metrics: &[HistoryMetric],
+    derived: Option<&DerivedColumns>,
+    serial: Option<&str>,
+) -> Result<()> {
+    let timestamps: Vec<i64> = columns
+        .timestamp
+        .iter()
+        .map(|seconds| seconds * unit.per_second())
+        .collect();
+    let mut row_group_writer = writer.next_row_group()?;
+    if let Some(mut col_writer) = row_group_writer.next_column()? {
         col_writer
             .typed::<Int64Type>()
-            .write_batch(&history_time.to_vec(), None, None)
-            .unwrap();
-        col_writer.close().unwrap()
+            .write_batch(&timestamps, None, None)?;
+        col_writer.close()?
     }
-    if let Some(mut col_writer) = row_group_writer.next_column().unwrap() {
-        let float_temp: Vec<f32> = (0..temperature.values.len())
-            .map(|i| temperature.get_f32_value(i))
-            .collect();
-        col_writer
-            .typed::<FloatType>()
-            .write_batch(&float_temp, None, None)
-            .unwrap();
-        col_writer.close().unwrap()
+    for metric in HistoryMetric::in_order(metrics) {
+        let Some(mut col_writer) = row_group_writer.next_column()? else {
+            break;
+        };
+        match metric {
+            HistoryMetric::Temperature => {
+                col_writer
+                    .typed::<FloatType>()
+                    .write_batch(&columns.temperature, None, None)?
+            }
+            HistoryMetric::Humidity => {
+                col_writer
+                    .typed::<Int32Type>()
+                    .write_batch(&columns.humidity, None, None)?
+            }
+            HistoryMetric::Pressure => {
+                col_writer
+                    .typed::<FloatType>()
+                    .write_batch(&columns.pressure, None, None)?
+            }
+            HistoryMetric::Co2 => {
+                col_writer
+                    .typed::<Int32Type>()
+                    .write_batch(&columns.co2, None, None)?
+            }
+        };
+        col_writer.close()?
     }
-    if let Some(mut col_writer) = row_group_writer.next_column().unwrap() {
-        let i32_humidity: Vec<i32> = humidity.values.iter().map(|v| *v as i32).collect();
-        col_writer
-            .typed::<Int32Type>()
-            .write_batch(&i32_humidity, None, None)
-            .unwrap();
-        col_writer.close().unwrap()
+    if let Some(derived) = derived {
+        if let Some(mut col_writer) = row_group_writer.next_column()? {
+            let values: Vec<f32> = derived.dew_point.iter().flatten().copied().collect();
+            let def_levels: Vec<i16> = derived
+                .dew_point
+                .iter()
+                .map(|dew_point| i16::from(dew_point.is_some()))
+                .collect();
+            col_writer
+                .typed::<FloatType>()
+                .write_batch(&values, Some(&def_levels), None)?;
+            col_writer.close()?
+        }
+        if let Some(mut col_writer) = row_group_writer.next_column()? {
+            col_writer
+                .typed::<FloatType>()
+                .write_batch(&derived.absolute_humidity, None, None)?;
+            col_writer.close()?
+        }
     }
-    if let Some(mut col_writer) = row_group_writer.next_column().unwrap() {
-        let float_pressure: Vec<f32> = (0..pressure.values.len())
-            .map(|i| pressure.get_f32_value(i))
-            .collect();
-        col_writer
-            .typed::<FloatType>()
-            .write_batch(&float_pressure, None, None)
-            .unwrap();
-        col_writer.close().unwrap()
+    if let Some(serial) = serial {
+        if let Some(mut col_writer) = row_group_writer.next_column()? {
+            let values = vec![ByteArray::from(serial); columns.len()];
+            col_writer
+                .typed::<ByteArrayType>()
+                .write_batch(&values, None, None)?;
+            col_writer.close()?
+        }
     }
-    if let Some(mut col_writer) = row_group_writer.next_column().unwrap() {
-        let i32_co2: Vec<i32> = co2.values.iter().map(|v| *v as i32).collect();
-        col_writer
-            .typed::<Int32Type>()
-            .write_batch(&i32_co2, None, None)
-            .unwrap();
-        col_writer.close().unwrap()
+    row_group_writer.close()?;
+    Ok(())
+}
+
+/// Rows in a row group of a history file. Writing holds one row group in memory, so an archive
+/// of any size can be written from a stream of rows.
+pub(crate) const HISTORY_ROW_GROUP_SIZE: usize = 65_536;
+
+/// Writes a history file row by row, a row group at a time. The file is only readable once
+/// [`finish`](HistoryWriter::finish) has written the footer.
+pub(crate) struct HistoryWriter<W: Write + Send> {
+    writer: SerializedFileWriter<W>,
+    pending: HistoryColumns,
+    /// The derived columns of the pending rows, if the file has them.
+    pending_derived: Option<DerivedColumns>,
+    row_group_size: usize,
+    timestamp_unit: TimestampUnit,
+    units: Units,
+    metrics: Vec<HistoryMetric>,
+    /// The serial number for the `device_serial` column, if the file has one.
+    serial: Option<String>,
+}
+
+impl<W: Write + Send> HistoryWriter<W> {
+    /// With `derived`, the file also gets dew point and absolute humidity columns, computed
+    /// from each row's temperature and humidity. The `source` sensor, if known, is recorded in
+    /// the file's metadata.
+    pub fn new(
+        dest: W,
+        timestamp_unit: TimestampUnit,
+        units: Units,
+        metrics: &[HistoryMetric],
+        derived: bool,
+        source: Option<&ArchiveSource>,
+        row_group_size: usize,
+    ) -> Result<Self> {
+        let serial = source
+            .filter(|source| source.serial_column)
+            .map(|source| source.serial.clone());
+        let writer = SerializedFileWriter::new(
+            dest,
+            history_schema(timestamp_unit, metrics, derived, serial.is_some()),
+            history_writer_properties(units, source),
+        )?;
+        Ok(HistoryWriter {
+            writer,
+            pending: HistoryColumns::default(),
+            pending_derived: derived.then(DerivedColumns::default),
+            row_group_size,
+            timestamp_unit,
+            units,
+            metrics: metrics.to_vec(),
+            serial,
+        })
+    }
+
+    pub fn write(&mut self, row: HistoryRow) -> Result<()> {
+        if let Some(derived) = &mut self.pending_derived {
+            derived.push(row.to_record(self.units)?.derived(), self.units);
+        }
+        self.pending.push(row);
+        if self.pending.len() >= self.row_group_size {
+            self.flush_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn flush_row_group(&mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            write_row_group(
+                &mut self.writer,
+                &self.pending,
+                self.timestamp_unit,
+                &self.metrics,
+                self.pending_derived.as_ref(),
+                self.serial.as_deref(),
+            )?;
+            self.pending = HistoryColumns::default();
+            if let Some(derived) = &mut self.pending_derived {
+                *derived = DerivedColumns::default();
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.flush_row_group()?;
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+/// Write `rows` as a history file with all the metrics, and the derived ones if `derived`.
+pub(crate) fn write_history_file<W: Write + Send>(
+    dest: W,
+    rows: impl IntoIterator<Item = HistoryRow>,
+    timestamp_unit: TimestampUnit,
+    units: Units,
+    derived: bool,
+    source: Option<&ArchiveSource>,
+) -> Result<()> {
+    let mut writer = HistoryWriter::new(
+        dest,
+        timestamp_unit,
+        units,
+        &HistoryMetric::ALL,
+        derived,
+        source,
+        HISTORY_ROW_GROUP_SIZE,
+    )?;
+    for row in rows {
+        writer.write(row)?;
+    }
+    writer.finish()
+}
+
+/// Write the history as a Parquet file with a column for each of `metrics`, followed by the
+/// dew point and absolute humidity if `derived`, recording the `source` sensor if known.
+#[allow(clippy::too_many_arguments)]
+pub async fn save_history_parquet<W: Write + Send + Sync>(
+    history_time: HistoryTime,
+    temperature: TemperatureData,
+    humidity: HumidityData,
+    pressure: PressureData,
+    co2: CO2Data,
+    dest: &mut W,
+    timestamp_unit: TimestampUnit,
+    units: Units,
+    metrics: &[HistoryMetric],
+    derived: bool,
+    source: Option<&ArchiveSource>,
+) -> Result<()> {
+    let mut writer = HistoryWriter::new(
+        dest,
+        timestamp_unit,
+        units,
+        metrics,
+        derived,
+        source,
+        HISTORY_ROW_GROUP_SIZE,
+    )?;
+    for record in history_records(&history_time, &temperature, &humidity, &pressure, &co2) {
+        writer.write(HistoryRow::from_record(&record?, units))?;
+    }
+    writer.finish()
+}
+
+fn open_history_file(path: &Path) -> Result<SerializedFileReader<File>> {
+    let file = File::open(path).wrap_err_with(|| format!("Could not open {}", path.display()))?;
+    SerializedFileReader::new(file)
+        .wrap_err_with(|| format!("Could not read Parquet file {}", path.display()))
+}
+
+fn temperature_unit_label(path: &Path) -> Result<Option<String>> {
+    let reader = open_history_file(path)?;
+    Ok(reader
+        .metadata()
+        .file_metadata()
+        .key_value_metadata()
+        .and_then(|metadata| metadata.iter().find(|kv| kv.key == TEMPERATURE_UNIT_KEY))
+        .and_then(|kv| kv.value.clone()))
+}
+
+/// Check that the history file at `path` stores values in `units`, so that merging doesn't mix
+/// units within one file. Files that don't record their units are assumed to match.
+pub fn check_parquet_units(path: &Path, units: Units) -> Result<()> {
+    let temperature_unit = temperature_unit_label(path)?;
+    if let Some(label) =
+        temperature_unit.filter(|label| label != TemperatureData::unit_label(units))
+    {
+        return Err(eyre!(
+            "{} stores {}, which doesn't match the {:?} units being written",
+            path.display(),
+            label,
+            units
+        ));
     }
-    row_group_writer.close().unwrap();
-    writer.close().unwrap();
     Ok(())
 }
 
+/// The units the history file at `path` stores values in, or `None` if it doesn't record them.
+pub(crate) fn parquet_units(path: &Path) -> Result<Option<Units>> {
+    let Some(label) = temperature_unit_label(path)? else {
+        return Ok(None);
+    };
+    [Units::Metric, Units::Imperial]
+        .into_iter()
+        .find(|&units| label == TemperatureData::unit_label(units))
+        .map(Some)
+        .ok_or(eyre!(
+            "{} stores values in unknown unit {}",
+            path.display(),
+            label
+        ))
+}
+
+/// Read back a history file written by [`save_history_parquet`], with timestamps converted to
+/// UNIX seconds whatever the file's [`TimestampUnit`]. Files from before timestamps had a
+/// logical type store plain seconds.
+pub(crate) fn read_history_columns(path: &Path) -> Result<HistoryColumns> {
+    let reader = open_history_file(path)?;
+    let unit = match reader
+        .metadata()
+        .file_metadata()
+        .schema_descr()
+        .column(0)
+        .logical_type()
+    {
+        Some(LogicalType::Timestamp {
+            unit: TimeUnit::MILLIS(_),
+            ..
+        }) => Some(TimestampUnit::Millis),
+        Some(LogicalType::Timestamp {
+            unit: TimeUnit::MICROS(_),
+            ..
+        }) => Some(TimestampUnit::Micros),
+        _ => None,
+    };
+    let mut columns = HistoryColumns::default();
+    for row in reader.get_row_iter(None)? {
+        let row = row?;
+        let timestamp = match unit {
+            None => row.get_long(0)?,
+            Some(TimestampUnit::Millis) => row.get_timestamp_millis(0)? / 1_000,
+            Some(TimestampUnit::Micros) => row.get_timestamp_micros(0)? / 1_000_000,
+        };
+        columns.timestamp.push(timestamp);
+        columns.temperature.push(row.get_float(1)?);
+        columns.humidity.push(row.get_int(2)?);
+        columns.pressure.push(row.get_float(3)?);
+        columns.co2.push(row.get_int(4)?);
+    }
+    Ok(columns)
+}
+
+/// The newest timestamp in a Parquet archive, or `None` if it has no rows.
+pub fn last_parquet_timestamp(path: &Path) -> Result<Option<i64>> {
+    Ok(read_history_columns(path)?.timestamp.into_iter().max())
+}
+
+/// Merge the history into the Parquet archive at `path`, keeping existing rows where
+/// timestamps coincide. Parquet files can't be appended to, so the merged file is written next
+/// to the archive and renamed over it, leaving the archive intact if anything fails. The
+/// `source` sensor is recorded as for a new archive, but without a serial number column, which
+/// the rows already in the archive don't have. Returns the number of new rows.
+#[allow(clippy::too_many_arguments)]
+pub async fn merge_history_parquet(
+    path: &Path,
+    history_time: HistoryTime,
+    temperature: TemperatureData,
+    humidity: HumidityData,
+    pressure: PressureData,
+    co2: CO2Data,
+    timestamp_unit: TimestampUnit,
+    units: Units,
+    source: Option<&ArchiveSource>,
+) -> Result<usize> {
+    check_parquet_units(path, units)?;
+    let existing = read_history_columns(path)?;
+    let mut rows = BTreeMap::new();
+    for row in existing.rows() {
+        rows.insert(row.timestamp, row);
+    }
+    for record in history_records(&history_time, &temperature, &humidity, &pressure, &co2) {
+        let record = record?;
+        rows.entry(record.timestamp)
+            .or_insert_with(|| HistoryRow::from_record(&record, units));
+    }
+    let added = rows.len() - existing.len();
+    let mut file = AtomicFile::create(path)?;
+    let source = source.map(|source| ArchiveSource {
+        serial_column: false,
+        ..source.clone()
+    });
+    write_history_file(
+        &mut file,
+        rows.into_values(),
+        timestamp_unit,
+        units,
+        false,
+        source.as_ref(),
+    )?;
+    file.commit()?;
+    Ok(added)
+}
+
 #[cfg(test)]
 mod test {
-    use crate::device::HistoryTime;
-    use crate::parquet_io::save_history_parquet;
-    use crate::types::{CO2Data, HumidityData, PressureData, TemperatureData};
-    use chrono::Utc;
+    use crate::device::{HistoryMetric, HistoryTime};
+    use crate::parquet_io::{
+        check_parquet_units, last_parquet_timestamp, merge_history_parquet, read_history_columns,
+        save_history_parquet, ArchiveSource, HistoryRow, HistoryWriter, TimestampUnit,
+        HISTORY_ROW_GROUP_SIZE,
+    };
+    use crate::types::{CO2Data, HumidityData, PressureData, TemperatureData, Units};
+    use chrono::{DateTime, Utc};
+    use parquet::basic::{LogicalType, TimeUnit};
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::format::MicroSeconds;
+    use parquet::record::RowAccessor;
+    use std::fs::File;
     use tokio;
 
     #[tokio::test]
@@ -137,6 +716,7 @@ mod test {
             update_interval: 300,
             since_update: 24,
             now: Utc::now(),
+            offset: 0,
         };
         let mut output = Vec::new();
         save_history_parquet(
@@ -146,13 +726,293 @@ mod test {
             pressure,
             co2,
             &mut output,
+            TimestampUnit::Millis,
+            Units::Metric,
+            &HistoryMetric::ALL,
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(output[0..4], *b"PAR1");
+        assert_eq!(output[(output.len() - 4)..output.len()], *b"PAR1");
+    }
+
+    #[tokio::test]
+    async fn test_save_history_parquet_timestamp_type() {
+        let bytes = [144u8, 1, 164, 1];
+        let history_time = HistoryTime {
+            num_samples: 2,
+            update_interval: 300,
+            since_update: 24,
+            now: DateTime::from_timestamp(1738621353, 0).unwrap(),
+            offset: 0,
+        };
+        let path = std::env::temp_dir().join("arachiver_test_parquet_timestamp_type.parquet");
+        let mut file = File::create(&path).unwrap();
+        save_history_parquet(
+            history_time,
+            TemperatureData::try_from(&bytes[..]).unwrap(),
+            HumidityData::try_from(&bytes[0..2]).unwrap(),
+            PressureData::try_from(&bytes[..]).unwrap(),
+            CO2Data::try_from(&bytes[..]).unwrap(),
+            &mut file,
+            TimestampUnit::Micros,
+            Units::Metric,
+            &HistoryMetric::ALL,
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let column = reader.metadata().file_metadata().schema_descr().column(0);
+        assert_eq!(
+            column.logical_type(),
+            Some(LogicalType::Timestamp {
+                is_adjusted_to_u_t_c: true,
+                unit: TimeUnit::MICROS(MicroSeconds {}),
+            })
+        );
+        let first_row = reader.get_row_iter(None).unwrap().next().unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            first_row.get_timestamp_micros(0).unwrap(),
+            1_738_621_029_000_000
+        );
+    }
+
+    #[tokio::test]
+    async fn test_save_history_parquet_columns() {
+        let bytes = [144u8, 1, 164, 1];
+        let history_time = HistoryTime {
+            num_samples: 2,
+            update_interval: 300,
+            since_update: 24,
+            now: Utc::now(),
+            offset: 0,
+        };
+        let path = std::env::temp_dir().join("arachiver_test_parquet_columns.parquet");
+        let mut file = File::create(&path).unwrap();
+        save_history_parquet(
+            history_time,
+            TemperatureData { values: vec![] },
+            HumidityData { values: vec![] },
+            PressureData { values: vec![] },
+            CO2Data::try_from(&bytes[..]).unwrap(),
+            &mut file,
+            TimestampUnit::Millis,
+            Units::Metric,
+            &[HistoryMetric::Co2],
+            false,
+            None,
+        )
+        .await
+        .unwrap();
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let schema = reader.metadata().file_metadata().schema_descr_ptr();
+        let names: Vec<&str> = schema.columns().iter().map(|c| c.name()).collect();
+        let first_row = reader.get_row_iter(None).unwrap().next().unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(names, vec!["timestamp", "co2"]);
+        assert_eq!(first_row.get_int(1).unwrap(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_merge_history_parquet() {
+        let history = |now: i64, bytes: &[u8]| {
+            (
+                HistoryTime {
+                    num_samples: bytes.len() / 2,
+                    update_interval: 300,
+                    since_update: 0,
+                    now: DateTime::from_timestamp(now, 0).unwrap(),
+                    offset: 0,
+                },
+                TemperatureData::try_from(bytes).unwrap(),
+                HumidityData::try_from(&bytes[..bytes.len() / 2]).unwrap(),
+                PressureData::try_from(bytes).unwrap(),
+                CO2Data::try_from(bytes).unwrap(),
+            )
+        };
+        let path = std::env::temp_dir().join("arachiver_test_merge_history_parquet.parquet");
+        let (ht, t, h, p, c) = history(1738621200, &[144, 1, 164, 1]);
+        let mut file = File::create(&path).unwrap();
+        save_history_parquet(
+            ht,
+            t,
+            h,
+            p,
+            c,
+            &mut file,
+            TimestampUnit::Millis,
+            Units::Imperial,
+            &HistoryMetric::ALL,
+            false,
+            None,
         )
         .await
         .unwrap();
-        assert_eq!(output[0..4], ['P' as u8, 'A' as u8, 'R' as u8, '1' as u8]);
+        // Five minutes later, the ring buffer has shifted by one reading
+        let (ht, t, h, p, c) = history(1738621500, &[164, 1, 184, 1]);
+        assert!(check_parquet_units(&path, Units::Metric).is_err());
+        let added = merge_history_parquet(
+            &path,
+            ht,
+            t,
+            h,
+            p,
+            c,
+            TimestampUnit::Millis,
+            Units::Imperial,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(added, 1);
+        let last = last_parquet_timestamp(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(last, Some(1738621500));
+    }
+
+    #[test]
+    fn test_history_writer_row_groups() {
+        let path = std::env::temp_dir().join("arachiver_test_history_writer.parquet");
+        let mut writer = HistoryWriter::new(
+            File::create(&path).unwrap(),
+            TimestampUnit::Millis,
+            Units::Metric,
+            &HistoryMetric::ALL,
+            false,
+            None,
+            2,
+        )
+        .unwrap();
+        for i in 0..5 {
+            writer
+                .write(HistoryRow {
+                    timestamp: 1738621200 + 300 * i,
+                    co2: 400 + i as i32,
+                    ..Default::default()
+                })
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let num_row_groups = reader.metadata().num_row_groups();
+        let columns = read_history_columns(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(num_row_groups, 3);
+        assert_eq!(columns.len(), 5);
+        assert_eq!(columns.row(4).timestamp, 1738622400);
+        assert_eq!(columns.row(4).co2, 404);
+    }
+
+    #[test]
+    fn test_history_writer_derived() {
+        let path = std::env::temp_dir().join("arachiver_test_history_writer_derived.parquet");
+        let mut writer = HistoryWriter::new(
+            File::create(&path).unwrap(),
+            TimestampUnit::Millis,
+            Units::Metric,
+            &HistoryMetric::ALL,
+            true,
+            None,
+            HISTORY_ROW_GROUP_SIZE,
+        )
+        .unwrap();
+        for humidity in [33, 0] {
+            writer
+                .write(HistoryRow {
+                    timestamp: 1738621200,
+                    temperature: 20.65,
+                    humidity,
+                    pressure: 1017.4,
+                    co2: 926,
+                })
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let schema = reader.metadata().file_metadata().schema_descr_ptr();
+        let names: Vec<&str> = schema.columns().iter().map(|c| c.name()).collect();
+        let rows: Vec<_> = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        let columns = read_history_columns(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
         assert_eq!(
-            output[(output.len() - 4)..output.len()],
-            ['P' as u8, 'A' as u8, 'R' as u8, '1' as u8]
+            names,
+            vec![
+                "timestamp",
+                "temperature",
+                "humidity",
+                "pressure",
+                "co2",
+                "dew_point",
+                "absolute_humidity"
+            ]
         );
+        assert_eq!(rows[0].get_float(5).unwrap(), 3.8);
+        assert_eq!(rows[0].get_float(6).unwrap(), 5.9);
+        // Perfectly dry air has no dew point
+        assert!(rows[1].get_float(5).is_err());
+        assert_eq!(rows[1].get_float(6).unwrap(), 0.0);
+        assert_eq!(columns.co2, vec![926, 926]);
+    }
+
+    #[test]
+    fn test_history_writer_source() {
+        let path = std::env::temp_dir().join("arachiver_test_history_writer_source.parquet");
+        let source = ArchiveSource {
+            name: "Aranet4 1A2B3".to_string(),
+            serial: "317960113191".to_string(),
+            model: "Aranet4".to_string(),
+            firmware: "v1.4.19".to_string(),
+            interval: 300,
+            serial_column: true,
+        };
+        let mut writer = HistoryWriter::new(
+            File::create(&path).unwrap(),
+            TimestampUnit::Millis,
+            Units::Metric,
+            &HistoryMetric::ALL,
+            false,
+            Some(&source),
+            HISTORY_ROW_GROUP_SIZE,
+        )
+        .unwrap();
+        writer
+            .write(HistoryRow {
+                timestamp: 1738621200,
+                co2: 926,
+                ..Default::default()
+            })
+            .unwrap();
+        writer.finish().unwrap();
+        let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+        let metadata = reader
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .unwrap()
+            .clone();
+        let value = |key: &str| {
+            metadata
+                .iter()
+                .find(|kv| kv.key == key)
+                .and_then(|kv| kv.value.clone())
+        };
+        let first_row = reader.get_row_iter(None).unwrap().next().unwrap().unwrap();
+        let columns = read_history_columns(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(value("device_serial").as_deref(), Some("317960113191"));
+        assert_eq!(value("firmware_revision").as_deref(), Some("v1.4.19"));
+        assert_eq!(value("measurement_interval").as_deref(), Some("300"));
+        assert!(value("archived_at").is_some_and(|time| time.ends_with('Z')));
+        assert_eq!(first_row.get_string(5).unwrap(), "317960113191");
+        assert_eq!(columns.co2, vec![926]);
     }
 }
@@ -0,0 +1,330 @@
+use std::path::Path;
+
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use plotters::coord::Shift;
+use plotters::prelude::{
+    BitMapBackend, ChartBuilder, Color as _, DrawingArea, DrawingBackend, IntoDrawingArea,
+    LineSeries, Palette, Palette99, SVGBackend, WHITE,
+};
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::symbols::Marker;
+use ratatui::widgets::{Axis, Block, Chart, Dataset, GraphType, Widget};
+
+use crate::device::History;
+use crate::types::{CO2Data, HumidityData, Metadata, PressureData, TemperatureData, Units};
+
+/// A history metric that can be plotted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PlotMetric {
+    Co2,
+    Temperature,
+    Humidity,
+    Pressure,
+}
+
+impl PlotMetric {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "co2" => Some(PlotMetric::Co2),
+            "temperature" => Some(PlotMetric::Temperature),
+            "humidity" => Some(PlotMetric::Humidity),
+            "pressure" => Some(PlotMetric::Pressure),
+            _ => None,
+        }
+    }
+
+    pub fn label(self, units: Units) -> &'static str {
+        match self {
+            PlotMetric::Co2 => CO2Data::unit_label(units),
+            PlotMetric::Temperature => TemperatureData::unit_label(units),
+            PlotMetric::Humidity => HumidityData::unit_label(units),
+            PlotMetric::Pressure => PressureData::unit_label(units),
+        }
+    }
+
+    pub fn precision(self, units: Units) -> usize {
+        match self {
+            PlotMetric::Co2 => CO2Data::precision(units),
+            PlotMetric::Temperature => TemperatureData::precision(units),
+            PlotMetric::Humidity => HumidityData::precision(units),
+            PlotMetric::Pressure => PressureData::precision(units),
+        }
+    }
+
+    /// The metric's values in `history` as (UNIX time, value) points, oldest first.
+    pub fn points(self, history: &History, units: Units) -> Result<Vec<(f64, f64)>> {
+        Ok(history
+            .time
+            .timestamps()
+            .enumerate()
+            .map(|(i, time)| {
+                let value = match self {
+                    PlotMetric::Co2 => history.co2.get_f32_value_in(i, units),
+                    PlotMetric::Temperature => history.temperature.get_f32_value_in(i, units),
+                    PlotMetric::Humidity => history.humidity.get_f32_value_in(i, units),
+                    PlotMetric::Pressure => history.pressure.get_f32_value_in(i, units),
+                };
+                (time.timestamp() as f64, f64::from(value))
+            })
+            .collect())
+    }
+}
+
+fn local_time(timestamp: f64) -> DateTime<Local> {
+    DateTime::from_timestamp(timestamp as i64, 0)
+        .unwrap_or_default()
+        .with_timezone(&Local)
+}
+
+fn time_label(timestamp: f64) -> String {
+    local_time(timestamp).format("%m-%d %H:%M").to_string()
+}
+
+/// Lower and upper bounds of `values`, widened a little when flat so the line isn't drawn on
+/// the chart's edge.
+fn bounds(values: impl Iterator<Item = f64>) -> [f64; 2] {
+    let [low, high] = values.fold([f64::INFINITY, f64::NEG_INFINITY], |[low, high], value| {
+        [low.min(value), high.max(value)]
+    });
+    if low == high {
+        [low - 1.0, high + 1.0]
+    } else {
+        [low, high]
+    }
+}
+
+/// A braille line chart of each metric in `history`, stacked top to bottom, `height` rows
+/// each, as plain text.
+pub fn render_terminal_plot(
+    history: &History,
+    metrics: &[PlotMetric],
+    units: Units,
+    width: u16,
+    height: u16,
+) -> Result<String> {
+    if history.time.num_samples == 0 {
+        return Err(eyre!("No readings to plot"));
+    }
+    let area = Rect::new(0, 0, width, height * metrics.len() as u16);
+    let mut buffer = Buffer::empty(area);
+    for (row, &metric) in metrics.iter().enumerate() {
+        let points = metric.points(history, units)?;
+        let x_bounds = bounds(points.iter().map(|&(time, _)| time));
+        let y_bounds = bounds(points.iter().map(|&(_, value)| value));
+        let precision = metric.precision(units);
+        let chart = Chart::new(vec![Dataset::default()
+            .marker(Marker::Braille)
+            .graph_type(GraphType::Line)
+            .data(&points)])
+        .block(Block::bordered().title(metric.label(units)))
+        .x_axis(
+            Axis::default()
+                .bounds(x_bounds)
+                .labels(x_bounds.map(time_label)),
+        )
+        .y_axis(
+            Axis::default()
+                .bounds(y_bounds)
+                .labels(y_bounds.map(|value| format!("{:.*}", precision, value))),
+        );
+        chart.render(
+            Rect::new(0, row as u16 * height, width, height),
+            &mut buffer,
+        );
+    }
+    let lines: Vec<String> = (0..area.height)
+        .map(|y| {
+            let line: String = (0..area.width).map(|x| buffer[(x, y)].symbol()).collect();
+            line.trim_end().to_string()
+        })
+        .collect();
+    Ok(lines.join("\n"))
+}
+
+/// Image formats for [`save_history_chart`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChartFormat {
+    Svg,
+    Png,
+}
+
+impl ChartFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "svg" => Some(ChartFormat::Svg),
+            "png" => Some(ChartFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+/// Draw each metric in `history` in its own panel, stacked top to bottom over a shared time
+/// axis, and save the chart as a `width` by `height` pixel image at `path`.
+pub fn save_history_chart(
+    history: &History,
+    metrics: &[PlotMetric],
+    units: Units,
+    path: &Path,
+    format: ChartFormat,
+    (width, height): (u32, u32),
+) -> Result<()> {
+    if history.time.num_samples == 0 {
+        return Err(eyre!("No readings to plot"));
+    }
+    match format {
+        ChartFormat::Svg => draw_history_chart(
+            SVGBackend::new(path, (width, height)).into_drawing_area(),
+            history,
+            metrics,
+            units,
+        ),
+        ChartFormat::Png => draw_history_chart(
+            BitMapBackend::new(path, (width, height)).into_drawing_area(),
+            history,
+            metrics,
+            units,
+        ),
+    }
+    .wrap_err_with(|| format!("Could not write chart to {}", path.display()))
+}
+
+fn draw_history_chart<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    history: &History,
+    metrics: &[PlotMetric],
+    units: Units,
+) -> Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+    let mut timestamps = history.time.timestamps();
+    let first = timestamps
+        .next()
+        .ok_or(eyre!("No readings to plot"))?
+        .timestamp() as f64;
+    let last = timestamps
+        .last()
+        .map_or(first, |time| time.timestamp() as f64);
+    let [first, last] = if first == last {
+        [first, last + f64::from(history.time.update_interval)]
+    } else {
+        [first, last]
+    };
+    let panels = root.split_evenly((metrics.len(), 1));
+    for (i, (panel, &metric)) in panels.iter().zip(metrics).enumerate() {
+        let points = metric.points(history, units)?;
+        let [low, high] = bounds(points.iter().map(|&(_, value)| value));
+        let margin = (high - low) * 0.05;
+        let precision = metric.precision(units);
+        let mut chart = ChartBuilder::on(panel)
+            .caption(metric.label(units), ("sans-serif", 18))
+            .margin(10)
+            .x_label_area_size(30)
+            .y_label_area_size(60)
+            .build_cartesian_2d(
+                local_time(first)..local_time(last),
+                (low - margin)..(high + margin),
+            )?;
+        chart
+            .configure_mesh()
+            .x_labels(8)
+            .x_label_formatter(&|time| time.format("%m-%d %H:%M").to_string())
+            .y_label_formatter(&|value| format!("{:.*}", precision, value))
+            .draw()?;
+        chart.draw_series(LineSeries::new(
+            points
+                .iter()
+                .map(|&(time, value)| (local_time(time), value)),
+            Palette99::pick(i).stroke_width(2),
+        ))?;
+    }
+    root.present()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+
+    use crate::device::{History, HistoryTime};
+    use crate::plot::{render_terminal_plot, save_history_chart, ChartFormat, PlotMetric};
+    use crate::types::{CO2Data, HumidityData, PressureData, TemperatureData, Units};
+
+    fn test_history() -> History {
+        History {
+            time: HistoryTime {
+                num_samples: 3,
+                update_interval: 300,
+                since_update: 0,
+                now: Utc::now(),
+                offset: 0,
+            },
+            temperature: TemperatureData {
+                values: vec![400, 410, 420],
+            },
+            humidity: HumidityData {
+                values: vec![30, 31, 32],
+            },
+            pressure: PressureData {
+                values: vec![10170, 10172, 10174],
+            },
+            co2: CO2Data {
+                values: vec![500, 900, 700],
+            },
+        }
+    }
+
+    #[test]
+    fn test_render_terminal_plot() {
+        let history = test_history();
+        let plot = render_terminal_plot(
+            &history,
+            &[PlotMetric::Co2, PlotMetric::Temperature],
+            Units::Metric,
+            60,
+            10,
+        )
+        .unwrap();
+        let lines: Vec<&str> = plot.lines().collect();
+        assert_eq!(lines.len(), 20);
+        assert!(lines[0].contains("CO₂ (ppm)"));
+        assert!(lines[10].contains("Temperature (°C)"));
+        assert!(plot.contains("900"));
+        assert!(plot.contains("21.00"));
+        assert!(plot.chars().any(|c| ('\u{2801}'..='\u{28ff}').contains(&c)));
+
+        let empty = History {
+            time: HistoryTime {
+                num_samples: 0,
+                ..history.time
+            },
+            ..history
+        };
+        assert!(render_terminal_plot(&empty, &[PlotMetric::Co2], Units::Metric, 60, 10).is_err());
+    }
+
+    #[test]
+    fn test_save_history_chart_svg() {
+        let path = std::env::temp_dir().join("arachiver_test_history_chart.svg");
+        assert_eq!(ChartFormat::from_path(&path), Some(ChartFormat::Svg));
+        save_history_chart(
+            &test_history(),
+            &[PlotMetric::Co2, PlotMetric::Humidity],
+            Units::Metric,
+            &path,
+            ChartFormat::Svg,
+            (800, 600),
+        )
+        .unwrap();
+        let svg = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("CO₂ (ppm)"));
+        assert!(svg.contains("Humidity (%)"));
+        assert!(svg.contains("<polyline"));
+    }
+}
@@ -0,0 +1,125 @@
+//! History in a PostgreSQL table, such as a TimescaleDB hypertable behind a home dashboard.
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Result};
+use sqlx::{Connection, PgConnection};
+
+use crate::device::HistoryTime;
+use crate::types::{history_records, CO2Data, HumidityData, PressureData, TemperatureData};
+
+/// Check that `table` is a plain, optionally schema-qualified, identifier, since it is pasted
+/// into the SQL rather than bound as a parameter.
+pub fn check_table_name(table: &str) -> Result<()> {
+    let is_identifier = |part: &str| {
+        let mut chars = part.chars();
+        chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    };
+    let parts: Vec<&str> = table.split('.').collect();
+    if parts.len() > 2 || !parts.iter().all(|part| is_identifier(part)) {
+        return Err(eyre!(
+            "Invalid table name {:?}; expected e.g. history or aranet.history",
+            table
+        ));
+    }
+    Ok(())
+}
+
+fn create_history_table(table: &str) -> String {
+    format!(
+        "CREATE TABLE IF NOT EXISTS {table} (
+            device_serial TEXT NOT NULL,
+            timestamp TIMESTAMPTZ NOT NULL,
+            temperature DOUBLE PRECISION NOT NULL,
+            humidity SMALLINT NOT NULL,
+            pressure DOUBLE PRECISION NOT NULL,
+            co2 INTEGER NOT NULL,
+            PRIMARY KEY (device_serial, timestamp)
+        )"
+    )
+}
+
+/// Insert the history into `table`, creating it if needed. Readings already in the table are
+/// left alone, so `table` may be a TimescaleDB hypertable created beforehand with the same
+/// columns. Returns the number of new rows.
+#[allow(clippy::too_many_arguments)]
+pub async fn save_history_postgres(
+    device_serial: &str,
+    history_time: HistoryTime,
+    temperature: TemperatureData,
+    humidity: HumidityData,
+    pressure: PressureData,
+    co2: CO2Data,
+    table: &str,
+    conn: &mut PgConnection,
+) -> Result<usize> {
+    check_table_name(table)?;
+    let insert = format!(
+        "INSERT INTO {table}
+            (device_serial, timestamp, temperature, humidity, pressure, co2)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT DO NOTHING"
+    );
+    let mut tx = conn.begin().await?;
+    sqlx::query(&create_history_table(table))
+        .execute(&mut *tx)
+        .await?;
+    let mut new_rows = 0;
+    for record in history_records(&history_time, &temperature, &humidity, &pressure, &co2) {
+        let record = record?;
+        let result = sqlx::query(&insert)
+            .bind(device_serial)
+            .bind(
+                DateTime::<Utc>::from_timestamp(record.timestamp, 0)
+                    .ok_or(eyre!("Timestamp {} out of range", record.timestamp))?,
+            )
+            .bind(record.temperature_c)
+            .bind(i16::from(record.humidity_pct))
+            .bind(record.pressure_mbar)
+            .bind(i32::from(record.co2_ppm))
+            .execute(&mut *tx)
+            .await?;
+        new_rows += result.rows_affected() as usize;
+    }
+    tx.commit().await?;
+    Ok(new_rows)
+}
+
+/// The timestamp (UNIX time) of the newest reading stored in `table` for `device_serial`, if
+/// any.
+pub async fn last_postgres_timestamp(
+    conn: &mut PgConnection,
+    table: &str,
+    device_serial: &str,
+) -> Result<Option<i64>> {
+    check_table_name(table)?;
+    sqlx::query(&create_history_table(table))
+        .execute(&mut *conn)
+        .await?;
+    let last: Option<DateTime<Utc>> = sqlx::query_scalar(&format!(
+        "SELECT MAX(timestamp) FROM {table} WHERE device_serial = $1"
+    ))
+    .bind(device_serial)
+    .fetch_one(&mut *conn)
+    .await?;
+    Ok(last.map(|time| time.timestamp()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::postgres_io::check_table_name;
+
+    #[test]
+    fn test_check_table_name() {
+        assert!(check_table_name("history").is_ok());
+        assert!(check_table_name("aranet.history_2025").is_ok());
+        assert!(check_table_name("_history").is_ok());
+        assert!(check_table_name("").is_err());
+        assert!(check_table_name("2025").is_err());
+        assert!(check_table_name("a.b.c").is_err());
+        assert!(check_table_name("history; DROP TABLE history").is_err());
+        assert!(check_table_name("\"history\"").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+//! Measured values with their unit in the type, so that a sensor's raw encoding (e.g.
+//! temperature in 1/20 °C) can't end up in an output unscaled.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Inches of mercury per hPa (or mbar).
+pub(crate) const INHG_PER_HPA: f64 = 0.029_529_983_071_445;
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Celsius(pub f32);
+
+/// Coefficients of the Magnus formula for saturation vapour pressure over water, as given by
+/// Sonntag (1990): 6.112 hPa·e^(a·T / (b + T)), with T in °C.
+const MAGNUS_A: f64 = 17.62;
+const MAGNUS_B: f64 = 243.12;
+/// Grams per m³ of water vapour at 1 hPa of vapour pressure and 1 K, i.e. 100 / R_v · 1000 with
+/// the specific gas constant of water vapour R_v = 461.5 J/(kg·K).
+const VAPOUR_DENSITY_FACTOR: f64 = 216.7;
+
+impl Celsius {
+    pub fn to_fahrenheit(self) -> f32 {
+        self.0 * 9.0 / 5.0 + 32.0
+    }
+
+    /// The temperature at which air at this temperature and `humidity` starts to condense, by
+    /// the Magnus formula, which is good to about 0.35 °C between -45 °C and 60 °C. Perfectly
+    /// dry air has none.
+    pub fn dew_point(self, humidity: RelativeHumidity) -> Option<Celsius> {
+        if humidity.0 == 0 {
+            return None;
+        }
+        let t = f64::from(self.0);
+        let gamma = (f64::from(humidity.0) / 100.0).ln() + MAGNUS_A * t / (MAGNUS_B + t);
+        Some(Celsius((MAGNUS_B * gamma / (MAGNUS_A - gamma)) as f32))
+    }
+
+    /// How much water vapour air at this temperature and `humidity` holds.
+    pub fn absolute_humidity(self, humidity: RelativeHumidity) -> GramsPerCubicMetre {
+        let t = f64::from(self.0);
+        let saturation_hpa = 6.112 * (MAGNUS_A * t / (MAGNUS_B + t)).exp();
+        let vapour_hpa = saturation_hpa * f64::from(humidity.0) / 100.0;
+        GramsPerCubicMetre((VAPOUR_DENSITY_FACTOR * vapour_hpa / (t + 273.15)) as f32)
+    }
+}
+
+impl fmt::Display for Celsius {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.2}°C", self.0)
+    }
+}
+
+/// Air pressure in mbar, which is the same as hPa.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Millibar(pub f32);
+
+impl Millibar {
+    pub fn to_kpa(self) -> f32 {
+        self.0 / 10.0
+    }
+
+    pub fn to_inhg(self) -> f32 {
+        (f64::from(self.0) * INHG_PER_HPA) as f32
+    }
+}
+
+impl fmt::Display for Millibar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1} hPa", self.0)
+    }
+}
+
+/// CO₂ concentration in parts per million.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Ppm(pub u16);
+
+impl fmt::Display for Ppm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ppm", self.0)
+    }
+}
+
+impl From<Ppm> for f64 {
+    fn from(value: Ppm) -> f64 {
+        f64::from(value.0)
+    }
+}
+
+/// Relative humidity in %.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RelativeHumidity(pub u8);
+
+impl fmt::Display for RelativeHumidity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}%", self.0)
+    }
+}
+
+impl From<RelativeHumidity> for f64 {
+    fn from(value: RelativeHumidity) -> f64 {
+        f64::from(value.0)
+    }
+}
+
+/// Absolute humidity: grams of water vapour per m³ of air.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GramsPerCubicMetre(pub f32);
+
+impl fmt::Display for GramsPerCubicMetre {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1} g/m³", self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::quantity::{Celsius, GramsPerCubicMetre, Millibar, Ppm, RelativeHumidity};
+
+    #[test]
+    fn test_conversions() {
+        assert_eq!(Celsius(20.0).to_fahrenheit(), 68.0);
+        assert_eq!(Celsius(-40.0).to_fahrenheit(), -40.0);
+        assert_eq!(Millibar(1013.0).to_kpa(), 101.3);
+        assert!((Millibar(1013.25).to_inhg() - 29.92).abs() < 0.005);
+        assert_eq!(Celsius(20.65).to_string(), "20.65°C");
+        assert_eq!(Millibar(1017.4).to_string(), "1017.4 hPa");
+        assert_eq!(Ppm(926).to_string(), "926 ppm");
+        assert_eq!(RelativeHumidity(33).to_string(), "33%");
+        assert_eq!(serde_json::to_string(&Ppm(926)).unwrap(), "926");
+        assert_eq!(GramsPerCubicMetre(8.64).to_string(), "8.6 g/m³");
+    }
+
+    #[test]
+    fn test_humidity_quantities() {
+        let dew_point = Celsius(20.0).dew_point(RelativeHumidity(50)).unwrap();
+        assert!((dew_point.0 - 9.26).abs() < 0.01, "{}", dew_point);
+        let dew_point = Celsius(20.0).dew_point(RelativeHumidity(100)).unwrap();
+        assert!((dew_point.0 - 20.0).abs() < 0.001, "{}", dew_point);
+        assert_eq!(Celsius(20.0).dew_point(RelativeHumidity(0)), None);
+        let absolute = Celsius(20.0).absolute_humidity(RelativeHumidity(50));
+        assert!((absolute.0 - 8.62).abs() < 0.01, "{}", absolute);
+        assert_eq!(
+            Celsius(20.0).absolute_humidity(RelativeHumidity(0)),
+            GramsPerCubicMetre(0.0)
+        );
+    }
+}
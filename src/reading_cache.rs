@@ -0,0 +1,125 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use crate::atomic_file::AtomicFile;
+use crate::types::CurrentSensorMeasurement;
+
+/// The latest current reading of each sensor, shared between arachiver processes, so that
+/// e.g. a scraper, the MQTT bridge and a `readout` asking within one measurement interval
+/// connect to the sensor once between them.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ReadingCache {
+    /// Reading keyed by peripheral ID.
+    pub readings: BTreeMap<String, CachedReading>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedReading {
+    /// When the sensor took the reading, in UNIX time.
+    pub measured_at: i64,
+    pub measurement: CurrentSensorMeasurement,
+}
+
+impl ReadingCache {
+    /// `arachiver/readings.json` in the platform cache directory, e.g. `~/.cache` on Linux.
+    pub fn default_path() -> Result<PathBuf> {
+        dirs::cache_dir()
+            .map(|dir| dir.join("arachiver").join("readings.json"))
+            .ok_or(eyre!("Could not determine the user cache directory"))
+    }
+
+    /// Load the cache file, or start from an empty cache if it doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(ReadingCache::default());
+        }
+        let contents = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Could not read reading cache {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .wrap_err_with(|| format!("Could not parse reading cache {}", path.display()))
+    }
+
+    /// Save the cache, replacing the file in one go so that other processes never read half
+    /// of it.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .wrap_err_with(|| format!("Could not create directory {}", dir.display()))?;
+        }
+        let mut file = AtomicFile::create(path)?;
+        file.write_all(serde_json::to_string_pretty(self)?.as_bytes())?;
+        file.commit()
+    }
+
+    /// The cached reading of `device`, unless the sensor will have taken a newer one by `now`.
+    /// Its age is brought up to date.
+    pub fn fresh(&self, device: &str, now: DateTime<Utc>) -> Option<CurrentSensorMeasurement> {
+        let cached = self.readings.get(device)?;
+        let age = now.timestamp() - cached.measured_at;
+        if age < 0 || age >= i64::from(cached.measurement.interval) {
+            return None;
+        }
+        let mut measurement = cached.measurement.clone();
+        measurement.ago = age as u16;
+        Some(measurement)
+    }
+
+    /// Remember `measurement` of `device`, read at `now`.
+    pub fn insert(
+        &mut self,
+        device: &str,
+        measurement: CurrentSensorMeasurement,
+        now: DateTime<Utc>,
+    ) {
+        let measured_at = now.timestamp() - i64::from(measurement.ago);
+        self.readings.insert(
+            device.to_string(),
+            CachedReading {
+                measured_at,
+                measurement,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta};
+
+    use crate::reading_cache::ReadingCache;
+    use crate::types::CurrentSensorMeasurement;
+
+    #[test]
+    fn test_reading_cache_expires_after_interval() {
+        let path = std::env::temp_dir()
+            .join("arachiver_test_reading_cache")
+            .join("readings.json");
+        let _ = std::fs::remove_file(&path);
+        let mut cache = ReadingCache::load(&path).unwrap();
+        // Measured 60 s before it was read, every 300 s
+        let measurement = CurrentSensorMeasurement::from([
+            144, 1, 0xc2, 0x01, 0x8e, 0x27, 40, 90, 1, 44, 1, 60, 0,
+        ]);
+        let now = DateTime::from_timestamp(1738621200, 0).unwrap();
+        cache.insert("hci0/dev_D4_8E_2A_11_5C_90", measurement, now);
+        cache.save(&path).unwrap();
+        let cache = ReadingCache::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let later = cache
+            .fresh("hci0/dev_D4_8E_2A_11_5C_90", now + TimeDelta::seconds(100))
+            .unwrap();
+        assert_eq!(later.ago, 160);
+        assert_eq!(later.co2_value(), 400.0);
+        assert!(cache
+            .fresh("hci0/dev_D4_8E_2A_11_5C_90", now + TimeDelta::seconds(240))
+            .is_none());
+        assert!(cache.fresh("hci0/dev_E1_07_93_4B_AA_12", now).is_none());
+    }
+}
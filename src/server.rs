@@ -0,0 +1,437 @@
+use std::convert::Infallible;
+use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::get;
+use axum::{Json, Router};
+use color_eyre::eyre::{Result, WrapErr};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, Mutex};
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tracing::{info, warn};
+
+use crate::client::Aranet4Client;
+use crate::json_io::{CurrentReadingRecord, HistoryDocument};
+use crate::monitor::{current_readings, reading_timestamp, ReadingSchedule};
+use crate::shutdown::shutdown_signal;
+use crate::sqlite_io::{last_sqlite_timestamp, load_history_sqlite, save_history_sqlite};
+use crate::types::{Aranet4Error, CurrentSensorMeasurement};
+
+/// A sensor as listed by `GET /devices`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceStatus {
+    pub name: String,
+    pub serial: String,
+    /// The latest reading, once one has been taken.
+    pub current: Option<CurrentReadingRecord>,
+}
+
+/// A new reading as pushed to `GET /stream` subscribers.
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamedReading {
+    pub serial: String,
+    #[serde(flatten)]
+    pub reading: CurrentReadingRecord,
+}
+
+/// How many readings a slow `/stream` subscriber may fall behind before it misses some.
+const STREAM_CAPACITY: usize = 16;
+
+/// Shared between the HTTP handlers and the loop polling the sensors. Current readings are
+/// kept in memory; history goes to the SQLite cache, in the same table as
+/// `archive_history_sqlite`.
+pub struct ServerState {
+    devices: RwLock<Vec<DeviceStatus>>,
+    db: Mutex<Connection>,
+    readings: broadcast::Sender<StreamedReading>,
+}
+
+impl ServerState {
+    pub fn new(devices: Vec<DeviceStatus>, db: Connection) -> Self {
+        ServerState {
+            devices: RwLock::new(devices),
+            db: Mutex::new(db),
+            readings: broadcast::channel(STREAM_CAPACITY).0,
+        }
+    }
+
+    /// Record the latest reading from `serial` and push it to `/stream` subscribers.
+    pub fn set_current(&self, serial: &str, record: CurrentReadingRecord) {
+        let mut devices = self.devices.write().unwrap();
+        if let Some(device) = devices.iter_mut().find(|device| device.serial == serial) {
+            device.current = Some(record.clone());
+            // Nobody may be subscribed, which is fine
+            let _ = self.readings.send(StreamedReading {
+                serial: serial.to_string(),
+                reading: record,
+            });
+        }
+    }
+
+    fn device(&self, serial: &str) -> Result<DeviceStatus, ApiError> {
+        self.devices
+            .read()
+            .unwrap()
+            .iter()
+            .find(|device| device.serial == serial)
+            .cloned()
+            .ok_or_else(|| {
+                (
+                    StatusCode::NOT_FOUND,
+                    format!("No device with serial {}", serial),
+                )
+            })
+    }
+}
+
+type ApiError = (StatusCode, String);
+
+async fn list_devices(State(state): State<Arc<ServerState>>) -> Json<Vec<DeviceStatus>> {
+    Json(state.devices.read().unwrap().clone())
+}
+
+async fn current(
+    State(state): State<Arc<ServerState>>,
+    Path(serial): Path<String>,
+) -> Result<Json<CurrentReadingRecord>, ApiError> {
+    state.device(&serial)?.current.map(Json).ok_or_else(|| {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            format!("No reading from {} yet", serial),
+        )
+    })
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    /// Only readings after this UNIX time.
+    since: Option<i64>,
+}
+
+async fn history(
+    State(state): State<Arc<ServerState>>,
+    Path(serial): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Result<Json<HistoryDocument>, ApiError> {
+    state.device(&serial)?;
+    let db = state.db.lock().await;
+    let readings = load_history_sqlite(&db, &serial, query.since)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))?;
+    Ok(Json(HistoryDocument::new(readings)))
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamQuery {
+    /// Only readings from this sensor.
+    serial: Option<String>,
+}
+
+/// Push every new reading as a server-sent `reading` event with a JSON [`StreamedReading`].
+async fn stream(
+    State(state): State<Arc<ServerState>>,
+    Query(query): Query<StreamQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    if let Some(serial) = &query.serial {
+        state.device(serial)?;
+    }
+    // A subscriber that lagged behind just misses the readings it couldn't keep up with
+    let events = BroadcastStream::new(state.readings.subscribe()).filter_map(move |reading| {
+        let reading = reading.ok()?;
+        if query
+            .serial
+            .as_ref()
+            .is_some_and(|serial| *serial != reading.serial)
+        {
+            return None;
+        }
+        Event::default()
+            .event("reading")
+            .json_data(reading)
+            .ok()
+            .map(Ok)
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+pub fn router(state: Arc<ServerState>) -> Router {
+    Router::new()
+        .route("/devices", get(list_devices))
+        .route("/devices/{serial}/current", get(current))
+        .route("/devices/{serial}/history", get(history))
+        .route("/stream", get(stream))
+        .with_state(state)
+}
+
+async fn set_current(
+    client: &Aranet4Client,
+    serial: &str,
+    state: &ServerState,
+    reading: Result<CurrentSensorMeasurement, Aranet4Error>,
+) {
+    match reading {
+        Ok(reading) => {
+            let record = CurrentReadingRecord::new(
+                client.local_name().await,
+                reading_timestamp(&reading),
+                &reading,
+            );
+            state.set_current(serial, record);
+        }
+        Err(e) => warn!("Failed to take reading: {}", e),
+    }
+}
+
+/// Download the history newer than what's cached for one sensor.
+async fn sync_history(client: &Aranet4Client, serial: &str, state: &ServerState) -> Result<()> {
+    let last_timestamp = last_sqlite_timestamp(&*state.db.lock().await, serial)?;
+    let history = match last_timestamp {
+        Some(last_timestamp) => client.read_history_after(last_timestamp).await?,
+        None => client.read_history().await?,
+    };
+    save_history_sqlite(
+        serial,
+        history.time,
+        history.temperature,
+        history.humidity,
+        history.pressure,
+        history.co2,
+        &mut *state.db.lock().await,
+    )
+    .await?;
+    Ok(())
+}
+
+type SensorReadings<'a> =
+    Pin<Box<dyn Stream<Item = (usize, Result<CurrentSensorMeasurement, Aranet4Error>)> + 'a>>;
+
+/// The readings of every one of `clients`, by index. Polled, the sensors are read one after the
+/// other, as most adapters cope poorly with several connections at once; subscribed, each
+/// keeps its connection and its readings come as the sensor takes them.
+fn sensor_readings(clients: &[Aranet4Client], schedule: ReadingSchedule) -> SensorReadings<'_> {
+    match schedule {
+        ReadingSchedule::Poll(interval) => Box::pin(futures::stream::unfold(
+            (tokio::time::interval(interval), clients.len()),
+            move |(mut ticker, i)| async move {
+                // Wait for the next tick once every sensor has been read
+                let i = if i == clients.len() {
+                    ticker.tick().await;
+                    0
+                } else {
+                    i
+                };
+                let reading = clients.get(i)?.read_current().await;
+                Some(((i, reading), (ticker, i + 1)))
+            },
+        )),
+        ReadingSchedule::Subscribe => {
+            Box::pin(futures::stream::select_all(clients.iter().enumerate().map(
+                |(i, client)| current_readings(client, schedule).map(move |reading| (i, reading)),
+            )))
+        }
+    }
+}
+
+/// Serve the readings of `clients` as JSON over HTTP on `listen` until interrupted with Ctrl-C
+/// or SIGTERM. Current readings are taken from each sensor on `schedule` and the history cache
+/// in `db` is refreshed every `history_interval`; failures are reported on stderr and retried
+/// next time.
+pub async fn serve(
+    clients: &[Aranet4Client],
+    listen: SocketAddr,
+    db: Connection,
+    schedule: ReadingSchedule,
+    history_interval: Duration,
+) -> Result<()> {
+    let mut devices = Vec::new();
+    for client in clients {
+        devices.push(DeviceStatus {
+            name: client.local_name().await.unwrap_or_default(),
+            serial: client.serial_number().await?.trim().to_string(),
+            current: None,
+        });
+    }
+    let serials: Vec<String> = devices.iter().map(|device| device.serial.clone()).collect();
+    let state = Arc::new(ServerState::new(devices, db));
+    let listener = TcpListener::bind(listen)
+        .await
+        .wrap_err_with(|| format!("Could not listen on {}", listen))?;
+    info!("Listening on http://{}", listener.local_addr()?);
+    let server = axum::serve(listener, router(state.clone())).into_future();
+    tokio::pin!(server);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let mut readings = sensor_readings(clients, schedule);
+    let mut history = tokio::time::interval(history_interval);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            result = &mut server => return result.wrap_err("HTTP server failed"),
+            Some((i, reading)) = readings.next() => {
+                set_current(&clients[i], &serials[i], &state, reading).await;
+            }
+            _ = history.tick() => {
+                for (client, serial) in clients.iter().zip(&serials) {
+                    if let Err(e) = sync_history(client, serial, &state).await {
+                        warn!("Failed to update history: {:#}", e);
+                    }
+                }
+            }
+        }
+    }
+    drop(readings);
+    for client in clients {
+        schedule.stop(client).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::future::IntoFuture;
+    use std::io::{BufRead, BufReader};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use chrono::DateTime;
+    use rusqlite::Connection;
+    use tokio::net::TcpListener;
+
+    use crate::device::HistoryTime;
+    use crate::json_io::CurrentReadingRecord;
+    use crate::server::{router, DeviceStatus, ServerState};
+    use crate::sqlite_io::save_history_sqlite;
+    use crate::types::{
+        CO2Data, CurrentSensorMeasurement, HumidityData, PressureData, TemperatureData,
+    };
+
+    fn get(url: String) -> (u16, String) {
+        match ureq::get(&url).call() {
+            Ok(response) => (response.status(), response.into_string().unwrap()),
+            Err(ureq::Error::Status(status, response)) => (status, response.into_string().unwrap()),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serve_devices() {
+        let mut db = Connection::open_in_memory().unwrap();
+        let bytes = [144u8, 1, 164, 1];
+        save_history_sqlite(
+            "317960113191",
+            HistoryTime {
+                num_samples: 2,
+                update_interval: 300,
+                since_update: 0,
+                now: DateTime::from_timestamp(1738621500, 0).unwrap(),
+                offset: 0,
+            },
+            TemperatureData::try_from(&bytes[..]).unwrap(),
+            HumidityData::try_from(&bytes[..2]).unwrap(),
+            PressureData::try_from(&bytes[..]).unwrap(),
+            CO2Data::try_from(&bytes[..]).unwrap(),
+            &mut db,
+        )
+        .await
+        .unwrap();
+        let device = |serial: &str, name: &str| DeviceStatus {
+            name: name.to_string(),
+            serial: serial.to_string(),
+            current: None,
+        };
+        let state = Arc::new(ServerState::new(
+            vec![
+                device("317960113191", "Aranet4 1BA27"),
+                device("317960113192", "Aranet4 1BA28"),
+            ],
+            db,
+        ));
+        let reading = CurrentSensorMeasurement::from([
+            0x9e, 0x03, 0x9d, 0x01, 0xbe, 0x27, 0x21, 0x16, 0x01, 0x2c, 0x01, 0xff, 0x00,
+        ]);
+        state.set_current(
+            "317960113191",
+            CurrentReadingRecord::new(None, 1738621500, &reading),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(axum::serve(listener, router(state)).into_future());
+
+        let responses = tokio::task::spawn_blocking(move || {
+            [
+                "/devices",
+                "/devices/317960113191/current",
+                "/devices/317960113192/current",
+                "/devices/0/current",
+                "/devices/317960113191/history?since=1738621200",
+            ]
+            .map(|path| get(format!("{}{}", base, path)))
+        })
+        .await
+        .unwrap();
+
+        let devices: serde_json::Value = serde_json::from_str(&responses[0].1).unwrap();
+        assert_eq!(devices[0]["serial"], "317960113191");
+        assert_eq!(devices[0]["current"]["co2"], 926);
+        assert!(devices[1]["current"].is_null());
+        let current: serde_json::Value = serde_json::from_str(&responses[1].1).unwrap();
+        assert_eq!(current["timestamp"], 1738621500);
+        assert_eq!(responses[2].0, 503);
+        assert_eq!(responses[3].0, 404);
+        let history: serde_json::Value = serde_json::from_str(&responses[4].1).unwrap();
+        assert_eq!(history["units"]["co2"], "CO₂ (ppm)");
+        assert_eq!(history["readings"].as_array().unwrap().len(), 1);
+        assert_eq!(history["readings"][0]["timestamp"], 1738621500);
+    }
+
+    #[tokio::test]
+    async fn test_stream_readings() {
+        let state = Arc::new(ServerState::new(
+            vec![DeviceStatus {
+                name: "Aranet4 1BA27".to_string(),
+                serial: "317960113191".to_string(),
+                current: None,
+            }],
+            Connection::open_in_memory().unwrap(),
+        ));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/stream", listener.local_addr().unwrap());
+        tokio::spawn(axum::serve(listener, router(state.clone())).into_future());
+
+        let subscriber = tokio::task::spawn_blocking(move || {
+            let response = ureq::get(&url).call().unwrap();
+            assert_eq!(response.content_type(), "text/event-stream");
+            let lines = BufReader::new(response.into_reader()).lines();
+            lines
+                .map(Result::unwrap)
+                .skip_while(|line| line != "event: reading")
+                .nth(1)
+                .unwrap()
+        });
+        let reading = CurrentSensorMeasurement::from([
+            0x9e, 0x03, 0x9d, 0x01, 0xbe, 0x27, 0x21, 0x16, 0x01, 0x2c, 0x01, 0xff, 0x00,
+        ]);
+        // Keep publishing until the subscriber has connected and seen a reading
+        while !subscriber.is_finished() {
+            state.set_current(
+                "317960113191",
+                CurrentReadingRecord::new(None, 1738621500, &reading),
+            );
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let data = subscriber.await.unwrap();
+        let event: serde_json::Value =
+            serde_json::from_str(data.strip_prefix("data: ").unwrap()).unwrap();
+        assert_eq!(event["serial"], "317960113191");
+        assert_eq!(event["co2"], 926);
+    }
+}
@@ -0,0 +1,41 @@
+use std::future::pending;
+
+/// Which signal asked us to stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shutdown {
+    Interrupt,
+    Terminate,
+}
+
+impl Shutdown {
+    /// The exit status shells report for a process killed by this signal, 128 + signal number.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Shutdown::Interrupt => 130,
+            Shutdown::Terminate => 143,
+        }
+    }
+}
+
+async fn interrupt() {
+    if tokio::signal::ctrl_c().await.is_err() {
+        // Without a handler the default disposition still applies, so wait for nothing
+        pending::<()>().await;
+    }
+}
+
+/// Wait for Ctrl-C or, on Unix, SIGTERM.
+pub async fn shutdown_signal() -> Shutdown {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut terminate) = signal(SignalKind::terminate()) {
+            return tokio::select! {
+                _ = interrupt() => Shutdown::Interrupt,
+                _ = terminate.recv() => Shutdown::Terminate,
+            };
+        }
+    }
+    interrupt().await;
+    Shutdown::Interrupt
+}
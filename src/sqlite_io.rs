@@ -0,0 +1,173 @@
+use color_eyre::eyre::Result;
+use rusqlite::{params, Connection};
+
+use crate::device::HistoryTime;
+use crate::types::{
+    history_records, CO2Data, HistoryRecord, HumidityData, PressureData, TemperatureData,
+};
+
+const CREATE_HISTORY_TABLE: &str = "CREATE TABLE IF NOT EXISTS history (
+    device_serial TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    temperature REAL NOT NULL,
+    humidity INTEGER NOT NULL,
+    pressure REAL NOT NULL,
+    co2 INTEGER NOT NULL,
+    PRIMARY KEY (device_serial, timestamp)
+)";
+
+const UPSERT_HISTORY_ROW: &str = "INSERT INTO history
+    (device_serial, timestamp, temperature, humidity, pressure, co2)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+    ON CONFLICT (device_serial, timestamp) DO UPDATE SET
+        temperature = excluded.temperature,
+        humidity = excluded.humidity,
+        pressure = excluded.pressure,
+        co2 = excluded.co2";
+
+/// Upsert the history into the `history` table of `conn`, creating the table if needed. Rows
+/// are keyed by (device serial, timestamp), so re-archiving overlapping history doesn't
+/// duplicate readings. Returns the number of new rows.
+pub async fn save_history_sqlite(
+    device_serial: &str,
+    history_time: HistoryTime,
+    temperature: TemperatureData,
+    humidity: HumidityData,
+    pressure: PressureData,
+    co2: CO2Data,
+    conn: &mut Connection,
+) -> Result<usize> {
+    write_history_sqlite(
+        device_serial,
+        history_records(&history_time, &temperature, &humidity, &pressure, &co2),
+        conn,
+    )
+}
+
+/// Like [`save_history_sqlite`], for readings from any source, e.g. an archive being converted.
+pub fn write_history_sqlite(
+    device_serial: &str,
+    records: impl IntoIterator<Item = Result<HistoryRecord>>,
+    conn: &mut Connection,
+) -> Result<usize> {
+    let tx = conn.transaction()?;
+    tx.execute(CREATE_HISTORY_TABLE, [])?;
+    let count_rows = |tx: &rusqlite::Transaction| -> rusqlite::Result<usize> {
+        tx.query_row(
+            "SELECT COUNT(*) FROM history WHERE device_serial = ?1",
+            [device_serial],
+            |row| row.get(0),
+        )
+    };
+    let rows_before = count_rows(&tx)?;
+    {
+        let mut upsert = tx.prepare(UPSERT_HISTORY_ROW)?;
+        for record in records {
+            let record = record?;
+            upsert.execute(params![
+                device_serial,
+                record.timestamp,
+                record.temperature_c,
+                record.humidity_pct,
+                record.pressure_mbar,
+                record.co2_ppm,
+            ])?;
+        }
+    }
+    let rows_after = count_rows(&tx)?;
+    tx.commit()?;
+    Ok(rows_after - rows_before)
+}
+
+/// The timestamp of the newest reading stored for `device_serial`, if any.
+pub fn last_sqlite_timestamp(conn: &Connection, device_serial: &str) -> Result<Option<i64>> {
+    conn.execute(CREATE_HISTORY_TABLE, [])?;
+    let last = conn.query_row(
+        "SELECT MAX(timestamp) FROM history WHERE device_serial = ?1",
+        [device_serial],
+        |row| row.get(0),
+    )?;
+    Ok(last)
+}
+
+/// The readings stored for `device_serial` newer than `since` (UNIX time), oldest first.
+pub fn load_history_sqlite(
+    conn: &Connection,
+    device_serial: &str,
+    since: Option<i64>,
+) -> Result<Vec<HistoryRecord>> {
+    conn.execute(CREATE_HISTORY_TABLE, [])?;
+    let mut query = conn.prepare(
+        "SELECT timestamp, temperature, humidity, pressure, co2 FROM history
+            WHERE device_serial = ?1 AND timestamp > ?2
+            ORDER BY timestamp",
+    )?;
+    let records = query
+        .query_map(params![device_serial, since.unwrap_or(i64::MIN)], |row| {
+            Ok(HistoryRecord {
+                timestamp: row.get(0)?,
+                temperature_c: row.get(1)?,
+                humidity_pct: row.get(2)?,
+                pressure_mbar: row.get(3)?,
+                co2_ppm: row.get(4)?,
+            })
+        })?
+        .collect::<rusqlite::Result<_>>()?;
+    Ok(records)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::device::HistoryTime;
+    use crate::sqlite_io::{last_sqlite_timestamp, load_history_sqlite, save_history_sqlite};
+    use crate::types::{CO2Data, HumidityData, PressureData, TemperatureData};
+    use chrono::DateTime;
+    use rusqlite::Connection;
+
+    async fn save(conn: &mut Connection, now: i64, bytes: &[u8]) -> usize {
+        let history_time = HistoryTime {
+            num_samples: bytes.len() / 2,
+            update_interval: 300,
+            since_update: 0,
+            now: DateTime::from_timestamp(now, 0).unwrap(),
+            offset: 0,
+        };
+        save_history_sqlite(
+            "317960113191",
+            history_time,
+            TemperatureData::try_from(bytes).unwrap(),
+            HumidityData::try_from(&bytes[..bytes.len() / 2]).unwrap(),
+            PressureData::try_from(bytes).unwrap(),
+            CO2Data::try_from(bytes).unwrap(),
+            conn,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_save_history_sqlite_deduplicates() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert_eq!(save(&mut conn, 1738621200, &[144, 1, 164, 1]).await, 2);
+        // Five minutes later, the ring buffer has shifted by one reading
+        assert_eq!(save(&mut conn, 1738621500, &[164, 1, 184, 1]).await, 1);
+        let (count, co2): (usize, u16) = conn
+            .query_row(
+                "SELECT COUNT(*), MAX(co2) FROM history WHERE device_serial = '317960113191'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(co2, 440);
+        assert_eq!(
+            last_sqlite_timestamp(&conn, "317960113191").unwrap(),
+            Some(1738621500)
+        );
+        assert_eq!(last_sqlite_timestamp(&conn, "other").unwrap(), None);
+        let records = load_history_sqlite(&conn, "317960113191", Some(1738621200)).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].timestamp, 1738621500);
+        assert_eq!(records[0].co2_ppm, 440);
+    }
+}
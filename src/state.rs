@@ -0,0 +1,81 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+/// What we remember about a device between incremental archive runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceSyncState {
+    /// UNIX time of the newest reading written to `archive`.
+    pub last_timestamp: i64,
+    pub archive: PathBuf,
+}
+
+/// Per-device sync state, keyed by serial number.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SyncState {
+    pub devices: BTreeMap<String, DeviceSyncState>,
+    /// UNIX time of the newest reading posted by `webhook`, by serial number.
+    #[serde(default)]
+    pub webhook: BTreeMap<String, i64>,
+}
+
+impl SyncState {
+    /// `arachiver/state.json` in the platform data directory, e.g. `~/.local/share` on Linux.
+    pub fn default_path() -> Result<PathBuf> {
+        dirs::data_dir()
+            .map(|dir| dir.join("arachiver").join("state.json"))
+            .ok_or(eyre!("Could not determine the user data directory"))
+    }
+
+    /// Load the state file, or start from an empty state if it doesn't exist yet.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(SyncState::default());
+        }
+        let contents = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Could not read state file {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .wrap_err_with(|| format!("Could not parse state file {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .wrap_err_with(|| format!("Could not create directory {}", dir.display()))?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .wrap_err_with(|| format!("Could not write state file {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::state::{DeviceSyncState, SyncState};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_state_round_trip() {
+        let path = std::env::temp_dir()
+            .join("arachiver_test_state_round_trip")
+            .join("state.json");
+        let _ = std::fs::remove_file(&path);
+        assert!(SyncState::load(&path).unwrap().devices.is_empty());
+        let mut state = SyncState::default();
+        state.devices.insert(
+            "317960113191".to_string(),
+            DeviceSyncState {
+                last_timestamp: 1738621029,
+                archive: PathBuf::from("history.csv"),
+            },
+        );
+        state.save(&path).unwrap();
+        let loaded = SyncState::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let device = &loaded.devices["317960113191"];
+        assert_eq!(device.last_timestamp, 1738621029);
+        assert_eq!(device.archive, PathBuf::from("history.csv"));
+    }
+}
@@ -0,0 +1,245 @@
+//! Summary statistics of a stretch of history, for a quick look at the air quality without
+//! exporting the readings to a spreadsheet or pandas.
+
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use color_eyre::eyre::{eyre, Result};
+
+use crate::check::Co2Thresholds;
+use crate::device::History;
+use crate::merge::read_archive_records;
+use crate::time_range::{format_span, HistoryRange};
+use crate::types::{history_records, split_label, HistoryRecord, Metadata, TemperatureData, Units};
+
+/// The smallest, largest, mean and median of a metric's values.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+}
+
+impl Summary {
+    /// `None` if there are no values.
+    pub fn new(mut values: Vec<f64>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f64::total_cmp);
+        let n = values.len();
+        let median = if n % 2 == 1 {
+            values[n / 2]
+        } else {
+            (values[n / 2 - 1] + values[n / 2]) / 2.0
+        };
+        Some(Summary {
+            min: values[0],
+            max: values[n - 1],
+            mean: values.iter().sum::<f64>() / n as f64,
+            median,
+        })
+    }
+}
+
+/// What `stats` reports about a stretch of history, in metric units.
+#[derive(Debug, Clone)]
+pub struct HistoryStats {
+    pub readings: usize,
+    pub first_timestamp: i64,
+    pub last_timestamp: i64,
+    /// The shortest time between consecutive readings, taken to be the measurement interval,
+    /// so that gaps in the history don't count towards the time above the thresholds.
+    pub interval: Duration,
+    pub co2: Summary,
+    pub thresholds: Co2Thresholds,
+    /// Readings at or above the warning and critical thresholds.
+    pub above_warn: usize,
+    pub above_crit: usize,
+    pub temperature: Summary,
+    pub humidity: Summary,
+}
+
+impl HistoryStats {
+    pub fn new(records: &[HistoryRecord], thresholds: Co2Thresholds) -> Result<Self> {
+        let summary = |value: fn(&HistoryRecord) -> f64| {
+            Summary::new(records.iter().map(value).collect())
+                .ok_or(eyre!("There are no readings to summarize"))
+        };
+        let co2 = summary(|record| f64::from(record.co2_ppm))?;
+        let mut timestamps: Vec<i64> = records.iter().map(|record| record.timestamp).collect();
+        timestamps.sort_unstable();
+        let interval = timestamps
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .filter(|&gap| gap > 0)
+            .min()
+            .unwrap_or(0);
+        let above = |threshold: u16| {
+            records
+                .iter()
+                .filter(|record| record.co2_ppm >= threshold)
+                .count()
+        };
+        Ok(HistoryStats {
+            readings: records.len(),
+            first_timestamp: timestamps[0],
+            last_timestamp: timestamps[timestamps.len() - 1],
+            interval: Duration::from_secs(interval as u64),
+            co2,
+            thresholds,
+            above_warn: above(thresholds.warn),
+            above_crit: above(thresholds.crit),
+            temperature: summary(|record| record.temperature_c)?,
+            humidity: summary(|record| f64::from(record.humidity_pct))?,
+        })
+    }
+
+    /// Summarize a downloaded history.
+    pub fn from_history(history: &History, thresholds: Co2Thresholds) -> Result<Self> {
+        let records: Vec<HistoryRecord> = history_records(
+            &history.time,
+            &history.temperature,
+            &history.humidity,
+            &history.pressure,
+            &history.co2,
+        )
+        .collect::<Result<_>>()?;
+        Self::new(&records, thresholds)
+    }
+
+    /// Summarize the readings of the CSV, Parquet, JSON or MessagePack archive at `path` within `range`.
+    pub fn from_archive(
+        path: &Path,
+        range: HistoryRange,
+        thresholds: Co2Thresholds,
+    ) -> Result<Self> {
+        let records: Vec<HistoryRecord> = read_archive_records(path)?
+            .into_iter()
+            .filter(|record| range.contains(record.timestamp))
+            .collect();
+        Self::new(&records, thresholds)
+    }
+
+    /// How long CO₂ was at or above the threshold `readings` of the readings were.
+    fn time_above(&self, readings: usize) -> String {
+        format!(
+            "{} ({:.0}%)",
+            format_span(self.interval * readings as u32),
+            100.0 * readings as f64 / self.readings as f64
+        )
+    }
+
+    /// The report `stats` prints, with temperatures converted to `units`.
+    pub fn display_in(&self, units: Units) -> String {
+        let time = |timestamp: i64| {
+            DateTime::from_timestamp(timestamp, 0)
+                .unwrap_or_default()
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M")
+        };
+        let temperature = |value: f64| {
+            format!(
+                "{:.*}{}",
+                TemperatureData::precision(units),
+                TemperatureData::convert(value, units),
+                split_label(TemperatureData::unit_label(units)).1
+            )
+        };
+        format!(
+            "Readings: {} from {} to {}\n\
+             CO₂: min {:.0} ppm, max {:.0} ppm, mean {:.0} ppm, median {:.0} ppm\n\
+             At or above {} ppm: {}\n\
+             At or above {} ppm: {}\n\
+             Temperature: {} to {}, mean {}\n\
+             Humidity: {:.0}% to {:.0}%, mean {:.0}%\n",
+            self.readings,
+            time(self.first_timestamp),
+            time(self.last_timestamp),
+            self.co2.min,
+            self.co2.max,
+            self.co2.mean,
+            self.co2.median,
+            self.thresholds.warn,
+            self.time_above(self.above_warn),
+            self.thresholds.crit,
+            self.time_above(self.above_crit),
+            temperature(self.temperature.min),
+            temperature(self.temperature.max),
+            temperature(self.temperature.mean),
+            self.humidity.min,
+            self.humidity.max,
+            self.humidity.mean,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::check::Co2Thresholds;
+    use crate::stats::{HistoryStats, Summary};
+    use crate::types::{HistoryRecord, Units};
+
+    fn record(timestamp: i64, co2_ppm: u16, temperature_c: f64) -> HistoryRecord {
+        HistoryRecord {
+            timestamp,
+            temperature_c,
+            humidity_pct: 40,
+            pressure_mbar: 1013.0,
+            co2_ppm,
+        }
+    }
+
+    #[test]
+    fn test_summary() {
+        let summary = Summary::new(vec![3.0, 1.0, 4.0, 2.0]).unwrap();
+        assert_eq!(
+            summary,
+            Summary {
+                min: 1.0,
+                max: 4.0,
+                mean: 2.5,
+                median: 2.5,
+            }
+        );
+        assert_eq!(Summary::new(vec![5.0, 1.0, 2.0]).unwrap().median, 2.0);
+        assert_eq!(Summary::new(vec![]), None);
+    }
+
+    #[test]
+    fn test_history_stats() {
+        // A gap of an hour before the last reading, which shouldn't count as time above
+        let records = [
+            record(0, 800, 20.0),
+            record(300, 1100, 21.0),
+            record(600, 1500, 22.0),
+            record(4200, 1450, 23.0),
+        ];
+        let stats = HistoryStats::new(&records, Co2Thresholds::default()).unwrap();
+        assert_eq!(stats.interval, Duration::from_secs(300));
+        assert_eq!((stats.above_warn, stats.above_crit), (3, 2));
+        assert_eq!(stats.co2.median, 1275.0);
+        let report = stats.display_in(Units::Imperial);
+        assert!(
+            report.contains("At or above 1000 ppm: 15 min (75%)\n"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("At or above 1400 ppm: 10 min (50%)\n"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("Temperature: 68.00°F to 73.40°F, mean 70.70°F\n"),
+            "{}",
+            report
+        );
+
+        assert!(HistoryStats::new(&[], Co2Thresholds::default()).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+use color_eyre::eyre::{eyre, Result};
+
+/// The readings to download, as UNIX times; an open end means from the oldest stored reading or
+/// through the newest one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HistoryRange {
+    /// Only readings taken at or after this time.
+    pub since: Option<i64>,
+    /// Only readings taken at or before this time.
+    pub until: Option<i64>,
+}
+
+impl HistoryRange {
+    /// Every stored reading.
+    pub const ALL: HistoryRange = HistoryRange {
+        since: None,
+        until: None,
+    };
+
+    pub fn new(since: Option<i64>, until: Option<i64>) -> Result<Self> {
+        if let (Some(since), Some(until)) = (since, until) {
+            if since > until {
+                return Err(eyre!("The start of the time range is after its end"));
+            }
+        }
+        Ok(HistoryRange { since, until })
+    }
+
+    /// Whether a reading taken at `timestamp` is in the range.
+    pub fn contains(&self, timestamp: i64) -> bool {
+        self.since.is_none_or(|since| timestamp >= since)
+            && self.until.is_none_or(|until| timestamp <= until)
+    }
+}
+
+/// Parse a time as RFC 3339, e.g. `2025-02-21T02:17:30-08:00`, or as a local date or date and
+/// time, e.g. `2025-02-21` or `2025-02-21 02:17`, into UNIX time.
+pub fn parse_time(text: &str) -> Result<i64> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.timestamp());
+    }
+    let naive = [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+    })
+    .ok_or(eyre!(
+        "Invalid time '{}'; use e.g. 2025-02-21, '2025-02-21 14:30' or RFC 3339",
+        text
+    ))?;
+    // Around a DST change, take the earlier of two ambiguous times
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|time| time.timestamp())
+        .ok_or(eyre!("{} doesn't exist in the local time zone", text))
+}
+
+/// Parse a duration like `90s`, `15m`, `24h`, `7d`, `2w` or a combination such as `1d12h`.
+pub fn parse_duration(text: &str) -> Result<Duration> {
+    let invalid = || eyre!("Invalid duration '{}'; use e.g. 30m, 24h or 7d", text);
+    let mut seconds: u64 = 0;
+    let mut rest = text.trim();
+    if rest.is_empty() {
+        return Err(invalid());
+    }
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let count: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        let unit = rest[digits..].chars().next().ok_or_else(invalid)?;
+        let multiplier = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86_400,
+            'w' => 604_800,
+            _ => return Err(invalid()),
+        };
+        seconds = count
+            .checked_mul(multiplier)
+            .and_then(|part| seconds.checked_add(part))
+            .ok_or_else(invalid)?;
+        rest = &rest[digits + unit.len_utf8()..];
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// `span` in days, hours and minutes, leaving out leading zero units.
+pub fn format_span(span: Duration) -> String {
+    let minutes = span.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    match (days, hours) {
+        (0, 0) => format!("{} min", minutes),
+        (0, _) => format!("{} h {} min", hours, minutes),
+        _ => format!("{} d {} h {} min", days, hours, minutes),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::time_range::{parse_duration, parse_time, HistoryRange};
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("24h").unwrap(), Duration::from_secs(86_400));
+        assert_eq!(
+            parse_duration("1d12h").unwrap(),
+            Duration::from_secs(129_600)
+        );
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        for invalid in ["", "24", "h", "5y", "-1h", "1.5h"] {
+            assert!(parse_duration(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(
+            parse_time("2025-02-21T02:17:30-08:00").unwrap(),
+            1_740_133_050
+        );
+        let midnight = parse_time("2025-02-21").unwrap();
+        assert_eq!(parse_time("2025-02-21 00:00").unwrap(), midnight);
+        assert_eq!(parse_time("2025-02-21T01:30:00").unwrap(), midnight + 5400);
+        assert!(parse_time("yesterday").is_err());
+
+        assert!(HistoryRange::new(Some(2), Some(1)).is_err());
+        let range = HistoryRange::new(Some(1), Some(2)).unwrap();
+        assert!(range.contains(1) && range.contains(2));
+        assert!(!range.contains(0) && !range.contains(3));
+        assert!(HistoryRange::ALL.contains(i64::MIN));
+    }
+}
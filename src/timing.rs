@@ -0,0 +1,181 @@
+//! The `--timing` report: how long each metric of a history download took and how fast its
+//! packets arrived, for telling whether tuning like `--interleave-history` or
+//! `--write-without-response` helps with a given sensor and adapter.
+
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::device::HistoryProgress;
+
+/// The transfer of one metric, or of all of them together in an interleaved download.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricTiming {
+    pub metric: &'static str,
+    /// From the last packet of the metric before, or the first packet of the download, to the
+    /// last packet of this one, so that it includes the round trips of requesting it.
+    pub elapsed: Duration,
+    pub readings: usize,
+    pub packets: usize,
+    pub bytes: usize,
+    pub largest_packet: usize,
+}
+
+impl MetricTiming {
+    pub fn readings_per_second(&self) -> f64 {
+        self.readings as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+#[derive(Debug, Default)]
+struct TimingState {
+    last_packet: Option<Instant>,
+    /// Whether the last packet completed a download, so that the next one starts another.
+    complete: bool,
+    metrics: Vec<MetricTiming>,
+}
+
+/// Collects [`HistoryProgress`] reports into [`MetricTiming`]s, e.g. from a progress callback.
+#[derive(Debug, Default)]
+pub struct TransferTiming {
+    state: Mutex<TimingState>,
+}
+
+impl TransferTiming {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, progress: &HistoryProgress) {
+        self.record_at(progress, Instant::now());
+    }
+
+    fn record_at(&self, progress: &HistoryProgress, now: Instant) {
+        let mut state = self.state.lock().unwrap();
+        let new_download = std::mem::take(&mut state.complete);
+        if new_download {
+            state.last_packet = None;
+        }
+        let since_last = state
+            .last_packet
+            .map_or(Duration::ZERO, |last| now.saturating_duration_since(last));
+        state.last_packet = Some(now);
+        state.complete = progress.is_complete();
+        let new_metric = new_download
+            || state
+                .metrics
+                .last()
+                .is_none_or(|timing| timing.metric != progress.metric);
+        if new_metric {
+            state.metrics.push(MetricTiming {
+                metric: progress.metric,
+                elapsed: Duration::ZERO,
+                readings: 0,
+                packets: 0,
+                bytes: 0,
+                largest_packet: 0,
+            });
+        }
+        let timing = state.metrics.last_mut().unwrap();
+        timing.elapsed += since_last;
+        timing.readings = progress.received;
+        timing.packets += 1;
+        timing.bytes += progress.packet_len;
+        timing.largest_packet = timing.largest_packet.max(progress.packet_len);
+    }
+
+    /// The metrics transferred so far, in the order they were.
+    pub fn metrics(&self) -> Vec<MetricTiming> {
+        self.state.lock().unwrap().metrics.clone()
+    }
+
+    /// The report `--timing` prints for the download from `device`, or `None` if nothing was
+    /// downloaded.
+    pub fn report(&self, device: &str) -> Option<String> {
+        let metrics = self.metrics();
+        if metrics.is_empty() {
+            return None;
+        }
+        let mut report = format!("History transfer from {}:\n", device);
+        for timing in &metrics {
+            writeln!(
+                report,
+                "  {}: {} readings in {:.1} s ({:.0} readings/s), {} packets of up to {} bytes ({:.1} kB/s)",
+                timing.metric,
+                timing.readings,
+                timing.elapsed.as_secs_f64(),
+                timing.readings_per_second(),
+                timing.packets,
+                timing.largest_packet,
+                timing.bytes as f64 / 1000.0 / timing.elapsed.as_secs_f64().max(f64::EPSILON)
+            )
+            .unwrap();
+        }
+        let elapsed: Duration = metrics.iter().map(|timing| timing.elapsed).sum();
+        let readings: usize = metrics.iter().map(|timing| timing.readings).sum();
+        let largest_packet = metrics
+            .iter()
+            .map(|timing| timing.largest_packet)
+            .max()
+            .unwrap_or(0);
+        writeln!(
+            report,
+            "  Total: {} readings in {:.1} s ({:.0} readings/s); packets of up to {} bytes mean an ATT MTU of at least {}",
+            readings,
+            elapsed.as_secs_f64(),
+            readings as f64 / elapsed.as_secs_f64().max(f64::EPSILON),
+            largest_packet,
+            largest_packet + 3
+        )
+        .unwrap();
+        Some(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::device::HistoryProgress;
+    use crate::timing::TransferTiming;
+
+    fn packet(metric: &'static str, metric_index: usize, received: usize) -> HistoryProgress {
+        HistoryProgress {
+            metric,
+            metric_index,
+            num_metrics: 2,
+            received,
+            num_readings: 200,
+            packet_len: 104,
+        }
+    }
+
+    #[test]
+    fn test_transfer_timing() {
+        let timing = TransferTiming::new();
+        let start = Instant::now();
+        let at = |seconds: f64| start + Duration::from_secs_f64(seconds);
+        timing.record_at(&packet("Temperature (°C)", 0, 50), at(0.0));
+        timing.record_at(&packet("Temperature (°C)", 0, 100), at(0.5));
+        timing.record_at(&packet("Temperature (°C)", 0, 200), at(1.0));
+        timing.record_at(&packet("CO₂ (ppm)", 1, 100), at(1.5));
+        timing.record_at(&packet("CO₂ (ppm)", 1, 200), at(2.0));
+        let metrics = timing.metrics();
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].elapsed, Duration::from_secs(1));
+        assert_eq!(metrics[0].packets, 3);
+        assert_eq!(metrics[0].readings_per_second(), 200.0);
+        assert_eq!(metrics[1].elapsed, Duration::from_secs(1));
+        assert_eq!(metrics[1].bytes, 208);
+        let report = timing.report("Aranet4 1BA27").unwrap();
+        assert!(report.starts_with("History transfer from Aranet4 1BA27:\n"));
+        assert!(report.contains("  CO₂ (ppm): 200 readings in 1.0 s (200 readings/s)"));
+        assert!(report.contains("Total: 400 readings in 2.0 s"));
+        assert!(report.contains("ATT MTU of at least 107"));
+
+        // The next download starts its clock afresh
+        timing.record_at(&packet("Temperature (°C)", 0, 50), at(60.0));
+        assert_eq!(timing.metrics()[2].elapsed, Duration::ZERO);
+        assert_eq!(TransferTiming::new().report("Aranet4 1BA27"), None);
+    }
+}
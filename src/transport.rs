@@ -0,0 +1,467 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use btleplug::api::{CharPropFlags, Characteristic, Peripheral as _, ValueNotification, WriteType};
+use btleplug::platform::Peripheral;
+use futures::Stream;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::mock::MockAranet4;
+use crate::types::Aranet4Error;
+
+/// Stream of notifications from every subscribed characteristic.
+pub type NotificationStream = Pin<Box<dyn Stream<Item = ValueNotification> + Send>>;
+
+/// The GATT operations the sensor protocol needs, so that the code in [`crate::device`] runs
+/// against a real btleplug [`Peripheral`] or an in-memory [`crate::mock::MockAranet4`] alike.
+/// Characteristics are addressed by UUID.
+pub trait SensorTransport: Send + Sync {
+    /// Connect and discover services, if not already done.
+    fn connect(&self) -> impl Future<Output = Result<(), Aranet4Error>> + Send;
+
+    fn disconnect(&self) -> impl Future<Output = Result<(), Aranet4Error>> + Send;
+
+    /// Called when an operation is done with its [`Connection`]. Disconnects, unless the
+    /// connection is kept for the next operation, as by a held [`ConnectedSensor`].
+    fn release(&self) -> impl Future<Output = Result<(), Aranet4Error>> + Send {
+        self.disconnect()
+    }
+
+    /// Start disconnecting without waiting for it, for use where we can't await, like `Drop`.
+    fn disconnect_in_background(&self);
+
+    /// The advertised name, if known.
+    fn local_name(&self) -> impl Future<Output = Option<String>> + Send;
+
+    fn read(&self, uuid: Uuid) -> impl Future<Output = Result<Vec<u8>, Aranet4Error>> + Send;
+
+    /// Write with response.
+    fn write(
+        &self,
+        uuid: Uuid,
+        data: &[u8],
+    ) -> impl Future<Output = Result<(), Aranet4Error>> + Send;
+
+    /// Write without waiting for the sensor to acknowledge it, where the characteristic allows
+    /// that; otherwise, and by default, with response.
+    fn write_without_response(
+        &self,
+        uuid: Uuid,
+        data: &[u8],
+    ) -> impl Future<Output = Result<(), Aranet4Error>> + Send {
+        self.write(uuid, data)
+    }
+
+    /// Write a request the sensor answers with notifications, like a history request. Losing
+    /// one only costs a retry, so [`ConnectedSensor`] can be told to skip the acknowledgement;
+    /// by default, it is a [`SensorTransport::write`].
+    fn write_request(
+        &self,
+        uuid: Uuid,
+        data: &[u8],
+    ) -> impl Future<Output = Result<(), Aranet4Error>> + Send {
+        self.write(uuid, data)
+    }
+
+    fn subscribe(&self, uuid: Uuid) -> impl Future<Output = Result<(), Aranet4Error>> + Send;
+
+    fn unsubscribe(&self, uuid: Uuid) -> impl Future<Output = Result<(), Aranet4Error>> + Send;
+
+    fn notifications(
+        &self,
+    ) -> impl Future<Output = Result<NotificationStream, Aranet4Error>> + Send;
+}
+
+/// A connection to a sensor that is closed again when the guard goes away, so that the sensor
+/// is free for other centrals, like the phone app, between operations. [`Connection::close`]
+/// releases the connection, which normally disconnects and waits for it; a guard dropped without
+/// closing, e.g. because a timeout cancelled the operation, leaves the disconnect to a background
+/// task.
+pub struct Connection<'a, S: SensorTransport> {
+    sensor: &'a S,
+    open: bool,
+}
+
+impl<'a, S: SensorTransport> Connection<'a, S> {
+    pub async fn open(sensor: &'a S) -> Result<Self, Aranet4Error> {
+        // Created before connecting, so that a failed or cancelled connect is cleaned up too
+        let connection = Connection { sensor, open: true };
+        debug!("Connecting");
+        sensor.connect().await?;
+        Ok(connection)
+    }
+
+    pub async fn close(mut self) -> Result<(), Aranet4Error> {
+        self.open = false;
+        debug!("Releasing connection");
+        self.sensor.release().await
+    }
+}
+
+impl<S: SensorTransport> Drop for Connection<'_, S> {
+    fn drop(&mut self) {
+        if self.open {
+            debug!("Disconnecting in the background");
+            self.sensor.disconnect_in_background();
+        }
+    }
+}
+
+/// A sensor whose connection can be kept open across operations. Once [held](Self::hold), the
+/// first operation connects and discovers services, and the ones after it reuse that connection
+/// and btleplug's map of the discovered characteristics instead of reconnecting and discovering
+/// them again, until [`ConnectedSensor::close`]. A dropped link is still reconnected: retries and
+/// cancelled operations disconnect for real.
+///
+/// Not held, it connects and disconnects around every operation like the sensor it wraps.
+#[derive(Debug)]
+pub struct ConnectedSensor<S = Peripheral> {
+    sensor: S,
+    held: AtomicBool,
+    connected: AtomicBool,
+    requests_without_response: AtomicBool,
+}
+
+impl<S: SensorTransport> ConnectedSensor<S> {
+    pub fn new(sensor: S) -> Self {
+        ConnectedSensor {
+            sensor,
+            held: AtomicBool::new(false),
+            connected: AtomicBool::new(false),
+            requests_without_response: AtomicBool::new(false),
+        }
+    }
+
+    pub fn sensor(&self) -> &S {
+        &self.sensor
+    }
+
+    /// Keep the connection open after each operation from now on.
+    pub fn hold(&self) {
+        self.held.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.held.load(Ordering::SeqCst)
+    }
+
+    /// Send [requests](SensorTransport::write_request) without waiting for the acknowledgement,
+    /// which saves a round trip per history metric. A request the sensor then misses fails the
+    /// download with a timeout, to be retried.
+    pub fn set_requests_without_response(&self, without_response: bool) {
+        self.requests_without_response
+            .store(without_response, Ordering::SeqCst);
+    }
+
+    /// Stop holding the connection, and disconnect if connected.
+    pub async fn close(&self) -> Result<(), Aranet4Error> {
+        self.held.store(false, Ordering::SeqCst);
+        if self.connected.load(Ordering::SeqCst) {
+            self.disconnect().await?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: SensorTransport> SensorTransport for ConnectedSensor<S> {
+    async fn connect(&self) -> Result<(), Aranet4Error> {
+        if self.connected.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.sensor.connect().await?;
+        self.connected.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<(), Aranet4Error> {
+        self.connected.store(false, Ordering::SeqCst);
+        self.sensor.disconnect().await
+    }
+
+    async fn release(&self) -> Result<(), Aranet4Error> {
+        if self.is_held() {
+            return Ok(());
+        }
+        self.disconnect().await
+    }
+
+    fn disconnect_in_background(&self) {
+        self.connected.store(false, Ordering::SeqCst);
+        self.sensor.disconnect_in_background();
+    }
+
+    async fn local_name(&self) -> Option<String> {
+        self.sensor.local_name().await
+    }
+
+    async fn read(&self, uuid: Uuid) -> Result<Vec<u8>, Aranet4Error> {
+        self.sensor.read(uuid).await
+    }
+
+    async fn write(&self, uuid: Uuid, data: &[u8]) -> Result<(), Aranet4Error> {
+        self.sensor.write(uuid, data).await
+    }
+
+    async fn write_without_response(&self, uuid: Uuid, data: &[u8]) -> Result<(), Aranet4Error> {
+        self.sensor.write_without_response(uuid, data).await
+    }
+
+    async fn write_request(&self, uuid: Uuid, data: &[u8]) -> Result<(), Aranet4Error> {
+        if self.requests_without_response.load(Ordering::SeqCst) {
+            self.sensor.write_without_response(uuid, data).await
+        } else {
+            self.sensor.write(uuid, data).await
+        }
+    }
+
+    async fn subscribe(&self, uuid: Uuid) -> Result<(), Aranet4Error> {
+        self.sensor.subscribe(uuid).await
+    }
+
+    async fn unsubscribe(&self, uuid: Uuid) -> Result<(), Aranet4Error> {
+        self.sensor.unsubscribe(uuid).await
+    }
+
+    async fn notifications(&self) -> Result<NotificationStream, Aranet4Error> {
+        self.sensor.notifications().await
+    }
+}
+
+/// The sensor behind an [`crate::Aranet4Client`]: a Bluetooth peripheral, or with `--simulate`,
+/// the built-in [`MockAranet4::simulated`] for trying arachiver out and testing it without one.
+#[derive(Debug)]
+pub enum SensorBackend {
+    Bluetooth(Peripheral),
+    Simulated(Box<MockAranet4>),
+}
+
+impl SensorTransport for SensorBackend {
+    async fn connect(&self) -> Result<(), Aranet4Error> {
+        match self {
+            SensorBackend::Bluetooth(peripheral) => SensorTransport::connect(peripheral).await,
+            SensorBackend::Simulated(sensor) => sensor.connect().await,
+        }
+    }
+
+    async fn disconnect(&self) -> Result<(), Aranet4Error> {
+        match self {
+            SensorBackend::Bluetooth(peripheral) => SensorTransport::disconnect(peripheral).await,
+            SensorBackend::Simulated(sensor) => sensor.disconnect().await,
+        }
+    }
+
+    fn disconnect_in_background(&self) {
+        match self {
+            SensorBackend::Bluetooth(peripheral) => peripheral.disconnect_in_background(),
+            SensorBackend::Simulated(sensor) => sensor.disconnect_in_background(),
+        }
+    }
+
+    async fn local_name(&self) -> Option<String> {
+        match self {
+            SensorBackend::Bluetooth(peripheral) => SensorTransport::local_name(peripheral).await,
+            SensorBackend::Simulated(sensor) => sensor.local_name().await,
+        }
+    }
+
+    async fn read(&self, uuid: Uuid) -> Result<Vec<u8>, Aranet4Error> {
+        match self {
+            SensorBackend::Bluetooth(peripheral) => SensorTransport::read(peripheral, uuid).await,
+            SensorBackend::Simulated(sensor) => sensor.read(uuid).await,
+        }
+    }
+
+    async fn write(&self, uuid: Uuid, data: &[u8]) -> Result<(), Aranet4Error> {
+        match self {
+            SensorBackend::Bluetooth(peripheral) => {
+                SensorTransport::write(peripheral, uuid, data).await
+            }
+            SensorBackend::Simulated(sensor) => sensor.write(uuid, data).await,
+        }
+    }
+
+    async fn write_without_response(&self, uuid: Uuid, data: &[u8]) -> Result<(), Aranet4Error> {
+        match self {
+            SensorBackend::Bluetooth(peripheral) => {
+                SensorTransport::write_without_response(peripheral, uuid, data).await
+            }
+            SensorBackend::Simulated(sensor) => sensor.write_without_response(uuid, data).await,
+        }
+    }
+
+    async fn subscribe(&self, uuid: Uuid) -> Result<(), Aranet4Error> {
+        match self {
+            SensorBackend::Bluetooth(peripheral) => {
+                SensorTransport::subscribe(peripheral, uuid).await
+            }
+            SensorBackend::Simulated(sensor) => sensor.subscribe(uuid).await,
+        }
+    }
+
+    async fn unsubscribe(&self, uuid: Uuid) -> Result<(), Aranet4Error> {
+        match self {
+            SensorBackend::Bluetooth(peripheral) => {
+                SensorTransport::unsubscribe(peripheral, uuid).await
+            }
+            SensorBackend::Simulated(sensor) => sensor.unsubscribe(uuid).await,
+        }
+    }
+
+    async fn notifications(&self) -> Result<NotificationStream, Aranet4Error> {
+        match self {
+            SensorBackend::Bluetooth(peripheral) => {
+                SensorTransport::notifications(peripheral).await
+            }
+            SensorBackend::Simulated(sensor) => sensor.notifications().await,
+        }
+    }
+}
+
+fn get_characteristic(
+    sensor: &Peripheral,
+    char_uuid: Uuid,
+) -> Result<Characteristic, Aranet4Error> {
+    let chars = sensor.characteristics();
+    chars
+        .iter()
+        .find(|c| c.uuid == char_uuid)
+        .cloned()
+        .ok_or(Aranet4Error::CharacteristicNotFound)
+}
+
+impl SensorTransport for Peripheral {
+    async fn connect(&self) -> Result<(), Aranet4Error> {
+        // btleplug has no say in the ATT MTU: BlueZ, CoreBluetooth and WinRT all exchange the
+        // largest both sides support while connecting, which sets the history packet size
+        btleplug::api::Peripheral::connect(self).await?;
+        self.discover_services().await?;
+        Ok(())
+    }
+
+    async fn disconnect(&self) -> Result<(), Aranet4Error> {
+        Ok(btleplug::api::Peripheral::disconnect(self).await?)
+    }
+
+    fn disconnect_in_background(&self) {
+        // Outside a runtime there is nothing to disconnect with; the OS drops the link on exit
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let peripheral = self.clone();
+            runtime.spawn(async move {
+                let _ = btleplug::api::Peripheral::disconnect(&peripheral).await;
+            });
+        }
+    }
+
+    async fn local_name(&self) -> Option<String> {
+        self.properties().await.ok().flatten()?.local_name
+    }
+
+    async fn read(&self, uuid: Uuid) -> Result<Vec<u8>, Aranet4Error> {
+        let char = get_characteristic(self, uuid)?;
+        Ok(btleplug::api::Peripheral::read(self, &char).await?)
+    }
+
+    async fn write(&self, uuid: Uuid, data: &[u8]) -> Result<(), Aranet4Error> {
+        let char = get_characteristic(self, uuid)?;
+        Ok(btleplug::api::Peripheral::write(self, &char, data, WriteType::WithResponse).await?)
+    }
+
+    async fn write_without_response(&self, uuid: Uuid, data: &[u8]) -> Result<(), Aranet4Error> {
+        let char = get_characteristic(self, uuid)?;
+        let write_type = if char
+            .properties
+            .contains(CharPropFlags::WRITE_WITHOUT_RESPONSE)
+        {
+            WriteType::WithoutResponse
+        } else {
+            WriteType::WithResponse
+        };
+        Ok(btleplug::api::Peripheral::write(self, &char, data, write_type).await?)
+    }
+
+    async fn subscribe(&self, uuid: Uuid) -> Result<(), Aranet4Error> {
+        let char = get_characteristic(self, uuid)?;
+        if !char.properties.contains(CharPropFlags::NOTIFY) {
+            return Err(Aranet4Error::InvalidResponse(
+                "No NOTIFY flag on subscribe characteristic!".to_string(),
+            ));
+        }
+        Ok(btleplug::api::Peripheral::subscribe(self, &char).await?)
+    }
+
+    async fn unsubscribe(&self, uuid: Uuid) -> Result<(), Aranet4Error> {
+        let char = get_characteristic(self, uuid)?;
+        Ok(btleplug::api::Peripheral::unsubscribe(self, &char).await?)
+    }
+
+    async fn notifications(&self) -> Result<NotificationStream, Aranet4Error> {
+        Ok(btleplug::api::Peripheral::notifications(self).await?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::device::{get_current_sensor_data, get_history, set_update_interval, DeviceInfo};
+    use crate::mock::MockAranet4;
+    use crate::transport::{ConnectedSensor, Connection};
+
+    #[tokio::test]
+    async fn test_connection_disconnects_when_dropped() {
+        let sensor = MockAranet4::new("Aranet4 1BA27");
+        let connection = Connection::open(&sensor).await.unwrap();
+        assert!(sensor.is_connected());
+        drop(connection);
+        assert!(!sensor.is_connected());
+
+        let connection = Connection::open(&sensor).await.unwrap();
+        connection.close().await.unwrap();
+        assert!(!sensor.is_connected());
+    }
+
+    #[tokio::test]
+    async fn test_connected_sensor_connects_once_while_held() {
+        let sensor = ConnectedSensor::new(MockAranet4::new("Aranet4 1BA27"));
+        get_current_sensor_data(&sensor).await.unwrap();
+        DeviceInfo::read_from_sensor(&sensor).await.unwrap();
+        assert_eq!(sensor.sensor().connect_count(), 2);
+        assert!(!sensor.sensor().is_connected());
+
+        sensor.hold();
+        get_current_sensor_data(&sensor).await.unwrap();
+        DeviceInfo::read_from_sensor(&sensor).await.unwrap();
+        get_history(&sensor).await.unwrap();
+        assert_eq!(sensor.sensor().connect_count(), 3);
+        assert!(sensor.sensor().is_connected());
+
+        // A cancelled operation drops the link, and the next one reconnects
+        drop(Connection::open(&sensor).await.unwrap());
+        assert!(!sensor.sensor().is_connected());
+        get_current_sensor_data(&sensor).await.unwrap();
+        assert_eq!(sensor.sensor().connect_count(), 4);
+
+        sensor.close().await.unwrap();
+        assert!(!sensor.sensor().is_connected());
+    }
+
+    #[tokio::test]
+    async fn test_requests_without_response() {
+        let sensor = ConnectedSensor::new(MockAranet4::new("Aranet4 1BA27").with_history(
+            &[400; 3],
+            &[40; 3],
+            &[10_000; 3],
+            &[500; 3],
+        ));
+        get_history(&sensor).await.unwrap();
+        assert_eq!(sensor.sensor().unacknowledged_write_count(), 0);
+
+        sensor.set_requests_without_response(true);
+        let history = get_history(&sensor).await.unwrap();
+        assert_eq!(history.co2.values, vec![500; 3]);
+        assert_eq!(sensor.sensor().unacknowledged_write_count(), 4);
+        // Other writes still wait for the sensor
+        set_update_interval(&sensor, 5).await.unwrap();
+        assert_eq!(sensor.sensor().unacknowledged_write_count(), 4);
+    }
+}
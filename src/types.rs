@@ -1,79 +1,468 @@
 use btleplug::Error as BtleplugError;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::time::Duration;
 use std::vec::Vec;
 use thiserror::Error;
 use unicode_segmentation::UnicodeSegmentation;
 
+use crate::device::HistoryTime;
+use crate::exit_status;
+use crate::quantity::{Celsius, Millibar, Ppm, RelativeHumidity, INHG_PER_HPA};
+
 #[derive(Error, Debug)]
 pub enum Aranet4Error {
     #[error("There was a Bluetooth error")]
     Btleplug {
-        #[from]
+        #[source]
         source: BtleplugError,
     },
+    #[error("The sensor needs to be paired first ({0}); run the pair subcommand and enter the PIN shown on the sensor's screen")]
+    PairingRequired(String),
     #[error("Aranet returned a response that didn't match our expectations")]
     InvalidResponse(String),
     #[error("Did not find requested characteristic")]
     CharacteristicNotFound,
+    /// The history metrics came out with different numbers of readings, which can't be lined
+    /// up by timestamp. Metrics that weren't requested have none.
+    #[error("History metrics have different numbers of readings: {temperature} temperature, {humidity} humidity, {pressure} pressure and {co2} CO₂")]
+    InconsistentHistoryLengths {
+        temperature: usize,
+        humidity: usize,
+        pressure: usize,
+        co2: usize,
+    },
+    #[error("Unsupported setting: {0}")]
+    UnsupportedSetting(String),
+    #[error("Timed out {0}")]
+    Timeout(String),
+    #[error("{0}")]
+    NoAdapter(String),
+    #[error("{0}")]
+    DeviceNotFound(String),
 }
 
-#[derive(Debug)]
+impl Aranet4Error {
+    /// Whether retrying the operation might succeed, as opposed to e.g. a rejected setting.
+    pub fn is_retryable(&self) -> bool {
+        !matches!(
+            self,
+            Aranet4Error::UnsupportedSetting(_) | Aranet4Error::PairingRequired(_)
+        )
+    }
+
+    /// The `arachiver` exit status for this error; see [`crate::exit_status`].
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Aranet4Error::NoAdapter(_) => exit_status::NO_ADAPTER,
+            Aranet4Error::DeviceNotFound(_) => exit_status::DEVICE_NOT_FOUND,
+            Aranet4Error::UnsupportedSetting(_) => exit_status::FAILURE,
+            Aranet4Error::PairingRequired(_) => exit_status::PAIRING_REQUIRED,
+            Aranet4Error::Btleplug { .. }
+            | Aranet4Error::InvalidResponse(_)
+            | Aranet4Error::CharacteristicNotFound
+            | Aranet4Error::InconsistentHistoryLengths { .. }
+            | Aranet4Error::Timeout(_) => exit_status::BLUETOOTH,
+        }
+    }
+}
+
+impl From<BtleplugError> for Aranet4Error {
+    fn from(source: BtleplugError) -> Self {
+        if is_authentication_error(&source) {
+            Aranet4Error::PairingRequired(source.to_string())
+        } else {
+            Aranet4Error::Btleplug { source }
+        }
+    }
+}
+
+/// Whether the platform refused an operation because the link isn't paired (bonded). btleplug
+/// passes these through as text: BlueZ reports e.g. "Insufficient Authentication" or
+/// `org.bluez.Error.NotPermitted`, CoreBluetooth "Authentication is insufficient".
+fn is_authentication_error(error: &BtleplugError) -> bool {
+    let message = error.to_string().to_lowercase();
+    [
+        "authentication",
+        "encryption",
+        "notpermitted",
+        "not permitted",
+    ]
+    .iter()
+    .any(|text| message.contains(text))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SensorData<Storage, const SENSORTYPE: u8> {
     pub values: Vec<Storage>,
 }
 
+/// Units for displayed and archived values. The sensor itself always measures in metric.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Units {
+    #[default]
+    Metric,
+    /// °F and inHg; humidity and CO₂ are the same either way.
+    Imperial,
+}
+
+impl Units {
+    pub fn from_name(name: &str) -> Option<Units> {
+        match name {
+            "metric" => Some(Units::Metric),
+            "imperial" => Some(Units::Imperial),
+            _ => None,
+        }
+    }
+}
+
 pub trait Metadata {
+    const KIND: MetricKind;
     const DISPLAY_MULTIPLIER: f32;
     const DISPLAY_PRECISION: usize;
-    fn label(&self) -> &'static str;
+    const LABEL: &'static str;
+    fn label(&self) -> &'static str {
+        Self::LABEL
+    }
+
+    /// Like [`Metadata::LABEL`], for values converted to `units`.
+    fn unit_label(_units: Units) -> &'static str {
+        Self::LABEL
+    }
+
+    /// Decimal places for values converted to `units`.
+    fn precision(_units: Units) -> usize {
+        Self::DISPLAY_PRECISION
+    }
+
+    /// Convert a metric value, i.e. one already scaled by [`Metadata::DISPLAY_MULTIPLIER`],
+    /// to `units`.
+    fn convert(value: f64, _units: Units) -> f64 {
+        value
+    }
+
+    /// The inverse of [`Metadata::convert`]: a value in `units` back in metric units.
+    fn to_metric(value: f64, _units: Units) -> f64 {
+        value
+    }
+}
+
+/// Split a label like `Temperature (°C)` into the name and the unit.
+pub(crate) fn split_label(label: &str) -> (&str, &str) {
+    match label.split_once(" (") {
+        Some((name, unit)) => (name, unit.trim_end_matches(')')),
+        None => (label, ""),
+    }
 }
 
 const TEMPERATURE: u8 = 1;
 const HUMIDITY: u8 = 2;
 const PRESSURE: u8 = 3;
 const CO2: u8 = 4;
+const HUMIDITY2: u8 = 5;
+const RADON: u8 = 10;
 
 pub type TemperatureData = SensorData<u16, TEMPERATURE>;
 pub type HumidityData = SensorData<u8, HUMIDITY>;
 pub type PressureData = SensorData<u16, PRESSURE>;
 pub type CO2Data = SensorData<u16, CO2>;
+/// Humidity as the Aranet2 and Aranet Radon Plus log it, in 1/10 %.
+pub type Humidity2Data = SensorData<u16, HUMIDITY2>;
+pub type RadonData = SensorData<u32, RADON>;
 
 impl Metadata for TemperatureData {
+    const KIND: MetricKind = MetricKind::Temperature;
     const DISPLAY_MULTIPLIER: f32 = 0.05;
     const DISPLAY_PRECISION: usize = 2;
-    fn label(&self) -> &'static str {
-        "Temperature (°C)"
+    const LABEL: &'static str = "Temperature (°C)";
+
+    fn unit_label(units: Units) -> &'static str {
+        match units {
+            Units::Metric => Self::LABEL,
+            Units::Imperial => "Temperature (°F)",
+        }
+    }
+
+    fn convert(value: f64, units: Units) -> f64 {
+        match units {
+            Units::Metric => value,
+            Units::Imperial => value * 9.0 / 5.0 + 32.0,
+        }
+    }
+
+    fn to_metric(value: f64, units: Units) -> f64 {
+        match units {
+            Units::Metric => value,
+            Units::Imperial => (value - 32.0) * 5.0 / 9.0,
+        }
     }
 }
 
 impl Metadata for HumidityData {
+    const KIND: MetricKind = MetricKind::Humidity;
     const DISPLAY_MULTIPLIER: f32 = 1.0;
     const DISPLAY_PRECISION: usize = 0;
-    fn label(&self) -> &'static str {
-        "Humidity (%)"
-    }
+    const LABEL: &'static str = "Humidity (%)";
 }
 
 impl Metadata for PressureData {
+    const KIND: MetricKind = MetricKind::Pressure;
     const DISPLAY_MULTIPLIER: f32 = 0.1;
     const DISPLAY_PRECISION: usize = 1;
-    fn label(&self) -> &'static str {
-        "Pressure (hPa)"
+    const LABEL: &'static str = "Pressure (hPa)";
+
+    fn unit_label(units: Units) -> &'static str {
+        match units {
+            Units::Metric => Self::LABEL,
+            Units::Imperial => "Pressure (inHg)",
+        }
+    }
+
+    fn precision(units: Units) -> usize {
+        match units {
+            Units::Metric => Self::DISPLAY_PRECISION,
+            Units::Imperial => 2,
+        }
+    }
+
+    fn convert(value: f64, units: Units) -> f64 {
+        match units {
+            Units::Metric => value,
+            Units::Imperial => value * INHG_PER_HPA,
+        }
+    }
+
+    fn to_metric(value: f64, units: Units) -> f64 {
+        match units {
+            Units::Metric => value,
+            Units::Imperial => value / INHG_PER_HPA,
+        }
+    }
+}
+
+impl TemperatureData {
+    pub fn get(&self, i: usize) -> Celsius {
+        Celsius(self.get_f32_value(i))
+    }
+}
+
+impl HumidityData {
+    pub fn get(&self, i: usize) -> RelativeHumidity {
+        RelativeHumidity(self.values[i])
+    }
+}
+
+impl PressureData {
+    pub fn get(&self, i: usize) -> Millibar {
+        Millibar(self.get_f32_value(i))
+    }
+}
+
+impl CO2Data {
+    pub fn get(&self, i: usize) -> Ppm {
+        Ppm(self.values[i])
     }
 }
 
 impl Metadata for CO2Data {
+    const KIND: MetricKind = MetricKind::Co2;
     const DISPLAY_MULTIPLIER: f32 = 1.0;
     const DISPLAY_PRECISION: usize = 0;
-    fn label(&self) -> &'static str {
-        "CO₂ (ppm)"
+    const LABEL: &'static str = "CO₂ (ppm)";
+}
+
+impl Metadata for Humidity2Data {
+    const KIND: MetricKind = MetricKind::Humidity2;
+    const DISPLAY_MULTIPLIER: f32 = 0.1;
+    const DISPLAY_PRECISION: usize = 1;
+    const LABEL: &'static str = "Humidity (%)";
+}
+
+impl Metadata for RadonData {
+    const KIND: MetricKind = MetricKind::Radon;
+    const DISPLAY_MULTIPLIER: f32 = 1.0;
+    const DISPLAY_PRECISION: usize = 0;
+    const LABEL: &'static str = "Radon (Bq/m³)";
+}
+
+/// How the sensor stores the raw values of a metric in its history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricStorage {
+    U8,
+    U16,
+    U32,
+}
+
+impl MetricStorage {
+    /// Bytes per value in a history packet.
+    pub fn size(self) -> usize {
+        match self {
+            MetricStorage::U8 => 1,
+            MetricStorage::U16 => 2,
+            MetricStorage::U32 => 4,
+        }
+    }
+}
+
+/// A metric an Aranet sensor logs, for choosing metrics at runtime. The typed [`SensorData`]
+/// aliases are what the history decoder works with; this is the same information without the
+/// sensor's codes in the type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MetricKind {
+    Temperature,
+    Humidity,
+    Pressure,
+    Co2,
+    /// Humidity as the Aranet2 and Aranet Radon Plus log it, in 1/10 %.
+    Humidity2,
+    Radon,
+}
+
+impl MetricKind {
+    pub const ALL: [MetricKind; 6] = [
+        MetricKind::Temperature,
+        MetricKind::Humidity,
+        MetricKind::Pressure,
+        MetricKind::Co2,
+        MetricKind::Humidity2,
+        MetricKind::Radon,
+    ];
+
+    /// The sensor's code for this kind of history.
+    pub(crate) fn code(self) -> u8 {
+        match self {
+            MetricKind::Temperature => TEMPERATURE,
+            MetricKind::Humidity => HUMIDITY,
+            MetricKind::Pressure => PRESSURE,
+            MetricKind::Co2 => CO2,
+            MetricKind::Humidity2 => HUMIDITY2,
+            MetricKind::Radon => RADON,
+        }
+    }
+
+    /// The name of the metric in archives, e.g. the Parquet column. Both kinds of humidity are
+    /// `humidity`.
+    pub fn name(self) -> &'static str {
+        match self {
+            MetricKind::Temperature => "temperature",
+            MetricKind::Humidity | MetricKind::Humidity2 => "humidity",
+            MetricKind::Pressure => "pressure",
+            MetricKind::Co2 => "co2",
+            MetricKind::Radon => "radon",
+        }
+    }
+
+    /// The label with the metric unit, e.g. `Temperature (°C)`.
+    pub fn label(self) -> &'static str {
+        self.unit_label(Units::Metric)
+    }
+
+    pub fn unit_label(self, units: Units) -> &'static str {
+        match self {
+            MetricKind::Temperature => TemperatureData::unit_label(units),
+            MetricKind::Humidity => HumidityData::unit_label(units),
+            MetricKind::Pressure => PressureData::unit_label(units),
+            MetricKind::Co2 => CO2Data::unit_label(units),
+            MetricKind::Humidity2 => Humidity2Data::unit_label(units),
+            MetricKind::Radon => RadonData::unit_label(units),
+        }
+    }
+
+    /// What a raw value is multiplied by to get the value in metric units.
+    pub fn scale(self) -> f64 {
+        f64::from(match self {
+            MetricKind::Temperature => TemperatureData::DISPLAY_MULTIPLIER,
+            MetricKind::Humidity => HumidityData::DISPLAY_MULTIPLIER,
+            MetricKind::Pressure => PressureData::DISPLAY_MULTIPLIER,
+            MetricKind::Co2 => CO2Data::DISPLAY_MULTIPLIER,
+            MetricKind::Humidity2 => Humidity2Data::DISPLAY_MULTIPLIER,
+            MetricKind::Radon => RadonData::DISPLAY_MULTIPLIER,
+        })
+    }
+
+    /// Decimal places for values in `units`.
+    pub fn precision(self, units: Units) -> usize {
+        match self {
+            MetricKind::Temperature => TemperatureData::precision(units),
+            MetricKind::Humidity => HumidityData::precision(units),
+            MetricKind::Pressure => PressureData::precision(units),
+            MetricKind::Co2 => CO2Data::precision(units),
+            MetricKind::Humidity2 => Humidity2Data::precision(units),
+            MetricKind::Radon => RadonData::precision(units),
+        }
+    }
+
+    /// Convert a value in metric units to `units`.
+    pub fn convert(self, value: f64, units: Units) -> f64 {
+        match self {
+            MetricKind::Temperature => TemperatureData::convert(value, units),
+            MetricKind::Pressure => PressureData::convert(value, units),
+            _ => value,
+        }
+    }
+
+    pub fn storage(self) -> MetricStorage {
+        match self {
+            MetricKind::Humidity => MetricStorage::U8,
+            MetricKind::Temperature
+            | MetricKind::Pressure
+            | MetricKind::Co2
+            | MetricKind::Humidity2 => MetricStorage::U16,
+            MetricKind::Radon => MetricStorage::U32,
+        }
+    }
+}
+
+/// The raw values of one metric of a history, with its kind known at runtime rather than in the
+/// type as with [`SensorData`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistorySeries {
+    pub kind: MetricKind,
+    pub values: Vec<u32>,
+}
+
+impl HistorySeries {
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// The `i`th value in metric units.
+    pub fn get(&self, i: usize) -> f64 {
+        f64::from(self.values[i]) * self.kind.scale()
+    }
+
+    pub fn get_in(&self, i: usize, units: Units) -> f64 {
+        self.kind.convert(self.get(i), units)
+    }
+
+    pub fn get_value_string_in(&self, i: usize, units: Units) -> String {
+        format!("{:.*}", self.kind.precision(units), self.get_in(i, units))
+    }
+}
+
+impl<Storage, const SENSORTYPE: u8> From<&SensorData<Storage, SENSORTYPE>> for HistorySeries
+where
+    u32: From<Storage>,
+    SensorData<Storage, SENSORTYPE>: Metadata,
+    Storage: Copy,
+{
+    fn from(data: &SensorData<Storage, SENSORTYPE>) -> Self {
+        HistorySeries {
+            kind: SensorData::<Storage, SENSORTYPE>::KIND,
+            values: data.values.iter().map(|&value| u32::from(value)).collect(),
+        }
     }
 }
 
 impl<const T: u8> TryFrom<&[u8]> for SensorData<u16, T> {
     type Error = Aranet4Error;
     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
-        if bytes.len() % 2 == 0 {
+        if bytes.len().is_multiple_of(2) {
             Ok(Self {
                 values: bytes
                     .chunks_exact(2)
@@ -88,6 +477,24 @@ impl<const T: u8> TryFrom<&[u8]> for SensorData<u16, T> {
     }
 }
 
+impl<const T: u8> TryFrom<&[u8]> for SensorData<u32, T> {
+    type Error = Aranet4Error;
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        if bytes.len().is_multiple_of(4) {
+            Ok(Self {
+                values: bytes
+                    .chunks_exact(4)
+                    .map(|x| u32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+                    .collect(),
+            })
+        } else {
+            Err(Aranet4Error::InvalidResponse(
+                "expected a multiple of 4 bytes".to_string(),
+            ))
+        }
+    }
+}
+
 impl<const T: u8> TryFrom<&[u8]> for SensorData<u8, T> {
     type Error = Aranet4Error;
     fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
@@ -104,16 +511,155 @@ where
     Storage: Copy,
 {
     pub fn get_value_string(&self, i: usize) -> String {
+        self.get_value_string_in(i, Units::Metric)
+    }
+
+    pub fn get_value_string_in(&self, i: usize, units: Units) -> String {
         format!(
             "{:.*}",
-            Self::DISPLAY_PRECISION,
-            f32::from(self.values[i]) * Self::DISPLAY_MULTIPLIER
+            Self::precision(units),
+            Self::convert(f64::from(self.get_f32_value(i)), units)
         )
     }
 
     pub fn get_f32_value(&self, i: usize) -> f32 {
         f32::from(self.values[i]) * Self::DISPLAY_MULTIPLIER
     }
+
+    pub fn get_f32_value_in(&self, i: usize, units: Units) -> f32 {
+        Self::convert(f64::from(self.get_f32_value(i)), units) as f32
+    }
+
+    /// The scaled value rounded to the display precision, e.g. for JSON output where the
+    /// shortest representation of an `f32` product would print as 20.649999618530273.
+    pub fn get_display_value(&self, i: usize) -> f64 {
+        display_value::<Self>(f64::from(f32::from(self.values[i])))
+    }
+}
+
+/// `raw` scaled by the multiplier of `M` and rounded to its display precision.
+pub(crate) fn display_value<M: Metadata>(raw: f64) -> f64 {
+    round_to_precision::<M>(raw * M::DISPLAY_MULTIPLIER as f64)
+}
+
+/// A metric `value` rounded to the display precision of `M`.
+pub(crate) fn round_to_precision<M: Metadata>(value: f64) -> f64 {
+    let scale = 10f64.powi(M::DISPLAY_PRECISION as i32);
+    (value * scale).round() / scale
+}
+
+/// One reading of the history in metric units, rounded to the display precision. Sinks that
+/// write other units convert with [`HistoryRecord::temperature_in`] and
+/// [`HistoryRecord::pressure_in`]. Serialized with the same field names as the Parquet
+/// columns.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct HistoryRecord {
+    /// UNIX time in seconds.
+    pub timestamp: i64,
+    #[serde(rename = "temperature")]
+    pub temperature_c: f64,
+    #[serde(rename = "humidity")]
+    pub humidity_pct: u8,
+    #[serde(rename = "pressure")]
+    pub pressure_mbar: f64,
+    #[serde(rename = "co2")]
+    pub co2_ppm: u16,
+}
+
+impl HistoryRecord {
+    pub fn temperature_in(&self, units: Units) -> f64 {
+        TemperatureData::convert(self.temperature_c, units)
+    }
+
+    pub fn pressure_in(&self, units: Units) -> f64 {
+        PressureData::convert(self.pressure_mbar, units)
+    }
+
+    pub fn derived(&self) -> DerivedHumidity {
+        DerivedHumidity::new(
+            Celsius(self.temperature_c as f32),
+            RelativeHumidity(self.humidity_pct),
+        )
+    }
+}
+
+/// Quantities derived from a reading's temperature and relative humidity, for watching out for
+/// condensation and mould, in metric units rounded to 0.1. Serialized as extra fields of the
+/// reading they were derived from.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct DerivedHumidity {
+    /// Dew point in °C; `None` in perfectly dry air.
+    pub dew_point: Option<f64>,
+    /// Water vapour in g/m³.
+    pub absolute_humidity: f64,
+}
+
+impl DerivedHumidity {
+    pub const DEW_POINT_NAME: &'static str = "dew_point";
+    pub const ABSOLUTE_HUMIDITY_NAME: &'static str = "absolute_humidity";
+    pub const ABSOLUTE_HUMIDITY_LABEL: &'static str = "Absolute humidity (g/m³)";
+
+    pub fn new(temperature: Celsius, humidity: RelativeHumidity) -> Self {
+        let round = |value: f32| (f64::from(value) * 10.0).round() / 10.0;
+        DerivedHumidity {
+            dew_point: temperature
+                .dew_point(humidity)
+                .map(|dew_point| round(dew_point.0)),
+            absolute_humidity: round(temperature.absolute_humidity(humidity).0),
+        }
+    }
+
+    /// The dew point converted to `units`, rounded to 0.1.
+    pub fn dew_point_in(&self, units: Units) -> Option<f64> {
+        self.dew_point
+            .map(|dew_point| (TemperatureData::convert(dew_point, units) * 10.0).round() / 10.0)
+    }
+
+    pub fn dew_point_label(units: Units) -> &'static str {
+        match units {
+            Units::Metric => "Dew point (°C)",
+            Units::Imperial => "Dew point (°F)",
+        }
+    }
+
+    /// The lines `readout --derived` adds to the readings.
+    pub fn display_in(&self, units: Units) -> String {
+        let (_, unit) = split_label(Self::dew_point_label(units));
+        let dew_point = match self.dew_point_in(units) {
+            Some(dew_point) => format!("{:.1}{}", dew_point, unit),
+            None => "none".to_string(),
+        };
+        format!(
+            "Dew point: {}\nAbsolute humidity: {:.1} g/m³\n",
+            dew_point, self.absolute_humidity
+        )
+    }
+}
+
+/// The downloaded history as one record per sample, oldest first. Metrics that weren't
+/// downloaded, and so have no values, come out as zero.
+pub fn history_records<'a>(
+    history_time: &'a HistoryTime,
+    temperature: &'a TemperatureData,
+    humidity: &'a HumidityData,
+    pressure: &'a PressureData,
+    co2: &'a CO2Data,
+) -> impl Iterator<Item = color_eyre::Result<HistoryRecord>> + 'a {
+    history_time.timestamps().enumerate().map(move |(i, time)| {
+        Ok(HistoryRecord {
+            timestamp: time.timestamp(),
+            temperature_c: temperature
+                .values
+                .get(i)
+                .map_or(0.0, |&raw| display_value::<TemperatureData>(f64::from(raw))),
+            humidity_pct: humidity.values.get(i).copied().unwrap_or(0),
+            pressure_mbar: pressure
+                .values
+                .get(i)
+                .map_or(0.0, |&raw| display_value::<PressureData>(f64::from(raw))),
+            co2_ppm: co2.values.get(i).copied().unwrap_or(0),
+        })
+    })
 }
 impl<Storage, const SENSORTYPE: u8> fmt::Display for SensorData<Storage, SENSORTYPE>
 where
@@ -152,16 +698,147 @@ where
     }
 }
 
-#[derive(Debug)]
+/// Colour of the CO₂ indicator, by the thresholds configured in the Aranet Home app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Co2Indicator {
+    /// Not reported, e.g. while the sensor is starting up.
+    None,
+    Green,
+    Yellow,
+    Red,
+}
+
+/// The CO₂ levels in ppm at or above which the sensor's indicator turns yellow and red, as
+/// configured in the Aranet Home app, so that other displays can colour readings the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Co2IndicatorThresholds {
+    pub yellow: u16,
+    pub red: u16,
+}
+
+impl Co2IndicatorThresholds {
+    /// The factory settings, which firmware without configurable thresholds always uses.
+    pub const DEFAULT: Co2IndicatorThresholds = Co2IndicatorThresholds {
+        yellow: 1000,
+        red: 1400,
+    };
+
+    /// The colour the sensor shows for `co2` ppm.
+    pub fn indicator(&self, co2: u16) -> Co2Indicator {
+        if co2 >= self.red {
+            Co2Indicator::Red
+        } else if co2 >= self.yellow {
+            Co2Indicator::Yellow
+        } else {
+            Co2Indicator::Green
+        }
+    }
+}
+
+impl Default for Co2IndicatorThresholds {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// The thresholds characteristic: the yellow and then the red level, each a little-endian u16.
+impl TryFrom<&[u8]> for Co2IndicatorThresholds {
+    type Error = Aranet4Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let [yellow_lo, yellow_hi, red_lo, red_hi] = bytes else {
+            return Err(Aranet4Error::InvalidResponse(
+                "Expected 4 bytes of CO₂ thresholds".to_string(),
+            ));
+        };
+        let thresholds = Co2IndicatorThresholds {
+            yellow: u16::from_le_bytes([*yellow_lo, *yellow_hi]),
+            red: u16::from_le_bytes([*red_lo, *red_hi]),
+        };
+        if thresholds.yellow > thresholds.red {
+            return Err(Aranet4Error::InvalidResponse(format!(
+                "CO₂ yellow threshold {} ppm is above the red threshold {} ppm",
+                thresholds.yellow, thresholds.red
+            )));
+        }
+        Ok(thresholds)
+    }
+}
+
+impl fmt::Display for Co2IndicatorThresholds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "yellow from {} ppm, red from {} ppm",
+            self.yellow, self.red
+        )
+    }
+}
+
+/// State of a CO₂ sensor calibration started from the Aranet Home app.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CalibrationState {
+    NotActive,
+    EndRequest,
+    InProgress,
+    Error,
+}
+
+/// The status byte of a current reading: bits 0-1 hold the CO₂ indicator and bits 2-3 the
+/// calibration state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatusFlags(pub u8);
+
+impl StatusFlags {
+    pub fn co2_indicator(self) -> Co2Indicator {
+        match self.0 & 0b11 {
+            1 => Co2Indicator::Green,
+            2 => Co2Indicator::Yellow,
+            3 => Co2Indicator::Red,
+            _ => Co2Indicator::None,
+        }
+    }
+
+    pub fn calibration(self) -> CalibrationState {
+        match (self.0 >> 2) & 0b11 {
+            1 => CalibrationState::EndRequest,
+            2 => CalibrationState::InProgress,
+            3 => CalibrationState::Error,
+            _ => CalibrationState::NotActive,
+        }
+    }
+}
+
+impl fmt::Display for StatusFlags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let indicator = match self.co2_indicator() {
+            Co2Indicator::None => "no CO₂ indicator",
+            Co2Indicator::Green => "green",
+            Co2Indicator::Yellow => "yellow",
+            Co2Indicator::Red => "red",
+        };
+        let calibration = match self.calibration() {
+            CalibrationState::NotActive => return write!(f, "{}", indicator),
+            CalibrationState::EndRequest => "ending",
+            CalibrationState::InProgress => "in progress",
+            CalibrationState::Error => "failed",
+        };
+        write!(f, "{}, calibration {}", indicator, calibration)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CurrentSensorMeasurement {
-    co2: u16,
-    temperature: u16,
-    pressure: u16,
-    humidity: u8,
-    battery: u8,
-    status: u8,
-    interval: u16,
-    ago: u16,
+    pub(crate) co2: u16,
+    pub(crate) temperature: u16,
+    pub(crate) pressure: u16,
+    pub(crate) humidity: u8,
+    pub(crate) battery: u8,
+    pub(crate) status: u8,
+    pub(crate) interval: u16,
+    pub(crate) ago: u16,
 }
 
 impl From<[u8; 13]> for CurrentSensorMeasurement {
@@ -179,27 +856,380 @@ impl From<[u8; 13]> for CurrentSensorMeasurement {
     }
 }
 
+impl CurrentSensorMeasurement {
+    pub fn co2(&self) -> Ppm {
+        Ppm(self.co2)
+    }
+
+    pub fn temperature(&self) -> Celsius {
+        Celsius((self.temperature as f32) * TemperatureData::DISPLAY_MULTIPLIER)
+    }
+
+    pub fn pressure(&self) -> Millibar {
+        Millibar((self.pressure as f32) * PressureData::DISPLAY_MULTIPLIER)
+    }
+
+    pub fn humidity(&self) -> RelativeHumidity {
+        RelativeHumidity(self.humidity)
+    }
+
+    /// CO₂ concentration in ppm.
+    pub fn co2_ppm(&self) -> u16 {
+        self.co2().0
+    }
+
+    /// Relative humidity in %.
+    pub fn humidity_percent(&self) -> u8 {
+        self.humidity().0
+    }
+
+    pub fn temperature_c(&self) -> f32 {
+        self.temperature().0
+    }
+
+    /// Pressure in mbar, which is the same as hPa.
+    pub fn pressure_mbar(&self) -> f32 {
+        self.pressure().0
+    }
+
+    /// Battery charge in %; the same as [`CurrentSensorMeasurement::battery`].
+    pub fn battery_percent(&self) -> u8 {
+        self.battery
+    }
+
+    /// How long ago the sensor took this reading, as of when it was read.
+    pub fn age(&self) -> Duration {
+        Duration::from_secs(u64::from(self.ago))
+    }
+
+    /// Time between measurements.
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(u64::from(self.interval))
+    }
+
+    /// Colour of the sensor's CO₂ indicator.
+    pub fn status(&self) -> Co2Indicator {
+        self.status_flags().co2_indicator()
+    }
+
+    pub fn co2_value(&self) -> f64 {
+        display_value::<CO2Data>(self.co2 as f64)
+    }
+
+    pub fn temperature_value(&self) -> f64 {
+        display_value::<TemperatureData>(self.temperature as f64)
+    }
+
+    pub fn pressure_value(&self) -> f64 {
+        display_value::<PressureData>(self.pressure as f64)
+    }
+
+    pub fn humidity_value(&self) -> f64 {
+        display_value::<HumidityData>(self.humidity as f64)
+    }
+
+    pub fn status_flags(&self) -> StatusFlags {
+        StatusFlags(self.status)
+    }
+
+    /// Battery charge in %.
+    pub fn battery(&self) -> u8 {
+        self.battery
+    }
+}
+
+impl CurrentSensorMeasurement {
+    /// The multi-line summary printed by `readout`, with values converted to `units`.
+    pub fn display_in(&self, units: Units) -> String {
+        let (temperature, temperature_unit, pressure, pressure_unit) = match units {
+            Units::Metric => (self.temperature().0, "°C", self.pressure().0, " hPa"),
+            Units::Imperial => (
+                self.temperature().to_fahrenheit(),
+                "°F",
+                self.pressure().to_inhg(),
+                " inHg",
+            ),
+        };
+        format!(
+            "CO₂: {}\nT: {:.*}{}\nP: {:.*}{}\nHumidity: {}\nBattery: {}%\nStatus: {}\nInterval: {} s\nAgo: {} s\n",
+            self.co2(),
+            TemperatureData::precision(units),
+            temperature,
+            temperature_unit,
+            PressureData::precision(units),
+            pressure,
+            pressure_unit,
+            self.humidity(),
+            self.battery,
+            self.status_flags(),
+            self.interval,
+            self.ago,
+        )
+    }
+}
+
 impl fmt::Display for CurrentSensorMeasurement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.display_in(Units::Metric))
+    }
+}
+
+/// Members of the Aranet family. They share the Aranet4's GATT service and history protocol,
+/// but each measures something different.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AranetModel {
+    Aranet4,
+    /// Temperature and humidity.
+    Aranet2,
+    /// Gamma dose rate and dose.
+    Radiation,
+    /// Radon Plus: radon, temperature, pressure and humidity.
+    Radon,
+}
+
+impl AranetModel {
+    /// Recognise the model from a model number, e.g. `Aranet2`, or an advertised name, e.g.
+    /// `AranetRn+ 0A1B2` or `Aranet☢ 1C3D4`.
+    pub fn detect(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        if name.contains("aranet4") {
+            Some(AranetModel::Aranet4)
+        } else if name.contains("aranet2") {
+            Some(AranetModel::Aranet2)
+        } else if name.contains("aranetrn") || name.contains("radon") {
+            Some(AranetModel::Radon)
+        } else if name.contains("aranet\u{2622}") || name.contains("radiation") {
+            Some(AranetModel::Radiation)
+        } else {
+            None
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AranetModel::Aranet4 => "Aranet4",
+            AranetModel::Aranet2 => "Aranet2",
+            AranetModel::Radiation => "Aranet Radiation",
+            AranetModel::Radon => "Aranet Radon Plus",
+        }
+    }
+
+    /// Whether [`crate::device::get_model_history`] can download this model's history.
+    pub fn has_model_history(self) -> bool {
+        matches!(self, AranetModel::Aranet2 | AranetModel::Radon)
+    }
+}
+
+impl fmt::Display for AranetModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Little-endian readers for the fixed layouts of current readings, which have been checked to
+/// be long enough.
+fn le_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// The current readings of the models other than the Aranet4 all start with the device type,
+/// the interval and age of the reading in s, and the battery charge.
+#[derive(Debug, Clone, Copy)]
+pub struct ReadingTiming {
+    pub interval: u16,
+    pub ago: u16,
+    pub battery: u8,
+}
+
+impl ReadingTiming {
+    const LEN: usize = 7;
+
+    fn decode(bytes: &[u8], len: usize, model: AranetModel) -> Result<Self, Aranet4Error> {
+        if bytes.len() < len {
+            return Err(Aranet4Error::InvalidResponse(format!(
+                "{} current reading is {} bytes, expected at least {}",
+                model,
+                bytes.len(),
+                len
+            )));
+        }
+        Ok(ReadingTiming {
+            interval: le_u16(bytes, 2),
+            ago: le_u16(bytes, 4),
+            battery: bytes[6],
+        })
+    }
+}
+
+impl fmt::Display for ReadingTiming {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "CO₂: {:.*} ppm\nT: {:.*}°C\nP: {:.*} hPa\nHumidity: {:.*}%\nBattery: {}%\nStatus: {}\nInterval: {} s\nAgo: {} s\n",
-            CO2Data::DISPLAY_PRECISION,
-            (self.co2 as f32) * CO2Data::DISPLAY_MULTIPLIER,
-            TemperatureData::DISPLAY_PRECISION,
-            (self.temperature as f32) * TemperatureData::DISPLAY_MULTIPLIER,
-            PressureData::DISPLAY_PRECISION,
-            (self.pressure as f32) * PressureData::DISPLAY_MULTIPLIER,
-            HumidityData::DISPLAY_PRECISION,
-            (self.humidity as f32) * HumidityData::DISPLAY_MULTIPLIER,
-            self.battery,
-            self.status,
-            self.interval,
-            self.ago,
+            "Battery: {}%\nInterval: {} s\nAgo: {} s\n",
+            self.battery, self.interval, self.ago
         )
     }
 }
 
+/// `raw` 1/20 °C and, if given, 1/10 hPa in `units`, as `readout` prints them.
+fn temperature_pressure_lines(temperature: u16, pressure: Option<u16>, units: Units) -> String {
+    let (temperature_unit, pressure_unit) = match units {
+        Units::Metric => ("°C", " hPa"),
+        Units::Imperial => ("°F", " inHg"),
+    };
+    let mut lines = format!(
+        "T: {:.*}{}\n",
+        TemperatureData::precision(units),
+        TemperatureData::convert(display_value::<TemperatureData>(temperature as f64), units),
+        temperature_unit
+    );
+    if let Some(pressure) = pressure {
+        lines += &format!(
+            "P: {:.*}{}\n",
+            PressureData::precision(units),
+            PressureData::convert(display_value::<PressureData>(pressure as f64), units),
+            pressure_unit
+        );
+    }
+    lines
+}
+
+#[derive(Debug, Clone)]
+pub struct Aranet2Measurement {
+    pub timing: ReadingTiming,
+    /// In 1/20 °C.
+    pub temperature: u16,
+    /// In 1/10 %.
+    pub humidity: u16,
+    pub status: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct RadonMeasurement {
+    pub timing: ReadingTiming,
+    /// In 1/20 °C.
+    pub temperature: u16,
+    /// In 1/10 hPa.
+    pub pressure: u16,
+    /// In 1/10 %.
+    pub humidity: u16,
+    /// In Bq/m³.
+    pub radon: u16,
+    pub status: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct RadiationMeasurement {
+    pub timing: ReadingTiming,
+    /// In nSv/h.
+    pub dose_rate: u32,
+    /// Accumulated since `duration` s ago, in nSv.
+    pub dose: u64,
+    pub duration: u64,
+    pub status: u8,
+}
+
+/// A current reading from any Aranet model. The Aranet4 has a reading characteristic of its
+/// own; the others share one, with the layout depending on the model.
+#[derive(Debug)]
+pub enum ModelMeasurement {
+    Aranet4(CurrentSensorMeasurement),
+    Aranet2(Aranet2Measurement),
+    Radiation(RadiationMeasurement),
+    Radon(RadonMeasurement),
+}
+
+impl ModelMeasurement {
+    pub fn decode(model: AranetModel, bytes: &[u8]) -> Result<Self, Aranet4Error> {
+        let timing = |len| ReadingTiming::decode(bytes, len, model);
+        Ok(match model {
+            AranetModel::Aranet4 => {
+                let bytes: [u8; 13] = bytes.try_into().map_err(|_| {
+                    Aranet4Error::InvalidResponse(
+                        "Unexpected current measurement length".to_string(),
+                    )
+                })?;
+                ModelMeasurement::Aranet4(bytes.into())
+            }
+            AranetModel::Aranet2 => ModelMeasurement::Aranet2(Aranet2Measurement {
+                timing: timing(ReadingTiming::LEN + 5)?,
+                temperature: le_u16(bytes, 7),
+                humidity: le_u16(bytes, 9),
+                status: bytes[11],
+            }),
+            // The Radon Plus appends averages over the last days, which aren't decoded
+            AranetModel::Radon => ModelMeasurement::Radon(RadonMeasurement {
+                timing: timing(ReadingTiming::LEN + 9)?,
+                temperature: le_u16(bytes, 7),
+                pressure: le_u16(bytes, 9),
+                humidity: le_u16(bytes, 11),
+                radon: le_u16(bytes, 13),
+                status: bytes[15],
+            }),
+            AranetModel::Radiation => ModelMeasurement::Radiation(RadiationMeasurement {
+                timing: timing(ReadingTiming::LEN + 21)?,
+                dose_rate: le_u32(bytes, 7),
+                dose: le_u64(bytes, 11),
+                duration: le_u64(bytes, 19),
+                status: bytes[27],
+            }),
+        })
+    }
+
+    /// Battery charge in %.
+    pub fn battery(&self) -> u8 {
+        match self {
+            ModelMeasurement::Aranet4(reading) => reading.battery,
+            ModelMeasurement::Aranet2(reading) => reading.timing.battery,
+            ModelMeasurement::Radiation(reading) => reading.timing.battery,
+            ModelMeasurement::Radon(reading) => reading.timing.battery,
+        }
+    }
+
+    /// The multi-line summary printed by `readout`, with values converted to `units`.
+    pub fn display_in(&self, units: Units) -> String {
+        let humidity_line = |humidity: u16| {
+            format!(
+                "Humidity: {:.*}%\n",
+                Humidity2Data::DISPLAY_PRECISION,
+                display_value::<Humidity2Data>(humidity as f64)
+            )
+        };
+        match self {
+            ModelMeasurement::Aranet4(reading) => reading.display_in(units),
+            ModelMeasurement::Aranet2(reading) => format!(
+                "{}{}{}",
+                temperature_pressure_lines(reading.temperature, None, units),
+                humidity_line(reading.humidity),
+                reading.timing
+            ),
+            ModelMeasurement::Radon(reading) => format!(
+                "Radon: {} Bq/m³\n{}{}{}",
+                reading.radon,
+                temperature_pressure_lines(reading.temperature, Some(reading.pressure), units),
+                humidity_line(reading.humidity),
+                reading.timing
+            ),
+            ModelMeasurement::Radiation(reading) => format!(
+                "Dose rate: {:.2} µSv/h\nDose: {:.4} mSv over {} s\n{}",
+                reading.dose_rate as f64 / 1e3,
+                reading.dose as f64 / 1e6,
+                reading.duration,
+                reading.timing
+            ),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct HistoryResponseHeader {
     pub type_code: u8,
@@ -226,3 +1256,224 @@ impl fmt::Display for HistoryResponseHeader {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use btleplug::Error as BtleplugError;
+    use chrono::DateTime;
+
+    use crate::device::HistoryTime;
+    use crate::types::{
+        history_records, split_label, Aranet4Error, AranetModel, CO2Data, CalibrationState,
+        Co2Indicator, Co2IndicatorThresholds, CurrentSensorMeasurement, HistorySeries,
+        Humidity2Data, HumidityData, MetricKind, MetricStorage, ModelMeasurement, PressureData,
+        RadonData, StatusFlags, TemperatureData, Units,
+    };
+
+    #[test]
+    fn test_co2_indicator_thresholds() {
+        let thresholds = Co2IndicatorThresholds::try_from(&[0x20, 0x03, 0xdc, 0x05][..]).unwrap();
+        assert_eq!(
+            thresholds,
+            Co2IndicatorThresholds {
+                yellow: 800,
+                red: 1500
+            }
+        );
+        assert_eq!(thresholds.indicator(799), Co2Indicator::Green);
+        assert_eq!(thresholds.indicator(800), Co2Indicator::Yellow);
+        assert_eq!(thresholds.indicator(1500), Co2Indicator::Red);
+        assert_eq!(
+            thresholds.to_string(),
+            "yellow from 800 ppm, red from 1500 ppm"
+        );
+        assert!(Co2IndicatorThresholds::try_from(&[0x20, 0x03][..]).is_err());
+        // Red below yellow
+        assert!(Co2IndicatorThresholds::try_from(&[0xdc, 0x05, 0x20, 0x03][..]).is_err());
+    }
+
+    #[test]
+    fn test_authentication_errors_need_pairing() {
+        let error = Aranet4Error::from(BtleplugError::Other(
+            "le-connection-abort-by-local: Insufficient Authentication".into(),
+        ));
+        assert!(matches!(error, Aranet4Error::PairingRequired(_)));
+        assert!(!error.is_retryable());
+        let error = Aranet4Error::from(BtleplugError::NotConnected);
+        assert!(matches!(error, Aranet4Error::Btleplug { .. }));
+        assert!(error.is_retryable());
+    }
+
+    #[test]
+    fn test_measurement_serde_round_trip() {
+        let measurement = CurrentSensorMeasurement::from([
+            0x9e, 0x03, 0x9d, 0x01, 0xbe, 0x27, 33, 22, 1, 0x2c, 0x01, 0x20, 0x00,
+        ]);
+        assert_eq!(measurement.co2_ppm(), 926);
+        assert_eq!(measurement.humidity_percent(), 33);
+        let json = serde_json::to_string(&measurement).unwrap();
+        let decoded: CurrentSensorMeasurement = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.co2_ppm(), 926);
+        assert_eq!(decoded.temperature_value(), measurement.temperature_value());
+
+        let co2 = CO2Data {
+            values: vec![413, 420],
+        };
+        let json = serde_json::to_string(&co2).unwrap();
+        assert_eq!(json, r#"{"values":[413,420]}"#);
+        let decoded: CO2Data = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded.values, co2.values);
+    }
+
+    #[test]
+    fn test_measurement_accessors() {
+        let measurement = CurrentSensorMeasurement::from([
+            0x9e, 0x03, 0x9d, 0x01, 0xbe, 0x27, 33, 22, 2, 0x2c, 0x01, 0x20, 0x00,
+        ]);
+        assert_eq!(measurement.temperature_c(), 20.65);
+        assert_eq!(measurement.pressure_mbar(), 1017.4);
+        assert_eq!(measurement.battery_percent(), 22);
+        assert_eq!(measurement.age(), Duration::from_secs(32));
+        assert_eq!(measurement.interval(), Duration::from_secs(300));
+        assert_eq!(measurement.status(), Co2Indicator::Yellow);
+    }
+
+    #[test]
+    fn test_history_records() {
+        let history_time = HistoryTime {
+            num_samples: 2,
+            update_interval: 300,
+            since_update: 0,
+            now: DateTime::from_timestamp(1738621200, 0).unwrap(),
+            offset: 0,
+        };
+        let temperature = TemperatureData {
+            values: vec![413, 420],
+        };
+        let humidity = HumidityData { values: vec![] };
+        let pressure = PressureData {
+            values: vec![10174, 10175],
+        };
+        let co2 = CO2Data {
+            values: vec![926, 930],
+        };
+        let records: Vec<_> =
+            history_records(&history_time, &temperature, &humidity, &pressure, &co2)
+                .collect::<color_eyre::Result<_>>()
+                .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].timestamp, 1738621200);
+        assert_eq!(records[0].temperature_c, 20.65);
+        assert_eq!(records[0].humidity_pct, 0);
+        assert_eq!(records[0].pressure_mbar, 1017.4);
+        assert_eq!(records[1].co2_ppm, 930);
+        assert!((records[0].temperature_in(Units::Imperial) - 69.17).abs() < 1e-9);
+        assert_eq!(
+            serde_json::to_string(&records[0]).unwrap(),
+            r#"{"timestamp":1738620900,"temperature":20.65,"humidity":0,"pressure":1017.4,"co2":926}"#
+        );
+    }
+
+    #[test]
+    fn test_split_label() {
+        assert_eq!(split_label("Pressure (inHg)"), ("Pressure", "inHg"));
+        assert_eq!(split_label("CO₂ (ppm)"), ("CO₂", "ppm"));
+        assert_eq!(split_label("Radon"), ("Radon", ""));
+    }
+
+    #[test]
+    fn test_history_series() {
+        let temperature = HistorySeries::from(&TemperatureData {
+            values: vec![413, 420],
+        });
+        assert_eq!(temperature.kind, MetricKind::Temperature);
+        assert_eq!(temperature.len(), 2);
+        assert_eq!(temperature.get_value_string_in(0, Units::Metric), "20.65");
+        assert_eq!(temperature.get_value_string_in(1, Units::Imperial), "69.80");
+        let humidity = HistorySeries::from(&Humidity2Data { values: vec![347] });
+        assert_eq!(humidity.kind.name(), "humidity");
+        assert_eq!(humidity.get_value_string_in(0, Units::Metric), "34.7");
+        let radon = HistorySeries::from(&RadonData {
+            values: vec![70_000],
+        });
+        assert_eq!(radon.get(0), 70_000.0);
+        assert_eq!(radon.kind.storage(), MetricStorage::U32);
+        assert_eq!(MetricKind::Co2.label(), "CO₂ (ppm)");
+        assert_eq!(
+            MetricKind::Pressure.unit_label(Units::Imperial),
+            "Pressure (inHg)"
+        );
+        assert_eq!(MetricKind::Humidity.storage().size(), 1);
+    }
+
+    #[test]
+    fn test_status_flags() {
+        assert_eq!(StatusFlags(1).co2_indicator(), Co2Indicator::Green);
+        assert_eq!(StatusFlags(1).calibration(), CalibrationState::NotActive);
+        assert_eq!(StatusFlags(1).to_string(), "green");
+        let calibrating = StatusFlags(0b1011);
+        assert_eq!(calibrating.co2_indicator(), Co2Indicator::Red);
+        assert_eq!(calibrating.calibration(), CalibrationState::InProgress);
+        assert_eq!(calibrating.to_string(), "red, calibration in progress");
+    }
+
+    #[test]
+    fn test_detect_model() {
+        assert_eq!(
+            AranetModel::detect("Aranet4 1BA27"),
+            Some(AranetModel::Aranet4)
+        );
+        assert_eq!(AranetModel::detect("Aranet2"), Some(AranetModel::Aranet2));
+        assert_eq!(
+            AranetModel::detect("AranetRn+ 0A1B2"),
+            Some(AranetModel::Radon)
+        );
+        assert_eq!(
+            AranetModel::detect("Aranet\u{2622} 1C3D4"),
+            Some(AranetModel::Radiation)
+        );
+        assert_eq!(AranetModel::detect("Thermometer"), None);
+    }
+
+    #[test]
+    fn test_decode_model_measurements() {
+        let aranet2 = ModelMeasurement::decode(
+            AranetModel::Aranet2,
+            &[
+                0x02, 0x00, 0x2c, 0x01, 0x1e, 0x00, 0x5a, 0x9d, 0x01, 0x5b, 0x01, 0x00,
+            ],
+        )
+        .unwrap();
+        assert_eq!(aranet2.battery(), 90);
+        assert_eq!(
+            aranet2.display_in(Units::Metric),
+            "T: 20.65°C\nHumidity: 34.7%\nBattery: 90%\nInterval: 300 s\nAgo: 30 s\n"
+        );
+
+        let radon = ModelMeasurement::decode(
+            AranetModel::Radon,
+            &[
+                0x03, 0x00, 0x58, 0x02, 0x0a, 0x00, 0x40, 0x9d, 0x01, 0xbe, 0x27, 0xc2, 0x01, 0x2d,
+                0x00, 0x00,
+            ],
+        )
+        .unwrap();
+        assert!(radon
+            .display_in(Units::Metric)
+            .starts_with("Radon: 45 Bq/m³\nT: 20.65°C\nP: 1017.4 hPa\nHumidity: 45.0%\n"));
+
+        let mut radiation = vec![0x04, 0x00, 0x2c, 0x01, 0x00, 0x00, 0x50];
+        radiation.extend_from_slice(&120u32.to_le_bytes());
+        radiation.extend_from_slice(&2_500_000u64.to_le_bytes());
+        radiation.extend_from_slice(&86_400u64.to_le_bytes());
+        radiation.push(0);
+        let radiation = ModelMeasurement::decode(AranetModel::Radiation, &radiation).unwrap();
+        assert!(radiation
+            .display_in(Units::Metric)
+            .starts_with("Dose rate: 0.12 µSv/h\nDose: 2.5000 mSv over 86400 s\n"));
+
+        assert!(ModelMeasurement::decode(AranetModel::Radon, &[0x03, 0x00, 0x58]).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+//! Upload of finished archives to S3, Google Cloud Storage or Azure Blob Storage.
+
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use object_store::aws::AmazonS3Builder;
+use object_store::azure::MicrosoftAzureBuilder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::path::Path as ObjectPath;
+use object_store::{ObjectStore, PutPayload, RetryConfig};
+use url::Url;
+
+/// Retries of a failed request, with exponential backoff, before an upload gives up.
+const UPLOAD_RETRIES: usize = 5;
+
+/// A bucket or container, and a prefix in it, to upload archives to.
+pub struct UploadTarget {
+    store: Box<dyn ObjectStore>,
+    url: Url,
+    prefix: ObjectPath,
+}
+
+impl UploadTarget {
+    /// Parse an `s3://`, `gs://` or `azure://` (also `az://`) URL such as
+    /// `s3://bucket/prefix/`. Credentials and regions are taken from the environment variables
+    /// each cloud's own tools use, e.g. `AWS_ACCESS_KEY_ID` and `AWS_REGION`.
+    pub fn from_url(url: &str) -> Result<Self> {
+        let parsed = Url::parse(url).wrap_err_with(|| format!("Invalid upload URL {}", url))?;
+        let retry = RetryConfig {
+            max_retries: UPLOAD_RETRIES,
+            retry_timeout: Duration::from_secs(300),
+            ..Default::default()
+        };
+        let store: Box<dyn ObjectStore> = match parsed.scheme() {
+            "s3" | "s3a" => Box::new(
+                AmazonS3Builder::from_env()
+                    .with_url(url)
+                    .with_retry(retry)
+                    .build()?,
+            ),
+            "gs" => Box::new(
+                GoogleCloudStorageBuilder::from_env()
+                    .with_url(url)
+                    .with_retry(retry)
+                    .build()?,
+            ),
+            "az" | "azure" | "abfs" | "abfss" => Box::new(
+                MicrosoftAzureBuilder::from_env()
+                    .with_url(url)
+                    .with_retry(retry)
+                    .build()?,
+            ),
+            scheme => {
+                return Err(eyre!(
+                    "Unsupported upload URL scheme {}://; expected s3://, gs:// or azure://",
+                    scheme
+                ))
+            }
+        };
+        Self::new(store, parsed)
+    }
+
+    fn new(store: Box<dyn ObjectStore>, url: Url) -> Result<Self> {
+        let prefix = ObjectPath::from_url_path(url.path())
+            .wrap_err_with(|| format!("Invalid upload prefix in {}", url))?;
+        Ok(UploadTarget { store, url, prefix })
+    }
+
+    /// Upload the file at `path` under the prefix, keeping its name. Returns the URL of the
+    /// uploaded object.
+    pub async fn upload(&self, path: &Path) -> Result<String> {
+        let file_name = path
+            .file_name()
+            .ok_or(eyre!(
+                "Can't upload {}, which is not a file",
+                path.display()
+            ))?
+            .to_string_lossy();
+        let key = self.prefix.child(file_name.as_ref());
+        let contents =
+            fs::read(path).wrap_err_with(|| format!("Could not read {}", path.display()))?;
+        let object_url = format!(
+            "{}://{}/{}",
+            self.url.scheme(),
+            self.url.host_str().unwrap_or_default(),
+            key
+        );
+        self.store
+            .put(&key, PutPayload::from(contents))
+            .await
+            .wrap_err_with(|| format!("Could not upload {} to {}", path.display(), object_url))?;
+        Ok(object_url)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use object_store::memory::InMemory;
+    use object_store::path::Path as ObjectPath;
+    use object_store::ObjectStore;
+    use url::Url;
+
+    use crate::upload::UploadTarget;
+
+    #[tokio::test]
+    async fn test_upload_keeps_file_name_under_prefix() {
+        let path = std::env::temp_dir().join("arachiver_test_upload.csv");
+        fs::write(&path, "time,co2\n").unwrap();
+        for (url, key) in [
+            (
+                "s3://bucket/aranet/office/",
+                "aranet/office/arachiver_test_upload.csv",
+            ),
+            ("s3://bucket", "arachiver_test_upload.csv"),
+        ] {
+            let target =
+                UploadTarget::new(Box::new(InMemory::new()), Url::parse(url).unwrap()).unwrap();
+            assert_eq!(
+                target.upload(&path).await.unwrap(),
+                format!("s3://bucket/{}", key)
+            );
+            let uploaded = target.store.get(&ObjectPath::from(key)).await.unwrap();
+            assert_eq!(&uploaded.bytes().await.unwrap()[..], b"time,co2\n");
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_from_url_rejects_other_schemes() {
+        assert!(UploadTarget::from_url("ftp://example.com/aranet/").is_err());
+        assert!(UploadTarget::from_url("not a url").is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::io::{self, IsTerminal, Write};
+use std::time::Duration;
+
+use chrono::Local;
+use color_eyre::eyre::Result;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::client::Aranet4Client;
+use crate::shutdown::shutdown_signal;
+use crate::types::{
+    CO2Data, CurrentSensorMeasurement, HumidityData, Metadata, PressureData, TemperatureData, Units,
+};
+
+/// Which way a value moved since the previous reading.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trend {
+    Up,
+    Down,
+    Steady,
+    /// No previous reading to compare with.
+    Unknown,
+}
+
+impl Trend {
+    pub fn between(previous: Option<u16>, current: u16) -> Self {
+        match previous {
+            Some(previous) if current > previous => Trend::Up,
+            Some(previous) if current < previous => Trend::Down,
+            Some(_) => Trend::Steady,
+            None => Trend::Unknown,
+        }
+    }
+
+    pub fn arrow(self) -> &'static str {
+        match self {
+            Trend::Up => "↑",
+            Trend::Down => "↓",
+            Trend::Steady => "→",
+            Trend::Unknown => " ",
+        }
+    }
+}
+
+/// The block `watch` redraws: `readout`'s values, converted to `units`, each with an arrow for
+/// how it changed since `previous`.
+pub fn render_watch_block(
+    name: &str,
+    reading: &CurrentSensorMeasurement,
+    previous: Option<&CurrentSensorMeasurement>,
+    units: Units,
+    updated: &str,
+) -> String {
+    let (temperature_unit, pressure_unit) = match units {
+        Units::Metric => ("°C", " hPa"),
+        Units::Imperial => ("°F", " inHg"),
+    };
+    let trend = |value: fn(&CurrentSensorMeasurement) -> u16| {
+        Trend::between(previous.map(value), value(reading)).arrow()
+    };
+    format!(
+        "{}\n{}\nCO₂: {:.*} ppm {}\nT: {:.*}{} {}\nP: {:.*}{} {}\nHumidity: {:.*}% {}\nBattery: {}%\nStatus: {}\nUpdated: {}\n",
+        name,
+        "=".repeat(name.graphemes(true).count()),
+        CO2Data::DISPLAY_PRECISION,
+        reading.co2_value(),
+        trend(|r| r.co2),
+        TemperatureData::precision(units),
+        TemperatureData::convert(reading.temperature_value(), units),
+        temperature_unit,
+        trend(|r| r.temperature),
+        PressureData::precision(units),
+        PressureData::convert(reading.pressure_value(), units),
+        pressure_unit,
+        trend(|r| r.pressure),
+        HumidityData::DISPLAY_PRECISION,
+        reading.humidity_value(),
+        trend(|r| r.humidity as u16),
+        reading.battery_percent(),
+        reading.status_flags(),
+        updated,
+    )
+}
+
+/// Print the current readings of `client` every `interval` until Ctrl-C or SIGTERM, redrawing
+/// them in place on a terminal. The sensor is only connected to while reading, and a failed
+/// read is reported below the last good reading rather than ending the watch.
+pub async fn watch(client: &Aranet4Client, interval: Duration, units: Units) -> Result<()> {
+    let name = client.local_name().await.unwrap_or_default();
+    let redraw = io::stdout().is_terminal();
+    let mut previous: Option<CurrentSensorMeasurement> = None;
+    let mut latest: Option<CurrentSensorMeasurement> = None;
+    let mut updated = String::new();
+    let mut ticks = tokio::time::interval(interval);
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            _ = ticks.tick() => {}
+        }
+        let error = match client.read_current().await {
+            Ok(reading) => {
+                previous = latest.replace(reading);
+                updated = Local::now().format("%H:%M:%S").to_string();
+                None
+            }
+            Err(e) => Some(format!("Failed to take reading: {}", e)),
+        };
+        let mut stdout = io::stdout().lock();
+        if redraw {
+            // Clear the screen and move to its top left
+            write!(stdout, "\x1b[2J\x1b[H")?;
+        }
+        if let Some(reading) = &latest {
+            write!(
+                stdout,
+                "{}",
+                render_watch_block(&name, reading, previous.as_ref(), units, &updated)
+            )?;
+        }
+        if let Some(error) = error {
+            writeln!(stdout, "{}", error)?;
+        }
+        if !redraw {
+            writeln!(stdout)?;
+        }
+        stdout.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::types::{CurrentSensorMeasurement, Units};
+    use crate::watch::{render_watch_block, Trend};
+
+    #[test]
+    fn test_trend() {
+        assert_eq!(Trend::between(Some(900), 926), Trend::Up);
+        assert_eq!(Trend::between(Some(926), 926), Trend::Steady);
+        assert_eq!(Trend::between(Some(950), 926), Trend::Down);
+        assert_eq!(Trend::between(None, 926), Trend::Unknown);
+    }
+
+    #[test]
+    fn test_render_watch_block() {
+        let previous = CurrentSensorMeasurement::from([
+            0x84, 0x03, 0x9d, 0x01, 0xc0, 0x27, 0x21, 0x16, 0x01, 0x2c, 0x01, 0x00, 0x00,
+        ]);
+        let reading = CurrentSensorMeasurement::from([
+            0x9e, 0x03, 0x9d, 0x01, 0xbe, 0x27, 0x21, 0x16, 0x01, 0x2c, 0x01, 0x00, 0x00,
+        ]);
+        let block = render_watch_block(
+            "Aranet4 1BA27",
+            &reading,
+            Some(&previous),
+            Units::Metric,
+            "14:02:03",
+        );
+        assert_eq!(
+            block,
+            "Aranet4 1BA27\n=============\nCO₂: 926 ppm ↑\nT: 20.65°C →\nP: 1017.4 hPa ↓\nHumidity: 33% →\nBattery: 22%\nStatus: green\nUpdated: 14:02:03\n"
+        );
+        let first = render_watch_block("Aranet4 1BA27", &reading, None, Units::Metric, "14:02:03");
+        assert!(first.contains("CO₂: 926 ppm  \n"));
+    }
+}
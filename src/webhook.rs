@@ -0,0 +1,211 @@
+//! Posting new history to a webhook, for those whose database is a spreadsheet: a Google Apps
+//! Script web app that appends the rows to a sheet, an IFTTT applet, or anything else that
+//! takes an HTTP POST.
+
+use std::time::Duration;
+
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, Result, WrapErr};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::csv_io::{write_history_csv, CsvTimestamps};
+use crate::device::{HistoryMetric, RetryPolicy};
+use crate::types::{HistoryRecord, Units};
+
+/// How readings are posted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum WebhookFormat {
+    /// An `application/json` object with `device`, `serial` and `readings`, each reading with
+    /// the fields of a JSON history archive.
+    #[default]
+    Json,
+    /// An `application/x-www-form-urlencoded` form with `device`, `serial` and `csv` fields,
+    /// the last holding the readings as a metric CSV archive, header included.
+    Csv,
+}
+
+impl WebhookFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(WebhookFormat::Json),
+            "csv" => Some(WebhookFormat::Csv),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    pub format: WebhookFormat,
+    /// Readings per request, which keeps each request within the time and size a script host
+    /// allows.
+    pub batch_size: usize,
+    /// Retries of a request that failed to connect, timed out, or got a 429 or 5xx status.
+    pub retry: RetryPolicy,
+}
+
+#[derive(Debug, Serialize)]
+struct JsonBatch<'a> {
+    device: &'a str,
+    serial: &'a str,
+    readings: &'a [HistoryRecord],
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum WebhookBody {
+    Json(String),
+    Form(Vec<(&'static str, String)>),
+}
+
+fn batch_body(
+    format: WebhookFormat,
+    name: &str,
+    serial: &str,
+    batch: &[HistoryRecord],
+) -> Result<WebhookBody> {
+    match format {
+        WebhookFormat::Json => Ok(WebhookBody::Json(serde_json::to_string(&JsonBatch {
+            device: name,
+            serial,
+            readings: batch,
+        })?)),
+        WebhookFormat::Csv => {
+            let mut csv = Vec::new();
+            write_history_csv(
+                batch.iter().copied().map(Ok),
+                &mut csv,
+                Units::Metric,
+                CsvTimestamps::default(),
+                &HistoryMetric::ALL,
+                false,
+                true,
+            )?;
+            Ok(WebhookBody::Form(vec![
+                ("device", name.to_string()),
+                ("serial", serial.to_string()),
+                ("csv", String::from_utf8(csv)?),
+            ]))
+        }
+    }
+}
+
+/// Whether sending the request again might succeed, as opposed to e.g. a rejected body.
+fn is_retryable(error: &ureq::Error) -> bool {
+    match error {
+        ureq::Error::Status(status, _) => *status == 429 || *status >= 500,
+        ureq::Error::Transport(_) => true,
+    }
+}
+
+/// Boxed, as a `ureq::Error` holds the whole response.
+fn send(url: &str, body: &WebhookBody) -> Result<(), Box<ureq::Error>> {
+    let request = ureq::post(url).timeout(Duration::from_secs(30));
+    match body {
+        WebhookBody::Json(json) => request
+            .set("Content-Type", "application/json")
+            .send_string(json)
+            .map_err(Box::new)?,
+        WebhookBody::Form(fields) => {
+            let fields: Vec<(&str, &str)> = fields
+                .iter()
+                .map(|(key, value)| (*key, value.as_str()))
+                .collect();
+            request.send_form(&fields).map_err(Box::new)?
+        }
+    };
+    Ok(())
+}
+
+/// POST `body` to the webhook, retrying as `config.retry` allows.
+async fn post(config: &WebhookConfig, body: WebhookBody) -> Result<()> {
+    let mut retry = 0;
+    loop {
+        let url = config.url.clone();
+        let attempt = body.clone();
+        match tokio::task::spawn_blocking(move || send(&url, &attempt)).await? {
+            Ok(()) => return Ok(()),
+            Err(e) if retry < config.retry.retries && is_retryable(&e) => {
+                warn!(
+                    "Webhook request failed: {}; retrying ({}/{})",
+                    e,
+                    retry + 1,
+                    config.retry.retries
+                );
+                tokio::time::sleep(config.retry.delay_before_retry(retry)).await;
+                retry += 1;
+            }
+            Err(e) => match *e {
+                ureq::Error::Status(status, response) => {
+                    return Err(eyre!(
+                        "The webhook rejected the readings with status {}: {}",
+                        status,
+                        response.into_string().unwrap_or_default().trim()
+                    ))
+                }
+                e => {
+                    return Err(e)
+                        .wrap_err_with(|| format!("Could not reach the webhook at {}", config.url))
+                }
+            },
+        }
+    }
+}
+
+/// POST `records`, new readings of the sensor `name` with serial number `serial` in time order,
+/// to the webhook in batches of `config.batch_size`. `posted` is called with the timestamp of
+/// the last reading of each batch once the webhook has accepted it, so that after a failure
+/// only the rest need posting again. Returns how many readings were posted.
+pub async fn post_history(
+    config: &WebhookConfig,
+    name: &str,
+    serial: &str,
+    records: &[HistoryRecord],
+    mut posted: impl FnMut(i64) -> Result<()>,
+) -> Result<usize> {
+    for batch in records.chunks(config.batch_size.max(1)) {
+        post(config, batch_body(config.format, name, serial, batch)?).await?;
+        posted(batch[batch.len() - 1].timestamp)?;
+    }
+    Ok(records.len())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::types::HistoryRecord;
+    use crate::webhook::{batch_body, WebhookBody, WebhookFormat};
+
+    #[test]
+    fn test_batch_body() {
+        let batch = [HistoryRecord {
+            timestamp: 1738621029,
+            temperature_c: 20.65,
+            humidity_pct: 33,
+            pressure_mbar: 1017.4,
+            co2_ppm: 926,
+        }];
+        assert_eq!(
+            batch_body(WebhookFormat::Json, "Office", "317960113191", &batch).unwrap(),
+            WebhookBody::Json(
+                "{\"device\":\"Office\",\"serial\":\"317960113191\",\"readings\":[{\
+                 \"timestamp\":1738621029,\"temperature\":20.65,\"humidity\":33,\
+                 \"pressure\":1017.4,\"co2\":926}]}"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            batch_body(WebhookFormat::Csv, "Office", "317960113191", &batch).unwrap(),
+            WebhookBody::Form(vec![
+                ("device", "Office".to_string()),
+                ("serial", "317960113191".to_string()),
+                (
+                    "csv",
+                    "timestamp,Temperature (°C),Humidity (%),Pressure (hPa),CO₂ (ppm)\n\
+                     1738621029,20.65,33,1017.4,926\n"
+                        .to_string()
+                ),
+            ])
+        );
+    }
+}
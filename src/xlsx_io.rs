@@ -0,0 +1,158 @@
+//! History as an Excel workbook, for reading in a spreadsheet without importing a CSV file.
+
+use std::io::Write;
+
+use chrono::{DateTime, Local};
+use color_eyre::eyre::{eyre, Result};
+use rust_xlsxwriter::{Chart, ChartType, Format, FormatBorder, Workbook};
+
+use crate::device::HistoryTime;
+use crate::types::{
+    history_records, split_label, CO2Data, HistoryRecord, HumidityData, Metadata, PressureData,
+    TemperatureData, Units,
+};
+
+const SHEET_NAME: &str = "History";
+/// The header and units rows, which stay in view when scrolling.
+const HEADER_ROWS: u32 = 2;
+
+fn decimal_format(precision: usize) -> Format {
+    if precision == 0 {
+        Format::new().set_num_format("0")
+    } else {
+        Format::new().set_num_format(format!("0.{}", "0".repeat(precision)))
+    }
+}
+
+/// Write the history as an XLSX workbook: one sheet with a bold header row and a row of
+/// units, both frozen, the timestamps as local date and time, and a chart of CO₂ next to the
+/// data.
+pub async fn save_history_xlsx<W: Write>(
+    history_time: HistoryTime,
+    temperature: TemperatureData,
+    humidity: HumidityData,
+    pressure: PressureData,
+    co2: CO2Data,
+    dest: &mut W,
+    units: Units,
+) -> Result<()> {
+    write_history_xlsx(
+        history_records(&history_time, &temperature, &humidity, &pressure, &co2),
+        dest,
+        units,
+    )
+}
+
+/// Like [`save_history_xlsx`], for readings from any source, e.g. an archive being converted.
+pub fn write_history_xlsx<W: Write>(
+    records: impl IntoIterator<Item = Result<HistoryRecord>>,
+    dest: &mut W,
+    units: Units,
+) -> Result<()> {
+    let mut workbook = Workbook::new();
+    let sheet = workbook.add_worksheet();
+    sheet.set_name(SHEET_NAME)?;
+
+    let header_format = Format::new()
+        .set_bold()
+        .set_border_bottom(FormatBorder::Thin);
+    let units_format = Format::new().set_italic();
+    let labels = [
+        ("Time", "local time"),
+        split_label(TemperatureData::unit_label(units)),
+        split_label(HumidityData::unit_label(units)),
+        split_label(PressureData::unit_label(units)),
+        split_label(CO2Data::unit_label(units)),
+    ];
+    for (col, (name, unit)) in labels.into_iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, name, &header_format)?;
+        sheet.write_string_with_format(1, col as u16, unit, &units_format)?;
+        sheet.set_column_width(col as u16, if col == 0 { 18 } else { 12 })?;
+    }
+    sheet.set_freeze_panes(HEADER_ROWS, 0)?;
+
+    let time_format = Format::new().set_num_format("yyyy-mm-dd hh:mm");
+    let temperature_format = decimal_format(TemperatureData::precision(units));
+    let pressure_format = decimal_format(PressureData::precision(units));
+    let integer_format = decimal_format(0);
+    let mut num_rows = 0;
+    for (i, record) in records.into_iter().enumerate() {
+        let record = record?;
+        let row = HEADER_ROWS + i as u32;
+        num_rows += 1;
+        let time = DateTime::from_timestamp(record.timestamp, 0)
+            .ok_or(eyre!("Timestamp {} is out of range", record.timestamp))?
+            .with_timezone(&Local)
+            .naive_local();
+        sheet.write_datetime_with_format(row, 0, time, &time_format)?;
+        sheet.write_number_with_format(
+            row,
+            1,
+            record.temperature_in(units),
+            &temperature_format,
+        )?;
+        sheet.write_number_with_format(row, 2, record.humidity_pct, &integer_format)?;
+        sheet.write_number_with_format(row, 3, record.pressure_in(units), &pressure_format)?;
+        sheet.write_number_with_format(row, 4, record.co2_ppm, &integer_format)?;
+    }
+
+    if num_rows > 0 {
+        let last_row = HEADER_ROWS + num_rows - 1;
+        let mut chart = Chart::new(ChartType::Line);
+        chart
+            .add_series()
+            .set_categories((SHEET_NAME, HEADER_ROWS, 0, last_row, 0))
+            .set_values((SHEET_NAME, HEADER_ROWS, 4, last_row, 4));
+        chart.title().set_name(CO2Data::unit_label(units));
+        chart
+            .x_axis()
+            .set_date_axis(true)
+            .set_num_format("mm-dd hh:mm");
+        chart.legend().set_hidden();
+        chart.set_width(720);
+        sheet.insert_chart(1, labels.len() as u16 + 1, &chart)?;
+    }
+
+    dest.write_all(&workbook.save_to_buffer()?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::device::HistoryTime;
+    use crate::types::{CO2Data, HumidityData, PressureData, TemperatureData, Units};
+    use crate::xlsx_io::save_history_xlsx;
+
+    #[tokio::test]
+    async fn test_save_history_xlsx() {
+        let history_time = HistoryTime {
+            num_samples: 2,
+            update_interval: 300,
+            since_update: 0,
+            now: chrono::DateTime::from_timestamp(1738621329, 0).unwrap(),
+            offset: 0,
+        };
+        let mut output = Vec::new();
+        save_history_xlsx(
+            history_time,
+            TemperatureData {
+                values: vec![413, 414],
+            },
+            HumidityData {
+                values: vec![33, 33],
+            },
+            PressureData {
+                values: vec![10174, 10173],
+            },
+            CO2Data {
+                values: vec![926, 930],
+            },
+            &mut output,
+            Units::Imperial,
+        )
+        .await
+        .unwrap();
+        // An XLSX file is a zip archive
+        assert_eq!(output[..4], *b"PK\x03\x04");
+    }
+}
@@ -0,0 +1,474 @@
+//! Every subcommand that talks to a sensor, run end to end against the simulated Aranet4 of
+//! `--simulate`, checking what it prints and the files it writes.
+
+use std::fs;
+use std::path::PathBuf;
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+
+/// Readings in the simulated sensor's history.
+const SIMULATED_READINGS: usize = 2016;
+
+fn arachiver() -> Command {
+    let mut command = Command::cargo_bin("arachiver").unwrap();
+    command.arg("--simulate");
+    command
+}
+
+/// An empty directory for one test's output files.
+fn output_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join("arachiver_test_cli").join(test);
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_device_info() {
+    arachiver()
+        .arg("device_info")
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("Aranet4 Simulated\n"))
+        .stdout(predicate::str::contains("Serial number: 317960113191"))
+        .stdout(predicate::str::contains(
+            "CO₂ thresholds: yellow from 1000 ppm, red from 1400 ppm",
+        ));
+}
+
+#[test]
+fn test_device_info_json() {
+    let output = arachiver()
+        .args(["device_info", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let info: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(info["device_name"], "Aranet4 Simulated");
+    assert_eq!(info["co2_thresholds"]["yellow"], 1000);
+}
+
+#[test]
+fn test_status() {
+    arachiver()
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Stored readings: 2016"))
+        .stdout(predicate::str::contains("Interval: 300 s"));
+}
+
+#[test]
+fn test_battery() {
+    arachiver()
+        .arg("battery")
+        .assert()
+        .success()
+        .stdout("Aranet4 Simulated: 22%\n");
+    arachiver()
+        .args(["battery", "--warn-below", "50"])
+        .assert()
+        .code(14);
+}
+
+#[test]
+fn test_readout_json() {
+    let output = arachiver()
+        .args(["readout", "--format", "json"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let reading: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(reading["name"], "Aranet4 Simulated");
+    assert_eq!(reading["co2"], 926);
+    assert_eq!(reading["humidity"], 33);
+}
+
+#[test]
+fn test_readout_derived() {
+    arachiver()
+        .args(["readout", "--derived"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Dew point: "))
+        .stdout(predicate::str::contains("Absolute humidity: "));
+    arachiver()
+        .args([
+            "archive_history_csv",
+            "--derived",
+            "--columns",
+            "co2,humidity",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--derived needs temperature"));
+}
+
+#[test]
+fn test_check_exit_status() {
+    arachiver()
+        .arg("check")
+        .assert()
+        .code(0)
+        .stdout(predicate::str::starts_with("CO2 OK"));
+    arachiver()
+        .args(["check", "--co2-warn", "900", "--co2-crit", "1200"])
+        .assert()
+        .code(1);
+    arachiver()
+        .args(["check", "--co2-warn", "800", "--co2-crit", "900"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_simulate_from_environment() {
+    Command::cargo_bin("arachiver")
+        .unwrap()
+        .env("ARACHIVER_SIMULATE", "1")
+        .arg("battery")
+        .assert()
+        .success()
+        .stdout("Aranet4 Simulated: 22%\n");
+}
+
+#[test]
+fn test_archive_history_csv() {
+    let dir = output_dir("csv");
+    let path = dir.join("history.csv");
+    arachiver()
+        .arg("archive_history_csv")
+        .arg("-o")
+        .arg(&path)
+        .assert()
+        .success();
+    let csv = fs::read_to_string(&path).unwrap();
+    assert!(csv.starts_with("timestamp,"));
+    assert_eq!(csv.lines().count(), 1 + SIMULATED_READINGS);
+}
+
+#[test]
+fn test_quiet() {
+    let dir = output_dir("quiet");
+    let path = dir.join("history.parquet");
+    arachiver()
+        .args(["--quiet", "archive_history_parquet", "-o"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(format!("{}\n", path.display()));
+    assert!(path.exists());
+}
+
+#[test]
+fn test_timing() {
+    let dir = output_dir("timing");
+    let path = dir.join("history.csv");
+    arachiver()
+        .args([
+            "--quiet",
+            "--timing",
+            "--write-without-response",
+            "archive_history_csv",
+            "--metrics",
+            "co2",
+            "-o",
+        ])
+        .arg(&path)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("History transfer from"))
+        .stderr(predicate::str::contains("CO₂ (ppm): 2016 readings in"))
+        .stderr(predicate::str::contains("ATT MTU of at least"));
+    assert!(path.exists());
+}
+
+#[test]
+fn test_dry_run() {
+    let dir = output_dir("dry_run");
+    let path = dir.join("history.csv");
+    arachiver()
+        .arg("archive_history_csv")
+        .arg("-o")
+        .arg(&path)
+        .arg("--dry-run")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "would download 2016 of 2016 stored readings",
+        ))
+        .stdout(predicate::str::contains(path.display().to_string()));
+    assert!(!path.exists());
+    arachiver()
+        .args(["--dry-run", "readout"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_archive_history_csv_metadata() {
+    let dir = output_dir("csv_metadata");
+    let path = dir.join("history.csv");
+    arachiver()
+        .args([
+            "archive_history_csv",
+            "--csv-metadata",
+            "comment-header",
+            "-o",
+        ])
+        .arg(&path)
+        .assert()
+        .success();
+    let csv = fs::read_to_string(&path).unwrap();
+    assert!(csv.starts_with("# device_name=Aranet4 Simulated\n"));
+    assert!(csv.contains("# device_serial=317960113191\n"));
+
+    arachiver()
+        .args([
+            "archive_history_csv",
+            "--csv-metadata",
+            "sidecar-json",
+            "-o",
+        ])
+        .arg(&path)
+        .assert()
+        .success();
+    let metadata: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(dir.join("history.meta.json")).unwrap()).unwrap();
+    assert_eq!(metadata["measurement_interval"], "300");
+    assert!(fs::read_to_string(&path).unwrap().starts_with("timestamp,"));
+}
+
+#[test]
+fn test_archive_history_csv_named_by_template() {
+    let dir = output_dir("csv_template");
+    arachiver()
+        .arg("--output-dir")
+        .arg(&dir)
+        .args(["--filename-template", "{name}.{format}"])
+        .arg("archive_history_csv")
+        .assert()
+        .success();
+    assert!(dir.join("Aranet4_Simulated.csv").exists());
+}
+
+#[test]
+fn test_archive_history_json() {
+    let dir = output_dir("json");
+    let path = dir.join("history.json");
+    arachiver()
+        .arg("archive_history_json")
+        .arg("-o")
+        .arg(&path)
+        .assert()
+        .success();
+    let history: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    assert!(history["units"].is_object());
+    assert_eq!(
+        history["readings"].as_array().unwrap().len(),
+        SIMULATED_READINGS
+    );
+
+    let path = dir.join("history.ndjson");
+    arachiver()
+        .args(["archive_history_json", "--ndjson", "-o"])
+        .arg(&path)
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap().lines().count(),
+        SIMULATED_READINGS
+    );
+}
+
+#[test]
+fn test_archive_history_binary_formats() {
+    let dir = output_dir("binary");
+    for (subcommand, file, magic) in [
+        ("archive_history_parquet", "history.parquet", &b"PAR1"[..]),
+        ("archive_history_arrow", "history.arrow", &b"ARROW1"[..]),
+        ("archive_history_xlsx", "history.xlsx", &b"PK"[..]),
+    ] {
+        let path = dir.join(file);
+        arachiver()
+            .arg(subcommand)
+            .arg("-o")
+            .arg(&path)
+            .assert()
+            .success();
+        let contents = fs::read(&path).unwrap();
+        assert!(contents.starts_with(magic), "{} has the wrong format", file);
+    }
+}
+
+#[test]
+fn test_archive_history_msgpack() {
+    let dir = output_dir("msgpack");
+    let path = dir.join("history.msgpack");
+    arachiver()
+        .args(["archive_history_msgpack", "-o"])
+        .arg(&path)
+        .assert()
+        .success();
+    let csv = dir.join("history.csv");
+    Command::cargo_bin("arachiver")
+        .unwrap()
+        .arg("convert")
+        .arg(&path)
+        .arg("-o")
+        .arg(&csv)
+        .assert()
+        .success();
+    assert!(fs::metadata(&path).unwrap().len() < fs::metadata(&csv).unwrap().len());
+    assert_eq!(
+        fs::read_to_string(&csv).unwrap().lines().count(),
+        1 + SIMULATED_READINGS
+    );
+}
+
+#[test]
+fn test_archive_history_sqlite() {
+    let dir = output_dir("sqlite");
+    let path = dir.join("history.sqlite");
+    arachiver()
+        .arg("archive_history_sqlite")
+        .arg("--database")
+        .arg(&path)
+        .assert()
+        .success();
+    assert!(fs::read(&path).unwrap().starts_with(b"SQLite format 3\0"));
+}
+
+#[test]
+fn test_archive_history_influx() {
+    let dir = output_dir("influx");
+    let path = dir.join("history.lp");
+    arachiver()
+        .arg("archive_history_influx")
+        .arg("-o")
+        .arg(&path)
+        .assert()
+        .success();
+    let lines = fs::read_to_string(&path).unwrap();
+    assert_eq!(lines.lines().count(), SIMULATED_READINGS);
+    assert!(lines.lines().all(|line| line.contains("co2=")));
+}
+
+#[test]
+fn test_batch() {
+    let dir = output_dir("batch");
+    arachiver()
+        .arg("--output-dir")
+        .arg(&dir)
+        .args(["batch", "--readout", "--csv", "--parquet"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("CO₂: 926 ppm"));
+    let mut written: Vec<String> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    written.sort();
+    assert_eq!(written.len(), 2);
+    assert!(written[0].ends_with("_Aranet4_Simulated_history.csv"));
+    assert!(written[1].ends_with("_Aranet4_Simulated_history.parquet"));
+}
+
+#[test]
+fn test_archive_history_combined() {
+    let dir = output_dir("combined");
+    let path = dir.join("home.csv");
+    arachiver()
+        .args(["archive_history_combined", "-o"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with(format!(
+            "Wrote {} rows to ",
+            SIMULATED_READINGS
+        )));
+    let csv = fs::read_to_string(&path).unwrap();
+    assert!(csv.starts_with("timestamp,Aranet4 Simulated Temperature (°C),"));
+
+    let path = dir.join("home.parquet");
+    arachiver()
+        .args(["combined", "--layout", "long", "-o"])
+        .arg(&path)
+        .assert()
+        .success();
+    assert!(fs::read(&path).unwrap().starts_with(b"PAR1"));
+}
+
+#[test]
+fn test_plot_svg() {
+    let dir = output_dir("plot");
+    let path = dir.join("co2.svg");
+    arachiver()
+        .args(["plot", "--metric", "co2,temperature", "-o"])
+        .arg(&path)
+        .assert()
+        .success();
+    assert!(fs::read_to_string(&path).unwrap().contains("<svg"));
+}
+
+#[test]
+fn test_stats() {
+    arachiver()
+        .args(["stats", "--last", "24h"])
+        .assert()
+        .success()
+        .stdout(predicate::str::starts_with("Aranet4 Simulated\n"))
+        .stdout(predicate::str::contains("Readings: "))
+        .stdout(predicate::str::contains("At or above 1000 ppm: "));
+}
+
+#[test]
+fn test_stats_from_archive() {
+    let dir = output_dir("stats");
+    let path = dir.join("history.csv");
+    fs::write(
+        &path,
+        "timestamp,Temperature (°C),Humidity (%),Pressure (hPa),CO₂ (ppm)\n\
+         1000,20.65,33,1017.4,926\n\
+         1300,20.70,34,1017.5,1010\n",
+    )
+    .unwrap();
+    Command::cargo_bin("arachiver")
+        .unwrap()
+        .args(["stats", "--input"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "CO₂: min 926 ppm, max 1010 ppm, mean 968 ppm, median 968 ppm\n",
+        ))
+        .stdout(predicate::str::contains(
+            "At or above 1000 ppm: 5 min (50%)\n",
+        ));
+}
+
+#[test]
+fn test_set_interval() {
+    arachiver()
+        .args(["set_interval", "10"])
+        .assert()
+        .success()
+        .stdout("Measurement interval is now 600 s\n");
+}
+
+#[test]
+fn test_bluetooth_only_subcommands_rejected() {
+    for subcommand in ["scan", "doctor", "list_adapters", "listen"] {
+        arachiver()
+            .arg(subcommand)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("can't be simulated"));
+    }
+    arachiver()
+        .args(["--address", "D4:8E:2A:11:5C:90", "readout"])
+        .assert()
+        .failure();
+}
@@ -0,0 +1,252 @@
+//! History packet parsing against notification dumps in `tests/fixtures` and generated
+//! packets, so that changes to the download protocol can't silently misplace readings.
+
+use std::fs;
+use std::path::Path;
+
+use proptest::prelude::*;
+
+use arachiver::device::get_history;
+use arachiver::mock::{history_packets, MockAranet4};
+use arachiver::types::{
+    Aranet4Error, CO2Data, HistoryResponseHeader, HumidityData, RadonData, TemperatureData,
+};
+use arachiver::History;
+
+/// The most readings an Aranet4 keeps before overwriting the oldest.
+const RING_BUFFER_SIZE: usize = 2016;
+
+const TEMPERATURE: u8 = 1;
+const HUMIDITY: u8 = 2;
+const PRESSURE: u8 = 3;
+const CO2: u8 = 4;
+
+/// The notifications in a fixture file: one packet per line in hex, `#` starting a comment.
+fn fixture_packets(name: &str) -> Vec<Vec<u8>> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name);
+    fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("Could not read {}: {}", path.display(), e))
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            (0..line.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&line[i..i + 2], 16).unwrap())
+                .collect()
+        })
+        .collect()
+}
+
+/// A sensor with `num_readings` readings of every metric, whose history requests are answered
+/// with `packets` for the given type codes instead.
+fn mock_with_packets(num_readings: usize, packets: Vec<(u8, Vec<Vec<u8>>)>) -> MockAranet4 {
+    let values: Vec<u16> = (0..num_readings).map(|i| 400 + i as u16).collect();
+    let humidity: Vec<u8> = (0..num_readings).map(|i| (i % 100) as u8).collect();
+    let mut sensor =
+        MockAranet4::new("Aranet4 1BA27").with_history(&values, &humidity, &values, &values);
+    for (type_code, packets) in packets {
+        sensor = sensor.with_history_packets(type_code, packets);
+    }
+    sensor
+}
+
+fn fixture_sensor() -> MockAranet4 {
+    mock_with_packets(
+        RING_BUFFER_SIZE,
+        vec![
+            (TEMPERATURE, fixture_packets("history_temperature.hex")),
+            (HUMIDITY, fixture_packets("history_humidity.hex")),
+            (PRESSURE, fixture_packets("history_pressure.hex")),
+            (CO2, fixture_packets("history_co2.hex")),
+        ],
+    )
+}
+
+fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+fn is_invalid_response(error: &color_eyre::Report) -> bool {
+    matches!(
+        error.downcast_ref::<Aranet4Error>(),
+        Some(Aranet4Error::InvalidResponse(_))
+    )
+}
+
+#[tokio::test]
+async fn test_fixture_full_ring_buffer() {
+    let sensor = fixture_sensor();
+    let History {
+        time,
+        temperature,
+        humidity,
+        pressure,
+        co2,
+    } = get_history(&sensor).await.unwrap();
+    assert_eq!(time.num_samples, RING_BUFFER_SIZE);
+    assert_eq!(temperature.values.len(), RING_BUFFER_SIZE);
+    assert_eq!(humidity.values.len(), RING_BUFFER_SIZE);
+    assert_eq!(pressure.values.len(), RING_BUFFER_SIZE);
+    assert_eq!(co2.values.len(), RING_BUFFER_SIZE);
+
+    assert_eq!(temperature.values[0], 403);
+    assert_eq!(temperature.values[RING_BUFFER_SIZE - 1], 405);
+    assert_eq!(
+        temperature.values.iter().map(|&v| v as u64).sum::<u64>(),
+        846_742
+    );
+    assert_eq!(humidity.values[0], 34);
+    assert_eq!(
+        humidity.values.iter().map(|&v| v as u64).sum::<u64>(),
+        76_900
+    );
+    assert_eq!(pressure.values[RING_BUFFER_SIZE - 1], 10_080);
+    assert_eq!(
+        pressure.values.iter().map(|&v| v as u64).sum::<u64>(),
+        20_434_754
+    );
+    assert_eq!(co2.values[0], 430);
+    assert_eq!(co2.values.iter().max(), Some(&1160));
+    assert_eq!(co2.values.iter().map(|&v| v as u64).sum::<u64>(), 1_210_897);
+    assert!(!sensor.is_connected());
+}
+
+#[test]
+fn test_fixture_headers() {
+    let packets = fixture_packets("history_co2.hex");
+    assert_eq!(packets.len(), RING_BUFFER_SIZE.div_ceil(120));
+    let mut next_index = 1;
+    for packet in &packets {
+        let header = HistoryResponseHeader::from(<[u8; 4]>::try_from(&packet[..4]).unwrap());
+        assert_eq!(header.type_code, CO2);
+        assert_eq!(header.start_index, next_index);
+        assert_eq!(packet.len(), 4 + 2 * header.packet_num_elem as usize);
+        next_index += header.packet_num_elem as u16;
+    }
+    assert_eq!(next_index as usize, RING_BUFFER_SIZE + 1);
+}
+
+#[tokio::test]
+async fn test_fixture_truncated_packet() {
+    let mut co2 = fixture_packets("history_co2.hex");
+    let last = co2.last_mut().unwrap();
+    last.truncate(last.len() - 3);
+    let sensor = mock_with_packets(RING_BUFFER_SIZE, vec![(CO2, co2)]);
+    let error = get_history(&sensor).await.unwrap_err();
+    assert!(is_invalid_response(&error), "{:?}", error);
+}
+
+#[tokio::test]
+async fn test_fixture_wrong_type_code() {
+    let mut co2 = fixture_packets("history_co2.hex");
+    co2[3][0] = PRESSURE;
+    let sensor = mock_with_packets(RING_BUFFER_SIZE, vec![(CO2, co2)]);
+    let error = get_history(&sensor).await.unwrap_err();
+    assert!(is_invalid_response(&error), "{:?}", error);
+}
+
+#[tokio::test]
+async fn test_packet_shorter_than_header() {
+    let sensor = mock_with_packets(RING_BUFFER_SIZE, vec![(CO2, vec![vec![CO2, 1, 0]])]);
+    let error = get_history(&sensor).await.unwrap_err();
+    assert!(is_invalid_response(&error), "{:?}", error);
+}
+
+proptest! {
+    #[test]
+    fn test_header_fields(bytes in any::<[u8; 4]>()) {
+        let header = HistoryResponseHeader::from(bytes);
+        prop_assert_eq!(header.type_code, bytes[0]);
+        prop_assert_eq!(header.start_index, u16::from_le_bytes([bytes[1], bytes[2]]));
+        prop_assert_eq!(header.packet_num_elem, bytes[3]);
+    }
+
+    #[test]
+    fn test_u16_data_round_trip(
+        values in prop::collection::vec(any::<u16>(), 0..=RING_BUFFER_SIZE),
+    ) {
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        prop_assert_eq!(TemperatureData::try_from(&bytes[..]).unwrap().values, values.clone());
+        prop_assert_eq!(CO2Data::try_from(&bytes[..]).unwrap().values, values);
+    }
+
+    #[test]
+    fn test_u8_data_round_trip(
+        bytes in prop::collection::vec(any::<u8>(), 0..=RING_BUFFER_SIZE),
+    ) {
+        prop_assert_eq!(HumidityData::try_from(&bytes[..]).unwrap().values, bytes);
+    }
+
+    #[test]
+    fn test_odd_byte_counts_rejected(bytes in prop::collection::vec(any::<u8>(), 0..=99)) {
+        prop_assume!(!bytes.len().is_multiple_of(2));
+        prop_assert!(TemperatureData::try_from(&bytes[..]).is_err());
+        prop_assert!(CO2Data::try_from(&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn test_u32_data_needs_whole_elements(bytes in prop::collection::vec(any::<u8>(), 0..=99)) {
+        prop_assert_eq!(RadonData::try_from(&bytes[..]).is_ok(), bytes.len().is_multiple_of(4));
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn test_download_round_trip(
+        co2 in prop::collection::vec(any::<u16>(), 1..=RING_BUFFER_SIZE),
+    ) {
+        let bytes: Vec<u8> = co2.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let packets = history_packets(CO2, 1, &bytes, 2);
+        let sensor = mock_with_packets(co2.len(), vec![(CO2, packets)]);
+        let history = block_on(get_history(&sensor)).unwrap();
+        prop_assert_eq!(history.time.num_samples, co2.len());
+        prop_assert_eq!(history.co2.values, co2);
+    }
+
+    #[test]
+    fn test_truncated_packets_rejected(
+        num_readings in 1..=300usize,
+        packet in any::<prop::sample::Index>(),
+        cut in 1..=240usize,
+    ) {
+        let bytes: Vec<u8> = (0..num_readings as u16)
+            .flat_map(|v| (500 + v).to_le_bytes())
+            .collect();
+        let mut packets = history_packets(CO2, 1, &bytes, 2);
+        let index = packet.index(packets.len());
+        let truncated = &mut packets[index];
+        let elements = truncated.len() - 4;
+        truncated.truncate(4 + elements - cut.min(elements));
+        let sensor = mock_with_packets(num_readings, vec![(CO2, packets)]);
+        let error = block_on(get_history(&sensor)).unwrap_err();
+        prop_assert!(is_invalid_response(&error), "{:?}", error);
+    }
+
+    #[test]
+    fn test_wrong_type_codes_rejected(
+        num_readings in 1..=300usize,
+        packet in any::<prop::sample::Index>(),
+        type_code in any::<u8>(),
+    ) {
+        prop_assume!(type_code != CO2);
+        let bytes: Vec<u8> = (0..num_readings as u16)
+            .flat_map(|v| (500 + v).to_le_bytes())
+            .collect();
+        let mut packets = history_packets(CO2, 1, &bytes, 2);
+        let len = packets.len();
+        packets[packet.index(len)][0] = type_code;
+        let sensor = mock_with_packets(num_readings, vec![(CO2, packets)]);
+        let error = block_on(get_history(&sensor)).unwrap_err();
+        prop_assert!(is_invalid_response(&error), "{:?}", error);
+    }
+}